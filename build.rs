@@ -0,0 +1,117 @@
+//! Generates the game database (see `src/rom/db.rs`) out of
+//! `data/nes20db.xml`, a file in the format of the NES 2.0 XML database.
+//! Every game in it looks like this:
+//!
+//! ```xml
+//! <game>
+//!     <!-- Super Mario Bros. (World).nes -->
+//!     <rom size="40960" crc32="3337EC46"/>
+//!     <pcb mapper="0" submapper="0" mirroring="V" battery="0"/>
+//!     <console type="0" region="0"/>
+//!     <expansion type="1"/>
+//! </game>
+//! ```
+//!
+//! The comment is the file the entry was made from, which is where the title
+//! comes from. The rest of the elements nes20db.xml has (the PRG and CHR
+//! chips on their own, the RAM sizes, ...) are skipped. The games end up in
+//! a `phf` map, written to `$OUT_DIR/games.rs` for `db.rs` to include.
+//!
+//! The whole of nes20db.xml is several megabytes, so builds can point the
+//! `NES20DB` environment variable at a copy of it instead of the file in
+//! `data/` (which only has a few games the tests look up).
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+const DATABASE: &str = "data/nes20db.xml";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=NES20DB");
+    let database = std::env::var("NES20DB").unwrap_or_else(|_| DATABASE.to_string());
+    println!("cargo:rerun-if-changed={database}");
+    let xml = std::fs::read_to_string(&database)
+        .unwrap_or_else(|err| panic!("can't read the game database {database}: {err}"));
+
+    let mut games = phf_codegen::Map::new();
+    let mut seen = HashSet::new();
+    for game in xml.split("<game>").skip(1) {
+        let game = game.split("</game>").next().unwrap();
+        let Some(crc32) = attribute(game, "rom", "crc32") else {
+            continue;
+        };
+        let crc32 = u32::from_str_radix(crc32, 16).expect("a <rom> has a bad crc32");
+        let number = |element, name| {
+            attribute(game, element, name).map_or(0, |value| {
+                value
+                    .parse::<u16>()
+                    .unwrap_or_else(|_| panic!("<{element} {name}> is not a number: {value}"))
+            })
+        };
+        let mapper = number("pcb", "mapper");
+        // the same dump under two names, or a mapper past the 255 iNES has
+        // room for (which nothing here emulates)
+        if mapper > 255 || !seen.insert(crc32) {
+            continue;
+        }
+
+        let mut info = String::new();
+        write!(
+            info,
+            "GameInfo {{ title: {:?}, mapper: {mapper}, submapper: {}, ",
+            title(game),
+            number("pcb", "submapper"),
+        )
+        .unwrap();
+        let mirroring = match attribute(game, "pcb", "mirroring") {
+            Some("V") => "Some(Mirroring::Vertical)",
+            Some("4") => "None",
+            _ => "Some(Mirroring::Horizontal)",
+        };
+        let region = match number("console", "region") {
+            0 => "Some(Region::Ntsc)",
+            1 => "Some(Region::Pal)",
+            3 => "Some(Region::Dendy)",
+            // runs on any of them
+            _ => "None",
+        };
+        write!(
+            info,
+            "mirroring: {mirroring}, battery: {}, region: {region}, \
+             expansion: expansion({}) }}",
+            number("pcb", "battery") != 0,
+            number("expansion", "type"),
+        )
+        .unwrap();
+        games.entry(crc32, info);
+    }
+
+    let path = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("games.rs");
+    std::fs::write(path, games.build().to_string()).expect("can't write the game database");
+}
+
+/// The value of attribute `name` of the first `<element>` in `game`
+fn attribute<'a>(game: &'a str, element: &str, name: &str) -> Option<&'a str> {
+    let start = game.find(&format!("<{element} "))?;
+    let tag = &game[start..];
+    let tag = &tag[..tag.find('>')?];
+    let value = &tag[tag.find(&format!(" {name}=\""))? + name.len() + 3..];
+    Some(&value[..value.find('"')?])
+}
+
+/// The game's title, out of the name of the file the entry was made from
+/// (without the folders, the extension and the No-Intro tags in brackets)
+fn title(game: &str) -> String {
+    let comment = game
+        .split("<!--")
+        .nth(1)
+        .and_then(|comment| comment.split("-->").next())
+        .unwrap_or_default()
+        .trim();
+    let file = comment.rsplit(['\\', '/']).next().unwrap_or(comment);
+    let name = [".nes", ".unf", ".unif", ".fds"]
+        .iter()
+        .find_map(|extension| file.strip_suffix(extension))
+        .unwrap_or(file);
+    name.split(" (").next().unwrap_or(name).trim().to_string()
+}
@@ -0,0 +1,216 @@
+//! Contains the delta modulation channel (DMC), which plays 1 bit delta
+//! encoded samples straight out of CPU memory: every bit moves the output
+//! level up or down by 2.
+
+use crate::savestate::{StateReader, StateWriter};
+
+/// The timer periods (in CPU cycles) bits 0 - 3 of $4010 pick from
+const RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+/// The periods on a PAL APU, which are shorter to make up for its slower
+/// clock
+const PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// The DMC, written through $4010 - $4013:
+/// * $4010: IRQ enable (7), loop (6), rate (0 - 3)
+/// * $4011: output level (0 - 6)
+/// * $4012: sample address, 0xC000 + value * 64
+/// * $4013: sample length, value * 16 + 1 bytes
+///
+/// The bus fetches the sample bytes for it: whenever `fetch_address` has an
+/// address, the byte there goes into `fill`
+#[derive(Debug, Clone)]
+pub(crate) struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    /// the periods $4010 picks from (NTSC's or PAL's)
+    rates: &'static [u16; 16],
+    rate: u16,
+    timer: u16,
+    level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    /// where the next byte gets fetched from
+    address: u16,
+    bytes_remaining: u16,
+    /// the byte fetched for the output unit, if it doesn't have it yet
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    /// the output unit ran out of bytes and leaves the level alone
+    silence: bool,
+    irq: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self {
+            irq_enabled: false,
+            looping: false,
+            rates: &RATES,
+            rate: RATES[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+}
+
+impl Dmc {
+    /// Switches to the periods of a PAL APU (or back to NTSC's)
+    pub fn set_pal(&mut self, pal: bool) {
+        let rates = if pal { &PAL_RATES } else { &RATES };
+        if let Some(index) = self.rates.iter().position(|&rate| rate == self.rate) {
+            self.rate = rates[index];
+        }
+        self.rates = rates;
+    }
+
+    /// Writes to register 0 - 3 ($4010 - $4013)
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0b1000_0000 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = value & 0b100_0000 != 0;
+                self.rate = self.rates[(value & 0b1111) as usize];
+            }
+            1 => self.level = value & 0b111_1111,
+            2 => self.sample_address = 0xC000 + value as u16 * 64,
+            3 => self.sample_length = value as u16 * 16 + 1,
+            _ => {}
+        }
+    }
+
+    /// Enables the channel through $4015, which starts the sample over if
+    /// it has finished, or disables it, which stops it. Either way the IRQ
+    /// is acknowledged
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Whether there are sample bytes left to play (what $4015 reads)
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// The address of the next sample byte, when the channel wants it
+    pub fn fetch_address(&self) -> Option<u16> {
+        (self.buffer.is_none() && self.bytes_remaining > 0).then_some(self.address)
+    }
+
+    /// Hands the channel the byte at `fetch_address`
+    pub fn fill(&mut self, value: u8) {
+        self.buffer = Some(value);
+        // the address wraps around to 0x8000
+        self.address = self.address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocks the timer (every CPU cycle), which plays the next bit
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(value) => {
+                    self.silence = false;
+                    self.shift = value;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// The output level (0 - 127)
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.irq_enabled);
+        state.bool(self.looping);
+        state.u16(self.rate);
+        state.u16(self.timer);
+        state.u8(self.level);
+        state.u16(self.sample_address);
+        state.u16(self.sample_length);
+        state.u16(self.address);
+        state.u16(self.bytes_remaining);
+        state.bool(self.buffer.is_some());
+        state.u8(self.buffer.unwrap_or(0));
+        state.u8(self.shift);
+        state.u8(self.bits_remaining);
+        state.bool(self.silence);
+        state.bool(self.irq);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = state.bool()?;
+        self.looping = state.bool()?;
+        self.rate = state.u16()?.max(1);
+        self.timer = state.u16()?;
+        self.level = state.u8()? & 0b111_1111;
+        self.sample_address = state.u16()?;
+        self.sample_length = state.u16()?;
+        self.address = state.u16()?;
+        self.bytes_remaining = state.u16()?;
+        let buffered = state.bool()?;
+        let buffer = state.u8()?;
+        self.buffer = buffered.then_some(buffer);
+        self.shift = state.u8()?;
+        self.bits_remaining = state.u8()?.clamp(1, 8);
+        self.silence = state.bool()?;
+        self.irq = state.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+//! Contains the APU (Audio Processing Unit), the sound half of the 2A03:
+//! two pulse channels, a triangle, noise and the delta modulation channel
+//! (DMC), mixed together the way the NES's resistor network does it, plus
+//! whatever audio the cartridge adds (expansion audio, like MMC5's).
+//!
+//! What's emulated:
+//! * the registers at [0x4000 ... 0x4013], 0x4015 (channel enables and
+//!   status) and 0x4017 (frame counter)
+//! * the frame counter, which clocks the envelopes and the linear counter
+//!   every quarter frame and the length counters and sweep units every half
+//!   frame, and raises an IRQ at the end of every 4 step sequence (unless
+//!   inhibited)
+//! * the DMC's sample fetches (through the bus, without the CPU stalls) and
+//!   its IRQ
+//!
+//! PAL APUs step the frame counter and pick the noise and DMC periods from
+//! tables of their own (see `set_region`).
+//!
+//! The mixed output is sampled at `SAMPLE_RATE` by averaging it over every
+//! sample period. Samples are only kept while recording (see
+//! `Bus::record_audio`), so there's no cost unless someone is listening.
+
+mod dmc;
+mod noise;
+pub(crate) mod pulse;
+mod triangle;
+mod units;
+
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
+use dmc::Dmc;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+/// The rate the output is sampled at (in Hz)
+pub const SAMPLE_RATE: u64 = 44_100;
+/// The rate the CPU (and so the APU) runs at on an NTSC NES (in Hz, see
+/// `Region::cpu_clock` for the others)
+pub const CPU_CLOCK: u64 = 1_789_773;
+
+/// The CPU cycles (since the frame counter was reset) the frame counter
+/// steps at, in 4 step and 5 step mode
+const FOUR_STEPS: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+/// The same on a PAL APU
+const PAL_FOUR_STEPS: [u32; 4] = [8313, 16627, 24939, 33253];
+const PAL_FIVE_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+// $4015 bits
+const STATUS_PULSE_1: u8 = 0b0000_0001;
+const STATUS_PULSE_2: u8 = 0b0000_0010;
+const STATUS_TRIANGLE: u8 = 0b0000_0100;
+const STATUS_NOISE: u8 = 0b0000_1000;
+const STATUS_DMC: u8 = 0b0001_0000;
+const STATUS_FRAME_IRQ: u8 = 0b0100_0000;
+const STATUS_DMC_IRQ: u8 = 0b1000_0000;
+
+// $4017 bits
+const FRAME_FIVE_STEPS: u8 = 0b1000_0000;
+const FRAME_IRQ_INHIBIT: u8 = 0b0100_0000;
+
+/// How loud the two pulse channels are together (`pulse` is the sum of
+/// their 0 - 15 volumes), the way the NES's mixer adds them up
+pub(crate) fn mix_pulses(pulse: f32) -> f32 {
+    if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    }
+}
+
+/// How loud the triangle (0 - 15), noise (0 - 15) and DMC (0 - 127) are
+/// together
+pub(crate) fn mix_tnd(triangle: f32, noise: f32, dmc: f32) -> f32 {
+    let sum = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+    if sum == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / sum + 100.0)
+    }
+}
+
+pub struct Apu {
+    region: Region,
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    /// 5 step mode (no IRQ, a longer sequence)
+    five_steps: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles since the frame counter was reset
+    frame_cycle: u32,
+    /// CPU cycles since power on (the pulse timers only count every other)
+    cycle: u64,
+    /// counts towards the next sample (`SAMPLE_RATE` per CPU cycle, a sample
+    /// every CPU clock)
+    sample_clock: u64,
+    sample_sum: f32,
+    sample_cycles: u32,
+    /// the samples put out since they were last taken, when recording
+    samples: Option<Vec<f32>>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            region: Region::Ntsc,
+            pulses: [Pulse::new(true), Pulse::new(false)],
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            five_steps: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            cycle: 0,
+            sample_clock: 0,
+            sample_sum: 0.0,
+            sample_cycles: 0,
+            samples: None,
+        }
+    }
+
+    /// Puts the APU back into its power on state (whether samples are being
+    /// recorded and the region stay the same)
+    pub(crate) fn power_cycle(&mut self) {
+        let samples = self.samples.take().map(|_| Vec::new());
+        let region = self.region;
+        *self = Self {
+            samples,
+            ..Self::new()
+        };
+        self.set_region(region);
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switches to the timing of another region: the CPU clock samples are
+    /// taken at, and PAL's frame counter steps and noise/DMC periods
+    pub(crate) fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_pal(region == Region::Pal);
+        self.dmc.set_pal(region == Region::Pal);
+    }
+
+    /// Writes to a register in [0x4000 ... 0x4013], 0x4015 or 0x4017
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulses[0].write(addr - 0x4000, value),
+            0x4004..=0x4007 => self.pulses[1].write(addr - 0x4004, value),
+            0x4008..=0x400B => self.triangle.write(addr - 0x4008, value),
+            0x400C..=0x400F => self.noise.write(addr - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, value),
+            0x4015 => {
+                self.pulses[0].set_enabled(value & STATUS_PULSE_1 != 0);
+                self.pulses[1].set_enabled(value & STATUS_PULSE_2 != 0);
+                self.triangle.set_enabled(value & STATUS_TRIANGLE != 0);
+                self.noise.set_enabled(value & STATUS_NOISE != 0);
+                self.dmc.set_enabled(value & STATUS_DMC != 0);
+            }
+            0x4017 => {
+                self.five_steps = value & FRAME_FIVE_STEPS != 0;
+                self.irq_inhibit = value & FRAME_IRQ_INHIBIT != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                // 5 step mode clocks everything right away
+                if self.five_steps {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads $4015: which channels are still playing and the IRQ flags.
+    /// This acknowledges the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    /// Returns what reading $4015 would return without acknowledging anything
+    pub fn peek_status(&self) -> u8 {
+        let flags = [
+            (self.pulses[0].active(), STATUS_PULSE_1),
+            (self.pulses[1].active(), STATUS_PULSE_2),
+            (self.triangle.active(), STATUS_TRIANGLE),
+            (self.noise.active(), STATUS_NOISE),
+            (self.dmc.active(), STATUS_DMC),
+            (self.frame_irq, STATUS_FRAME_IRQ),
+            (self.dmc.irq(), STATUS_DMC_IRQ),
+        ];
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |status, (_, bit)| status | bit)
+    }
+
+    /// Whether the frame counter or the DMC is asserting the IRQ line
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq()
+    }
+
+    /// The address the DMC wants the next sample byte from (see `fill_dmc`)
+    pub(crate) fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    pub(crate) fn fill_dmc(&mut self, value: u8) {
+        self.dmc.fill(value);
+    }
+
+    /// Advances the APU by a CPU cycle. `expansion` is what the cartridge's
+    /// own audio puts out, on the same scale as the mixer's output
+    pub(crate) fn clock(&mut self, expansion: f32) {
+        self.cycle += 1;
+        if self.cycle.is_multiple_of(2) {
+            for pulse in &mut self.pulses {
+                pulse.clock_timer();
+            }
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        self.clock_frame_counter();
+
+        if self.samples.is_some() {
+            self.sample_sum += self.output() + expansion;
+            self.sample_cycles += 1;
+            self.sample_clock += SAMPLE_RATE;
+            let cpu_clock = self.region.cpu_clock();
+            if self.sample_clock >= cpu_clock {
+                self.sample_clock -= cpu_clock;
+                let sample = self.sample_sum / self.sample_cycles as f32;
+                (self.sample_sum, self.sample_cycles) = (0.0, 0);
+                if let Some(samples) = &mut self.samples {
+                    samples.push(sample);
+                }
+            }
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let (four_steps, five_steps) = match self.region {
+            Region::Pal => (&PAL_FOUR_STEPS, &PAL_FIVE_STEPS),
+            Region::Ntsc | Region::Dendy => (&FOUR_STEPS, &FIVE_STEPS),
+        };
+        if self.five_steps {
+            match five_steps.iter().position(|&step| step == self.frame_cycle) {
+                Some(0) | Some(2) => self.quarter_frame(),
+                Some(1) => {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+                Some(4) => {
+                    self.quarter_frame();
+                    self.half_frame();
+                    self.frame_cycle = 0;
+                }
+                _ => {}
+            }
+        } else {
+            match four_steps.iter().position(|&step| step == self.frame_cycle) {
+                Some(0) | Some(2) => self.quarter_frame(),
+                Some(1) => {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+                Some(3) => {
+                    self.quarter_frame();
+                    self.half_frame();
+                    if !self.irq_inhibit {
+                        self.frame_irq = true;
+                    }
+                    self.frame_cycle = 0;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn quarter_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.clock_envelope();
+        }
+        self.triangle.clock_linear();
+        self.noise.clock_envelope();
+    }
+
+    fn half_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.clock_length();
+        }
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
+    /// The mixed output of the 5 channels right now (0.0 - 1.0)
+    pub fn output(&self) -> f32 {
+        let pulse = self.pulses[0].output() + self.pulses[1].output();
+        mix_pulses(pulse as f32)
+            + mix_tnd(
+                self.triangle.output() as f32,
+                self.noise.output() as f32,
+                self.dmc.output() as f32,
+            )
+    }
+
+    /// Turns the recording of samples on or off (dropping any that weren't
+    /// taken when it's turned off)
+    pub fn record_samples(&mut self, record: bool) {
+        if record != self.samples.is_some() {
+            self.samples = record.then(Vec::new);
+            (self.sample_sum, self.sample_cycles) = (0.0, 0);
+        }
+    }
+
+    /// Returns the samples (0.0 - 1.0, at `SAMPLE_RATE`) put out since the
+    /// last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        match &mut self.samples {
+            Some(samples) => std::mem::take(samples),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        for pulse in &self.pulses {
+            pulse.save_state(state);
+        }
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        state.bool(self.five_steps);
+        state.bool(self.irq_inhibit);
+        state.bool(self.frame_irq);
+        state.u64(self.frame_cycle as u64);
+        state.u64(self.cycle);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for pulse in &mut self.pulses {
+            pulse.load_state(state)?;
+        }
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.five_steps = state.bool()?;
+        self.irq_inhibit = state.bool()?;
+        self.frame_irq = state.bool()?;
+        self.frame_cycle = state.u64()? as u32;
+        self.cycle = state.u64()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! Contains the noise channel, which puts out pseudo-random bits from a 15
+//! bit linear feedback shift register.
+
+use super::units::{Envelope, LengthCounter};
+use crate::savestate::{StateReader, StateWriter};
+
+/// The timer periods (in CPU cycles) bits 0 - 3 of $400E pick from
+const PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+/// The periods on a PAL APU, which are shorter to make up for its slower
+/// clock
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// The noise channel, written through $400C, $400E and $400F:
+/// * $400C: length counter halt (5), envelope (0 - 5)
+/// * $400E: mode (7), period (0 - 3)
+/// * $400F: length counter load (3 - 7)
+#[derive(Debug, Clone)]
+pub(crate) struct Noise {
+    /// short mode takes the feedback from bit 6 instead of bit 1, which
+    /// makes the sequence 93 steps long and sound metallic
+    short_mode: bool,
+    /// the periods $400E picks from (NTSC's or PAL's)
+    periods: &'static [u16; 16],
+    period: u16,
+    timer: u16,
+    shift: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            short_mode: false,
+            periods: &PERIODS,
+            period: PERIODS[0],
+            timer: 0,
+            // the shift register is 1 at power on
+            shift: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+}
+
+impl Noise {
+    /// Switches to the periods of a PAL APU (or back to NTSC's)
+    pub fn set_pal(&mut self, pal: bool) {
+        let periods = if pal { &PAL_PERIODS } else { &PERIODS };
+        if let Some(index) = self
+            .periods
+            .iter()
+            .position(|&period| period == self.period)
+        {
+            self.period = periods[index];
+        }
+        self.periods = periods;
+    }
+
+    /// Writes to register 0 - 3 ($400C - $400F)
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length.set_halted(value & 0b10_0000 != 0);
+                self.envelope.write(value);
+            }
+            2 => {
+                self.short_mode = value & 0b1000_0000 != 0;
+                self.period = self.periods[(value & 0b1111) as usize];
+            }
+            3 => {
+                self.length.load(value);
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    /// Clocks the timer (every CPU cycle), which shifts the register
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_length(&mut self) {
+        self.length.clock();
+    }
+
+    /// The volume the channel puts out right now (0 - 15)
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.short_mode);
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u16(self.shift);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.short_mode = state.bool()?;
+        self.period = state.u16()?.max(1);
+        self.timer = state.u16()?;
+        self.shift = state.u16()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)
+    }
+}
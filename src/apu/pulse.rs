@@ -0,0 +1,202 @@
+//! Contains the pulse (square wave) channel. The APU has two, and MMC5 has
+//! two more that work the same minus the sweep unit.
+
+use super::units::{Envelope, LengthCounter};
+use crate::savestate::{StateReader, StateWriter};
+
+/// The 8 step waveforms of the 4 duty cycles (12.5%, 25%, 50%, 75%)
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// A pulse channel, written through 4 registers:
+/// * 0: duty cycle (bits 6 - 7), length counter halt (5), envelope (0 - 5)
+/// * 1: sweep unit enable (7), period (4 - 6), negate (3), shift (0 - 2)
+/// * 2: timer period low 8 bits
+/// * 3: length counter load (3 - 7), timer period high 3 bits (0 - 2)
+#[derive(Debug, Clone)]
+pub(crate) struct Pulse {
+    duty: u8,
+    /// where in the waveform the channel is (0 - 7)
+    step: u8,
+    period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+    /// pulse 1 subtracts one more when sweeping down than pulse 2 does
+    ones_complement: bool,
+    /// MMC5's pulses have no sweep unit, so nothing mutes them
+    has_sweep: bool,
+}
+
+impl Pulse {
+    /// Instantiates the first (`ones_complement`) or second pulse channel of
+    /// the APU
+    pub fn new(ones_complement: bool) -> Self {
+        Self {
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0,
+            ones_complement,
+            has_sweep: true,
+        }
+    }
+
+    /// Instantiates a pulse channel without a sweep unit (MMC5's)
+    pub fn without_sweep() -> Self {
+        Self {
+            has_sweep: false,
+            ..Self::new(false)
+        }
+    }
+
+    /// Writes to register 0 - 3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length.set_halted(value & 0b10_0000 != 0);
+                self.envelope.write(value);
+            }
+            1 if self.has_sweep => {
+                self.sweep_enabled = value & 0b1000_0000 != 0;
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 0b1000 != 0;
+                self.sweep_shift = value & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x700) | value as u16,
+            3 => {
+                self.period = (self.period & 0xFF) | ((value as u16 & 0b111) << 8);
+                self.length.load(value);
+                self.step = 0;
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    /// Whether the length counter is still running (what $4015 reads)
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    /// Clocks the timer (every other CPU cycle), which steps through the
+    /// waveform
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the envelope (every quarter frame)
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clocks the length counter and the sweep unit (every half frame)
+    pub fn clock_length(&mut self) {
+        self.length.clock();
+        if !self.has_sweep {
+            return;
+        }
+        if self.sweep_divider == 0
+            && self.sweep_enabled
+            && self.sweep_shift > 0
+            && !self.sweep_muted()
+        {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// The period the sweep unit is heading for
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            self.period
+                .saturating_sub(change + self.ones_complement as u16)
+        } else {
+            self.period + change
+        }
+    }
+
+    /// The sweep unit mutes the channel when the period is too short, or when
+    /// it would sweep past the longest one (even when it's disabled)
+    fn sweep_muted(&self) -> bool {
+        self.has_sweep && (self.period < 8 || self.sweep_target() > 0x7FF)
+    }
+
+    /// The volume the channel puts out right now (0 - 15)
+    pub fn output(&self) -> u8 {
+        if !self.length.active()
+            || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0
+            || self.sweep_muted()
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.duty);
+        state.u8(self.step);
+        state.u16(self.period);
+        state.u16(self.timer);
+        self.envelope.save_state(state);
+        self.length.save_state(state);
+        state.bool(self.sweep_enabled);
+        state.u8(self.sweep_period);
+        state.bool(self.sweep_negate);
+        state.u8(self.sweep_shift);
+        state.bool(self.sweep_reload);
+        state.u8(self.sweep_divider);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.duty = state.u8()? & 0b11;
+        self.step = state.u8()? % 8;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.envelope.load_state(state)?;
+        self.length.load_state(state)?;
+        self.sweep_enabled = state.bool()?;
+        self.sweep_period = state.u8()?;
+        self.sweep_negate = state.bool()?;
+        self.sweep_shift = state.u8()?;
+        self.sweep_reload = state.bool()?;
+        self.sweep_divider = state.u8()?;
+        Ok(())
+    }
+}
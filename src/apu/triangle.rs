@@ -0,0 +1,114 @@
+//! Contains the triangle channel, which steps through a 32 step triangle
+//! wave at full volume (it has no volume control, just a linear counter on
+//! top of the length counter to silence it).
+
+use super::units::LengthCounter;
+use crate::savestate::{StateReader, StateWriter};
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// The triangle channel, written through $4008, $400A and $400B:
+/// * $4008: length counter halt and linear counter control (7), linear
+///   counter reload value (0 - 6)
+/// * $400A: timer period low 8 bits
+/// * $400B: length counter load (3 - 7), timer period high 3 bits (0 - 2)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Triangle {
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: LengthCounter,
+    /// keeps the linear counter reloading (and halts the length counter)
+    control: bool,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    linear: u8,
+}
+
+impl Triangle {
+    /// Writes to register 0 - 3 ($4008 - $400B)
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0b1000_0000 != 0;
+                self.length.set_halted(self.control);
+                self.linear_reload_value = value & 0b111_1111;
+            }
+            2 => self.period = (self.period & 0x700) | value as u16,
+            3 => {
+                self.period = (self.period & 0xFF) | ((value as u16 & 0b111) << 8);
+                self.length.load(value);
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    /// Clocks the timer (every CPU cycle). The wave only moves while both
+    /// counters are running, so silencing the channel holds its output
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the linear counter (every quarter frame)
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_reload_value;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// Clocks the length counter (every half frame)
+    pub fn clock_length(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.step);
+        state.u16(self.period);
+        state.u16(self.timer);
+        self.length.save_state(state);
+        state.bool(self.control);
+        state.u8(self.linear_reload_value);
+        state.bool(self.linear_reload);
+        state.u8(self.linear);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.step = state.u8()? % 32;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.length.load_state(state)?;
+        self.control = state.bool()?;
+        self.linear_reload_value = state.u8()?;
+        self.linear_reload = state.bool()?;
+        self.linear = state.u8()?;
+        Ok(())
+    }
+}
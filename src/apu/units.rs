@@ -0,0 +1,138 @@
+//! Contains the envelope and length counter the pulse and noise channels
+//! share (the triangle has a length counter too).
+
+use crate::savestate::{StateReader, StateWriter};
+
+/// The lengths (in half frames) the top 5 bits of a channel's last register
+/// pick from
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Decays the volume from 15 down to 0 (clocked every quarter frame), or
+/// puts out a constant volume
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Envelope {
+    /// restart the decay on the next clock
+    start: bool,
+    /// start over at 15 after reaching 0
+    looping: bool,
+    constant: bool,
+    /// the constant volume, or the period of the decay
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Takes the loop flag, the constant volume flag and the volume/period
+    /// from bits 5, 4 and 0 - 3 of a channel's first register
+    pub fn write(&mut self, value: u8) {
+        self.looping = value & 0b10_0000 != 0;
+        self.constant = value & 0b1_0000 != 0;
+        self.volume = value & 0b1111;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.start);
+        state.bool(self.looping);
+        state.bool(self.constant);
+        state.u8(self.volume);
+        state.u8(self.divider);
+        state.u8(self.decay);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.start = state.bool()?;
+        self.looping = state.bool()?;
+        self.constant = state.bool()?;
+        self.volume = state.u8()?;
+        self.divider = state.u8()?;
+        self.decay = state.u8()?;
+        Ok(())
+    }
+}
+
+/// Silences a channel once it runs out (counting down every half frame,
+/// unless it's halted)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LengthCounter {
+    /// disabled through $4015, which also clears the counter
+    enabled: bool,
+    halted: bool,
+    value: u8,
+}
+
+impl LengthCounter {
+    /// Loads the length the top 5 bits of `value` pick (only when enabled)
+    pub fn load(&mut self, value: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[(value >> 3) as usize];
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+        }
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halted && self.value > 0 {
+            self.value -= 1;
+        }
+    }
+
+    /// Whether the channel is still playing (what $4015 reads)
+    pub fn active(&self) -> bool {
+        self.value > 0
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.bool(self.halted);
+        state.u8(self.value);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.enabled = state.bool()?;
+        self.halted = state.bool()?;
+        self.value = state.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,593 @@
+//! Contains a small two-pass 6502 assembler, mostly meant for writing test
+//! programs and ROM patches as readable assembly instead of hex arrays.
+//!
+//! Supported syntax:
+//! * labels (`loop:`) and constants (`SCREEN = $0200`)
+//! * `.org`, `.byte`/`.db` (numbers and "strings") and `.word`/`.dw` directives
+//! * every addressing mode: `#$10`, `$10`, `$10,X`, `$1234,Y`, `($10,X)`,
+//!   `($10),Y`, `($FFFC)` and `A` (or nothing) for accumulator instructions
+//! * expressions with `+ - * /`, parentheses, `<`/`>` for the low/high byte,
+//!   `*` for the current address, `$hex`, `%binary`, decimal and `'c'` chars
+//! * `;` comments
+//!
+//! Only the opcodes the CPU knows how to execute can be assembled.
+//!
+//! The first pass works out how big each line is and where every label lives,
+//! the second pass emits the bytes. A zero page addressing mode is only picked
+//! when the operand is already known to fit in a byte during the first pass,
+//! so forward references to labels always use the absolute variant.
+
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::opcodes::OpCode;
+use crate::disasm::Symbols;
+use std::collections::HashMap;
+
+/// Assembles a list of source lines (joined with newlines) into bytes,
+/// panicking on any assembler error. Intended for tests:
+///
+/// ```
+/// use nes_emulator::asm;
+///
+/// let program = asm!(
+///     "    LDX #$08",
+///     "loop:",
+///     "    DEX",
+///     "    BNE loop",
+///     "    BRK",
+/// );
+/// assert_eq!(program, vec![0xa2, 0x08, 0xca, 0xd0, 0xfd, 0x00]);
+/// ```
+#[macro_export]
+macro_rules! asm {
+    ($($line:expr),* $(,)?) => {
+        $crate::asm::assemble(&[$($line),*].join("\n"))
+            .unwrap_or_else(|err| panic!("{}", err))
+            .bytes
+    };
+}
+
+/// The result of assembling a program
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// the address the first byte of `bytes` should live at (wherever the
+    /// first byte was emitted, code can't go below it)
+    pub origin: u16,
+    /// the assembled bytes (gaps between `.org` regions are filled with 0x00)
+    pub bytes: Vec<u8>,
+    /// every label and constant defined in the source
+    pub symbols: Symbols,
+}
+
+/// Assembles a program. Errors are reported as `line N: message`
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(line_no, line)| {
+            parse_line(line).map_err(|err| format!("line {}: {err}", line_no + 1))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut assembler = Assembler {
+        labels: HashMap::new(),
+        pc: 0,
+        final_pass: false,
+        output: Vec::new(),
+        origin: None,
+    };
+
+    // the modes picked in the first pass are locked in so that the size of
+    // every line (and therefore the address of every label) stays the same
+    // in the second pass
+    let mut modes = vec![None; lines.len()];
+    for final_pass in [false, true] {
+        assembler.pc = 0;
+        assembler.final_pass = final_pass;
+        for (line_no, line) in lines.iter().enumerate() {
+            assembler
+                .assemble_line(line, &mut modes[line_no])
+                .map_err(|err| format!("line {}: {err}", line_no + 1))?;
+        }
+    }
+
+    let mut symbols = Symbols::new();
+    for (label, addr) in &assembler.labels {
+        symbols.insert(*addr as u16, label);
+    }
+    Ok(Program {
+        origin: assembler.origin.unwrap_or_default(),
+        bytes: assembler.output,
+        symbols,
+    })
+}
+
+/// A single line of source, split into its parts
+#[derive(Debug, Default)]
+struct Line {
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+#[derive(Debug)]
+enum Statement {
+    Constant(String, String),
+    Org(String),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Instruction(String, String),
+}
+
+/// Strips the comment from a line, ignoring any `;` inside of quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (idx, ch) in line.char_indices() {
+        match (ch, quote) {
+            ('"' | '\'', None) => quote = Some(ch),
+            (ch, Some(open)) if ch == open => quote = None,
+            (';', None) => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits a comma separated argument list, ignoring commas inside of quotes
+fn split_args(args: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quote = None;
+    for ch in args.chars() {
+        match (ch, quote) {
+            ('"' | '\'', None) => quote = Some(ch),
+            (ch, Some(open)) if ch == open => quote = None,
+            (',', None) => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        parts.last_mut().unwrap().push(ch);
+    }
+    parts
+        .into_iter()
+        .map(|part| part.trim().to_string())
+        .collect()
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(ch) if ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+fn parse_line(line: &str) -> Result<Line, String> {
+    let mut rest = strip_comment(line).trim();
+    let mut parsed = Line::default();
+
+    if let Some((label, after)) = rest.split_once(':')
+        && is_identifier(label.trim())
+    {
+        parsed.label = Some(label.trim().to_string());
+        rest = after.trim();
+    }
+    if rest.is_empty() {
+        return Ok(parsed);
+    }
+
+    if let Some((name, value)) = rest.split_once('=')
+        && is_identifier(name.trim())
+    {
+        parsed.statement = Some(Statement::Constant(
+            name.trim().to_string(),
+            value.trim().to_string(),
+        ));
+        return Ok(parsed);
+    }
+
+    let (keyword, args) = match rest.split_once(char::is_whitespace) {
+        Some((keyword, args)) => (keyword, args.trim()),
+        None => (rest, ""),
+    };
+    parsed.statement = Some(match keyword.to_ascii_lowercase().as_str() {
+        ".org" => Statement::Org(args.to_string()),
+        ".byte" | ".db" => Statement::Bytes(split_args(args)),
+        ".word" | ".dw" => Statement::Words(split_args(args)),
+        directive if directive.starts_with('.') => {
+            return Err(format!("Unknown directive {keyword}"));
+        }
+        _ => Statement::Instruction(keyword.to_ascii_uppercase(), args.to_string()),
+    });
+    Ok(parsed)
+}
+
+struct Assembler {
+    labels: HashMap<String, i64>,
+    pc: i64,
+    final_pass: bool,
+    output: Vec<u8>,
+    origin: Option<u16>,
+}
+
+impl Assembler {
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if !self.final_pass && self.labels.insert(name.to_string(), value).is_some() {
+            return Err(format!("Label {name} is defined more than once"));
+        }
+        Ok(())
+    }
+
+    /// Evaluates an expression. Outside of the final pass, unknown labels
+    /// evaluate to `None` instead of an error since they may be defined
+    /// further down in the source
+    fn eval(&self, expr: &str) -> Result<Option<i64>, String> {
+        let tokens = tokenize(expr)?;
+        let mut parser = ExprParser {
+            tokens: &tokens,
+            pos: 0,
+            assembler: self,
+        };
+        let value = parser.sum()?;
+        if parser.pos != tokens.len() {
+            return Err(format!("Invalid expression {expr}"));
+        }
+        Ok(value)
+    }
+
+    /// Emits bytes at the current address
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.pc + bytes.len() as i64 > 0x10000 {
+            return Err("Program runs past the end of memory".to_string());
+        }
+        if self.final_pass {
+            let origin = *self.origin.get_or_insert(self.pc as u16) as i64;
+            if self.pc < origin {
+                return Err(format!(".org ${:04X} is below the program start", self.pc));
+            }
+            let offset = (self.pc - origin) as usize;
+            if self.output.len() > offset {
+                return Err(format!("Code at ${:04X} overlaps earlier code", self.pc));
+            }
+            self.output.resize(offset, 0);
+            self.output.extend_from_slice(bytes);
+        }
+        self.pc += bytes.len() as i64;
+        Ok(())
+    }
+
+    fn assemble_line(
+        &mut self,
+        line: &Line,
+        mode: &mut Option<AddressingMode>,
+    ) -> Result<(), String> {
+        if let Some(label) = &line.label {
+            self.define(label, self.pc)?;
+        }
+
+        match &line.statement {
+            None => {}
+            Some(Statement::Constant(name, value)) => {
+                // constants have to be defined before they're used so that we
+                // know whether they fit in the zero page during the first pass
+                let value = self
+                    .eval(value)?
+                    .ok_or(format!("Constant {name} uses a label defined after it"))?;
+                self.define(name, value)?;
+            }
+            Some(Statement::Org(addr)) => {
+                let addr = self
+                    .eval(addr)?
+                    .ok_or(".org can't use a label defined after it")?;
+                if !(0..=0xFFFF).contains(&addr) {
+                    return Err(format!(".org {addr} is out of range"));
+                }
+                self.pc = addr;
+            }
+            Some(Statement::Bytes(args)) => {
+                for arg in args {
+                    if let Some(text) = arg.strip_prefix('"') {
+                        let text = text.strip_suffix('"').ok_or("Unterminated string")?;
+                        self.emit(text.as_bytes())?;
+                    } else {
+                        let value = self.eval(arg)?.unwrap_or_default();
+                        self.emit(&[to_byte(value)?])?;
+                    }
+                }
+            }
+            Some(Statement::Words(args)) => {
+                for arg in args {
+                    let value = self.eval(arg)?.unwrap_or_default();
+                    self.emit(&to_word(value)?.to_le_bytes())?;
+                }
+            }
+            Some(Statement::Instruction(mnemonic, operand)) => {
+                self.assemble_instruction(mnemonic, operand, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn assemble_instruction(
+        &mut self,
+        mnemonic: &str,
+        operand: &str,
+        locked_mode: &mut Option<AddressingMode>,
+    ) -> Result<(), String> {
+        let has_mode = |mode| OpCode::find(mnemonic, mode).is_some();
+        if !OpCode::exists(mnemonic) {
+            return Err(format!("Unknown instruction {mnemonic}"));
+        }
+        let operand = operand.split_whitespace().collect::<String>();
+        let upper = operand.to_ascii_uppercase();
+
+        // work out what the operand looks like first, and the exact mode later
+        let (expr, candidates): (&str, &[AddressingMode]) = if operand.is_empty() || upper == "A" {
+            ("", &[AddressingMode::Implicit, AddressingMode::Accumulator])
+        } else if let Some(expr) = operand.strip_prefix('#') {
+            (expr, &[AddressingMode::Immediate])
+        } else if operand.starts_with('(') && upper.ends_with(",X)") {
+            (&operand[1..operand.len() - 3], &[AddressingMode::IndirectX])
+        } else if operand.starts_with('(') && upper.ends_with("),Y") {
+            (&operand[1..operand.len() - 3], &[AddressingMode::IndirectY])
+        } else if operand.starts_with('(')
+            && operand.ends_with(')')
+            && has_mode(AddressingMode::Indirect)
+        {
+            (&operand[1..operand.len() - 1], &[AddressingMode::Indirect])
+        } else if upper.ends_with(",X") {
+            (
+                &operand[..operand.len() - 2],
+                &[AddressingMode::ZeroPageX, AddressingMode::AbsoluteX],
+            )
+        } else if upper.ends_with(",Y") {
+            (
+                &operand[..operand.len() - 2],
+                &[AddressingMode::ZeroPageY, AddressingMode::AbsoluteY],
+            )
+        } else if has_mode(AddressingMode::Relative) {
+            (&operand[..], &[AddressingMode::Relative])
+        } else {
+            (
+                &operand[..],
+                &[AddressingMode::ZeroPage, AddressingMode::Absolute],
+            )
+        };
+
+        let value = if expr.is_empty() {
+            None
+        } else {
+            self.eval(expr)?
+        };
+
+        let mode = match *locked_mode {
+            Some(mode) => mode,
+            None => {
+                let available = candidates
+                    .iter()
+                    .copied()
+                    .filter(|mode| has_mode(*mode))
+                    .collect::<Vec<_>>();
+                let mode = match available[..] {
+                    [] => {
+                        return Err(format!("{mnemonic} doesn't support operand {operand}"));
+                    }
+                    [mode] => mode,
+                    // the zero page variant is listed first, so only pick
+                    // it when we know the operand fits in a byte
+                    [zero_page, absolute] => match value {
+                        Some(0..=0xFF) => zero_page,
+                        _ => absolute,
+                    },
+                    _ => unreachable!(),
+                };
+                *locked_mode = Some(mode);
+                mode
+            }
+        };
+
+        let opcode = OpCode::find(mnemonic, mode)
+            .ok_or(format!("{mnemonic} doesn't support operand {operand}"))?;
+        let value = value.unwrap_or_default();
+        let operand_bytes = match mode {
+            AddressingMode::Implicit | AddressingMode::Accumulator => vec![],
+            AddressingMode::Relative => {
+                let offset = value - (self.pc + 2);
+                if self.final_pass && !(-128..=127).contains(&offset) {
+                    return Err(format!("Branch target is too far away ({offset} bytes)"));
+                }
+                vec![offset as u8]
+            }
+            _ if opcode.len == 2 => {
+                if self.final_pass
+                    && !(0..=0xFF).contains(&value)
+                    && mode != AddressingMode::Immediate
+                {
+                    return Err(format!("Operand {operand} doesn't fit in the zero page"));
+                }
+                vec![to_byte(value)?]
+            }
+            _ => to_word(value)?.to_le_bytes().to_vec(),
+        };
+
+        let mut bytes = vec![opcode.code];
+        bytes.extend(operand_bytes);
+        self.emit(&bytes)
+    }
+}
+
+/// Converts a value into a byte, allowing negative values down to -128
+fn to_byte(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=0xFF => Ok(value as u8),
+        _ => Err(format!("Value {value} doesn't fit in a byte")),
+    }
+}
+
+/// Converts a value into a 16 bit word, allowing negative values down to -32768
+fn to_word(value: i64) -> Result<u16, String> {
+    match value {
+        -32768..=0xFFFF => Ok(value as u16),
+        _ => Err(format!("Value {value} doesn't fit in a word")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Label(String),
+    Op(char),
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let chars = expr.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let ch = chars[pos];
+        let start = pos;
+        let take_while = |pos: &mut usize, pred: fn(char) -> bool| {
+            while *pos < chars.len() && pred(chars[*pos]) {
+                *pos += 1;
+            }
+            chars[start + 1..*pos].iter().collect::<String>()
+        };
+        let token = match ch {
+            ' ' | '\t' => {
+                pos += 1;
+                continue;
+            }
+            '$' => {
+                pos += 1;
+                let digits = take_while(&mut pos, |ch| ch.is_ascii_hexdigit());
+                Token::Number(i64::from_str_radix(&digits, 16).map_err(|_| "Invalid hex number")?)
+            }
+            '%' => {
+                pos += 1;
+                let digits = take_while(&mut pos, |ch| ch == '0' || ch == '1');
+                Token::Number(i64::from_str_radix(&digits, 2).map_err(|_| "Invalid binary number")?)
+            }
+            '\'' if chars.get(pos + 2) == Some(&'\'') => {
+                pos += 3;
+                Token::Number(chars[start + 1] as i64)
+            }
+            '0'..='9' => {
+                let digits = take_while(&mut pos, |ch| ch.is_ascii_digit());
+                Token::Number(
+                    format!("{ch}{digits}")
+                        .parse()
+                        .map_err(|_| "Invalid number")?,
+                )
+            }
+            ch if ch.is_ascii_alphabetic() || ch == '_' => {
+                let rest = take_while(&mut pos, |ch| ch.is_ascii_alphanumeric() || ch == '_');
+                Token::Label(format!("{ch}{rest}"))
+            }
+            '+' | '-' | '*' | '/' | '<' | '>' | '(' | ')' => {
+                pos += 1;
+                Token::Op(ch)
+            }
+            _ => return Err(format!("Unexpected character {ch} in expression {expr}")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// The error for expressions whose value doesn't fit in an `i64`
+const OVERFLOW: &str = "Expression overflow";
+
+/// A recursive descent parser for expressions:
+/// * sum   := term (('+' | '-') term)*
+/// * term  := unary (('*' | '/') unary)*
+/// * unary := ('-' | '<' | '>') unary | atom
+/// * atom  := number | label | '*' | '(' sum ')'
+///
+/// `None` is carried through the whole expression once any label in it is
+/// unknown
+struct ExprParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    assembler: &'a Assembler,
+}
+
+impl ExprParser<'_> {
+    fn next_op_is(&self, ops: &[char]) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Result<Option<i64>, String> {
+        let mut value = self.term()?;
+        while let Some(op) = self.next_op_is(&['+', '-']) {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = match (value, rhs) {
+                (Some(lhs), Some(rhs)) => {
+                    let value = if op == '+' {
+                        lhs.checked_add(rhs)
+                    } else {
+                        lhs.checked_sub(rhs)
+                    };
+                    Some(value.ok_or(OVERFLOW)?)
+                }
+                _ => None,
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<Option<i64>, String> {
+        let mut value = self.unary()?;
+        while let Some(op) = self.next_op_is(&['*', '/']) {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            value = match (value, rhs) {
+                (Some(lhs), Some(rhs)) if op == '*' => Some(lhs.checked_mul(rhs).ok_or(OVERFLOW)?),
+                (Some(_), Some(0)) => return Err("Division by zero".to_string()),
+                // only i64::MIN / -1 overflows
+                (Some(lhs), Some(rhs)) => Some(lhs.checked_div(rhs).ok_or(OVERFLOW)?),
+                _ => None,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<Option<i64>, String> {
+        if let Some(op) = self.next_op_is(&['-', '<', '>']) {
+            self.pos += 1;
+            let value = self.unary()?;
+            return value
+                .map(|value| match op {
+                    '-' => value.checked_neg().ok_or(OVERFLOW),
+                    '<' => Ok(value & 0xFF),
+                    _ => Ok((value >> 8) & 0xFF),
+                })
+                .transpose()
+                .map_err(str::to_string);
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Option<i64>, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(Some(value)),
+            Some(Token::Op('*')) => Ok(Some(self.assembler.pc)),
+            Some(Token::Label(label)) => match self.assembler.labels.get(&label) {
+                Some(value) => Ok(Some(*value)),
+                None if self.assembler.final_pass => Err(format!("Unknown label {label}")),
+                None => Ok(None),
+            },
+            Some(Token::Op('(')) => {
+                let value = self.sum()?;
+                if self.next_op_is(&[')']).is_none() {
+                    return Err("Missing closing parenthesis".to_string());
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err("Incomplete expression".to_string()),
+        }
+    }
+}
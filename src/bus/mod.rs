@@ -3,8 +3,40 @@
 ///     - Data reads/writes
 ///     - Routing hardware interrupts to CPU
 /// * Handling memory mappings
-/// * Coordinating PPU and CPU clock cycles
+/// * Coordinating PPU and CPU clock cycles (3 PPU dots per CPU cycle, or
+///   3.2 on PAL, see the `region` module)
 use crate::Mem;
+use crate::apu::Apu;
+use crate::cdl::{CHR_READ, CHR_RENDERED, CodeDataLog, PRG_CODE, PRG_DATA};
+use crate::cdl::{PRG_INDIRECT_CODE, PRG_INDIRECT_DATA};
+use crate::cheats::{Cheat, Cheats};
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::opcodes::OpCode;
+use crate::gamepad::Gamepad;
+use crate::mapper::{self, DiskDrive, Mapper};
+use crate::nsf::Nsf;
+use crate::ppu::{Mirroring, NesPPU};
+use crate::region::Region;
+use crate::rom::Rom;
+use crate::rom::fds::DiskImage;
+use crate::savestate::{StateReader, StateWriter};
+
+/// Whether a memory access was a read or a write
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A single memory access made by the CPU through the bus. Mirrored
+/// addresses are recorded as the address they mirror (i.e. a read from
+/// 0x3FFA shows up as a read from 0x2002, the PPUSTATUS register)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub access: Access,
+    pub value: u8,
+}
 
 /// NES's Memory Map Regions:
 /// * RAM - [0x0000 ... 0x2000]
@@ -12,8 +44,37 @@ use crate::Mem;
 /// * Special storage for cartridges: [0x4020 ... 0x6000]
 /// * RAM Space for Cartridge: [0x6000 ... 0x8000]
 /// * PRG ROM: [0x8000 ... 0xFFFF]
+///
+/// Everything from 0x4020 up belongs to the cartridge, whose mapper decides
+/// what's there
 pub struct Bus {
     cpu_vram: [u8; 2048],
+    /// the cartridge's board (None when there's no cartridge)
+    mapper: Option<Box<dyn Mapper>>,
+    /// whether the PRG RAM is battery backed (it survives power cycles)
+    battery: bool,
+    /// whether the PPU's pattern tables are RAM instead of ROM
+    chr_ram: bool,
+    /// the MD5 of the cartridge's ROM
+    rom_checksum: Option<[u8; 16]>,
+    ppu: NesPPU,
+    apu: Apu,
+    /// the controllers in port 1 and 2
+    gamepads: [Gamepad; 2],
+    /// how many CPU cycles have passed since power on
+    cycles: usize,
+    /// the fraction of a PPU dot left over from the last tick (in fifths,
+    /// only PAL runs a number of dots per cycle that isn't whole)
+    dot_remainder: u64,
+    /// the last value that went over the data bus, which is what reading an
+    /// address nothing answers gets (open bus)
+    data_bus: u8,
+    /// when recording, every CPU read and write is logged here
+    access_log: Option<Vec<BusAccess>>,
+    /// when logging, how the cartridge's ROM gets used is recorded here
+    code_data_log: Option<CodeDataLog>,
+    /// Game Genie codes and RAM freezes applied to CPU reads
+    cheats: Cheats,
 }
 
 impl Default for Bus {
@@ -26,6 +87,488 @@ impl Bus {
     pub fn new() -> Self {
         Self {
             cpu_vram: [0; 2048],
+            mapper: None,
+            battery: false,
+            chr_ram: true,
+            rom_checksum: None,
+            ppu: NesPPU::new_empty(),
+            apu: Apu::new(),
+            gamepads: [Gamepad::new(), Gamepad::new()],
+            cycles: 0,
+            dot_remainder: 0,
+            data_bus: 0,
+            access_log: None,
+            code_data_log: None,
+            cheats: Cheats::default(),
+        }
+    }
+
+    /// Instantiates the bus with a cartridge inserted. Its mapper (see the
+    /// `mapper` module for the supported ones) takes over [0x4020 ... 0xFFFF]
+    /// and its CHR ROM (or 8 KiB of CHR RAM) is handed to the PPU. The
+    /// machine is set up for the region the ROM asks for (NTSC if it
+    /// doesn't say)
+    pub fn with_rom(rom: Rom) -> Result<Self, String> {
+        let mapper = mapper::create(&rom)?;
+        let region = rom.region.unwrap_or_default();
+        let rom_checksum = rom.checksum();
+        let chr_ram = rom.chr_rom.is_empty();
+        let chr = if chr_ram {
+            vec![0; CHR_RAM_SIZE]
+        } else {
+            rom.chr_rom
+        };
+        let mut bus = Self {
+            mapper: Some(mapper),
+            battery: rom.battery,
+            chr_ram,
+            rom_checksum: Some(rom_checksum),
+            ppu: NesPPU::new(chr, rom.screen_mirroring),
+            ..Self::new()
+        };
+        bus.set_region(region);
+        bus.sync_mapper();
+        Ok(bus)
+    }
+
+    /// Instantiates the bus with the board an NSF tune plays on (see the
+    /// `nsf` module) in place of a cartridge
+    pub fn with_nsf(tune: &Nsf) -> Self {
+        let mut bus = Self {
+            mapper: Some(mapper::create_nsf(tune)),
+            chr_ram: true,
+            ppu: NesPPU::new(vec![0; CHR_RAM_SIZE], Mirroring::Horizontal),
+            ..Self::new()
+        };
+        bus.sync_mapper();
+        bus
+    }
+
+    /// The TV system the machine runs as
+    pub fn region(&self) -> Region {
+        self.ppu.region()
+    }
+
+    /// Switches the machine to another region's timing (the PPU's frame,
+    /// the clock ratio and the APU). Best done before powering it on, games
+    /// tend to only check which one they're on at boot
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.dot_remainder = 0;
+    }
+
+    /// Whether a cartridge is inserted
+    pub fn has_cartridge(&self) -> bool {
+        self.mapper.is_some()
+    }
+
+    /// Maps a CPU address in [0x8000 ... 0xFFFF] onto an offset into PRG ROM,
+    /// going through the banks the mapper has switched in
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        self.mapper.as_ref()?.prg_offset(addr)
+    }
+
+    /// Hands the CHR banks, the mirroring and the extended video the mapper
+    /// has picked to the PPU
+    fn sync_mapper(&mut self) {
+        if let Some(mapper) = &self.mapper {
+            self.ppu.set_chr_banks(mapper.chr_banks());
+            self.ppu.mirroring = mapper.mirroring();
+            self.ppu.set_extended_video(mapper.video());
+        }
+    }
+
+    /// The MD5 of the cartridge's PRG ROM followed by its CHR ROM (which is
+    /// how FCEUX identifies a game), or None without a cartridge
+    pub fn rom_checksum(&self) -> Option<[u8; 16]> {
+        self.rom_checksum
+    }
+
+    /// How many sides the Famicom Disk System's disk has (0 without one)
+    pub fn disk_sides(&self) -> usize {
+        self.disk_drive().map_or(0, |drive| drive.side_count())
+    }
+
+    /// The side of the disk in the Famicom Disk System's drive, or None
+    /// while it's empty
+    pub fn disk_side(&self) -> Option<usize> {
+        self.disk_drive()?.inserted()
+    }
+
+    /// Takes the disk out of the Famicom Disk System's drive
+    pub fn eject_disk(&mut self) -> Result<(), String> {
+        self.disk_drive_mut()?.eject();
+        Ok(())
+    }
+
+    /// Puts `side` of the disk in the Famicom Disk System's drive. If
+    /// another side is in there it comes out first, and the new one goes in
+    /// a second later so the game notices the switch
+    pub fn insert_disk(&mut self, side: usize) -> Result<(), String> {
+        self.disk_drive_mut()?.insert(side)
+    }
+
+    /// The Famicom Disk System's disk, with everything the game wrote to it
+    pub fn disk_image(&self) -> Option<DiskImage> {
+        Some(self.disk_drive()?.image())
+    }
+
+    /// An IPS patch (see `rom::ips`) of everything the game wrote to the
+    /// Famicom Disk System's disk, which is how disk saves are kept
+    pub fn disk_diff(&self) -> Result<Vec<u8>, String> {
+        self.disk_drive()
+            .ok_or_else(|| NO_DISK_SYSTEM.to_string())?
+            .diff()
+    }
+
+    /// Applies a patch made by `disk_diff` to the disk as it was loaded
+    pub fn apply_disk_diff(&mut self, patch: &[u8]) -> Result<(), String> {
+        self.disk_drive_mut()?.apply_diff(patch)
+    }
+
+    /// The cartridge's battery backed RAM, which is what its save file
+    /// holds, or None when it has no battery
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        Some(self.mapper.as_ref()?.battery_ram())
+    }
+
+    /// Loads a save made from `battery_ram`
+    pub fn load_battery_ram(&mut self, ram: &[u8]) -> Result<(), String> {
+        match &mut self.mapper {
+            Some(mapper) if self.battery => mapper.load_battery_ram(ram),
+            _ => Err("The cartridge has no battery".to_string()),
+        }
+    }
+
+    fn disk_drive(&self) -> Option<&DiskDrive> {
+        self.mapper.as_ref()?.disk_drive()
+    }
+
+    fn disk_drive_mut(&mut self) -> Result<&mut DiskDrive, String> {
+        self.mapper
+            .as_mut()
+            .and_then(|mapper| mapper.disk_drive_mut())
+            .ok_or_else(|| NO_DISK_SYSTEM.to_string())
+    }
+
+    /// Turns the machine off and on again: RAM, the PPU and the controllers
+    /// go back to their power on state, and so do the mapper's banks. Battery
+    /// backed PRG RAM keeps its contents. The CPU has to be reset afterwards
+    pub fn power_cycle(&mut self) {
+        self.cpu_vram = [0; 2048];
+        if let Some(mapper) = &mut self.mapper {
+            if !self.battery {
+                mapper.clear_prg_ram();
+            }
+            mapper.power_cycle();
+        }
+        self.ppu.power_cycle(self.chr_ram);
+        self.apu.power_cycle();
+        self.sync_mapper();
+        for gamepad in &mut self.gamepads {
+            *gamepad = Gamepad::new();
+        }
+        self.cycles = 0;
+        self.dot_remainder = 0;
+        self.data_bus = 0;
+    }
+
+    /// The controller in port 1 (0) or port 2 (1)
+    pub fn gamepad(&self, port: usize) -> &Gamepad {
+        &self.gamepads[port]
+    }
+
+    pub fn gamepad_mut(&mut self, port: usize) -> &mut Gamepad {
+        &mut self.gamepads[port]
+    }
+
+    /// The 2 KiB of internal RAM at [0x0000 ... 0x07FF]
+    pub fn ram(&self) -> &[u8] {
+        &self.cpu_vram
+    }
+
+    /// The cartridge's RAM at [0x6000 ... 0x7FFF] (empty when there's no
+    /// cartridge)
+    pub fn prg_ram(&self) -> &[u8] {
+        self.mapper.as_ref().map_or(&[], |mapper| mapper.prg_ram())
+    }
+
+    /// Returns the PPU connected to the bus
+    pub fn ppu(&self) -> &NesPPU {
+        &self.ppu
+    }
+
+    /// Returns the APU connected to the bus
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// Turns the recording of audio samples on or off (recording is off by
+    /// default, so nothing piles up unless someone is listening)
+    pub fn record_audio(&mut self, record: bool) {
+        self.apu.record_samples(record);
+    }
+
+    /// Returns the audio samples (the APU and the cartridge's expansion
+    /// audio mixed, at `apu::SAMPLE_RATE`) recorded since the last call
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    /// How many CPU cycles have passed since power on
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    /// Advances the rest of the system by the number of cycles the CPU
+    /// just spent. The PPU runs 3 dots for every CPU cycle (3.2 on PAL), the
+    /// APU and the cartridge's mapper one
+    pub fn tick(&mut self, cycles: usize) {
+        self.cycles += cycles;
+        let frame = self.ppu.frame();
+        let scanline = self.ppu.scanline();
+        let (dots, per_cycles) = self.region().ppu_dots_per_cycles();
+        let dots = cycles as u64 * dots + self.dot_remainder;
+        self.dot_remainder = dots % per_cycles;
+        self.ppu.tick((dots / per_cycles) as usize);
+        if self.ppu.scanline() != scanline {
+            self.start_scanlines(scanline);
+        }
+        self.clock_audio(cycles);
+        if self.code_data_log.is_some() && self.ppu.frame() != frame {
+            self.log_visible_tiles();
+        }
+    }
+
+    /// Tells the mapper about every scanline the PPU started since it was on
+    /// `previous`
+    fn start_scanlines(&mut self, previous: u16) {
+        let Some(mapper) = &mut self.mapper else {
+            return;
+        };
+        let rendering = self.ppu.rendering_enabled();
+        let mut scanline = previous;
+        while scanline != self.ppu.scanline() {
+            scanline = (scanline + 1) % self.ppu.region().scanlines();
+            mapper.scanline(scanline, rendering);
+        }
+    }
+
+    /// Clocks the APU and the mapper for every CPU cycle, fetching the DMC's
+    /// sample bytes from the cartridge
+    fn clock_audio(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            let expansion = match &mut self.mapper {
+                Some(mapper) => {
+                    mapper.clock();
+                    mapper.audio_output()
+                }
+                None => 0.0,
+            };
+            self.apu.clock(expansion);
+            if let Some(addr) = self.apu.dmc_fetch_address() {
+                let value = self.mapper.as_ref().and_then(|mapper| mapper.peek(addr));
+                self.apu.fill_dmc(value.unwrap_or(0));
+            }
+        }
+    }
+
+    /// Returns whether the APU or the cartridge's mapper is asserting the IRQ
+    /// line (it stays asserted until they're acknowledged)
+    pub fn irq_pending(&self) -> bool {
+        self.apu.irq() || self.mapper.as_ref().is_some_and(|mapper| mapper.irq())
+    }
+
+    /// Returns whether an NMI was raised (by the PPU), acknowledging it
+    pub fn poll_nmi_status(&mut self) -> bool {
+        self.ppu.poll_nmi_interrupt()
+    }
+
+    /// Returns whether an NMI is waiting to be serviced by the CPU
+    pub fn nmi_pending(&self) -> bool {
+        self.ppu.nmi_pending()
+    }
+
+    /// Turns the recording of every CPU memory access on or off (recording
+    /// is off by default, so there is no cost unless someone is watching)
+    pub fn record_accesses(&mut self, record: bool) {
+        self.access_log = record.then(Vec::new);
+    }
+
+    /// Returns all memory accesses recorded since the last call
+    pub fn take_accesses(&mut self) -> Vec<BusAccess> {
+        match &mut self.access_log {
+            Some(log) => std::mem::take(log),
+            None => Vec::new(),
+        }
+    }
+
+    /// The cheats that are applied to CPU reads
+    pub fn cheats(&self) -> &[Cheat] {
+        self.cheats.list()
+    }
+
+    /// Adds a cheat (applied right away if it's enabled), returning its index
+    pub fn add_cheat(&mut self, cheat: Cheat) -> usize {
+        self.cheats.add(cheat)
+    }
+
+    pub fn remove_cheat(&mut self, index: usize) -> Option<Cheat> {
+        self.cheats.remove(index)
+    }
+
+    /// Turns a cheat on or off, returning whether it exists
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        self.cheats.set_enabled(index, enabled)
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cheats.clear();
+    }
+
+    /// Starts logging how the cartridge's ROM gets used (continuing the log
+    /// that was set with `set_code_data_log`, if any)
+    pub fn start_code_data_log(&mut self) {
+        if self.code_data_log.is_none() {
+            // like FCEUX, there's only a CHR part when there's CHR ROM
+            let chr_len = if self.chr_ram {
+                0
+            } else {
+                self.ppu.chr_rom.len()
+            };
+            let prg_len = self
+                .mapper
+                .as_ref()
+                .map_or(0, |mapper| mapper.prg_rom().len());
+            self.code_data_log = Some(CodeDataLog::new(prg_len, chr_len));
+        }
+    }
+
+    /// Replaces the code/data log (i.e. with one loaded from a file so logging
+    /// picks up where it left off). `None` stops logging
+    pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) {
+        self.code_data_log = log;
+    }
+
+    pub fn code_data_log(&self) -> Option<&CodeDataLog> {
+        self.code_data_log.as_ref()
+    }
+
+    /// Stops logging, returning the log
+    pub fn take_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.code_data_log.take()
+    }
+
+    pub fn is_logging_code(&self) -> bool {
+        self.code_data_log.is_some()
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.cpu_vram);
+        if let Some(mapper) = &self.mapper {
+            mapper.save_state(state);
+        }
+        state.u64(self.cycles as u64);
+        state.u8(self.region() as u8);
+        state.u64(self.dot_remainder);
+        state.u8(self.data_bus);
+        for gamepad in &self.gamepads {
+            gamepad.save_state(state);
+        }
+        self.apu.save_state(state);
+        self.ppu.save_state(state, self.chr_ram);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.cpu_vram)?;
+        if let Some(mapper) = &mut self.mapper {
+            mapper.load_state(state)?;
+        }
+        self.cycles = state.u64()? as usize;
+        let region = state.u8()?;
+        let region = *Region::ALL
+            .get(region as usize)
+            .ok_or_else(|| format!("Savestate has an unknown region {region}"))?;
+        self.set_region(region);
+        self.dot_remainder = state.u64()?;
+        self.data_bus = state.u8()?;
+        for gamepad in &mut self.gamepads {
+            gamepad.load_state(state)?;
+        }
+        self.apu.load_state(state)?;
+        // the banks have to be in place before the PPU draws the loaded frame
+        self.sync_mapper();
+        self.ppu.load_state(state, self.chr_ram)
+    }
+
+    /// Logs how an instruction the CPU just executed (from `pc`) used PRG ROM,
+    /// given every memory access it made. `opcode` is None when the CPU
+    /// serviced an interrupt instead, in which case everything read is data
+    pub(crate) fn log_instruction(
+        &mut self,
+        pc: u16,
+        opcode: Option<&OpCode>,
+        accesses: &[BusAccess],
+        next_pc: u16,
+    ) {
+        let Some(log) = &mut self.code_data_log else {
+            return;
+        };
+        let mapper = self.mapper.as_deref();
+        let prg_offset = |addr| mapper?.prg_offset(addr);
+
+        let len = opcode.map_or(0, |opcode| opcode.len as u16);
+        let indirect_data = opcode.is_some_and(|opcode| {
+            matches!(
+                opcode.mode,
+                AddressingMode::IndirectX | AddressingMode::IndirectY
+            )
+        });
+        for hit in accesses.iter().filter(|hit| hit.access == Access::Read) {
+            let Some(offset) = prg_offset(hit.addr) else {
+                continue;
+            };
+            if hit.addr.wrapping_sub(pc) < len {
+                log.mark_prg(offset, hit.addr, PRG_CODE);
+                log.mark_executed(offset, hit.addr == pc);
+            } else if indirect_data {
+                log.mark_prg(offset, hit.addr, PRG_DATA | PRG_INDIRECT_DATA);
+            } else {
+                log.mark_prg(offset, hit.addr, PRG_DATA);
+            }
+        }
+
+        // JMP ($nnnn)
+        if opcode.is_some_and(|opcode| opcode.mode == AddressingMode::Indirect)
+            && let Some(offset) = prg_offset(next_pc)
+        {
+            log.mark_prg(offset, next_pc, PRG_INDIRECT_CODE);
+        }
+    }
+
+    /// Marks the CHR ROM of every tile that's on screen as rendered
+    fn log_visible_tiles(&mut self) {
+        let Some(log) = &mut self.code_data_log else {
+            return;
+        };
+        for tile in self.ppu.visible_tiles() {
+            for offset in 0..16 {
+                log.mark_chr(self.ppu.chr_offset(tile + offset), CHR_RENDERED);
+            }
+        }
+    }
+
+    fn log_access(&mut self, addr: u16, access: Access, value: u8) {
+        if let Some(log) = &mut self.access_log {
+            log.push(BusAccess {
+                addr: mirror_down(addr),
+                access,
+                value,
+            });
         }
     }
 }
@@ -34,41 +577,142 @@ impl Bus {
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 
-// PPU memory space
+// PPU memory space (8 registers mirrored every 8 bytes)
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
+// PPU registers (mirrored down)
+const PPU_CTRL: u16 = 0x2000;
+const PPU_MASK: u16 = 0x2001;
+const PPU_STATUS: u16 = 0x2002;
+const OAM_ADDR: u16 = 0x2003;
+const OAM_DATA: u16 = 0x2004;
+const PPU_SCROLL: u16 = 0x2005;
+const PPU_ADDR: u16 = 0x2006;
+const PPU_DATA: u16 = 0x2007;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const APU_FRAME_COUNTER: u16 = 0x4017;
+// the APU's channel registers
+const APU_CHANNELS: u16 = 0x4000;
+const APU_CHANNELS_END: u16 = 0x4013;
+const JOYPAD_1: u16 = 0x4016;
+const JOYPAD_2: u16 = 0x4017;
+
+// cartridge space
+const CARTRIDGE: u16 = 0x4020;
+const CARTRIDGE_END: u16 = 0xFFFF;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+const NO_DISK_SYSTEM: &str = "The cartridge isn't a Famicom Disk System";
+
+/// Maps a mirrored address onto the address it mirrors
+pub(crate) fn mirror_down(addr: u16) -> u16 {
+    match addr {
+        RAM..=RAM_MIRRORS_END => addr & 0b111_11111111,
+        PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => addr & 0b100000_00000111,
+        _ => addr,
+    }
+}
+
 impl Mem for Bus {
-    fn mem_read(&self, addr: u16) -> u8 {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mir_dn_addr = addr & 0b111_11111111;
-                self.cpu_vram[mir_dn_addr as usize]
-            }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mir_dn_addr = addr & 0b100000_00000111;
-                todo!("Need to implement PPU first before working on this");
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = match mirror_down(addr) {
+            mir_dn_addr @ RAM..=RAM_MIRRORS_END => self.cpu_vram[mir_dn_addr as usize],
+            PPU_STATUS => self.ppu.read_status(),
+            OAM_DATA => self.ppu.read_oam_data(),
+            PPU_DATA => {
+                let vram_addr = self.ppu.vram_addr();
+                if let Some(log) = &mut self.code_data_log
+                    && vram_addr < 0x2000
+                {
+                    log.mark_chr(self.ppu.chr_offset(vram_addr), CHR_READ);
+                }
+                self.ppu.read_data()
             }
-            _ => {
-                println!("Can't perform mem access for {addr} yet");
-                0
-            }
-        }
+            // the rest of the PPU registers are write only
+            PPU_CTRL | PPU_MASK | OAM_ADDR | PPU_SCROLL | PPU_ADDR => 0,
+            // and so are the APU's channels and OAMDMA
+            APU_CHANNELS..=APU_CHANNELS_END | OAM_DMA => 0,
+            APU_STATUS => self.apu.read_status(),
+            JOYPAD_1 => self.gamepads[0].read(),
+            JOYPAD_2 => self.gamepads[1].read(),
+            // nothing answering in cartridge space is open bus
+            CARTRIDGE..=CARTRIDGE_END if self.has_cartridge() => self
+                .mapper
+                .as_mut()
+                .unwrap()
+                .read(addr)
+                .unwrap_or(self.data_bus),
+            _ => self.data_bus,
+        };
+        let value = self.cheats.apply(addr, value);
+        self.data_bus = value;
+        self.log_access(addr, Access::Read, value);
+        value
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        let value = match mirror_down(addr) {
+            mir_dn_addr @ RAM..=RAM_MIRRORS_END => self.cpu_vram[mir_dn_addr as usize],
+            PPU_STATUS => self.ppu.peek_status(),
+            OAM_DATA => self.ppu.read_oam_data(),
+            PPU_DATA => self.ppu.peek_data(),
+            APU_STATUS => self.apu.peek_status(),
+            JOYPAD_1 => self.gamepads[0].peek(),
+            JOYPAD_2 => self.gamepads[1].peek(),
+            CARTRIDGE..=CARTRIDGE_END => self
+                .mapper
+                .as_ref()
+                .and_then(|mapper| mapper.peek(addr))
+                .unwrap_or(self.data_bus),
+            _ => self.data_bus,
+        };
+        self.cheats.apply(addr, value)
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mir_dn_addr = addr & 0b111_11111111;
+        self.data_bus = data;
+        self.log_access(addr, Access::Write, data);
+        match mirror_down(addr) {
+            mir_dn_addr @ RAM..=RAM_MIRRORS_END => {
                 self.cpu_vram[mir_dn_addr as usize] = data;
             }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mir_dn_addr = addr & 0b100000_00000111;
-                todo!("Need to implement PPU first before working on this");
+            PPU_CTRL => self.ppu.write_to_ctrl(data),
+            PPU_MASK => self.ppu.write_to_mask(data),
+            OAM_ADDR => self.ppu.write_to_oam_addr(data),
+            OAM_DATA => self.ppu.write_to_oam_data(data),
+            PPU_SCROLL => self.ppu.write_to_scroll(data),
+            PPU_ADDR => self.ppu.write_to_ppu_addr(data),
+            PPU_DATA => self.ppu.write_to_data(data),
+            // PPUSTATUS is read only
+            PPU_STATUS => {}
+            OAM_DMA => {
+                // copies the page 0xXX00 - 0xXXFF into OAM, which stalls
+                // the CPU for 513 cycles
+                let mut page = [0; 256];
+                let page_start = (data as u16) << 8;
+                for (offset, value) in page.iter_mut().enumerate() {
+                    *value = self.mem_peek(page_start + offset as u16);
+                }
+                self.ppu.write_oam_dma(&page);
+                self.tick(513);
+            }
+            APU_CHANNELS..=APU_CHANNELS_END | APU_STATUS | APU_FRAME_COUNTER => {
+                self.apu.write(addr, data)
+            }
+            // the strobe goes to both controllers
+            JOYPAD_1 => {
+                for gamepad in &mut self.gamepads {
+                    gamepad.write(data);
+                }
             }
-            _ => {
-                println!("Can't perform mem write for {addr} yet");
+            CARTRIDGE..=CARTRIDGE_END if self.has_cartridge() => {
+                self.mapper.as_mut().unwrap().write(addr, data);
+                self.sync_mapper();
             }
+            // nothing listens
+            _ => {}
         }
     }
 }
@@ -0,0 +1,177 @@
+//! Contains the code/data logger (CDL), which records how every byte of a
+//! cartridge's PRG ROM and CHR ROM gets used while the game runs. Knowing
+//! which bytes were executed and which were only read as data is what makes
+//! it possible to disassemble a ROM without mistaking tables for code.
+//!
+//! Logs are saved and loaded in the FCEUX `.cdl` format: one flag byte per
+//! PRG ROM byte followed by one flag byte per CHR ROM byte.
+//!
+//! PRG ROM flags (`xPdcAADC`):
+//! * `C` (bit 0) - executed as code (opcode or operand)
+//! * `D` (bit 1) - read as data
+//! * `AA` (bits 2-3) - which 8 KiB window of 0x8000 - 0xFFFF the byte was
+//!   mapped into when it was last accessed
+//! * `c` (bit 4) - indirectly accessed as code (i.e. the target of `JMP ($nnnn)`)
+//! * `d` (bit 5) - indirectly accessed as data (i.e. through `LDA ($nn),Y`)
+//! * `P` (bit 6) - played as PCM audio data
+//!
+//! CHR ROM flags (`xxxxxxRD`):
+//! * `D` (bit 0) - drawn on screen
+//! * `R` (bit 1) - read by the CPU through PPUDATA ($2007)
+//!
+//! On top of what the file format stores, the logger also remembers which code
+//! bytes were executed as opcodes and which as operands, for the disassembler.
+//! That information is lost when saving.
+
+use std::path::Path;
+
+pub const PRG_CODE: u8 = 0b0000_0001;
+pub const PRG_DATA: u8 = 0b0000_0010;
+pub const PRG_BANK_MASK: u8 = 0b0000_1100;
+pub const PRG_INDIRECT_CODE: u8 = 0b0001_0000;
+pub const PRG_INDIRECT_DATA: u8 = 0b0010_0000;
+pub const PRG_PCM_DATA: u8 = 0b0100_0000;
+
+pub const CHR_RENDERED: u8 = 0b0000_0001;
+pub const CHR_READ: u8 = 0b0000_0010;
+
+// how a code byte was executed (not part of the file format)
+const EXECUTED_OPCODE: u8 = 0b01;
+const EXECUTED_OPERAND: u8 = 0b10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    /// whether each PRG ROM byte was executed as an opcode and/or operand
+    executed: Vec<u8>,
+}
+
+impl CodeDataLog {
+    /// Instantiates an empty log for a cartridge with the given ROM sizes
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        Self {
+            prg: vec![0; prg_len],
+            chr: vec![0; chr_len],
+            executed: vec![0; prg_len],
+        }
+    }
+
+    /// Parses the contents of an FCEUX `.cdl` file for a cartridge with
+    /// `prg_len` bytes of PRG ROM (the rest of the file is the CHR ROM part)
+    pub fn from_bytes(bytes: &[u8], prg_len: usize) -> Result<Self, String> {
+        if bytes.len() < prg_len {
+            return Err(format!(
+                "CDL file has {} bytes but the ROM has {prg_len} bytes of PRG ROM",
+                bytes.len()
+            ));
+        }
+        Ok(Self {
+            prg: bytes[..prg_len].to_vec(),
+            chr: bytes[prg_len..].to_vec(),
+            executed: vec![0; prg_len],
+        })
+    }
+
+    /// Reads an FCEUX `.cdl` file
+    pub fn load<P: AsRef<Path>>(path: P, prg_len: usize) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::from_bytes(&bytes, prg_len)
+    }
+
+    /// Returns the log in the FCEUX `.cdl` format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    /// Writes the log out as an FCEUX `.cdl` file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes()).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// The flags of every PRG ROM byte
+    pub fn prg(&self) -> &[u8] {
+        &self.prg
+    }
+
+    /// The flags of every CHR ROM byte
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
+    pub fn is_code(&self, prg_offset: usize) -> bool {
+        self.prg_flags(prg_offset) & PRG_CODE != 0
+    }
+
+    pub fn is_data(&self, prg_offset: usize) -> bool {
+        self.prg_flags(prg_offset) & PRG_DATA != 0
+    }
+
+    /// Whether the byte was executed as an opcode. Always false for logs
+    /// loaded from a file (the format doesn't record it)
+    pub fn is_opcode(&self, prg_offset: usize) -> bool {
+        self.executed_flags(prg_offset) & EXECUTED_OPCODE != 0
+    }
+
+    /// Whether the byte was only ever executed as part of an instruction's
+    /// operand. Always false for logs loaded from a file
+    pub fn is_operand(&self, prg_offset: usize) -> bool {
+        self.executed_flags(prg_offset) == EXECUTED_OPERAND
+    }
+
+    pub fn is_rendered(&self, chr_offset: usize) -> bool {
+        self.chr
+            .get(chr_offset)
+            .is_some_and(|flags| flags & CHR_RENDERED != 0)
+    }
+
+    /// How many PRG ROM bytes were logged as code, as data, and not at all
+    pub fn prg_coverage(&self) -> (usize, usize, usize) {
+        let code = self
+            .prg
+            .iter()
+            .filter(|flags| *flags & PRG_CODE != 0)
+            .count();
+        let data = self
+            .prg
+            .iter()
+            .filter(|flags| *flags & (PRG_CODE | PRG_DATA) == PRG_DATA)
+            .count();
+        (code, data, self.prg.len() - code - data)
+    }
+
+    fn prg_flags(&self, prg_offset: usize) -> u8 {
+        self.prg.get(prg_offset).copied().unwrap_or(0)
+    }
+
+    fn executed_flags(&self, prg_offset: usize) -> u8 {
+        self.executed.get(prg_offset).copied().unwrap_or(0)
+    }
+
+    /// Marks a PRG ROM byte that the CPU accessed through `addr`
+    pub(crate) fn mark_prg(&mut self, prg_offset: usize, addr: u16, flags: u8) {
+        if let Some(logged) = self.prg.get_mut(prg_offset) {
+            let bank = ((addr >> 13) & 0b11) as u8;
+            *logged = (*logged & !PRG_BANK_MASK) | flags | (bank << 2);
+        }
+    }
+
+    /// Marks a code byte as executed as an opcode (or as an operand)
+    pub(crate) fn mark_executed(&mut self, prg_offset: usize, opcode: bool) {
+        if let Some(executed) = self.executed.get_mut(prg_offset) {
+            *executed |= if opcode {
+                EXECUTED_OPCODE
+            } else {
+                EXECUTED_OPERAND
+            };
+        }
+    }
+
+    pub(crate) fn mark_chr(&mut self, chr_offset: usize, flags: u8) {
+        if let Some(logged) = self.chr.get_mut(chr_offset) {
+            *logged |= flags;
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Contains the cheat engine. Two kinds of cheats are supported:
+//!
+//! * Game Genie codes (6 or 8 letters, i.e. `SXIOPO`). They patch what the
+//!   CPU reads from the cartridge at [0x8000 ... 0xFFFF]. 8 letter codes carry
+//!   a compare value and only patch the read when the ROM holds that value
+//!   (which keeps them from breaking other banks of bank switched games)
+//! * Pro Action Replay style RAM freezes (i.e. `0x0075:09`). Every read of
+//!   the address returns the value, no matter what the game writes there
+//!
+//! Cheats never change the underlying memory, so turning one off brings the
+//! game's own value back.
+//!
+//! Cheat lists are saved and loaded in the FCEUX `.cht` format, one cheat
+//! per line:
+//!
+//! ```text
+//! [S][C][:]AAAA:VV[:CC]:Name
+//! ```
+//!
+//! where `S` marks a Game Genie style (ROM patch) cheat, `C` marks a cheat
+//! with a compare value `CC` and a leading `:` marks a disabled cheat.
+
+use std::fmt;
+use std::path::Path;
+
+use crate::bus::mirror_down;
+
+const GAME_GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CheatKind {
+    /// patches reads from the cartridge ROM
+    GameGenie,
+    /// freezes a RAM address to a value
+    Freeze,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub kind: CheatKind,
+    pub addr: u16,
+    pub value: u8,
+    /// only patch when the original value is this one
+    pub compare: Option<u8>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Decodes a 6 or 8 letter Game Genie code
+    pub fn game_genie(code: &str) -> Result<Cheat, String> {
+        let letters = code
+            .trim()
+            .chars()
+            .map(|letter| {
+                GAME_GENIE_LETTERS
+                    .find(letter.to_ascii_uppercase())
+                    .map(|value| value as u16)
+                    .ok_or_else(|| format!("Invalid Game Genie letter '{letter}' in {code}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if letters.len() != 6 && letters.len() != 8 {
+            return Err(format!("Game Genie codes have 6 or 8 letters, got {code}"));
+        }
+
+        // the bits of the address, value and compare value are scrambled
+        // across the letters
+        let n = |index: usize| letters[index];
+        let addr = 0x8000
+            | ((n(3) & 7) << 12)
+            | ((n(5) & 7) << 8)
+            | ((n(4) & 8) << 8)
+            | ((n(2) & 7) << 4)
+            | ((n(1) & 8) << 4)
+            | (n(4) & 7)
+            | (n(3) & 8);
+        let value_low_bit = if letters.len() == 6 { n(5) } else { n(7) };
+        let value = ((n(1) & 7) << 4) | ((n(0) & 8) << 4) | (n(0) & 7) | (value_low_bit & 8);
+        let compare = (letters.len() == 8)
+            .then(|| ((n(7) & 7) << 4) | ((n(6) & 8) << 4) | (n(6) & 7) | (n(5) & 8));
+
+        Ok(Cheat {
+            name: code.trim().to_ascii_uppercase(),
+            kind: CheatKind::GameGenie,
+            addr,
+            value: value as u8,
+            compare: compare.map(|compare| compare as u8),
+            enabled: true,
+        })
+    }
+
+    /// Freezes `addr` to `value`
+    pub fn freeze(addr: u16, value: u8) -> Cheat {
+        Cheat {
+            name: format!("{addr:04X}:{value:02X}"),
+            kind: CheatKind::Freeze,
+            addr,
+            value,
+            compare: None,
+            enabled: true,
+        }
+    }
+
+    /// Parses either a Game Genie code or a RAM freeze. Freezes are written
+    /// as `AAAA:VV` (optionally with a `0x` or `$` prefix) or as the 6 hex
+    /// digits of a Pro Action Replay code (`AAAAVV`)
+    pub fn parse(code: &str) -> Result<Cheat, String> {
+        let code = code.trim();
+        let hex = |text: &str| {
+            let text = text.trim_start_matches("0x").trim_start_matches('$');
+            u16::from_str_radix(text, 16).map_err(|_| format!("Invalid cheat code {code}"))
+        };
+
+        if let Some((addr, value)) = code.split_once(':') {
+            let value = hex(value)?;
+            if value > 0xFF {
+                return Err(format!("Invalid cheat value in {code}"));
+            }
+            return Ok(Cheat::freeze(hex(addr)?, value as u8));
+        }
+        if code.len() == 6 && code.chars().all(|digit| digit.is_ascii_hexdigit()) {
+            return Ok(Cheat::freeze(hex(&code[..4])?, hex(&code[4..])? as u8));
+        }
+        Cheat::game_genie(code)
+    }
+
+    /// Returns what a read of `addr` returns with this cheat applied, where
+    /// `value` is what the read returns without it
+    fn apply(&self, addr: u16, value: u8) -> Option<u8> {
+        let applies = self.enabled
+            && mirror_down(self.addr) == mirror_down(addr)
+            && self.compare.is_none_or(|compare| compare == value);
+        applies.then_some(self.value)
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            CheatKind::GameGenie => "Game Genie",
+            CheatKind::Freeze => "freeze",
+        };
+        write!(
+            f,
+            "[{}] {} ({kind}: ${:04X} = ${:02X}",
+            if self.enabled { "on" } else { "off" },
+            self.name,
+            self.addr,
+            self.value
+        )?;
+        if let Some(compare) = self.compare {
+            write!(f, " if ${compare:02X}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// All the cheats the bus applies to memory reads
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Cheats {
+    list: Vec<Cheat>,
+    /// whether any cheat is turned on (so reads don't have to look when
+    /// none are)
+    active: bool,
+}
+
+impl Cheats {
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    /// Adds a cheat, returning its index
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.list.push(cheat);
+        self.update_active();
+        self.list.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        let cheat = (index < self.list.len()).then(|| self.list.remove(index));
+        self.update_active();
+        cheat
+    }
+
+    /// Turns a cheat on or off, returning whether it exists
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        let Some(cheat) = self.list.get_mut(index) else {
+            return false;
+        };
+        cheat.enabled = enabled;
+        self.update_active();
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.active = false;
+    }
+
+    /// Returns what a CPU read of `addr` returns with the cheats applied,
+    /// where `value` is what the read returns without them. Game Genie codes
+    /// only apply to the cartridge ROM, freezes also catch mirrors of the
+    /// frozen address
+    #[inline]
+    pub fn apply(&self, addr: u16, value: u8) -> u8 {
+        if !self.active {
+            return value;
+        }
+        self.list
+            .iter()
+            .filter(|cheat| cheat.kind == CheatKind::Freeze || addr >= 0x8000)
+            .find_map(|cheat| cheat.apply(addr, value))
+            .unwrap_or(value)
+    }
+
+    fn update_active(&mut self) {
+        self.active = self.list.iter().any(|cheat| cheat.enabled);
+    }
+}
+
+/// Parses a cheat list in the FCEUX `.cht` format. Empty lines are skipped
+pub fn parse_cht(text: &str) -> Result<Vec<Cheat>, String> {
+    let mut cheats = Vec::new();
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |msg: &str| format!("line {}: {msg}", line_num + 1);
+
+        let mut rest = line;
+        let kind = match rest.strip_prefix('S') {
+            Some(stripped) => {
+                rest = stripped;
+                CheatKind::GameGenie
+            }
+            None => CheatKind::Freeze,
+        };
+        let has_compare = match rest.strip_prefix('C') {
+            Some(stripped) => {
+                rest = stripped;
+                true
+            }
+            None => false,
+        };
+        let enabled = match rest.strip_prefix(':') {
+            Some(stripped) => {
+                rest = stripped;
+                false
+            }
+            None => true,
+        };
+
+        let fields = if has_compare { 4 } else { 3 };
+        let parts = rest.splitn(fields, ':').collect::<Vec<_>>();
+        if parts.len() != fields {
+            return Err(error("Expected AAAA:VV[:CC]:Name"));
+        }
+        let addr = u16::from_str_radix(parts[0], 16).map_err(|_| error("Invalid address"))?;
+        let value = u8::from_str_radix(parts[1], 16).map_err(|_| error("Invalid value"))?;
+        let compare = if has_compare {
+            Some(u8::from_str_radix(parts[2], 16).map_err(|_| error("Invalid compare value"))?)
+        } else {
+            None
+        };
+
+        cheats.push(Cheat {
+            name: parts[fields - 1].to_string(),
+            kind,
+            addr,
+            value,
+            compare,
+            enabled,
+        });
+    }
+    Ok(cheats)
+}
+
+/// Formats a cheat list in the FCEUX `.cht` format
+pub fn to_cht(cheats: &[Cheat]) -> String {
+    cheats
+        .iter()
+        .map(|cheat| {
+            let mut line = String::new();
+            if cheat.kind == CheatKind::GameGenie {
+                line.push('S');
+            }
+            if cheat.compare.is_some() {
+                line.push('C');
+            }
+            if !cheat.enabled {
+                line.push(':');
+            }
+            line += &format!("{:04x}:{:02x}:", cheat.addr, cheat.value);
+            if let Some(compare) = cheat.compare {
+                line += &format!("{compare:02x}:");
+            }
+            line + &cheat.name + "\n"
+        })
+        .collect()
+}
+
+/// Reads a `.cht` file
+pub fn load_cht<P: AsRef<Path>>(path: P) -> Result<Vec<Cheat>, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    parse_cht(&text).map_err(|err| format!("{}: {err}", path.display()))
+}
+
+/// Writes a `.cht` file
+pub fn save_cht<P: AsRef<Path>>(path: P, cheats: &[Cheat]) -> Result<(), String> {
+    let path = path.as_ref();
+    std::fs::write(path, to_cht(cheats)).map_err(|err| format!("{}: {err}", path.display()))
+}
@@ -5,7 +5,7 @@
 use super::CPU;
 use crate::Mem;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// See https://www.nesdev.org/obelisk-6502-guide/addressing.html#IMP
 /// for details on what each addressing mode does
 pub(crate) enum AddressingMode {
@@ -27,7 +27,7 @@ pub(crate) enum AddressingMode {
 impl CPU {
     /// Given an addressing mode for an op code, return the target address of
     /// that the op code wants to operate on
-    pub(crate) fn get_operand_address(&self, mode: AddressingMode) -> u16 {
+    pub(crate) fn get_operand_address(&mut self, mode: AddressingMode) -> u16 {
         match mode {
             AddressingMode::Implicit => {
                 // certain instructions do not need a target
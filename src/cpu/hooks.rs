@@ -0,0 +1,350 @@
+//! Contains the execution hooks API. Hooks are callbacks that run when the
+//! CPU reaches an address, touches memory, gets interrupted, finishes a frame
+//! or runs into an illegal opcode. Every hook gets `&mut CPU`, so it can
+//! inspect or modify any state, and tells the CPU whether to keep going
+//! through the `HookAction` it returns.
+//!
+//! When no hooks are registered, `CPU::step` only pays for checking that
+//! there are none.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use super::{CPU, Executed};
+use crate::bus::{Access, BusAccess};
+
+/// What a hook wants the CPU to do once it returns
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HookAction {
+    /// keep running
+    Continue,
+    /// stop running (`CPU::step` returns false and `CPU::stopped_by_hook`
+    /// returns true)
+    Stop,
+}
+
+/// The interrupts the CPU can service
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+/// Identifies a registered hook, so it can be removed later
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HookId(u32);
+
+type PcHook = Box<dyn FnMut(&mut CPU) -> HookAction + Send>;
+type MemHook = Box<dyn FnMut(&mut CPU, BusAccess) -> HookAction + Send>;
+type InterruptHook = Box<dyn FnMut(&mut CPU, Interrupt) -> HookAction + Send>;
+type FrameHook = Box<dyn FnMut(&mut CPU, u64) -> HookAction + Send>;
+type IllegalOpcodeHook = Box<dyn FnMut(&mut CPU, u8) -> HookAction + Send>;
+
+struct MemWatch {
+    id: HookId,
+    access: Access,
+    range: RangeInclusive<u16>,
+    hook: MemHook,
+}
+
+/// All the hooks registered on a CPU
+#[derive(Default)]
+pub(super) struct Hooks {
+    pc: BTreeMap<u16, Vec<(HookId, PcHook)>>,
+    mem: Vec<MemWatch>,
+    interrupt: Vec<(HookId, InterruptHook)>,
+    frame: Vec<(HookId, FrameHook)>,
+    illegal_opcode: Vec<(HookId, IllegalOpcodeHook)>,
+}
+
+/// Bookkeeping the CPU needs for running hooks
+#[derive(Default)]
+pub(super) struct HookState {
+    next_id: u32,
+    /// PC hooks at this address are skipped once, so resuming after a PC hook
+    /// stopped the CPU doesn't immediately stop again
+    resume_pc: Option<u16>,
+    /// whether the last step was stopped by a hook
+    stopped: bool,
+    /// whether memory accesses should be recorded for memory hooks and the
+    /// code/data logger (only while an instruction executes)
+    pub(super) watching_memory: bool,
+    /// memory accesses made by the instruction being executed
+    pub(super) accesses: Vec<BusAccess>,
+}
+
+impl Hooks {
+    fn is_empty(&self) -> bool {
+        self.pc.is_empty()
+            && self.mem.is_empty()
+            && self.interrupt.is_empty()
+            && self.frame.is_empty()
+            && self.illegal_opcode.is_empty()
+    }
+
+    fn remove(&mut self, id: HookId) -> bool {
+        let mut removed = false;
+        for hooks in self.pc.values_mut() {
+            hooks.retain(|(hook_id, _)| {
+                removed |= *hook_id == id;
+                *hook_id != id
+            });
+        }
+        self.pc.retain(|_, hooks| !hooks.is_empty());
+        self.mem.retain(|watch| {
+            removed |= watch.id == id;
+            watch.id != id
+        });
+        removed |= self.interrupt.remove_id(id);
+        removed |= self.frame.remove_id(id);
+        removed |= self.illegal_opcode.remove_id(id);
+        removed
+    }
+
+    /// Adds hooks that were registered while these ones were running
+    fn merge(&mut self, other: Hooks) {
+        for (addr, hooks) in other.pc {
+            self.pc.entry(addr).or_default().extend(hooks);
+        }
+        self.mem.extend(other.mem);
+        self.interrupt.extend(other.interrupt);
+        self.frame.extend(other.frame);
+        self.illegal_opcode.extend(other.illegal_opcode);
+    }
+}
+
+/// Lets hooks of different types be removed the same way
+trait HookList {
+    fn remove_id(&mut self, id: HookId) -> bool;
+}
+
+impl<T> HookList for Vec<(HookId, T)> {
+    fn remove_id(&mut self, id: HookId) -> bool {
+        let len = self.len();
+        self.retain(|(hook_id, _)| *hook_id != id);
+        self.len() != len
+    }
+}
+
+/// Runs every hook in the list, returning whether any of them asked to stop
+fn run_all<T, F>(hooks: &mut [(HookId, T)], mut call: F) -> bool
+where
+    F: FnMut(&mut T) -> HookAction,
+{
+    // every hook gets to run, even if an earlier one asked to stop
+    hooks.iter_mut().fold(false, |stop, (_, hook)| {
+        (call(hook) == HookAction::Stop) | stop
+    })
+}
+
+impl CPU {
+    /// Registers a hook that runs right before the instruction at `addr` is
+    /// executed. If it asks to stop, the instruction isn't executed (and the
+    /// hook won't run again when resuming from there). If it moves the PC,
+    /// execution continues from the new address
+    pub fn add_pc_hook<F>(&mut self, addr: u16, hook: F) -> HookId
+    where
+        F: FnMut(&mut CPU) -> HookAction + Send + 'static,
+    {
+        let id = self.next_hook_id();
+        self.hooks_mut()
+            .pc
+            .entry(addr)
+            .or_default()
+            .push((id, Box::new(hook)));
+        id
+    }
+
+    /// Registers a hook that runs after an instruction read from an address in
+    /// `range`. Every bus read counts, including instruction fetches. Mirrored
+    /// addresses are reported as the address they mirror (i.e. 0x0800 is 0x0000)
+    pub fn add_read_hook<F>(&mut self, range: RangeInclusive<u16>, hook: F) -> HookId
+    where
+        F: FnMut(&mut CPU, BusAccess) -> HookAction + Send + 'static,
+    {
+        self.add_mem_hook(Access::Read, range, Box::new(hook))
+    }
+
+    /// Registers a hook that runs after an instruction wrote to an address in
+    /// `range`. Mirrored addresses are handled the same way as for read hooks
+    pub fn add_write_hook<F>(&mut self, range: RangeInclusive<u16>, hook: F) -> HookId
+    where
+        F: FnMut(&mut CPU, BusAccess) -> HookAction + Send + 'static,
+    {
+        self.add_mem_hook(Access::Write, range, Box::new(hook))
+    }
+
+    /// Registers a hook that runs after the CPU jumped to an interrupt handler
+    pub fn add_interrupt_hook<F>(&mut self, hook: F) -> HookId
+    where
+        F: FnMut(&mut CPU, Interrupt) -> HookAction + Send + 'static,
+    {
+        let id = self.next_hook_id();
+        self.hooks_mut().interrupt.push((id, Box::new(hook)));
+        id
+    }
+
+    /// Registers a hook that runs once the PPU finished a frame. It gets the
+    /// number of the frame that just started
+    pub fn add_frame_hook<F>(&mut self, hook: F) -> HookId
+    where
+        F: FnMut(&mut CPU, u64) -> HookAction + Send + 'static,
+    {
+        let id = self.next_hook_id();
+        self.hooks_mut().frame.push((id, Box::new(hook)));
+        id
+    }
+
+    /// Registers a hook that runs when the CPU reaches an opcode it doesn't
+    /// know, with the PC pointing at it. If the hooks leave the PC alone, the
+    /// opcode is skipped like a 1 byte, 2 cycle NOP. Without any of these
+    /// hooks, illegal opcodes panic (or are returned by `CPU::try_step`)
+    pub fn add_illegal_opcode_hook<F>(&mut self, hook: F) -> HookId
+    where
+        F: FnMut(&mut CPU, u8) -> HookAction + Send + 'static,
+    {
+        let id = self.next_hook_id();
+        self.hooks_mut().illegal_opcode.push((id, Box::new(hook)));
+        id
+    }
+
+    /// Removes a hook, returning whether it was registered. Hooks can't be
+    /// removed from inside a hook
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let Some(hooks) = &mut self.hooks else {
+            return false;
+        };
+        let removed = hooks.remove(id);
+        if hooks.is_empty() {
+            self.clear_hooks();
+        }
+        removed
+    }
+
+    /// Removes every hook
+    pub fn clear_hooks(&mut self) {
+        self.hooks = None;
+        self.hook_state.resume_pc = None;
+        self.hook_state.stopped = false;
+    }
+
+    /// Whether the last call to `step` returned false because a hook asked
+    /// to stop (rather than the CPU reaching BRK)
+    pub fn stopped_by_hook(&self) -> bool {
+        self.hook_state.stopped
+    }
+
+    fn next_hook_id(&mut self) -> HookId {
+        self.hook_state.next_id += 1;
+        HookId(self.hook_state.next_id)
+    }
+
+    fn hooks_mut(&mut self) -> &mut Hooks {
+        self.hooks.get_or_insert_default()
+    }
+
+    fn add_mem_hook(
+        &mut self,
+        access: Access,
+        range: RangeInclusive<u16>,
+        hook: MemHook,
+    ) -> HookId {
+        let id = self.next_hook_id();
+        self.hooks_mut().mem.push(MemWatch {
+            id,
+            access,
+            range,
+            hook,
+        });
+        id
+    }
+
+    /// Notes down a memory access for the memory hooks
+    #[inline]
+    pub(super) fn record_access(&mut self, addr: u16, access: Access, value: u8) {
+        if self.hook_state.watching_memory {
+            self.hook_state.accesses.push(BusAccess {
+                addr: crate::bus::mirror_down(addr),
+                access,
+                value,
+            });
+        }
+    }
+
+    /// `try_step`, for when there are hooks to run
+    pub(super) fn step_with_hooks(&mut self, mut hooks: Box<Hooks>) -> Result<bool, u8> {
+        // memory accesses are only recorded while the instruction executes,
+        // so the ones made by the hooks themselves don't count
+        let watching_memory = !hooks.mem.is_empty();
+        self.hook_state.stopped = false;
+        let mut stop = false;
+
+        let pc = self.program_counter;
+        let resuming = self.hook_state.resume_pc.take() == Some(pc);
+        if !resuming
+            && !self.bus.nmi_pending()
+            && let Some(pc_hooks) = hooks.pc.get_mut(&pc)
+            && run_all(pc_hooks, |hook| hook(self))
+        {
+            self.hook_state.resume_pc = Some(pc);
+            return Ok(self.finish_hooks(hooks, true));
+        }
+
+        let frame = self.bus.ppu().frame();
+        self.hook_state.watching_memory = watching_memory;
+        self.hook_state.accesses.clear();
+        let executed = self.execute_logged();
+        self.hook_state.watching_memory = false;
+
+        match executed {
+            Executed::Instruction => {}
+            Executed::Interrupt(interrupt) => {
+                stop |= run_all(&mut hooks.interrupt, |hook| hook(self, interrupt));
+            }
+            Executed::Brk => {
+                self.finish_hooks(hooks, false);
+                return Ok(false);
+            }
+            Executed::Illegal(opcode) => {
+                if hooks.illegal_opcode.is_empty() {
+                    self.finish_hooks(hooks, false);
+                    return Err(opcode);
+                }
+                stop |= run_all(&mut hooks.illegal_opcode, |hook| hook(self, opcode));
+                if self.program_counter == pc {
+                    self.program_counter = pc.wrapping_add(1);
+                    self.bus.tick(2);
+                }
+            }
+        }
+
+        let accesses = std::mem::take(&mut self.hook_state.accesses);
+        for hit in &accesses {
+            for watch in &mut hooks.mem {
+                if watch.access == hit.access && watch.range.contains(&hit.addr) {
+                    stop |= (watch.hook)(self, *hit) == HookAction::Stop;
+                }
+            }
+        }
+        // hand the buffer back so it doesn't get reallocated every step
+        self.hook_state.accesses = accesses;
+
+        let new_frame = self.bus.ppu().frame();
+        if new_frame != frame {
+            stop |= run_all(&mut hooks.frame, |hook| hook(self, new_frame));
+        }
+
+        Ok(self.finish_hooks(hooks, stop))
+    }
+
+    /// Puts the hooks back into the CPU once they're done running. Returns
+    /// whether the CPU can keep going
+    fn finish_hooks(&mut self, mut hooks: Box<Hooks>, stop: bool) -> bool {
+        if let Some(added) = self.hooks.take() {
+            hooks.merge(*added);
+        }
+        self.hooks = Some(hooks);
+        self.hook_state.stopped = stop;
+        !stop
+    }
+}
@@ -2,23 +2,28 @@
 //! on game inserts
 
 pub mod addressing_mode;
+pub mod hooks;
 pub mod instructions;
 pub mod opcodes;
 pub mod processor_status;
 
 use crate::Mem;
-use crate::bus::Bus;
+use crate::bus::{Access, Bus};
+use hooks::{HookState, Hooks, Interrupt};
 use opcodes::{OpCode, OpCodeName};
+use processor_status::{BREAK_BIT, UNUSED_BIT};
 
 /// The stack pointer offsets from this
 /// base address
 const STACK: u16 = 0x100;
 const STACK_RESET: u8 = 0xfd;
 
-/// temp static variable to initialize PC for now
-/// (TODO: need to change this to fetching initial PC from
-/// reset addr 0xFFFC)
-static mut PC_INIT: u16 = 0x0000;
+/// The address the PC jumps to on an NMI is stored here
+const NMI_VECTOR: u16 = 0xFFFA;
+/// The address the PC starts from after a reset is stored here
+const RESET_VECTOR: u16 = 0xFFFC;
+/// The address the PC jumps to on an IRQ is stored here
+const IRQ_VECTOR: u16 = 0xFFFE;
 
 pub struct CPU {
     /// accumulator CPU register
@@ -35,6 +40,25 @@ pub struct CPU {
     pub program_counter: u16,
     /// the bus to read and write data from
     pub bus: Bus,
+    /// where the PC starts after a reset when there's no cartridge (and so
+    /// no reset vector), set by loading a raw program
+    pc_init: u16,
+    /// execution hooks (None until one is registered, which keeps `step`
+    /// cheap when nobody is hooked in)
+    hooks: Option<Box<Hooks>>,
+    hook_state: HookState,
+}
+
+/// What a call to `execute` did
+enum Executed {
+    /// executed a regular instruction
+    Instruction,
+    /// jumped to an interrupt handler instead of executing an instruction
+    Interrupt(Interrupt),
+    /// reached a BRK instruction (the PC points at it)
+    Brk,
+    /// reached an opcode the CPU doesn't know (the PC points at it)
+    Illegal(u8),
 }
 
 impl Default for CPU {
@@ -46,6 +70,12 @@ impl Default for CPU {
 impl CPU {
     /// Instantiates the CPU (all set to 0)
     pub fn new() -> Self {
+        Self::with_bus(Bus::new())
+    }
+
+    /// Instantiates the CPU connected to the given bus (i.e. one with a
+    /// cartridge inserted)
+    pub fn with_bus(bus: Bus) -> Self {
         Self {
             register_a: 0,
             register_x: 0,
@@ -54,7 +84,10 @@ impl CPU {
             status: 0b10_0100, // decimal and interrupt disable flag is turned on
             program_counter: 0,
             // memory: [0; 0xFFFF],
-            bus: Bus::new(),
+            bus,
+            pc_init: 0,
+            hooks: None,
+            hook_state: HookState::default(),
         }
     }
 
@@ -71,8 +104,18 @@ impl CPU {
         self.stack_pointer = STACK_RESET;
         self.status = 0b10_0100;
 
-        // self.program_counter = self.mem_read_u16(0xFFFC);
-        self.program_counter = unsafe { PC_INIT };
+        self.program_counter = if self.bus.has_cartridge() {
+            self.mem_read_u16(RESET_VECTOR)
+        } else {
+            self.pc_init
+        };
+    }
+
+    /// Turns the machine off and on again (a hard reset, as opposed to the
+    /// soft reset of the console's reset button that `reset` does)
+    pub fn power_cycle(&mut self) {
+        self.bus.power_cycle();
+        self.reset();
     }
 
     /// Copies the program data into Program ROM (PRG ROM) space of memory.
@@ -92,9 +135,7 @@ impl CPU {
             self.mem_write(i, program[i as usize]);
         }
         // self.mem_write_u16(0xFFFC, 0x0000);
-        unsafe {
-            PC_INIT = 0x0000;
-        }
+        self.pc_init = 0x0000;
     }
 
     #[doc(hidden)]
@@ -105,7 +146,19 @@ impl CPU {
             self.mem_write(0x0600 + i, program[i as usize]);
         }
         // self.mem_write_u16(0xFFFC, 0x0600);
-        unsafe { PC_INIT = 0x0600 };
+        self.pc_init = 0x0600;
+    }
+
+    /// Copies the program data into memory starting at `addr` and marks `addr`
+    /// as where the PC starts after the next reset. This is how raw 6502
+    /// programs that aren't meant to live at 0x0000 get loaded (i.e. by the
+    /// debugger)
+    #[inline]
+    pub fn load_at(&mut self, addr: u16, program: &[u8]) {
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(addr.wrapping_add(i as u16), *byte);
+        }
+        self.pc_init = addr;
     }
 
     /// Loads the program into memory, reset all registers and PC to default state,
@@ -123,125 +176,237 @@ impl CPU {
     /// - Exec instruction
     /// - Rinse and repeat
     pub fn run(&mut self) {
-        loop {
-            let opcode = self.mem_read(self.program_counter);
-            self.program_counter = self.program_counter.wrapping_add(1);
-            if let Some(opcode_struct) = OpCode::get(opcode) {
-                match opcode_struct.mnemonic {
-                    OpCodeName::ADC => self.adc(opcode_struct.mode),
-                    OpCodeName::AND => self.and(opcode_struct.mode),
-                    OpCodeName::ASL => self.asl(opcode_struct.mode),
-                    OpCodeName::BCC => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Carry),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BCS => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Carry),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BEQ => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Zero),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BIT => self.bit(opcode_struct.mode),
-                    OpCodeName::BMI => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Negative),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BNE => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Zero),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BPL => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Negative),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BRK => {
-                        // By technicality on 6502, the PC should not be incremented
-                        // when a BRK instruction is reached. Since I don't want
-                        // to rewrite this code to have incrementation occur everywhere
-                        // except BRK, I'll just decrement the PC by 1 here.
-                        self.program_counter = self.program_counter.wrapping_sub(1);
-                        return;
-                    }
-                    OpCodeName::BVC => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Overflow),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BVS => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Overflow),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::CLC => self.clear(processor_status::ProcessorStatus::Carry),
-                    OpCodeName::CLD => self.clear(processor_status::ProcessorStatus::Decimal),
-                    OpCodeName::CLI => {
-                        self.clear(processor_status::ProcessorStatus::InterruptDisable)
-                    }
-                    OpCodeName::CLV => self.clear(processor_status::ProcessorStatus::Overflow),
-                    OpCodeName::CMP => self.cmp(opcode_struct.mode),
-                    OpCodeName::CPX => self.cpx(opcode_struct.mode),
-                    OpCodeName::CPY => self.cpy(opcode_struct.mode),
-                    OpCodeName::DEC => self.dec(opcode_struct.mode),
-                    OpCodeName::DEX => self.dex(),
-                    OpCodeName::DEY => self.dey(),
-                    OpCodeName::EOR => self.eor(opcode_struct.mode),
-                    OpCodeName::INC => self.inc(opcode_struct.mode),
-                    OpCodeName::INX => self.inx(),
-                    OpCodeName::INY => self.iny(),
-                    OpCodeName::JMP => {
-                        self.jmp(opcode_struct.mode);
-                        // the reason why we continue is bc
-                        // jump instructions are not *relative*
-                        // to the next instruction, so we do not
-                        // add the opcode_struct.len() - 1 bytes
-                        // to the PC
-                        continue;
-                    }
-                    OpCodeName::JSR => {
-                        self.jsr(opcode_struct.mode);
-                        continue;
-                    }
-                    OpCodeName::LDA => self.lda(opcode_struct.mode),
-                    OpCodeName::LDX => self.ldx(opcode_struct.mode),
-                    OpCodeName::LDY => self.ldy(opcode_struct.mode),
-                    OpCodeName::LSR => self.lsr(opcode_struct.mode),
-                    OpCodeName::NOP => {} // does nothing lol
-                    OpCodeName::ORA => self.ora(opcode_struct.mode),
-                    OpCodeName::PHA => self.pha(),
-                    OpCodeName::PHP => self.php(),
-                    OpCodeName::PLA => self.pla(),
-                    OpCodeName::PLP => self.plp(),
-                    OpCodeName::ROL => self.rol(opcode_struct.mode),
-                    OpCodeName::ROR => self.ror(opcode_struct.mode),
-                    OpCodeName::RTI => self.rti(),
-                    OpCodeName::RTS => self.rts(),
-                    OpCodeName::SBC => self.sbc(opcode_struct.mode),
-                    OpCodeName::SEC => self.sec(),
-                    OpCodeName::SED => self.sed(),
-                    OpCodeName::SEI => self.sei(),
-                    OpCodeName::STA => self.sta(opcode_struct.mode),
-                    OpCodeName::STX => self.stx(opcode_struct.mode),
-                    OpCodeName::STY => self.sty(opcode_struct.mode),
-                    OpCodeName::TAX => self.tax(),
-                    OpCodeName::TAY => self.tay(),
-                    OpCodeName::TSX => self.tsx(),
-                    OpCodeName::TXA => self.txa(),
-                    OpCodeName::TXS => self.txs(),
-                    OpCodeName::TYA => self.tya(),
+        while self.step() {}
+    }
+
+    /// Executes a single instruction (or services a pending NMI instead) and
+    /// returns whether the CPU can keep going. Once BRK is reached, false is
+    /// returned and the PC is left pointing at the BRK instruction. False is
+    /// also returned when a hook asks to stop (see `stopped_by_hook`).
+    /// Panics on an illegal opcode nothing handles (see `try_step`)
+    pub fn step(&mut self) -> bool {
+        match self.try_step() {
+            Ok(running) => running,
+            Err(opcode) => panic!(
+                "Illegal instruction {} reached at address {:#x}",
+                opcode, self.program_counter
+            ),
+        }
+    }
+
+    /// Same as `step`, but reaching an illegal opcode that no hook handles
+    /// returns it as an error instead of panicking. The PC is left pointing
+    /// at the opcode
+    pub fn try_step(&mut self) -> Result<bool, u8> {
+        if let Some(hooks) = self.hooks.take() {
+            return self.step_with_hooks(hooks);
+        }
+
+        match self.execute_logged() {
+            Executed::Instruction | Executed::Interrupt(_) => Ok(true),
+            Executed::Brk => Ok(false),
+            Executed::Illegal(opcode) => Err(opcode),
+        }
+    }
+
+    /// `execute`, but also tells the bus how the instruction used memory so
+    /// it can update the code/data log
+    fn execute_logged(&mut self) -> Executed {
+        if !self.bus.is_logging_code() {
+            return self.execute();
+        }
+
+        let pc = self.program_counter;
+        let opcode = OpCode::get(self.mem_peek(pc));
+        // memory hooks might be recording accesses already, in which case
+        // they get to keep them
+        let watching_memory = self.hook_state.watching_memory;
+        let start = self.hook_state.accesses.len();
+        self.hook_state.watching_memory = true;
+        let executed = self.execute();
+        self.hook_state.watching_memory = watching_memory;
+
+        let accesses = std::mem::take(&mut self.hook_state.accesses);
+        let opcode = match executed {
+            Executed::Instruction | Executed::Brk => opcode,
+            Executed::Interrupt(_) | Executed::Illegal(_) => None,
+        };
+        if !matches!(executed, Executed::Illegal(_)) {
+            self.bus.log_instruction(
+                pc,
+                opcode.as_ref(),
+                &accesses[start..],
+                self.program_counter,
+            );
+        }
+        self.hook_state.accesses = accesses;
+        if !watching_memory {
+            self.hook_state.accesses.truncate(start);
+        }
+        executed
+    }
+
+    /// Executes a single instruction or services a pending NMI (or IRQ,
+    /// unless interrupts are disabled)
+    fn execute(&mut self) -> Executed {
+        if self.bus.poll_nmi_status() {
+            self.interrupt(NMI_VECTOR);
+            return Executed::Interrupt(Interrupt::Nmi);
+        }
+        if self.bus.irq_pending()
+            && !self.is_status_flag_set(processor_status::ProcessorStatus::InterruptDisable)
+        {
+            self.interrupt(IRQ_VECTOR);
+            return Executed::Interrupt(Interrupt::Irq);
+        }
+
+        let opcode = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        if let Some(opcode_struct) = OpCode::get(opcode) {
+            match opcode_struct.mnemonic {
+                OpCodeName::ADC => self.adc(opcode_struct.mode),
+                OpCodeName::AND => self.and(opcode_struct.mode),
+                OpCodeName::ASL => self.asl(opcode_struct.mode),
+                OpCodeName::BCC => self.branch(
+                    !self.is_status_flag_set(processor_status::ProcessorStatus::Carry),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::BCS => self.branch(
+                    self.is_status_flag_set(processor_status::ProcessorStatus::Carry),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::BEQ => self.branch(
+                    self.is_status_flag_set(processor_status::ProcessorStatus::Zero),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::BIT => self.bit(opcode_struct.mode),
+                OpCodeName::BMI => self.branch(
+                    self.is_status_flag_set(processor_status::ProcessorStatus::Negative),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::BNE => self.branch(
+                    !self.is_status_flag_set(processor_status::ProcessorStatus::Zero),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::BPL => self.branch(
+                    !self.is_status_flag_set(processor_status::ProcessorStatus::Negative),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::BRK => {
+                    // By technicality on 6502, the PC should not be incremented
+                    // when a BRK instruction is reached. Since I don't want
+                    // to rewrite this code to have incrementation occur everywhere
+                    // except BRK, I'll just decrement the PC by 1 here.
+                    self.program_counter = self.program_counter.wrapping_sub(1);
+                    return Executed::Brk;
+                }
+                OpCodeName::BVC => self.branch(
+                    !self.is_status_flag_set(processor_status::ProcessorStatus::Overflow),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::BVS => self.branch(
+                    self.is_status_flag_set(processor_status::ProcessorStatus::Overflow),
+                    opcode_struct.mode,
+                ),
+                OpCodeName::CLC => self.clear(processor_status::ProcessorStatus::Carry),
+                OpCodeName::CLD => self.clear(processor_status::ProcessorStatus::Decimal),
+                OpCodeName::CLI => self.clear(processor_status::ProcessorStatus::InterruptDisable),
+                OpCodeName::CLV => self.clear(processor_status::ProcessorStatus::Overflow),
+                OpCodeName::CMP => self.cmp(opcode_struct.mode),
+                OpCodeName::CPX => self.cpx(opcode_struct.mode),
+                OpCodeName::CPY => self.cpy(opcode_struct.mode),
+                OpCodeName::DEC => self.dec(opcode_struct.mode),
+                OpCodeName::DEX => self.dex(),
+                OpCodeName::DEY => self.dey(),
+                OpCodeName::EOR => self.eor(opcode_struct.mode),
+                OpCodeName::INC => self.inc(opcode_struct.mode),
+                OpCodeName::INX => self.inx(),
+                OpCodeName::INY => self.iny(),
+                OpCodeName::JMP => {
+                    self.jmp(opcode_struct.mode);
+                    // the reason why we continue is bc
+                    // jump instructions are not *relative*
+                    // to the next instruction, so we do not
+                    // add the opcode_struct.len() - 1 bytes
+                    // to the PC
+                    self.bus.tick(opcode_struct.cycles as usize);
+                    return Executed::Instruction;
                 }
-                // move PC to the next instruction to process
-                self.program_counter = self
-                    .program_counter
-                    .wrapping_add((opcode_struct.len - 1) as u16);
-            } else {
-                panic!(
-                    "Illegal instruction {} reached at address {:#x}",
-                    opcode, self.program_counter
-                )
+                OpCodeName::JSR => {
+                    self.jsr(opcode_struct.mode);
+                    self.bus.tick(opcode_struct.cycles as usize);
+                    return Executed::Instruction;
+                }
+                OpCodeName::LDA => self.lda(opcode_struct.mode),
+                OpCodeName::LDX => self.ldx(opcode_struct.mode),
+                OpCodeName::LDY => self.ldy(opcode_struct.mode),
+                OpCodeName::LSR => self.lsr(opcode_struct.mode),
+                OpCodeName::NOP => {} // does nothing lol
+                OpCodeName::ORA => self.ora(opcode_struct.mode),
+                OpCodeName::PHA => self.pha(),
+                OpCodeName::PHP => self.php(),
+                OpCodeName::PLA => self.pla(),
+                OpCodeName::PLP => self.plp(),
+                OpCodeName::ROL => self.rol(opcode_struct.mode),
+                OpCodeName::ROR => self.ror(opcode_struct.mode),
+                OpCodeName::RTI => self.rti(),
+                OpCodeName::RTS => self.rts(),
+                OpCodeName::SBC => self.sbc(opcode_struct.mode),
+                OpCodeName::SEC => self.sec(),
+                OpCodeName::SED => self.sed(),
+                OpCodeName::SEI => self.sei(),
+                OpCodeName::STA => self.sta(opcode_struct.mode),
+                OpCodeName::STX => self.stx(opcode_struct.mode),
+                OpCodeName::STY => self.sty(opcode_struct.mode),
+                OpCodeName::TAX => self.tax(),
+                OpCodeName::TAY => self.tay(),
+                OpCodeName::TSX => self.tsx(),
+                OpCodeName::TXA => self.txa(),
+                OpCodeName::TXS => self.txs(),
+                OpCodeName::TYA => self.tya(),
             }
+            // move PC to the next instruction to process
+            self.program_counter = self
+                .program_counter
+                .wrapping_add((opcode_struct.len - 1) as u16);
+            self.bus.tick(opcode_struct.cycles as usize);
+            Executed::Instruction
+        } else {
+            self.program_counter = self.program_counter.wrapping_sub(1);
+            Executed::Illegal(opcode)
         }
     }
 
+    /// Services a hardware interrupt:
+    /// * NMI - Non-Maskable Interrupt, raised by the PPU at the start of VBlank
+    ///   (when enabled through PPUCTRL), vector at 0xFFFA
+    /// * IRQ - Interrupt Request, raised by the APU or the cartridge's mapper
+    ///   for as long as they hold the line (ignored while interrupts are
+    ///   disabled), vector at 0xFFFE
+    ///
+    /// The PC and processor status are pushed onto the stack the same way BRK
+    /// would, except the B flag is pushed as 0 since this is a hardware interrupt.
+    /// Interrupts are then disabled and the PC jumps to the address stored in
+    /// the vector
+    fn interrupt(&mut self, vector: u16) {
+        let (msb_byte, lsb_byte) = (
+            (self.program_counter >> 8) as u8,
+            (self.program_counter & 0xff) as u8,
+        );
+        self.mem_write(STACK + self.stack_pointer as u16, msb_byte);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        self.mem_write(STACK + self.stack_pointer as u16, lsb_byte);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+
+        let status = (self.status & !BREAK_BIT) | UNUSED_BIT;
+        self.mem_write(STACK + self.stack_pointer as u16, status);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        self.update_interrupt_flag(true);
+
+        self.bus.tick(7);
+        self.program_counter = self.mem_read_u16(vector);
+    }
+
     #[doc(hidden)]
     #[inline]
     pub fn test_run(&mut self) {
@@ -254,126 +419,7 @@ impl CPU {
     where
         F: FnMut(&mut CPU),
     {
-        loop {
-            let opcode = self.mem_read(self.program_counter);
-            self.program_counter = self.program_counter.wrapping_add(1);
-            // println!(
-            //     "Reading instruction: {:#x} with PC at: {:#x}",
-            //     opcode, self.program_counter
-            // );
-            if let Some(opcode_struct) = OpCode::get(opcode) {
-                match opcode_struct.mnemonic {
-                    OpCodeName::ADC => self.adc(opcode_struct.mode),
-                    OpCodeName::AND => self.and(opcode_struct.mode),
-                    OpCodeName::ASL => self.asl(opcode_struct.mode),
-                    OpCodeName::BCC => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Carry),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BCS => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Carry),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BEQ => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Zero),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BIT => self.bit(opcode_struct.mode),
-                    OpCodeName::BMI => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Negative),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BNE => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Zero),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BPL => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Negative),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BRK => {
-                        // By technicality on 6502, the PC should not be incremented
-                        // when a BRK instruction is reached. Since I don't want
-                        // to rewrite this code to have incrementation occur everywhere
-                        // except BRK, I'll just decrement the PC by 1 here.
-                        self.program_counter = self.program_counter.wrapping_sub(1);
-                        return;
-                    }
-                    OpCodeName::BVC => self.branch(
-                        !self.is_status_flag_set(processor_status::ProcessorStatus::Overflow),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::BVS => self.branch(
-                        self.is_status_flag_set(processor_status::ProcessorStatus::Overflow),
-                        opcode_struct.mode,
-                    ),
-                    OpCodeName::CLC => self.clear(processor_status::ProcessorStatus::Carry),
-                    OpCodeName::CLD => self.clear(processor_status::ProcessorStatus::Decimal),
-                    OpCodeName::CLI => {
-                        self.clear(processor_status::ProcessorStatus::InterruptDisable)
-                    }
-                    OpCodeName::CLV => self.clear(processor_status::ProcessorStatus::Overflow),
-                    OpCodeName::CMP => self.cmp(opcode_struct.mode),
-                    OpCodeName::CPX => self.cpx(opcode_struct.mode),
-                    OpCodeName::CPY => self.cpy(opcode_struct.mode),
-                    OpCodeName::DEC => self.dec(opcode_struct.mode),
-                    OpCodeName::DEX => self.dex(),
-                    OpCodeName::DEY => self.dey(),
-                    OpCodeName::EOR => self.eor(opcode_struct.mode),
-                    OpCodeName::INC => self.inc(opcode_struct.mode),
-                    OpCodeName::INX => self.inx(),
-                    OpCodeName::INY => self.iny(),
-                    OpCodeName::JMP => {
-                        self.jmp(opcode_struct.mode);
-                        // the reason why we continue is bc
-                        // jump instructions are not *relative*
-                        // to the next instruction, so we do not
-                        // add the opcode_struct.len() - 1 bytes
-                        // to the PC
-                        continue;
-                    }
-                    OpCodeName::JSR => {
-                        self.jsr(opcode_struct.mode);
-                        continue;
-                    }
-                    OpCodeName::LDA => self.lda(opcode_struct.mode),
-                    OpCodeName::LDX => self.ldx(opcode_struct.mode),
-                    OpCodeName::LDY => self.ldy(opcode_struct.mode),
-                    OpCodeName::LSR => self.lsr(opcode_struct.mode),
-                    OpCodeName::NOP => {} // does nothing lol
-                    OpCodeName::ORA => self.ora(opcode_struct.mode),
-                    OpCodeName::PHA => self.pha(),
-                    OpCodeName::PHP => self.php(),
-                    OpCodeName::PLA => self.pla(),
-                    OpCodeName::PLP => self.plp(),
-                    OpCodeName::ROL => self.rol(opcode_struct.mode),
-                    OpCodeName::ROR => self.ror(opcode_struct.mode),
-                    OpCodeName::RTI => self.rti(),
-                    OpCodeName::RTS => self.rts(),
-                    OpCodeName::SBC => self.sbc(opcode_struct.mode),
-                    OpCodeName::SEC => self.sec(),
-                    OpCodeName::SED => self.sed(),
-                    OpCodeName::SEI => self.sei(),
-                    OpCodeName::STA => self.sta(opcode_struct.mode),
-                    OpCodeName::STX => self.stx(opcode_struct.mode),
-                    OpCodeName::STY => self.sty(opcode_struct.mode),
-                    OpCodeName::TAX => self.tax(),
-                    OpCodeName::TAY => self.tay(),
-                    OpCodeName::TSX => self.tsx(),
-                    OpCodeName::TXA => self.txa(),
-                    OpCodeName::TXS => self.txs(),
-                    OpCodeName::TYA => self.tya(),
-                }
-                // move PC to the next instruction to process
-                self.program_counter = self
-                    .program_counter
-                    .wrapping_add((opcode_struct.len - 1) as u16);
-            } else {
-                panic!(
-                    "Illegal instruction {} reached at address {:#x}",
-                    opcode, self.program_counter
-                )
-            }
+        while self.step() {
             callback(self);
         }
     }
@@ -381,22 +427,20 @@ impl CPU {
 
 impl Mem for CPU {
     #[inline]
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.bus.mem_read(addr)
-    }
-
-    #[inline]
-    fn mem_write(&mut self, addr: u16, data: u8) {
-        self.bus.mem_write(addr, data)
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = self.bus.mem_read(addr);
+        self.record_access(addr, Access::Read, value);
+        value
     }
 
     #[inline]
-    fn mem_read_u16(&self, addr: u16) -> u16 {
-        self.bus.mem_read_u16(addr)
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.bus.mem_peek(addr)
     }
 
     #[inline]
-    fn mem_write_u16(&mut self, addr: u16, data: u16) {
-        self.bus.mem_write_u16(addr, data)
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.record_access(addr, Access::Write, data);
+        self.bus.mem_write(addr, data)
     }
 }
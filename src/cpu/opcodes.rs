@@ -77,7 +77,6 @@ pub(crate) struct OpCode {
     pub mnemonic: OpCodeName,
     /// how many bytes does this OpCode take
     pub len: u8,
-    #[allow(dead_code)]
     /// how many cycles does this OpCode run for
     pub cycles: u8,
     /// the addressing mode of the OpCode (i.e. Immediate, Zero Page, etc.)
@@ -107,6 +106,23 @@ impl OpCode {
     pub fn get(code: u8) -> Option<OpCode> {
         CPU_OPS_CODES.get(&code).cloned()
     }
+
+    /// Given a mnemonic name (i.e. "LDA") and an addressing mode, returns
+    /// the OpCode struct associating with it. This is the reverse lookup
+    /// of `OpCode::get` and is what the assembler uses to pick opcodes
+    pub fn find(mnemonic: &str, mode: AddressingMode) -> Option<OpCode> {
+        CPU_OPS_CODES
+            .values()
+            .find(|opcode| opcode.mode == mode && format!("{:?}", opcode.mnemonic) == mnemonic)
+            .cloned()
+    }
+
+    /// Checks whether any OpCode uses this mnemonic name (i.e. "LDA")
+    pub fn exists(mnemonic: &str) -> bool {
+        CPU_OPS_CODES
+            .values()
+            .any(|opcode| format!("{:?}", opcode.mnemonic) == mnemonic)
+    }
 }
 
 /// Contains all CPU op codes in a compile time hashmap
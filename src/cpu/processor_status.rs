@@ -24,6 +24,8 @@ pub(crate) const CARRY_BIT: u8 = 0b0000_0001;
 pub(crate) const ZERO_BIT: u8 = 0b0000_0010;
 pub(crate) const INTERRUPT_DISABLE_BIT: u8 = 0b0000_0100;
 pub(crate) const DECIMAL_BIT: u8 = 0b0000_1000;
+pub(crate) const BREAK_BIT: u8 = 0b0001_0000;
+pub(crate) const UNUSED_BIT: u8 = 0b0010_0000;
 pub(crate) const OVERFLOW_BIT: u8 = 0b0100_0000;
 pub(crate) const NEGATIVE_BIT: u8 = 0b1000_0000;
 
@@ -0,0 +1,825 @@
+//! Contains an interactive command line debugger (a "monitor") for the CPU.
+//!
+//! It supports breakpoints on the PC, read/write watchpoints on addresses
+//! (I/O registers included), stepping into/over/out of subroutines, register,
+//! memory and disassembly views, and running until the next NMI or frame.
+//!
+//! Everything the debugger shows is read through `Mem::mem_peek`, so looking
+//! at memory never disturbs the machine (i.e. dumping 0x2002 won't clear the
+//! VBlank flag or the PPU's w latch the way a CPU read would).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::Mem;
+use crate::bus::{Access, BusAccess, mirror_down};
+use crate::cheats::{Cheat, load_cht, save_cht};
+use crate::cpu::CPU;
+use crate::cpu::processor_status::{BREAK_BIT, ProcessorStatus};
+use crate::disasm::{DisasmLine, Symbols, disassemble_one};
+use crate::filter::{NtscFilter, NtscPreset};
+use crate::palette::Palette;
+use crate::ramsearch::{CompareTo, Comparison, RamSearch, ValueSize};
+use crate::screenshot::Screenshot;
+
+// opcodes the debugger needs to recognize to step over/out of subroutines
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+/// Which kinds of memory accesses a watchpoint triggers on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+/// Why the debugger handed control back to the user
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// the requested number of instructions were executed
+    Step,
+    /// the PC reached a breakpoint
+    Breakpoint(u16),
+    /// a watched address was accessed
+    Watchpoint(BusAccess),
+    /// the current subroutine returned (step over/out)
+    Return,
+    /// the CPU started servicing an NMI
+    Nmi,
+    /// a new frame started
+    Frame(u64),
+    /// the CPU reached a BRK instruction
+    Brk,
+    /// an execution hook asked the CPU to stop
+    Hook,
+    /// the CPU reached an opcode it doesn't know: (address, opcode)
+    IllegalOpcode(u16, u8),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "Stepped"),
+            StopReason::Breakpoint(addr) => write!(f, "Breakpoint at ${addr:04X}"),
+            StopReason::Watchpoint(hit) => {
+                let access = match hit.access {
+                    Access::Read => "read from",
+                    Access::Write => "write to",
+                };
+                write!(
+                    f,
+                    "Watchpoint: {access} ${:04X} (value ${:02X})",
+                    hit.addr, hit.value
+                )
+            }
+            StopReason::Return => write!(f, "Returned"),
+            StopReason::Nmi => write!(f, "NMI"),
+            StopReason::Frame(frame) => write!(f, "Frame {frame} started"),
+            StopReason::Brk => write!(f, "BRK reached"),
+            StopReason::Hook => write!(f, "Stopped by a hook"),
+            StopReason::IllegalOpcode(addr, opcode) => {
+                write!(f, "Illegal opcode ${opcode:02X} at ${addr:04X}")
+            }
+        }
+    }
+}
+
+/// What happened during a single step, handed to the stop conditions
+struct Executed {
+    /// the opcode that was executed (meaningless if `nmi` is set)
+    opcode: u8,
+    /// whether the step serviced an NMI instead of executing an instruction
+    nmi: bool,
+    /// the frame the PPU was on before the step
+    frame: u64,
+}
+
+const HELP: &str = "\
+break <addr>          (b)  set a breakpoint on the PC
+delete <addr>         (d)  remove a breakpoint
+watch <addr> [r|w|rw] (w)  stop on reads and/or writes of an address
+unwatch <addr>             remove a watchpoint
+list                  (l)  list breakpoints and watchpoints
+step [n]              (s)  execute n instructions, stepping into subroutines
+next                  (n)  execute one instruction, stepping over subroutines
+finish                (f)  run until the current subroutine returns
+continue              (c)  run until a breakpoint, watchpoint, BRK or illegal
+                           opcode
+nmi                        run until the next NMI is serviced
+frame                      run until the next frame starts
+regs                  (r)  show the registers and flags
+ppu                        show the PPU registers and timing
+screenshot <file> [frame]  save the last frame drawn as a PNG (running until
+                           the given frame is drawn first)
+palette <name|file>        take screenshots with a built-in palette, ntsc (a
+                           generated one) or a .pal file
+filter <name|off>          take screenshots through the NTSC filter (composite,
+                           svideo or rgb), or not
+mem <addr> [len]      (m)  hex dump memory
+dis [addr] [n]        (u)  disassemble around the PC (or from an address)
+cheat <code>               add a Game Genie code or RAM freeze (AAAA:VV)
+cheat on|off|del <n>       turn a cheat on or off, or delete it
+cheat load|save <file>     load or save the cheats in an FCEUX .cht file
+cheats                     list the cheats
+disk [eject|<side>]        show which side of the Famicom Disk System's disk is
+                           in the drive, eject it, or insert a side (from 0)
+search start [8|16] [u|s]  start a RAM search (8 or 16 bit, unsigned or signed)
+search <op> [value|by <n>] keep the candidates whose value compares with op
+                           (= != < > <= >=) to the last search, a value, or
+                           the last search plus n (also: changed, unchanged)
+search list [n]            list the first n (default 20) search candidates
+help                  (h)  show this message
+quit                  (q)  exit the debugger
+Addresses are hex ($ or 0x prefixes are optional) or labels. An empty line
+repeats the last command.";
+
+pub struct Debugger {
+    /// the machine being debugged
+    pub cpu: CPU,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeMap<u16, WatchKind>,
+    symbols: Symbols,
+    last_command: String,
+    ram_search: Option<RamSearch>,
+    /// the palette screenshots are taken with
+    palette: Palette,
+    /// the NTSC filter screenshots are taken through, if any
+    filter: Option<NtscFilter>,
+}
+
+impl Debugger {
+    /// Instantiates a debugger around a CPU that already has its program loaded
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            symbols: Symbols::new(),
+            last_command: String::new(),
+            ram_search: None,
+            palette: Palette::default(),
+            filter: None,
+        }
+    }
+
+    /// Uses these labels in disassembly and for address arguments
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Takes screenshots with this palette
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Takes screenshots through this NTSC filter (or with the palette when
+    /// there's none)
+    pub fn set_filter(&mut self, filter: Option<NtscFilter>) {
+        self.filter = filter;
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Watches an address. Mirrored addresses are matched against the address
+    /// they mirror, so watching 0x2002 (or 0x3FFA) catches reads from both
+    pub fn add_watchpoint(&mut self, addr: u16, kind: WatchKind) {
+        self.watchpoints.insert(mirror_down(addr), kind);
+        self.cpu.bus.record_accesses(true);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        let removed = self.watchpoints.remove(&mirror_down(addr)).is_some();
+        self.cpu.bus.record_accesses(!self.watchpoints.is_empty());
+        removed
+    }
+
+    /// Executes instructions until `done` asks to stop (checked after every
+    /// step), or until a breakpoint, watchpoint, BRK or illegal opcode is
+    /// hit. A breakpoint on the instruction we start on is ignored so we can
+    /// resume from it
+    fn run_until<F>(&mut self, mut done: F) -> StopReason
+    where
+        F: FnMut(&CPU, &Executed) -> Option<StopReason>,
+    {
+        let mut first = true;
+        loop {
+            let pc = self.cpu.program_counter;
+            if !first && self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+            first = false;
+
+            let executed = Executed {
+                opcode: self.cpu.mem_peek(pc),
+                nmi: self.cpu.bus.nmi_pending(),
+                frame: self.cpu.bus.ppu().frame(),
+            };
+            match self.cpu.try_step() {
+                Ok(true) => {}
+                Ok(false) if self.cpu.stopped_by_hook() => return StopReason::Hook,
+                Ok(false) => return StopReason::Brk,
+                Err(opcode) => {
+                    return StopReason::IllegalOpcode(self.cpu.program_counter, opcode);
+                }
+            }
+
+            if !self.watchpoints.is_empty() {
+                let hit = self.cpu.bus.take_accesses().into_iter().find(|hit| {
+                    self.watchpoints
+                        .get(&hit.addr)
+                        .is_some_and(|kind| kind.matches(hit.access))
+                });
+                if let Some(hit) = hit {
+                    return StopReason::Watchpoint(hit);
+                }
+            }
+
+            if let Some(reason) = done(&self.cpu, &executed) {
+                return reason;
+            }
+        }
+    }
+
+    /// Executes a single instruction, stepping into subroutines
+    pub fn step(&mut self) -> StopReason {
+        self.run_until(|_, _| Some(StopReason::Step))
+    }
+
+    /// Executes a single instruction, running a whole subroutine if the
+    /// instruction is a JSR
+    pub fn step_over(&mut self) -> StopReason {
+        let pc = self.cpu.program_counter;
+        if self.cpu.mem_peek(pc) != JSR {
+            return self.step();
+        }
+
+        let return_addr = pc.wrapping_add(3);
+        let stack_pointer = self.cpu.stack_pointer;
+        self.run_until(|cpu, _| {
+            // checking the stack pointer makes sure a recursive call
+            // returning to the same address doesn't stop us early
+            (cpu.program_counter == return_addr && cpu.stack_pointer >= stack_pointer)
+                .then_some(StopReason::Return)
+        })
+    }
+
+    /// Runs until the current subroutine (or interrupt handler) returns
+    pub fn step_out(&mut self) -> StopReason {
+        let stack_pointer = self.cpu.stack_pointer;
+        self.run_until(|cpu, executed| {
+            let returned = !executed.nmi && matches!(executed.opcode, RTS | RTI);
+            (returned && cpu.stack_pointer > stack_pointer).then_some(StopReason::Return)
+        })
+    }
+
+    /// Runs until a breakpoint, watchpoint, BRK or illegal opcode is hit
+    pub fn resume(&mut self) -> StopReason {
+        self.run_until(|_, _| None)
+    }
+
+    /// Same as `resume`, but gives control back with `StopReason::Step` after
+    /// at most `max_steps` instructions. This lets frontends (like the GDB
+    /// stub) check for user interrupts while a program is running, and is
+    /// what stepping several instructions at once does
+    pub fn resume_for(&mut self, max_steps: usize) -> StopReason {
+        let mut steps = 0;
+        self.run_until(|_, _| {
+            steps += 1;
+            (steps >= max_steps).then_some(StopReason::Step)
+        })
+    }
+
+    /// Runs until the CPU starts servicing the next NMI
+    pub fn run_until_nmi(&mut self) -> StopReason {
+        self.run_until(|_, executed| executed.nmi.then_some(StopReason::Nmi))
+    }
+
+    /// Runs until the PPU starts the next frame
+    pub fn run_until_frame(&mut self) -> StopReason {
+        self.run_until(|cpu, executed| {
+            let frame = cpu.bus.ppu().frame();
+            (frame != executed.frame).then_some(StopReason::Frame(frame))
+        })
+    }
+
+    /// Formats the registers and processor status flags. Flags that are
+    /// set are shown in upper case
+    pub fn registers(&self) -> String {
+        let cpu = &self.cpu;
+        let flag = |set: bool, name: char| {
+            if set { name } else { name.to_ascii_lowercase() }
+        };
+        let flags = [
+            flag(cpu.is_status_flag_set(ProcessorStatus::Negative), 'N'),
+            flag(cpu.is_status_flag_set(ProcessorStatus::Overflow), 'V'),
+            '-',
+            flag(cpu.status & BREAK_BIT != 0, 'B'),
+            flag(cpu.is_status_flag_set(ProcessorStatus::Decimal), 'D'),
+            flag(
+                cpu.is_status_flag_set(ProcessorStatus::InterruptDisable),
+                'I',
+            ),
+            flag(cpu.is_status_flag_set(ProcessorStatus::Zero), 'Z'),
+            flag(cpu.is_status_flag_set(ProcessorStatus::Carry), 'C'),
+        ]
+        .iter()
+        .collect::<String>();
+
+        format!(
+            "A=${:02X} X=${:02X} Y=${:02X} SP=${:02X} P=${:02X} [{flags}] PC=${:04X} CYC={}",
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.stack_pointer,
+            cpu.status,
+            cpu.program_counter,
+            cpu.bus.cycles(),
+        )
+    }
+
+    /// Formats the state of the PPU
+    pub fn ppu_state(&self) -> String {
+        let ppu = self.cpu.bus.ppu();
+        let (scroll_x, scroll_y) = ppu.scroll();
+        format!(
+            "CTRL=${:02X} MASK=${:02X} STATUS=${:02X} ADDR=${:04X} SCROLL=({scroll_x},{scroll_y}) \
+             SCANLINE={} DOT={} FRAME={} ({}, {:.2} fps)",
+            ppu.ctrl(),
+            ppu.mask(),
+            ppu.peek_status(),
+            ppu.vram_addr(),
+            ppu.scanline(),
+            ppu.dot(),
+            ppu.frame(),
+            ppu.region(),
+            ppu.region().frame_rate(),
+        )
+    }
+
+    /// Hex dumps `len` bytes of memory starting at `addr`, 16 bytes per row
+    pub fn dump_memory(&self, addr: u16, len: usize) -> String {
+        let mut rows = Vec::new();
+        for row_start in (0..len).step_by(16) {
+            let row_addr = addr.wrapping_add(row_start as u16);
+            let bytes = (row_start..len.min(row_start + 16))
+                .map(|offset| {
+                    let value = self.cpu.mem_peek(addr.wrapping_add(offset as u16));
+                    format!("{value:02X}")
+                })
+                .collect::<Vec<_>>();
+            rows.push(format!("{row_addr:04X}: {}", bytes.join(" ")));
+        }
+        rows.join("\n")
+    }
+
+    fn disassemble_at(&self, addr: u16) -> DisasmLine {
+        let bytes = (0..3)
+            .map(|offset| self.cpu.mem_peek(addr.wrapping_add(offset)))
+            .collect::<Vec<_>>();
+        disassemble_one(&bytes, addr, Some(&self.symbols))
+    }
+
+    /// Disassembles `count` instructions starting at `addr`
+    pub fn disassemble(&self, addr: u16, count: usize) -> String {
+        let mut addr = addr;
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let line = self.disassemble_at(addr);
+            addr = addr.wrapping_add(line.bytes.len() as u16);
+            lines.push(self.format_line(&line));
+        }
+        lines.join("\n")
+    }
+
+    /// Disassembles a few instructions before the PC and `count` instructions
+    /// starting at the PC.
+    ///
+    /// Since 6502 instructions have different lengths, we can't just decode
+    /// backwards. Instead we try starting a few bytes before the PC and keep
+    /// the earliest start whose instructions line up exactly with the PC
+    pub fn disassemble_around_pc(&self, count: usize) -> String {
+        let pc = self.cpu.program_counter;
+        let mut before = Vec::new();
+        for back in (1..=9u16).rev() {
+            let mut addr = pc.wrapping_sub(back);
+            let mut lines = Vec::new();
+            while addr != pc && lines.len() < back as usize {
+                let line = self.disassemble_at(addr);
+                addr = addr.wrapping_add(line.bytes.len() as u16);
+                lines.push(line);
+            }
+            if addr == pc && lines.iter().all(|line| !line.text.starts_with(".db")) {
+                before = lines;
+                break;
+            }
+        }
+
+        let skip = before.len().saturating_sub(3);
+        let mut output = before[skip..]
+            .iter()
+            .map(|line| self.format_line(line))
+            .collect::<Vec<_>>();
+        output.push(self.disassemble(pc, count));
+        output.join("\n")
+    }
+
+    /// Marks the current instruction with `>` and breakpoints with `*`
+    fn format_line(&self, line: &DisasmLine) -> String {
+        let pc_marker = if line.addr == self.cpu.program_counter {
+            '>'
+        } else {
+            ' '
+        };
+        let break_marker = if self.breakpoints.contains(&line.addr) {
+            '*'
+        } else {
+            ' '
+        };
+        let label = match self.symbols.get(line.addr) {
+            Some(label) => format!("{label}:\n"),
+            None => String::new(),
+        };
+        format!("{label}{pc_marker}{break_marker}{line}")
+    }
+
+    /// Parses an address argument: a label or hex (with an optional `$`
+    /// or `0x` prefix)
+    fn parse_addr(&self, arg: Option<&str>) -> Result<u16, String> {
+        let arg = arg.ok_or("Missing address")?;
+        if let Some(addr) = self.symbols.addr_of(arg) {
+            return Ok(addr);
+        }
+        let hex = arg
+            .strip_prefix('$')
+            .or_else(|| arg.strip_prefix("0x"))
+            .unwrap_or(arg);
+        u16::from_str_radix(hex, 16).map_err(|_| format!("Invalid address {arg}"))
+    }
+
+    fn parse_count(arg: Option<&str>, default: usize) -> Result<usize, String> {
+        match arg {
+            Some(arg) => arg.parse().map_err(|_| format!("Invalid count {arg}")),
+            None => Ok(default),
+        }
+    }
+
+    fn list_cheats(&self) -> String {
+        self.cpu
+            .bus
+            .cheats()
+            .iter()
+            .enumerate()
+            .map(|(index, cheat)| format!("{index}: {cheat}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Handles the subcommands of `cheat`
+    fn cheat_command(&mut self, arg1: Option<&str>, arg2: Option<&str>) -> Result<String, String> {
+        let index = || Self::parse_count(Some(arg2.ok_or("Missing cheat number")?), 0);
+        match arg1.ok_or("Missing cheat code")? {
+            command @ ("on" | "off") => {
+                let index = index()?;
+                if self.cpu.bus.set_cheat_enabled(index, command == "on") {
+                    Ok(format!("Cheat {index} turned {command}"))
+                } else {
+                    Err(format!("No cheat {index}"))
+                }
+            }
+            "del" => {
+                let index = index()?;
+                let cheat = self
+                    .cpu
+                    .bus
+                    .remove_cheat(index)
+                    .ok_or(format!("No cheat {index}"))?;
+                Ok(format!("Deleted {}", cheat.name))
+            }
+            "load" => {
+                let cheats = load_cht(arg2.ok_or("Missing file")?)?;
+                let count = cheats.len();
+                for cheat in cheats {
+                    self.cpu.bus.add_cheat(cheat);
+                }
+                Ok(format!("Loaded {count} cheats"))
+            }
+            "save" => {
+                save_cht(arg2.ok_or("Missing file")?, self.cpu.bus.cheats())?;
+                Ok(format!("Saved {} cheats", self.cpu.bus.cheats().len()))
+            }
+            code => {
+                let cheat = Cheat::parse(code)?;
+                let text = cheat.to_string();
+                let index = self.cpu.bus.add_cheat(cheat);
+                Ok(format!("{index}: {text}"))
+            }
+        }
+    }
+
+    /// Handles the subcommands of `search`
+    fn search_command(
+        &mut self,
+        arg1: Option<&str>,
+        arg2: Option<&str>,
+        arg3: Option<&str>,
+    ) -> Result<String, String> {
+        let (comparison, compare_to) = match arg1.ok_or("Missing search command")? {
+            "start" => {
+                let mut size = ValueSize::Byte;
+                let mut signed = false;
+                for arg in [arg2, arg3].into_iter().flatten() {
+                    match arg {
+                        "8" => size = ValueSize::Byte,
+                        "16" => size = ValueSize::Word,
+                        "u" => signed = false,
+                        "s" => signed = true,
+                        _ => return Err(format!("Unknown search option {arg}")),
+                    }
+                }
+                let search = RamSearch::new(&self.cpu.bus, size, signed);
+                let count = search.candidates().len();
+                self.ram_search = Some(search);
+                return Ok(format!("{count} candidates"));
+            }
+            "list" => {
+                let search = self.ram_search.as_ref().ok_or("No search started")?;
+                let count = Self::parse_count(arg2, 20)?;
+                let lines = search
+                    .candidates()
+                    .iter()
+                    .take(count)
+                    .map(|&addr| format!("${addr:04X}: {}", search.value(addr).unwrap_or(0)))
+                    .collect::<Vec<_>>();
+                return Ok(lines.join("\n"));
+            }
+            "changed" => (Comparison::NotEqual, CompareTo::Previous),
+            "unchanged" => (Comparison::Equal, CompareTo::Previous),
+            op => {
+                let comparison = match op {
+                    "=" | "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    ">" => Comparison::Greater,
+                    "<=" => Comparison::LessOrEqual,
+                    ">=" => Comparison::GreaterOrEqual,
+                    _ => return Err(format!("Unknown comparison {op}")),
+                };
+                let compare_to = match (arg2, arg3) {
+                    (None, _) => CompareTo::Previous,
+                    (Some("by"), delta) => {
+                        CompareTo::ChangedBy(Self::parse_value(delta.ok_or("Missing change")?)?)
+                    }
+                    (Some(value), _) => CompareTo::Value(Self::parse_value(value)?),
+                };
+                (comparison, compare_to)
+            }
+        };
+        let search = self.ram_search.as_mut().ok_or("No search started")?;
+        let count = search.filter(&self.cpu.bus, comparison, compare_to);
+        Ok(format!("{count} candidates"))
+    }
+
+    /// Parses a (possibly negative) decimal value, or a hex one with a `$` or
+    /// `0x` prefix
+    fn parse_value(arg: &str) -> Result<i32, String> {
+        let (negative, digits) = match arg.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, arg),
+        };
+        let value = match digits
+            .strip_prefix('$')
+            .or_else(|| digits.strip_prefix("0x"))
+        {
+            Some(hex) => i32::from_str_radix(hex, 16),
+            None => digits.parse(),
+        }
+        .map_err(|_| format!("Invalid value {arg}"))?;
+        Ok(if negative { -value } else { value })
+    }
+
+    /// Handles `screenshot`, running until the frame asked for has been
+    /// drawn first (if it hasn't been yet)
+    fn screenshot_command(
+        &mut self,
+        path: Option<&str>,
+        frame: Option<&str>,
+    ) -> Result<String, String> {
+        let path = path.ok_or("Missing file")?;
+        if let Some(frame) = frame {
+            let frame: u64 = frame
+                .parse()
+                .map_err(|_| format!("Invalid frame {frame}"))?;
+            while self.cpu.bus.ppu().frame() < frame {
+                let reason = self.run_until_frame();
+                if !matches!(reason, StopReason::Frame(_)) {
+                    return Ok(self.stopped(reason));
+                }
+            }
+        }
+        let screenshot = match &self.filter {
+            Some(filter) => Screenshot::capture_ntsc(&self.cpu, filter),
+            None => Screenshot::capture(&self.cpu, &self.palette),
+        };
+        screenshot.save(path)?;
+        Ok(format!("Saved frame {} to {path}", screenshot.frame))
+    }
+
+    /// Handles `filter`
+    fn filter_command(&mut self, name: Option<&str>) -> Result<String, String> {
+        match name.ok_or("Missing filter")? {
+            "off" => {
+                self.filter = None;
+                Ok("NTSC filter off".to_string())
+            }
+            name => {
+                self.filter = Some(NtscFilter::new(NtscPreset::parse(name)?, 2)?);
+                Ok(format!("Using the {name} NTSC filter"))
+            }
+        }
+    }
+
+    fn disk_command(&mut self, arg: Option<&str>) -> Result<String, String> {
+        match arg {
+            None => {}
+            Some("eject") => self.cpu.bus.eject_disk()?,
+            Some(side) => {
+                let side = side.parse().map_err(|_| format!("Invalid side {side}"))?;
+                self.cpu.bus.insert_disk(side)?;
+            }
+        }
+        let sides = self.cpu.bus.disk_sides();
+        if sides == 0 {
+            return Err("The cartridge isn't a Famicom Disk System".to_string());
+        }
+        Ok(match (arg, self.cpu.bus.disk_side()) {
+            (_, Some(side)) => format!("Side {side} of {sides} is in the drive"),
+            (Some("eject") | None, None) => "The drive is empty".to_string(),
+            (Some(side), None) => format!("Side {side} goes in in a second"),
+        })
+    }
+
+    /// Reports why we stopped along with the instruction we stopped on
+    fn stopped(&self, reason: StopReason) -> String {
+        let line = self.disassemble_at(self.cpu.program_counter);
+        format!("{reason}\n{}", self.format_line(&line))
+    }
+
+    /// Executes a single debugger command and returns its output, or `None`
+    /// if the user asked to quit
+    pub fn execute(&mut self, command: &str) -> Option<String> {
+        let command = if command.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            command.trim().to_string()
+        };
+        self.last_command = command.clone();
+
+        let mut args = command.split_whitespace();
+        let name = args.next().unwrap_or_default();
+        let arg1 = args.next();
+        let arg2 = args.next();
+
+        let output = match name {
+            "" => Ok(String::new()),
+            "quit" | "q" => return None,
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" => self.parse_addr(arg1).map(|addr| {
+                self.add_breakpoint(addr);
+                format!("Breakpoint set at ${addr:04X}")
+            }),
+            "delete" | "d" => self.parse_addr(arg1).and_then(|addr| {
+                if self.remove_breakpoint(addr) {
+                    Ok(format!("Breakpoint at ${addr:04X} removed"))
+                } else {
+                    Err(format!("No breakpoint at ${addr:04X}"))
+                }
+            }),
+            "watch" | "w" => {
+                let kind = match arg2.unwrap_or("rw") {
+                    "r" => Ok(WatchKind::Read),
+                    "w" => Ok(WatchKind::Write),
+                    "rw" => Ok(WatchKind::ReadWrite),
+                    kind => Err(format!("Unknown watch kind {kind} (expected r, w or rw)")),
+                };
+                kind.and_then(|kind| {
+                    let addr = self.parse_addr(arg1)?;
+                    self.add_watchpoint(addr, kind);
+                    Ok(format!("Watchpoint set at ${addr:04X}"))
+                })
+            }
+            "unwatch" => self.parse_addr(arg1).and_then(|addr| {
+                if self.remove_watchpoint(addr) {
+                    Ok(format!("Watchpoint at ${addr:04X} removed"))
+                } else {
+                    Err(format!("No watchpoint at ${addr:04X}"))
+                }
+            }),
+            "list" | "l" => {
+                let mut lines = self
+                    .breakpoints
+                    .iter()
+                    .map(|addr| format!("break ${addr:04X}"))
+                    .collect::<Vec<_>>();
+                lines.extend(
+                    self.watchpoints
+                        .iter()
+                        .map(|(addr, kind)| format!("watch ${addr:04X} {kind:?}")),
+                );
+                Ok(lines.join("\n"))
+            }
+            "step" | "s" => Self::parse_count(arg1, 1).map(|count| {
+                // stepping more than once stops on breakpoints along the way
+                let reason = self.resume_for(count.max(1));
+                self.stopped(reason)
+            }),
+            "next" | "n" => {
+                let reason = self.step_over();
+                Ok(self.stopped(reason))
+            }
+            "finish" | "f" => {
+                let reason = self.step_out();
+                Ok(self.stopped(reason))
+            }
+            "continue" | "c" => {
+                let reason = self.resume();
+                Ok(self.stopped(reason))
+            }
+            "nmi" => {
+                let reason = self.run_until_nmi();
+                Ok(self.stopped(reason))
+            }
+            "frame" => {
+                let reason = self.run_until_frame();
+                Ok(self.stopped(reason))
+            }
+            "regs" | "r" => Ok(self.registers()),
+            "ppu" => Ok(self.ppu_state()),
+            "screenshot" => self.screenshot_command(arg1, arg2),
+            "filter" => self.filter_command(arg1),
+            "palette" => Palette::find(arg1.unwrap_or("default")).map(|palette| {
+                self.palette = palette;
+                format!("Using palette {}", arg1.unwrap_or("default"))
+            }),
+            "mem" | "m" => self.parse_addr(arg1).and_then(|addr| {
+                let len = Self::parse_count(arg2, 64)?;
+                Ok(self.dump_memory(addr, len))
+            }),
+            "cheat" => self.cheat_command(arg1, arg2),
+            "cheats" => Ok(self.list_cheats()),
+            "disk" => self.disk_command(arg1),
+            "search" => self.search_command(arg1, arg2, args.next()),
+            "dis" | "u" => match arg1 {
+                Some(_) => self.parse_addr(arg1).and_then(|addr| {
+                    let count = Self::parse_count(arg2, 10)?;
+                    Ok(self.disassemble(addr, count))
+                }),
+                None => Ok(self.disassemble_around_pc(8)),
+            },
+            _ => Err(format!("Unknown command {name} (try help)")),
+        };
+
+        Some(output.unwrap_or_else(|err| format!("Error: {err}")))
+    }
+
+    /// Reads commands from `input` until it runs out or the user quits,
+    /// writing all output (and prompts) to `output`
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        let line = self.disassemble_at(self.cpu.program_counter);
+        writeln!(output, "{}", self.format_line(&line))?;
+        write!(output, "(nesdb) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            match self.execute(&line?) {
+                Some(text) => {
+                    if !text.is_empty() {
+                        writeln!(output, "{text}")?;
+                    }
+                }
+                None => break,
+            }
+            write!(output, "(nesdb) ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+//! Contains a 6502 disassembler that turns raw bytes (either a byte slice
+//! or a region of memory) back into readable assembly lines of the form:
+//!
+//! ```text
+//! 0600  A9 10     LDA #$10
+//! 0602  85 FE     STA $FE
+//! 0604  D0 FC     BNE $0602
+//! 0606  02        .db $02
+//! ```
+//!
+//! Decoding is driven by the same `OpCode` table the CPU executes from, so
+//! the disassembler always agrees with the CPU on instruction lengths and
+//! addressing modes. Bytes that don't decode to an instruction the CPU knows
+//! about are emitted as `.db` data lines.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::Mem;
+use crate::cdl::CodeDataLog;
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::opcodes::OpCode;
+
+/// A table of labels that the disassembler substitutes in place of raw
+/// addresses in operands (i.e. `JSR $8123` becomes `JSR read_pads`)
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    labels: HashMap<u16, String>,
+}
+
+impl Symbols {
+    /// Instantiates an empty symbol table
+    pub fn new() -> Self {
+        Self {
+            labels: HashMap::new(),
+        }
+    }
+
+    /// Associates a label with an address (overwriting any previous label
+    /// at that address)
+    pub fn insert(&mut self, addr: u16, label: &str) {
+        self.labels.insert(addr, label.to_string());
+    }
+
+    /// Returns the label stored for this address, if there is one
+    pub fn get(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.as_str())
+    }
+
+    /// Returns the address a label points to, if there is one
+    pub fn addr_of(&self, label: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, name)| name.as_str() == label)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Iterates over every (address, label) pair
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.labels
+            .iter()
+            .map(|(addr, label)| (*addr, label.as_str()))
+    }
+
+    /// Parses the contents of a symbol file. Two line formats are accepted:
+    /// * `label = $8000` (or `0x8000` or plain decimal) as written by most assemblers
+    /// * `$8000#label#comment` as written in FCEUX `.nl` files
+    ///
+    /// Empty lines and lines starting with `;` are ignored
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let (addr, label) = if let Some(nl_line) = line.strip_prefix('$')
+                && nl_line.contains('#')
+            {
+                let mut fields = nl_line.split('#');
+                let addr = fields.next().unwrap_or_default();
+                let label = fields.next().unwrap_or_default();
+                (parse_addr(&format!("${addr}")), label.trim())
+            } else if let Some((label, addr)) = line.split_once('=') {
+                (parse_addr(addr.trim()), label.trim())
+            } else {
+                (None, "")
+            };
+
+            match addr {
+                Some(addr) if !label.is_empty() => symbols.insert(addr, label),
+                _ => return Err(format!("Invalid symbol on line {}: {line}", line_no + 1)),
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Reads and parses a symbol file from disk (see `Symbols::parse`)
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+        Self::parse(&text)
+    }
+}
+
+/// Parses an address written as `$C000`, `0xC000` or `49152`
+fn parse_addr(text: &str) -> Option<u16> {
+    if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = text.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// A single disassembled instruction (or data byte)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    /// the address the first byte of this line lives at
+    pub addr: u16,
+    /// the raw bytes making up this line (opcode + operand)
+    pub bytes: Vec<u8>,
+    /// the mnemonic and operand, i.e. `LDA ($10),Y` or `.db $02`
+    pub text: String,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:04X}  {:<8}  {}", self.addr, bytes, self.text)
+    }
+}
+
+/// Formats an address operand, using a label in its place if one exists
+fn fmt_addr(addr: u16, zero_page: bool, symbols: Option<&Symbols>) -> String {
+    match symbols.and_then(|symbols| symbols.get(addr)) {
+        Some(label) => label.to_string(),
+        None if zero_page => format!("${addr:02X}"),
+        None => format!("${addr:04X}"),
+    }
+}
+
+/// Disassembles the single instruction at the start of `bytes`, where
+/// `bytes[0]` lives at `addr`.
+///
+/// If the first byte is not an opcode the CPU knows or the slice ends
+/// before the instruction's operand does, a one byte `.db` line is returned
+/// instead so the caller can move on to the next byte.
+///
+/// Panics if `bytes` is empty
+pub fn disassemble_one(bytes: &[u8], addr: u16, symbols: Option<&Symbols>) -> DisasmLine {
+    let code = bytes[0];
+    let data_line = || DisasmLine {
+        addr,
+        bytes: vec![code],
+        text: format!(".db ${code:02X}"),
+    };
+
+    let opcode = match OpCode::get(code) {
+        Some(opcode) if bytes.len() >= opcode.len as usize => opcode,
+        _ => return data_line(),
+    };
+
+    let operand_bytes = &bytes[1..opcode.len as usize];
+    let byte_operand = operand_bytes.first().copied().unwrap_or_default();
+    let word_operand = match operand_bytes {
+        [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+        _ => byte_operand as u16,
+    };
+
+    let operand = match opcode.mode {
+        AddressingMode::Implicit => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${byte_operand:02X}"),
+        AddressingMode::ZeroPage => fmt_addr(word_operand, true, symbols),
+        AddressingMode::ZeroPageX => format!("{},X", fmt_addr(word_operand, true, symbols)),
+        AddressingMode::ZeroPageY => format!("{},Y", fmt_addr(word_operand, true, symbols)),
+        AddressingMode::Relative => {
+            // branch offsets are relative to the address of the next
+            // instruction (the branch itself is 2 bytes long)
+            let target = addr.wrapping_add(2).wrapping_add(byte_operand as i8 as u16);
+            fmt_addr(target, false, symbols)
+        }
+        AddressingMode::Absolute => fmt_addr(word_operand, false, symbols),
+        AddressingMode::AbsoluteX => format!("{},X", fmt_addr(word_operand, false, symbols)),
+        AddressingMode::AbsoluteY => format!("{},Y", fmt_addr(word_operand, false, symbols)),
+        AddressingMode::Indirect => format!("({})", fmt_addr(word_operand, false, symbols)),
+        AddressingMode::IndirectX => format!("({},X)", fmt_addr(word_operand, true, symbols)),
+        AddressingMode::IndirectY => format!("({}),Y", fmt_addr(word_operand, true, symbols)),
+    };
+
+    let mnemonic = format!("{:?}", opcode.mnemonic);
+    DisasmLine {
+        addr,
+        bytes: bytes[..opcode.len as usize].to_vec(),
+        text: if operand.is_empty() {
+            mnemonic
+        } else {
+            format!("{mnemonic} {operand}")
+        },
+    }
+}
+
+/// Disassembles a whole byte slice, where `bytes[0]` lives at `start_addr`
+pub fn disassemble(bytes: &[u8], start_addr: u16, symbols: Option<&Symbols>) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let line = disassemble_one(
+            &bytes[offset..],
+            start_addr.wrapping_add(offset as u16),
+            symbols,
+        );
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+/// Disassembles the instructions starting within `[start ..= end]` of a
+/// memory mapped space.
+///
+/// The last instruction is allowed to have its operand extend past `end`
+/// so that it is not cut off into `.db` lines.
+pub fn disassemble_range<M: Mem>(
+    mem: &M,
+    start: u16,
+    end: u16,
+    symbols: Option<&Symbols>,
+) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        // fetch the longest possible instruction (3 bytes), stopping at the
+        // end of the address space
+        let bytes = (addr..(addr + 3).min(0x10000))
+            .map(|pos| mem.mem_peek(pos as u16))
+            .collect::<Vec<_>>();
+        let line = disassemble_one(&bytes, addr as u16, symbols);
+        addr += line.bytes.len() as u32;
+        lines.push(line);
+    }
+    lines
+}
+
+/// Disassembles a cartridge's PRG ROM (where `prg[0]` lives at `start_addr`)
+/// using a code/data log to tell code from data. Only bytes logged as code are
+/// decoded as instructions, everything else is emitted as `.db` lines of up to
+/// 8 bytes. With a log recorded by the emulator (rather than loaded from a
+/// file), decoding only starts on bytes that were executed as opcodes
+pub fn disassemble_logged(
+    prg: &[u8],
+    start_addr: u16,
+    log: &CodeDataLog,
+    symbols: Option<&Symbols>,
+) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < prg.len() {
+        let addr = start_addr.wrapping_add(offset as u16);
+        if log.is_code(offset) && !log.is_operand(offset) {
+            let line = disassemble_one(&prg[offset..], addr, symbols);
+            // an instruction running into bytes that weren't executed means
+            // this isn't really an instruction
+            if (1..line.bytes.len()).all(|index| log.is_code(offset + index)) {
+                offset += line.bytes.len();
+                lines.push(line);
+                continue;
+            }
+        }
+
+        let mut bytes = vec![prg[offset]];
+        offset += 1;
+        while offset < prg.len() && bytes.len() < 8 && !log.is_code(offset) {
+            bytes.push(prg[offset]);
+            offset += 1;
+        }
+        let text = bytes
+            .iter()
+            .map(|byte| format!("${byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        lines.push(DisasmLine {
+            addr,
+            bytes,
+            text: format!(".db {text}"),
+        });
+    }
+    lines
+}
@@ -0,0 +1,197 @@
+//! Contains the NTSC filter, which makes frames look the way they did on a
+//! TV. Instead of looking every pixel up in a palette, it recreates the
+//! composite signal the PPU puts out and decodes it like a TV would, which
+//! is where the color fringes on edges and the dot crawl of the real thing
+//! come from.
+//!
+//! The PPU draws a dot every 8 master clock ticks, and the color subcarrier
+//! takes 12 ticks per cycle, so a dot covers two thirds of a cycle and where
+//! a dot starts in the cycle moves around:
+//! * every scanline (341 dots) starts 4 ticks later in the cycle than the one
+//!   above it
+//! * every other frame starts 4 ticks later too (the PPU skips a dot on odd
+//!   frames, so the shift goes back and forth instead of adding up)
+//!
+//! The output has 2 or 3 times the horizontal resolution, since the signal
+//! has more detail than 256 pixels across. Presets pick the connection:
+//! * `Composite`: luma and chroma share the signal, so both are decoded over
+//!   a whole subcarrier cycle, smearing colors across edges
+//! * `SVideo`: luma comes on its own wire and stays sharp, chroma is still
+//!   decoded over a cycle
+//! * `Rgb`: every dot is decoded on its own, with no artifacts at all
+//!
+//! The colors come out of the signal, so the filter doesn't use a palette
+//! (the `NtscSettings` of the palette generator adjust them instead).
+
+use crate::palette::ntsc::{carrier, signal, yiq_to_rgb};
+use crate::palette::{NtscSettings, Rgb};
+
+/// Master clock ticks per dot
+const TICKS_PER_DOT: usize = 8;
+/// Master clock ticks per color subcarrier cycle
+const TICKS_PER_CYCLE: usize = 12;
+/// How much later in the cycle a scanline starts than the one above it
+const SCANLINE_SHIFT: usize = 341 * TICKS_PER_DOT % TICKS_PER_CYCLE;
+/// How much later in the cycle odd frames start
+const FRAME_SHIFT: usize = 4;
+/// How many ticks of the S-Video luma get averaged into an output pixel
+const SVIDEO_LUMA_TICKS: usize = 4;
+/// Number of 9-bit pixel values (palette index and emphasis bits)
+const PIXEL_VALUES: usize = 512;
+
+/// The connection between the console and the TV
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NtscPreset {
+    Composite,
+    SVideo,
+    Rgb,
+}
+
+impl NtscPreset {
+    /// Parses a preset name (composite, svideo or rgb)
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "composite" => Ok(NtscPreset::Composite),
+            "svideo" | "s-video" => Ok(NtscPreset::SVideo),
+            "rgb" => Ok(NtscPreset::Rgb),
+            _ => Err(format!(
+                "Unknown filter {name} (expected composite, svideo or rgb)"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NtscFilter {
+    pub preset: NtscPreset,
+    pub settings: NtscSettings,
+    /// how many output pixels every dot becomes horizontally
+    scale: usize,
+    /// the signal level of every pixel value at every phase of the cycle
+    levels: Vec<[f64; TICKS_PER_CYCLE]>,
+}
+
+impl NtscFilter {
+    /// Instantiates a filter that outputs `scale` (2 or 3) times the width
+    pub fn new(preset: NtscPreset, scale: usize) -> Result<Self, String> {
+        if !(2..=3).contains(&scale) {
+            return Err(format!(
+                "Unsupported filter scale {scale} (expected 2 or 3)"
+            ));
+        }
+        let levels = (0..PIXEL_VALUES as u16)
+            .map(|pixel| std::array::from_fn(|phase| signal(pixel, phase)))
+            .collect();
+        Ok(Self {
+            preset,
+            settings: NtscSettings::default(),
+            scale,
+            levels,
+        })
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// How wide the output of a `width` pixel wide frame is
+    pub fn output_width(&self, width: usize) -> usize {
+        width * self.scale
+    }
+
+    /// Filters a frame of 9-bit pixels (palette index in bits 0 - 5, the
+    /// emphasis bits in 6 - 8), row by row from the top left. The PPU frame
+    /// number decides the phase the frame starts at. Returns the RGB output
+    /// (3 bytes per pixel, `output_width(width)` pixels per row)
+    pub fn filter(&self, pixels: &[u16], width: usize, frame: u64) -> Vec<u8> {
+        let carrier: [(f64, f64); TICKS_PER_CYCLE] =
+            std::array::from_fn(|phase| carrier(phase, &self.settings));
+        // decoding a whole cycle of a dot's own signal gives its plain color
+        let plain: Vec<Rgb> = self
+            .levels
+            .iter()
+            .map(|levels| {
+                let (y, i, q) = decode(levels, &carrier, 0);
+                yiq_to_rgb(y, i, q, &self.settings)
+            })
+            .collect();
+        let luma: Vec<f64> = self
+            .levels
+            .iter()
+            .map(|levels| levels.iter().sum::<f64>() / TICKS_PER_CYCLE as f64)
+            .collect();
+
+        let mut output = Vec::with_capacity(pixels.len() * self.scale * 3);
+        let frame_phase = (frame % 2) as usize * FRAME_SHIFT;
+        for (row, line) in pixels.chunks(width.max(1)).enumerate() {
+            let line_phase = (frame_phase + row * SCANLINE_SHIFT) % TICKS_PER_CYCLE;
+            let value = |pixel: u16| pixel as usize % PIXEL_VALUES;
+            // the signal of the whole scanline, tick by tick
+            let ticks = line.len() * TICKS_PER_DOT;
+            let composite: Vec<f64> = (0..ticks)
+                .map(|tick| {
+                    let pixel = value(line[tick / TICKS_PER_DOT]);
+                    self.levels[pixel][(line_phase + tick) % TICKS_PER_CYCLE]
+                })
+                .collect();
+
+            for x in 0..line.len() * self.scale {
+                if self.preset == NtscPreset::Rgb {
+                    output.extend_from_slice(&plain[value(line[x / self.scale])]);
+                    continue;
+                }
+                // the tick in the middle of the output pixel
+                let center = (2 * x + 1) * TICKS_PER_DOT / (2 * self.scale);
+                let (mut y, i, q) = decode_window(&composite, &carrier, line_phase, center);
+                if self.preset == NtscPreset::SVideo {
+                    // luma doesn't carry any chroma, so it only needs a few
+                    // ticks, and chroma doesn't carry any luma
+                    let luma_at = |tick: usize| luma[value(line[tick / TICKS_PER_DOT])];
+                    let start = center.saturating_sub(SVIDEO_LUMA_TICKS / 2);
+                    let end = (start + SVIDEO_LUMA_TICKS).min(ticks);
+                    y = (start..end).map(luma_at).sum::<f64>() / (end - start) as f64;
+                }
+                output.extend_from_slice(&yiq_to_rgb(y, i, q, &self.settings));
+            }
+        }
+        output
+    }
+}
+
+/// Decodes a cycle of signal that starts at `phase` into luma and chroma
+fn decode(
+    levels: &[f64; TICKS_PER_CYCLE],
+    carrier: &[(f64, f64); TICKS_PER_CYCLE],
+    phase: usize,
+) -> (f64, f64, f64) {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for (tick, level) in levels.iter().enumerate() {
+        let (cos, sin) = carrier[(phase + tick) % TICKS_PER_CYCLE];
+        y += level;
+        i += level * cos;
+        q += level * sin;
+    }
+    let cycle = TICKS_PER_CYCLE as f64;
+    (y / cycle, i / cycle, q / cycle)
+}
+
+/// Decodes the cycle of a scanline's signal centered on a tick (the signal
+/// is black past the ends of the scanline)
+fn decode_window(
+    line: &[f64],
+    carrier: &[(f64, f64); TICKS_PER_CYCLE],
+    line_phase: usize,
+    center: usize,
+) -> (f64, f64, f64) {
+    let start = center as isize - (TICKS_PER_CYCLE / 2) as isize;
+    let levels: [f64; TICKS_PER_CYCLE] = std::array::from_fn(|tick| {
+        let tick = start + tick as isize;
+        if tick < 0 {
+            0.0
+        } else {
+            line.get(tick as usize).copied().unwrap_or(0.0)
+        }
+    });
+    let phase = (line_phase as isize + start).rem_euclid(TICKS_PER_CYCLE as isize) as usize;
+    decode(&levels, carrier, phase)
+}
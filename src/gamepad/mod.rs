@@ -0,0 +1,96 @@
+//! Contains the standard NES controller, which the CPU reads through
+//! 0x4016 (port 1) and 0x4017 (port 2).
+//!
+//! The controller is a shift register:
+//! * writing 1 to 0x4016 (the strobe) makes the controller continuously reload
+//!   the state of its buttons, writing 0 again freezes it
+//! * every read then returns the state of the next button (1 = pressed) in
+//!   the order A, B, Select, Start, Up, Down, Left, Right. Once all 8 are read,
+//!   reads return 1
+//!
+//! The button bits below follow that order, which is also the order the
+//! buttons are kept in by movie files.
+
+use crate::savestate::{StateReader, StateWriter};
+
+pub const BUTTON_A: u8 = 0b0000_0001;
+pub const BUTTON_B: u8 = 0b0000_0010;
+pub const SELECT: u8 = 0b0000_0100;
+pub const START: u8 = 0b0000_1000;
+pub const UP: u8 = 0b0001_0000;
+pub const DOWN: u8 = 0b0010_0000;
+pub const LEFT: u8 = 0b0100_0000;
+pub const RIGHT: u8 = 0b1000_0000;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Gamepad {
+    /// while set, the shift register keeps reloading (reads return A)
+    strobe: bool,
+    /// the button the next read returns
+    button_index: u8,
+    /// which buttons are held down
+    buttons: u8,
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The buttons that are held down
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    /// Holds down exactly these buttons
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    /// Presses or releases a single button (i.e. `gamepad::START`)
+    pub fn set_button(&mut self, button: u8, pressed: bool) {
+        if pressed {
+            self.buttons |= button;
+        } else {
+            self.buttons &= !button;
+        }
+    }
+
+    /// Handles a write to 0x4016
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    /// Handles a read of the controller's port, shifting to the next button
+    pub fn read(&mut self) -> u8 {
+        let value = self.peek();
+        if !self.strobe && self.button_index < 8 {
+            self.button_index += 1;
+        }
+        value
+    }
+
+    /// What a read of the controller's port would return, without shifting
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.buttons >> self.button_index) & 1
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.strobe);
+        state.u8(self.button_index);
+        state.u8(self.buttons);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.strobe = state.bool()?;
+        self.button_index = state.u8()?.min(8);
+        self.buttons = state.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+//! Contains a GDB remote serial protocol (RSP) stub for the CPU, so that
+//! standard debugger front-ends can attach to the emulator over TCP.
+//!
+//! The stub is built on top of the `Debugger`, so breakpoints, watchpoints
+//! and stepping behave exactly like they do in the built-in monitor.
+//!
+//! Registers are exposed in this order (which is also described to the client
+//! through `qXfer:features:read:target.xml`):
+//!
+//! | # | name | size    |
+//! |---|------|---------|
+//! | 0 | a    | 8 bits  |
+//! | 1 | x    | 8 bits  |
+//! | 2 | y    | 8 bits  |
+//! | 3 | sp   | 8 bits  |
+//! | 4 | p    | 8 bits  |
+//! | 5 | pc   | 16 bits |
+//!
+//! Supported packets: `?`, `g`, `G`, `p`, `P`, `m`, `M`, `c`, `s`, `Z0`-`Z4`,
+//! `z0`-`z4`, `k`, `D`, `qSupported`, `qAttached`, `qXfer:features:read` and
+//! `QStartNoAckMode`. Anything else gets the empty "unsupported" reply.
+//! A `0x03` byte sent while the program is running interrupts it.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::Mem;
+use crate::bus::Access;
+use crate::cpu::CPU;
+use crate::debugger::{Debugger, StopReason, WatchKind};
+
+/// How many instructions to run between checks for an interrupt from the client
+const INTERRUPT_POLL_STEPS: usize = 10_000;
+
+/// How many bytes the CPU can address, the most a memory packet can cover
+const ADDRESS_SPACE: usize = 0x10000;
+
+// signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nes-emulator.mos6502">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// The watchpoints GDB asked for on a single address
+#[derive(Debug, Default, Copy, Clone)]
+struct GdbWatch {
+    read: bool,
+    write: bool,
+    access: bool,
+}
+
+/// What the server should do after handling a packet
+enum Reply {
+    /// send this packet back
+    Packet(String),
+    /// resume the program (until it stops, then send the stop reply)
+    Continue,
+    /// the client is done with us, close the connection
+    Close(Option<String>),
+}
+
+pub struct GdbServer {
+    /// the debugger driving the machine
+    pub debugger: Debugger,
+    watches: BTreeMap<u16, GdbWatch>,
+    no_ack: bool,
+}
+
+impl GdbServer {
+    /// Instantiates a server around a CPU that already has its program loaded
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            debugger: Debugger::new(cpu),
+            watches: BTreeMap::new(),
+            no_ack: false,
+        }
+    }
+
+    /// Waits for a single client to connect and serves it until it detaches,
+    /// kills the program or disconnects
+    pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        self.serve_stream(stream)
+    }
+
+    /// Serves a single connected client
+    pub fn serve_stream(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        self.no_ack = false;
+        loop {
+            let packet = match read_packet(&mut stream, self.no_ack)? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+
+            match self.handle_packet(&packet) {
+                Reply::Packet(reply) => write_packet(&mut stream, &reply)?,
+                Reply::Continue => {
+                    let reply = self.run(&mut stream)?;
+                    write_packet(&mut stream, &reply)?;
+                }
+                Reply::Close(reply) => {
+                    if let Some(reply) = reply {
+                        write_packet(&mut stream, &reply)?;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Runs the program until it stops on its own or the client sends an
+    /// interrupt (0x03), returning the stop reply
+    fn run(&mut self, stream: &mut TcpStream) -> io::Result<String> {
+        loop {
+            let reason = self.debugger.resume_for(INTERRUPT_POLL_STEPS);
+            if reason != StopReason::Step {
+                return Ok(self.stop_reply(reason));
+            }
+
+            stream.set_nonblocking(true)?;
+            let mut byte = [0];
+            let polled = stream.read(&mut byte);
+            stream.set_nonblocking(false)?;
+            match polled {
+                Ok(1) if byte[0] == 0x03 => return Ok(format!("S{SIGINT:02x}")),
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Err(err) if err.kind() != ErrorKind::WouldBlock => return Err(err),
+                _ => {}
+            }
+        }
+    }
+
+    /// Builds the stop reply packet for why the program stopped
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Watchpoint(hit) => {
+                let watch = self.watches.get(&hit.addr).copied().unwrap_or_default();
+                let kind = match hit.access {
+                    _ if watch.access => "awatch",
+                    Access::Read => "rwatch",
+                    Access::Write => "watch",
+                };
+                format!("T{SIGTRAP:02x}{kind}:{:04x};", hit.addr)
+            }
+            // the PC is left on the opcode, so GDB shows where it is
+            StopReason::IllegalOpcode(..) => format!("T{SIGILL:02x}"),
+            _ => format!("S{SIGTRAP:02x}"),
+        }
+    }
+
+    /// Tells the debugger about the combined watchpoints GDB set on an address
+    fn update_watch(&mut self, addr: u16, watch: GdbWatch) {
+        let kind = match watch {
+            GdbWatch { access: true, .. }
+            | GdbWatch {
+                read: true,
+                write: true,
+                ..
+            } => Some(WatchKind::ReadWrite),
+            GdbWatch { read: true, .. } => Some(WatchKind::Read),
+            GdbWatch { write: true, .. } => Some(WatchKind::Write),
+            _ => None,
+        };
+        match kind {
+            Some(kind) => {
+                self.watches.insert(addr, watch);
+                self.debugger.add_watchpoint(addr, kind);
+            }
+            None => {
+                self.watches.remove(&addr);
+                self.debugger.remove_watchpoint(addr);
+            }
+        }
+    }
+
+    fn registers(&self) -> [u8; 7] {
+        let cpu = &self.debugger.cpu;
+        let [pc_lo, pc_hi] = cpu.program_counter.to_le_bytes();
+        [
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.stack_pointer,
+            cpu.status,
+            pc_lo,
+            pc_hi,
+        ]
+    }
+
+    /// Sets register number `reg` from its little endian bytes
+    fn set_register(&mut self, reg: usize, bytes: &[u8]) -> Option<()> {
+        let cpu = &mut self.debugger.cpu;
+        match (reg, bytes) {
+            (0, [value]) => cpu.register_a = *value,
+            (1, [value]) => cpu.register_x = *value,
+            (2, [value]) => cpu.register_y = *value,
+            (3, [value]) => cpu.stack_pointer = *value,
+            (4, [value]) => cpu.status = *value,
+            (5, [lo, hi]) => cpu.program_counter = u16::from_le_bytes([*lo, *hi]),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn handle_packet(&mut self, packet: &str) -> Reply {
+        let reply = |text: &str| Reply::Packet(text.to_string());
+        let error = || reply("E01");
+
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        match command {
+            "?" => Reply::Packet(format!("S{SIGTRAP:02x}")),
+            "g" => Reply::Packet(to_hex(&self.registers())),
+            "G" => {
+                let Some(bytes) = from_hex(args).filter(|bytes| bytes.len() == 7) else {
+                    return error();
+                };
+                for (reg, range) in [0..1, 1..2, 2..3, 3..4, 4..5, 5..7].into_iter().enumerate() {
+                    self.set_register(reg, &bytes[range]);
+                }
+                reply("OK")
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(reg @ 0..=4) => Reply::Packet(to_hex(&self.registers()[reg..reg + 1])),
+                Ok(5) => Reply::Packet(to_hex(&self.registers()[5..7])),
+                _ => error(),
+            },
+            "P" => {
+                let set = args.split_once('=').and_then(|(reg, value)| {
+                    let reg = usize::from_str_radix(reg, 16).ok()?;
+                    self.set_register(reg, &from_hex(value)?)
+                });
+                if set.is_some() { reply("OK") } else { error() }
+            }
+            "m" => {
+                let Some((addr, len)) = parse_addr_len(args) else {
+                    return error();
+                };
+                // reading memory is inspection, so it must not disturb the machine
+                let bytes = (0..len)
+                    .map(|offset| self.debugger.cpu.mem_peek(addr.wrapping_add(offset as u16)))
+                    .collect::<Vec<_>>();
+                Reply::Packet(to_hex(&bytes))
+            }
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_addr_len(range)?;
+                    let bytes = from_hex(data).filter(|bytes| bytes.len() == len)?;
+                    for (offset, byte) in bytes.iter().enumerate() {
+                        self.debugger
+                            .cpu
+                            .mem_write(addr.wrapping_add(offset as u16), *byte);
+                    }
+                    Some(())
+                });
+                if write.is_some() {
+                    reply("OK")
+                } else {
+                    error()
+                }
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(addr) => self.debugger.cpu.program_counter = addr,
+                        Err(_) => return error(),
+                    }
+                }
+                if command == "s" {
+                    let reason = self.debugger.step();
+                    Reply::Packet(self.stop_reply(reason))
+                } else {
+                    Reply::Continue
+                }
+            }
+            "Z" | "z" => {
+                let insert = command == "Z";
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let Some((addr, len)) = fields
+                    .next()
+                    .zip(fields.next())
+                    .and_then(|(addr, len)| parse_addr_len(&format!("{addr},{len}")))
+                else {
+                    return error();
+                };
+
+                match kind {
+                    // software and hardware breakpoints are the same thing to us
+                    Some("0") | Some("1") => {
+                        if insert {
+                            self.debugger.add_breakpoint(addr);
+                        } else {
+                            self.debugger.remove_breakpoint(addr);
+                        }
+                    }
+                    Some(kind @ ("2" | "3" | "4")) => {
+                        for offset in 0..len.max(1) {
+                            let addr = addr.wrapping_add(offset as u16);
+                            let mut watch = self.watches.get(&addr).copied().unwrap_or_default();
+                            match kind {
+                                "2" => watch.write = insert,
+                                "3" => watch.read = insert,
+                                _ => watch.access = insert,
+                            }
+                            self.update_watch(addr, watch);
+                        }
+                    }
+                    _ => return reply(""),
+                }
+                reply("OK")
+            }
+            "k" => Reply::Close(None),
+            "D" => Reply::Close(Some("OK".to_string())),
+            "H" => reply("OK"),
+            _ => self.handle_query(packet),
+        }
+    }
+
+    /// Handles the `q` and `Q` general query packets
+    fn handle_query(&mut self, packet: &str) -> Reply {
+        let reply = |text: &str| Reply::Packet(text.to_string());
+        if packet.starts_with("qSupported") {
+            reply("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+")
+        } else if packet == "QStartNoAckMode" {
+            // the OK reply still gets acknowledged, after that nothing does
+            self.no_ack = true;
+            reply("OK")
+        } else if packet == "qAttached" {
+            reply("1")
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = range.split_once(',').and_then(|(offset, len)| {
+                Some((
+                    usize::from_str_radix(offset, 16).ok()?,
+                    usize::from_str_radix(len, 16).ok()?,
+                ))
+            }) else {
+                return reply("E01");
+            };
+            let start = offset.min(TARGET_XML.len());
+            let end = start.saturating_add(len).min(TARGET_XML.len());
+            let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            Reply::Packet(format!("{marker}{}", &TARGET_XML[start..end]))
+        } else {
+            reply("")
+        }
+    }
+}
+
+/// Parses `addr,len` (both hex). Lengths past the size of the 64 KiB
+/// address space are refused
+fn parse_addr_len(args: &str) -> Option<(u16, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    if len > ADDRESS_SPACE {
+        return None;
+    }
+    Some((u16::from_str_radix(addr, 16).ok()?, len))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+/// Sends a packet framed as `$data#checksum`
+fn write_packet(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    write!(stream, "${data}#{:02x}", checksum(data))?;
+    stream.flush()
+}
+
+/// Reads the next packet from the client (acknowledging it unless we're
+/// in no-ack mode). Acks from the client and stray interrupts are skipped.
+/// Returns `None` once the client disconnects
+fn read_packet(stream: &mut TcpStream, no_ack: bool) -> io::Result<Option<String>> {
+    let mut byte = [0];
+    loop {
+        // wait for the start of a packet
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut data = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum)?;
+        let data = String::from_utf8_lossy(&data).to_string();
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            == Some(checksum(&data));
+
+        if !no_ack {
+            stream.write_all(if valid { b"+" } else { b"-" })?;
+        }
+        if valid {
+            return Ok(Some(data));
+        }
+    }
+}
@@ -1,11 +1,43 @@
+pub mod apu;
+pub mod asm;
 pub mod bus;
+pub mod cdl;
+pub mod cheats;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod filter;
+pub mod gamepad;
+pub mod gdb;
+pub mod hash;
+pub mod mapper;
+pub mod movie;
+pub mod nsf;
+pub mod palette;
+pub mod ppu;
+pub mod ramsearch;
+pub mod region;
+pub mod rom;
+pub mod savestate;
+pub mod screenshot;
+pub mod verify;
+pub mod wav;
+pub mod zip;
 
 /// A trait implementation to perform 8 bit or 16 bit read and write operations
 /// in memory mapped space
 pub trait Mem {
     /// Returns the value stored at provided memory address
-    fn mem_read(&self, addr: u16) -> u8;
+    ///
+    /// Note that reading certain addresses has side effects on real hardware
+    /// (i.e. reading PPUSTATUS clears the VBlank flag), which is why reads
+    /// need mutable access
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    /// Returns the value a read of the provided memory address would return,
+    /// but without any of the side effects a read has. This is what tools
+    /// like the debugger use to inspect memory without disturbing the machine
+    fn mem_peek(&self, addr: u16) -> u8;
 
     /// Writes 8 bit of data into a specific memory address
     ///
@@ -20,7 +52,7 @@ pub trait Mem {
     /// In other words, our MSB comes from pos + 1, LSB comes from pos
     /// and we need to merge these together
     #[inline]
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
         let hi = self.mem_read(pos.wrapping_add(1)) as u16;
         (hi << 8) | lo
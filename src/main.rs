@@ -1,3 +1,599 @@
+use nes_emulator::apu::SAMPLE_RATE;
+use nes_emulator::asm::assemble;
+use nes_emulator::bus::Bus;
+use nes_emulator::cdl::CodeDataLog;
+use nes_emulator::cheats::load_cht;
+use nes_emulator::cpu::CPU;
+use nes_emulator::cpu::hooks::HookAction;
+use nes_emulator::debugger::Debugger;
+use nes_emulator::disasm::{Symbols, disassemble_logged};
+use nes_emulator::filter::{NtscFilter, NtscPreset};
+use nes_emulator::gdb::GdbServer;
+use nes_emulator::movie::{Movie, MoviePlayer, MovieRecorder};
+use nes_emulator::nsf::Nsf;
+use nes_emulator::nsf::player::NsfPlayer;
+use nes_emulator::palette::Palette;
+use nes_emulator::region::Region;
+use nes_emulator::rom::fds::DiskImage;
+use nes_emulator::rom::{self, Rom, ips};
+use nes_emulator::screenshot::Screenshot;
+use nes_emulator::verify::{HashLog, hash_movie};
+use nes_emulator::wav;
+
+/// Where the Famicom Disk System's BIOS is looked for without --bios
+const DEFAULT_BIOS: &str = "disksys.rom";
+
+const USAGE: &str = "\
+Usage: nes_emulator [options] <program>
+
+Loads a raw 6502 program (or .asm source) into memory, a .nes or .unf
+cartridge or a Famicom Disk System .fds image (or the first of them in a .zip
+archive), and runs it until BRK. What gets written to a
+disk is saved next to it as an IPS patch (<image>.sav), and so is a
+cartridge's battery backed RAM (<rom>.sav). For .nsf and .nsfe
+music rips it lists the songs, or renders one to a WAV file with --wav.
+
+Options:
+  --debug            start the interactive debugger instead of running
+  --org <addr>       hex address to load the program at (default 0600, or the
+                     .org of an .asm source)
+  --symbols <file>   symbol file with labels for the debugger
+  --gdb <port>       wait for a GDB client on localhost:<port> instead of running
+  --frames <n>       stop after running n frames
+  --cdl <file>       log how the cartridge's ROM gets used to an FCEUX .cdl file
+                     (an existing log is continued)
+  --disasm           disassemble the cartridge's PRG ROM using the --cdl log
+                     instead of running
+  --cheats <file>    apply the cheats in an FCEUX .cht file
+  --play <file>      play back an FCEUX .fm2 or BizHawk .bk2 movie (stops when
+                     it ends)
+  --record <file>    record an FCEUX .fm2 movie from power on
+  --verify           play the --play movie twice and check that every frame
+                     ends in the same state (CPU, RAM and frame buffer)
+  --save-hashes <file>
+                     save the state at the end of every frame of the --play
+                     movie to a hash log
+  --check-hashes <file>
+                     check playing the --play movie against a saved hash log
+  --screenshot <n> <file>
+                     save frame n as a PNG screenshot
+  --palette <name>   palette for screenshots: default, 2c02, fceux,
+                     nestopia-yuv, ntsc (generated) or a .pal file
+  --filter <name>    run screenshots through the NTSC filter (at twice the
+                     width): composite, svideo or rgb
+  --region <name>    the TV system to run as: ntsc, pal, dendy or auto (the
+                     default, what the ROM's header or the NSF says)
+  --patch <file>     soft patch the program with an IPS, UPS or BPS patch (in
+                     memory, nothing gets written)
+  --no-db            take the .nes header as it is, instead of correcting it
+                     with the game database
+  --bios <file>      the Famicom Disk System's BIOS for .fds images (default
+                     disksys.rom)
+  --track <n>        the song of an NSF to render (from 1, default the one the
+                     tune starts with)
+  --wav <file>       render the --track song of an NSF to a WAV file
+  --length <seconds> how long to render it for (default the length the NSFe
+                     gives it, or 150 seconds), before fading out
+  --help             show this message";
+
+/// Command line options
+struct Args {
+    program: String,
+    debug: bool,
+    org: Option<u16>,
+    symbols: Option<String>,
+    gdb: Option<u16>,
+    frames: Option<u64>,
+    cdl: Option<String>,
+    disasm: bool,
+    cheats: Option<String>,
+    play: Option<String>,
+    record: Option<String>,
+    verify: bool,
+    save_hashes: Option<String>,
+    check_hashes: Option<String>,
+    screenshot: Option<(u64, String)>,
+    palette: Option<String>,
+    filter: Option<NtscPreset>,
+    /// None to go with what the ROM says
+    region: Option<Region>,
+    patch: Option<String>,
+    no_db: bool,
+    bios: Option<String>,
+    track: Option<u8>,
+    wav: Option<String>,
+    length: Option<u32>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut program = None;
+    let mut debug = false;
+    let mut org = None;
+    let mut symbols = None;
+    let mut gdb = None;
+    let mut frames = None;
+    let mut cdl = None;
+    let mut disasm = false;
+    let mut cheats = None;
+    let mut play = None;
+    let mut record = None;
+    let mut verify = false;
+    let mut save_hashes = None;
+    let mut check_hashes = None;
+    let mut screenshot = None;
+    let mut palette = None;
+    let mut filter = None;
+    let mut region = None;
+    let mut patch = None;
+    let mut no_db = false;
+    let mut bios = None;
+    let mut track = None;
+    let mut wav = None;
+    let mut length = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--org" => {
+                let addr = args.next().ok_or("--org needs an address")?;
+                let hex = addr.trim_start_matches('$').trim_start_matches("0x");
+                org = Some(
+                    u16::from_str_radix(hex, 16).map_err(|_| format!("Invalid address {addr}"))?,
+                );
+            }
+            "--symbols" => symbols = Some(args.next().ok_or("--symbols needs a file")?),
+            "--gdb" => {
+                let port = args.next().ok_or("--gdb needs a port")?;
+                gdb = Some(port.parse().map_err(|_| format!("Invalid port {port}"))?);
+            }
+            "--frames" => {
+                let count = args.next().ok_or("--frames needs a number")?;
+                frames = Some(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid frame count {count}"))?,
+                );
+            }
+            "--cdl" => cdl = Some(args.next().ok_or("--cdl needs a file")?),
+            "--disasm" => disasm = true,
+            "--cheats" => cheats = Some(args.next().ok_or("--cheats needs a file")?),
+            "--play" => play = Some(args.next().ok_or("--play needs a file")?),
+            "--record" => record = Some(args.next().ok_or("--record needs a file")?),
+            "--verify" => verify = true,
+            "--save-hashes" => save_hashes = Some(args.next().ok_or("--save-hashes needs a file")?),
+            "--check-hashes" => {
+                check_hashes = Some(args.next().ok_or("--check-hashes needs a file")?);
+            }
+            "--screenshot" => {
+                let frame = args.next().ok_or("--screenshot needs a frame and a file")?;
+                let frame = frame
+                    .parse()
+                    .map_err(|_| format!("Invalid frame {frame}"))?;
+                let path = args.next().ok_or("--screenshot needs a frame and a file")?;
+                screenshot = Some((frame, path));
+            }
+            "--palette" => palette = Some(args.next().ok_or("--palette needs a name or file")?),
+            "--filter" => {
+                let name = args.next().ok_or("--filter needs a name")?;
+                filter = Some(NtscPreset::parse(&name)?);
+            }
+            "--region" => {
+                let name = args.next().ok_or("--region needs a name")?;
+                region = match name.as_str() {
+                    "auto" => None,
+                    name => Some(Region::parse(name)?),
+                };
+            }
+            "--patch" => patch = Some(args.next().ok_or("--patch needs a file")?),
+            "--no-db" => no_db = true,
+            "--bios" => bios = Some(args.next().ok_or("--bios needs a file")?),
+            "--track" => {
+                let number = args.next().ok_or("--track needs a number")?;
+                track = Some(
+                    number
+                        .parse()
+                        .map_err(|_| format!("Invalid track {number}"))?,
+                );
+            }
+            "--wav" => wav = Some(args.next().ok_or("--wav needs a file")?),
+            "--length" => {
+                let seconds = args.next().ok_or("--length needs a number of seconds")?;
+                length = Some(
+                    seconds
+                        .parse()
+                        .map_err(|_| format!("Invalid length {seconds}"))?,
+                );
+            }
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}\n\n{USAGE}")),
+            _ => program = Some(arg),
+        }
+    }
+
+    Ok(Args {
+        program: program.ok_or(USAGE)?,
+        debug,
+        org,
+        symbols,
+        gdb,
+        frames,
+        cdl,
+        disasm,
+        cheats,
+        play,
+        record,
+        verify,
+        save_hashes,
+        check_hashes,
+        screenshot,
+        palette,
+        filter,
+        region,
+        patch,
+        no_db,
+        bios,
+        track,
+        wav,
+        length,
+    })
+}
+
+/// Where what's written to a disk image, or a cartridge's battery backed
+/// RAM, is kept
+fn save_path(image: &str) -> std::path::PathBuf {
+    std::path::Path::new(image).with_extension("sav")
+}
+
+/// Saves what was written to the disk as an IPS patch, unless nothing was
+fn save_disk(cpu: &CPU, path: &std::path::Path) -> Result<(), String> {
+    let patch = cpu.bus.disk_diff()?;
+    if ips::is_empty(&patch) && !path.exists() {
+        return Ok(());
+    }
+    std::fs::write(path, &patch).map_err(|err| format!("{}: {err}", path.display()))?;
+    eprintln!("{}: saved the disk", path.display());
+    Ok(())
+}
+
+/// Saves the cartridge's battery backed RAM, if it has a battery
+fn save_battery_ram(cpu: &CPU, path: &std::path::Path) -> Result<(), String> {
+    let Some(ram) = cpu.bus.battery_ram() else {
+        return Ok(());
+    };
+    std::fs::write(path, ram).map_err(|err| format!("{}: {err}", path.display()))?;
+    eprintln!("{}: saved the battery backed RAM", path.display());
+    Ok(())
+}
+
+/// Reads the program (out of its ZIP archive) with the --patch applied,
+/// along with the name it goes by in lower case (the one in the archive for
+/// ZIP archives)
+fn read_program(args: &Args) -> Result<(String, Vec<u8>), String> {
+    let (name, raw) = rom::read_file(&args.program)?;
+    let raw = match &args.patch {
+        Some(path) => {
+            let patch = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+            rom::apply_patch(&raw, &patch).map_err(|err| format!("{path}: {err}"))?
+        }
+        None => raw,
+    };
+    Ok((name.to_ascii_lowercase(), raw))
+}
+
+/// Lists the songs of an NSF tune, or renders one of them to a WAV file
+fn play_nsf(args: &Args, raw: &[u8]) -> Result<(), String> {
+    let mut player = NsfPlayer::new(Nsf::new(raw)?);
+    if let Some(region) = args.region {
+        player.set_region(region);
+    }
+    let song = match args.track {
+        Some(track) => track.checked_sub(1).ok_or("Tracks count from 1")?,
+        None => player.song(),
+    };
+    player.start(song)?;
+    let nsf = player.nsf().clone();
+
+    let Some(path) = &args.wav else {
+        println!("{} - {} ({})", nsf.title, nsf.artist, nsf.copyright);
+        for song in 0..nsf.songs {
+            let (length, _) = nsf.track_length(song);
+            println!(
+                "{:3}. {} ({}:{:02})",
+                song as u16 + 1,
+                nsf.track_name(song),
+                length / 60_000,
+                length / 1000 % 60
+            );
+        }
+        return Ok(());
+    };
+    let (length, fade) = match args.length {
+        Some(seconds) => (seconds * 1000, nsf.track_length(song).1),
+        None => nsf.track_length(song),
+    };
+    let samples = player.render(length, fade);
+    wav::save(path, &samples, SAMPLE_RATE as u32)?;
+    eprintln!("{path}: rendered {}", nsf.track_name(song));
+    Ok(())
+}
+
+/// Prints the disassembly of a cartridge's PRG ROM, telling code from data
+/// with a code/data log
+fn disassemble_rom(rom: &Rom, cdl: Option<&str>, symbols: &Symbols) -> Result<(), String> {
+    let path = cdl.ok_or("--disasm needs a --cdl log to tell code from data")?;
+    let log = CodeDataLog::load(path, rom.prg_rom.len())?;
+    // 16 KiB ROMs are mirrored, but code refers to the upper copy since
+    // that's where the vectors are
+    let start = if rom.prg_rom.len() <= 0x4000 {
+        0xC000
+    } else {
+        0x8000
+    };
+    for line in disassemble_logged(&rom.prg_rom, start, &log, Some(symbols)) {
+        if let Some(label) = symbols.get(line.addr) {
+            println!("{label}:");
+        }
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Plays a movie (twice, for --verify) on fresh machines and compares the
+/// state at the end of every frame, between both runs or with a hash log
+fn verify_movie(args: &Args, rom: &Rom, movie: Movie) -> Result<(), String> {
+    let machine = || -> Result<CPU, String> {
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom.clone())?);
+        cpu.reset();
+        Ok(cpu)
+    };
+    let log = hash_movie(&mut machine()?, movie.clone())?;
+
+    let mut expected = Vec::new();
+    if args.verify {
+        expected.push(("the first run".to_string(), log.clone()));
+    }
+    if let Some(path) = &args.check_hashes {
+        expected.push((path.clone(), HashLog::load(path)?));
+    }
+    let actual = if args.verify {
+        hash_movie(&mut machine()?, movie)?
+    } else {
+        log.clone()
+    };
+    for (name, expected) in expected {
+        if let Some(divergence) = expected.compare(&actual) {
+            return Err(format!("{divergence} compared to {name}"));
+        }
+        eprintln!("{} frames match {name}", actual.frames.len());
+    }
+
+    if let Some(path) = &args.save_hashes {
+        log.save(path)?;
+        eprintln!("{path}: saved the state of {} frames", log.frames.len());
+    }
+    Ok(())
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let (name, raw) = read_program(&args)?;
+    if name.ends_with(".nsf") || name.ends_with(".nsfe") {
+        return play_nsf(&args, &raw);
+    }
+    let palette = match &args.palette {
+        Some(name) => Palette::find(name)?,
+        None => Palette::default(),
+    };
+    let filter = match args.filter {
+        Some(preset) => Some(NtscFilter::new(preset, 2)?),
+        None => None,
+    };
+    let mut symbols = match &args.symbols {
+        Some(path) => Symbols::load(path)?,
+        None => Symbols::new(),
+    };
+
+    let disk = name.ends_with(".fds");
+    let unif = name.ends_with(".unf") || name.ends_with(".unif");
+    let (mut cpu, movie) = if name.ends_with(".nes") || disk || unif {
+        let mut rom = if disk {
+            let path = args.bios.as_deref().unwrap_or(DEFAULT_BIOS);
+            let bios = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+            Rom::from_fds(DiskImage::new(&raw)?, &bios)?
+        } else if unif {
+            Rom::from_unif(&raw)?
+        } else {
+            Rom::parse(&raw, !args.no_db)?
+        };
+        if args.region.is_some() {
+            rom.region = args.region;
+        }
+        if args.disasm {
+            return disassemble_rom(&rom, args.cdl.as_deref(), &symbols);
+        }
+        // BizHawk movies identify the ROM differently, so they're checked
+        // against it while importing
+        let movie = match &args.play {
+            Some(path) if path.ends_with(".bk2") => Some(Movie::load_bk2(path, &rom)?),
+            Some(path) => Some(Movie::load(path)?),
+            None => None,
+        };
+        if args.verify || args.save_hashes.is_some() || args.check_hashes.is_some() {
+            let movie =
+                movie.ok_or("--verify, --save-hashes and --check-hashes need a --play movie")?;
+            return verify_movie(&args, &rom, movie);
+        }
+        let prg_len = rom.prg_rom.len();
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom)?);
+        if let Some(path) = &args.cdl
+            && std::path::Path::new(path).exists()
+        {
+            let log = CodeDataLog::load(path, prg_len)?;
+            cpu.bus.set_code_data_log(Some(log));
+        }
+        let save = save_path(&args.program);
+        if save.exists() {
+            let data = std::fs::read(&save).map_err(|err| format!("{}: {err}", save.display()))?;
+            if disk {
+                cpu.bus.apply_disk_diff(&data)?;
+            } else if cpu.bus.battery_ram().is_some() {
+                cpu.bus
+                    .load_battery_ram(&data)
+                    .map_err(|err| format!("{}: {err}", save.display()))?;
+            }
+        }
+        (cpu, movie)
+    } else {
+        if args.cdl.is_some()
+            || args.disasm
+            || args.play.is_some()
+            || args.record.is_some()
+            || args.verify
+            || args.save_hashes.is_some()
+            || args.check_hashes.is_some()
+        {
+            return Err(
+                "--cdl, --disasm, --play, --record and the movie checks need a cartridge"
+                    .to_string(),
+            );
+        }
+        let (origin, program) = if name.ends_with(".asm") {
+            let source = String::from_utf8(raw)
+                .map_err(|_| format!("{}: stream did not contain valid UTF-8", args.program))?;
+            let assembled = assemble(&source)?;
+            for (addr, label) in assembled.symbols.iter() {
+                symbols.insert(addr, label);
+            }
+            (args.org.unwrap_or(assembled.origin), assembled.bytes)
+        } else {
+            (args.org.unwrap_or(0x0600), raw)
+        };
+        let mut cpu = CPU::new();
+        cpu.bus.set_region(args.region.unwrap_or_default());
+        cpu.load_at(origin, &program);
+        (cpu, None)
+    };
+    cpu.reset();
+
+    if let Some(path) = &args.cheats {
+        for cheat in load_cht(path)? {
+            cpu.bus.add_cheat(cheat);
+        }
+    }
+    if args.cdl.is_some() {
+        cpu.bus.start_code_data_log();
+    }
+    let player = match movie {
+        Some(movie) => Some(MoviePlayer::start(&mut cpu, movie)?),
+        None => None,
+    };
+    let recorder = match &args.record {
+        Some(_) if player.is_some() => {
+            return Err("Can't play and record a movie at the same time".to_string());
+        }
+        Some(_) => {
+            let rom_filename = std::path::Path::new(&args.program)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            Some(MovieRecorder::start(&mut cpu, &rom_filename, true)?)
+        }
+        None => None,
+    };
+    if let Some((frame, path)) = args.screenshot.clone() {
+        let palette = palette.clone();
+        let filter = filter.clone();
+        cpu.add_frame_hook(move |cpu, new_frame| {
+            if new_frame != frame {
+                return HookAction::Continue;
+            }
+            let screenshot = match &filter {
+                Some(filter) => Screenshot::capture_ntsc(cpu, filter),
+                None => Screenshot::capture(cpu, &palette),
+            };
+            match screenshot.save(&path) {
+                Ok(()) => {
+                    eprintln!("{path}: saved frame {frame}");
+                    HookAction::Continue
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    HookAction::Stop
+                }
+            }
+        });
+    }
+    if let Some(frames) = args.frames {
+        cpu.add_frame_hook(move |_, frame| {
+            if frame >= frames {
+                HookAction::Stop
+            } else {
+                HookAction::Continue
+            }
+        });
+    }
+
+    let mut cpu = if let Some(port) = args.gdb {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+            .map_err(|err| format!("Can't listen on port {port}: {err}"))?;
+        eprintln!("Waiting for GDB on 127.0.0.1:{port}");
+        let mut server = GdbServer::new(cpu);
+        server.debugger.set_symbols(symbols);
+        server.serve(&listener).map_err(|err| err.to_string())?;
+        server.debugger.cpu
+    } else if args.debug {
+        let mut debugger = Debugger::new(cpu);
+        debugger.set_symbols(symbols);
+        debugger.set_palette(palette);
+        debugger.set_filter(filter);
+        debugger
+            .repl(std::io::stdin().lock(), std::io::stdout())
+            .map_err(|err| err.to_string())?;
+        debugger.cpu
+    } else {
+        cpu.run();
+        println!(
+            "A=${:02X} X=${:02X} Y=${:02X} SP=${:02X} P=${:02X} PC=${:04X}",
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.stack_pointer,
+            cpu.status,
+            cpu.program_counter
+        );
+        cpu
+    };
+
+    if let Some(player) = player
+        && !player.is_finished()
+    {
+        eprintln!("Movie stopped at frame {}", player.frame());
+    }
+    if let Some(path) = &args.record
+        && let Some(recorder) = recorder
+    {
+        let movie = recorder.finish(&mut cpu);
+        movie.save(path)?;
+        eprintln!("{path}: recorded {} frames", movie.frames.len());
+    }
+    if let Some(path) = &args.cdl
+        && let Some(log) = cpu.bus.code_data_log()
+    {
+        log.save(path)?;
+        let (code, data, unused) = log.prg_coverage();
+        eprintln!("{path}: {code} bytes of code, {data} bytes of data, {unused} bytes not used");
+    }
+    if disk {
+        save_disk(&cpu, &save_path(&args.program))?;
+    } else {
+        save_battery_ram(&cpu, &save_path(&args.program))?;
+    }
+    Ok(())
+}
+
 pub fn main() {
-    println!("Hello world");
+    if let Err(err) = parse_args().and_then(run) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,405 @@
+//! Contains the boards built out of discrete logic chips (a latch or two
+//! instead of a mapper ASIC). They switch whole 32 KiB (or 16 KiB) PRG
+//! banks and 8 KiB (or 4 KiB) CHR banks through a single register:
+//!
+//! | Mapper | Board             | Register          | PRG bank      | CHR bank      | Bus conflicts |
+//! |--------|-------------------|-------------------|---------------|---------------|---------------|
+//! | 0      | NROM              | none              | fixed         | fixed         | -             |
+//! | 7      | AxROM             | [0x8000 ... ]     | 32K, bits 0-2 | RAM           | submapper 2   |
+//! | 11     | Color Dreams      | [0x8000 ... ]     | 32K, bits 0-1 | 8K, bits 4-7  | yes           |
+//! | 34     | BNROM             | [0x8000 ... ]     | 32K, all bits | RAM           | yes           |
+//! | 34     | NINA-001          | 0x7FFD - 0x7FFF   | 32K, bit 0    | 2 x 4K        | no            |
+//! | 66     | GxROM             | [0x8000 ... ]     | 32K, bits 4-5 | 8K, bits 0-1  | yes           |
+//! | 71     | Camerica BF909x   | [0xC000 ... ]     | 16K, bits 0-3 | RAM           | no            |
+//! | 79     | NINA-03/06        | 0x41xx (A8 set)   | 32K, bit 3    | 8K, bits 0-2  | no            |
+//! | 87     | Jaleco/Konami     | [0x6000 ... ]     | fixed         | 8K, bits 1, 0 | no            |
+//! | 140    | Jaleco JF-11/14   | [0x6000 ... ]     | 32K, bits 4-5 | 8K, bits 0-3  | no            |
+//!
+//! AxROM also picks which KiB of VRAM all 4 name tables show with bit 4, and
+//! so does Fire Hawk's Camerica board (BF9097) with bit 4 of a write to
+//! [0x9000 ... 0x9FFF]. Boards with registers at [0x6000 ... 0x7FFF] have no
+//! PRG RAM (NINA-001 has both, a write to a register lands in RAM too).
+
+use super::{Cartridge, Mapper, PRG_RAM_SIZE};
+use crate::ppu::Mirroring;
+use crate::rom::Rom;
+
+/// NROM (mapper 0): 16 or 32 KiB of PRG ROM (16 KiB is mirrored into both
+/// halves) and 8 KiB of CHR, nothing to switch
+pub(super) struct Nrom {
+    cartridge: Cartridge,
+}
+
+impl Nrom {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn power_cycle(&mut self) {}
+}
+
+/// AxROM (mapper 7): 32 KiB PRG banks and single screen mirroring.
+/// ANROM, AMROM and AOROM have bus conflicts (NES 2.0 submapper 2), AN1ROM
+/// doesn't (submapper 1). An iNES header can't tell them apart, and some
+/// games for the boards without conflicts write values that don't match
+/// ROM, so they're only applied when the submapper asks for them
+pub(super) struct Axrom {
+    cartridge: Cartridge,
+    bus_conflicts: bool,
+}
+
+impl Axrom {
+    pub fn new(rom: &Rom) -> Self {
+        let mut axrom = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+            bus_conflicts: rom.submapper == 2,
+        };
+        axrom.power_cycle();
+        axrom
+    }
+}
+
+impl Mapper for Axrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, _addr: u16, data: u8) {
+        self.cartridge.map_prg_32k((data & 0b111) as usize);
+        self.cartridge.set_mirroring(if data & 0b1_0000 != 0 {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::SingleScreenLower
+        });
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        self.bus_conflicts
+    }
+
+    fn power_cycle(&mut self) {
+        self.write_register(0x8000, 0);
+    }
+}
+
+/// Color Dreams (mapper 11): 32 KiB PRG banks in bits 0 - 1 and 8 KiB CHR
+/// banks in bits 4 - 7
+pub(super) struct ColorDreams {
+    cartridge: Cartridge,
+}
+
+impl ColorDreams {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+        }
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, _addr: u16, data: u8) {
+        self.cartridge.map_prg_32k((data & 0b11) as usize);
+        self.cartridge.map_chr_8k((data >> 4) as usize);
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        true
+    }
+
+    fn power_cycle(&mut self) {
+        self.write_register(0x8000, 0);
+    }
+}
+
+/// BNROM (mapper 34, submapper 2 or CHR RAM): 32 KiB PRG banks, the whole value
+/// written picks one (so oversized ROMs work too)
+pub(super) struct Bnrom {
+    cartridge: Cartridge,
+}
+
+impl Bnrom {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+        }
+    }
+}
+
+impl Mapper for Bnrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, _addr: u16, data: u8) {
+        self.cartridge.map_prg_32k(data as usize);
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        true
+    }
+
+    fn power_cycle(&mut self) {
+        self.write_register(0x8000, 0);
+    }
+}
+
+/// NINA-001 (mapper 34, submapper 1 or more than 8 KiB of CHR ROM):
+/// registers at the top of PRG RAM. 0x7FFD picks the 32 KiB PRG bank, 0x7FFE and 0x7FFF the
+/// 4 KiB CHR banks at 0x0000 and 0x1000
+pub(super) struct Nina001 {
+    cartridge: Cartridge,
+}
+
+impl Nina001 {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+        }
+    }
+}
+
+impl Mapper for Nina001 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.cartridge.write_ram(addr, data);
+        match addr {
+            0x7FFD => self.cartridge.map_prg_32k((data & 1) as usize),
+            0x7FFE => self.cartridge.map_chr_4k(0, (data & 0b1111) as usize),
+            0x7FFF => self.cartridge.map_chr_4k(1, (data & 0b1111) as usize),
+            _ => {}
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        self.cartridge.map_prg_32k(0);
+        self.cartridge.map_chr_8k(0);
+    }
+}
+
+/// GxROM (mapper 66): 32 KiB PRG banks in bits 4 - 5 and 8 KiB CHR banks in
+/// bits 0 - 1
+pub(super) struct Gxrom {
+    cartridge: Cartridge,
+}
+
+impl Gxrom {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+        }
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, _addr: u16, data: u8) {
+        self.cartridge.map_prg_32k((data >> 4 & 0b11) as usize);
+        self.cartridge.map_chr_8k((data & 0b11) as usize);
+    }
+
+    fn bus_conflicts(&self) -> bool {
+        true
+    }
+
+    fn power_cycle(&mut self) {
+        self.write_register(0x8000, 0);
+    }
+}
+
+/// Camerica BF9093/BF9097 (mapper 71): UxROM-like, a 16 KiB PRG bank at
+/// 0x8000 picked through [0xC000 ... 0xFFFF] and the last one fixed at
+/// 0xC000. Fire Hawk's BF9097 adds single screen mirroring through
+/// [0x9000 ... 0x9FFF], which the other games never write to
+pub(super) struct Camerica {
+    cartridge: Cartridge,
+}
+
+impl Camerica {
+    pub fn new(rom: &Rom) -> Self {
+        let mut camerica = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+        };
+        camerica.power_cycle();
+        camerica
+    }
+}
+
+impl Mapper for Camerica {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9000..=0x9FFF => self.cartridge.set_mirroring(if data & 0b1_0000 != 0 {
+                Mirroring::SingleScreenUpper
+            } else {
+                Mirroring::SingleScreenLower
+            }),
+            0xC000..=0xFFFF => self.cartridge.map_prg_16k(0, (data & 0b1111) as usize),
+            _ => {}
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        let last = self.cartridge.prg_bank_count(0x4000) - 1;
+        self.cartridge.map_prg_16k(0, 0);
+        self.cartridge.map_prg_16k(1, last);
+        let mirroring = self.cartridge.header_mirroring;
+        self.cartridge.set_mirroring(mirroring);
+    }
+}
+
+/// NINA-03/06 (mapper 79): a register at every address in
+/// [0x4100 ... 0x5FFF] with A8 set, 32 KiB PRG banks in bit 3 and 8 KiB CHR
+/// banks in bits 0 - 2
+pub(super) struct Nina03 {
+    cartridge: Cartridge,
+}
+
+impl Nina03 {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+        }
+    }
+}
+
+impl Mapper for Nina03 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if addr & 0xE100 == 0x4100 {
+            self.cartridge.map_prg_32k((data >> 3 & 1) as usize);
+            self.cartridge.map_chr_8k((data & 0b111) as usize);
+        } else {
+            self.cartridge.write_ram(addr, data);
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        self.write(0x4100, 0);
+    }
+}
+
+/// Jaleco JF-xx/Konami (mapper 87): fixed PRG ROM like NROM and an 8 KiB CHR
+/// bank picked through [0x6000 ... 0x7FFF], with bits 0 and 1 swapped
+pub(super) struct Mapper87 {
+    cartridge: Cartridge,
+}
+
+impl Mapper87 {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, 0),
+        }
+    }
+}
+
+impl Mapper for Mapper87 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            let bank = (data & 1) << 1 | (data >> 1 & 1);
+            self.cartridge.map_chr_8k(bank as usize);
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        self.cartridge.map_chr_8k(0);
+    }
+}
+
+/// Jaleco JF-11/JF-14 (mapper 140): 32 KiB PRG banks in bits 4 - 5 and 8 KiB
+/// CHR banks in bits 0 - 3, picked through [0x6000 ... 0x7FFF]
+pub(super) struct Mapper140 {
+    cartridge: Cartridge,
+}
+
+impl Mapper140 {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            cartridge: Cartridge::new(rom, 0),
+        }
+    }
+}
+
+impl Mapper for Mapper140 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.cartridge.map_prg_32k((data >> 4 & 0b11) as usize);
+            self.cartridge.map_chr_8k((data & 0b1111) as usize);
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        self.write(0x6000, 0);
+    }
+}
@@ -0,0 +1,537 @@
+//! Contains the Famicom Disk System's RAM adapter (mapper 20), which plugs
+//! into the cartridge slot and connects the disk drive. It has 32 KiB of
+//! PRG RAM at [0x6000 ... 0xDFFF] that games load into from the disk, the
+//! BIOS at [0xE000 ... 0xFFFF], 8 KiB of CHR RAM, a 16 bit timer IRQ and
+//! an audio channel (see the `fds_audio` module).
+//!
+//! The registers:
+//!
+//! | Address         | What                                                  |
+//! |-----------------|-------------------------------------------------------|
+//! | $4020 / $4021   | timer IRQ reload value                                |
+//! | $4022           | timer IRQ: repeat (0), enable (1)                     |
+//! | $4023           | enable the disk registers (0) and the sound (1)       |
+//! | $4024           | the byte to write to the disk                         |
+//! | $4025           | control: motor (0), transfer reset (1), read (2),     |
+//! |                 | mirroring (3), write CRC (4), start transfer (6),     |
+//! |                 | disk IRQ (7)                                          |
+//! | $4030 (read)    | status: timer IRQ (0), byte transferred (1)           |
+//! | $4031 (read)    | the byte read from the disk                           |
+//! | $4032 (read)    | drive: no disk (0), not ready (1), write protect (2)  |
+//! | $4033 (read)    | battery good (7)                                      |
+//! | $4040 - $4092   | audio                                                 |
+//!
+//! The drive reads and writes a byte every 150 CPU cycles while the motor
+//! is on, from the start of the track (see `rom::fds`) to the end, after
+//! which it goes back to the start. When reading, nothing is handed over
+//! until the game starts a transfer and the start mark at the end of a gap
+//! goes by. The CRCs aren't checked (the BIOS only looks at whether the
+//! drive says one was bad, which it never does) and zeros are written in
+//! their place.
+
+use super::fds_audio::FdsAudio;
+use super::{Cartridge, Mapper};
+use crate::ppu::Mirroring;
+use crate::rom::Rom;
+use crate::rom::fds::DiskImage;
+use crate::savestate::{StateReader, StateWriter};
+
+const RAM: u16 = 0x6000;
+const RAM_END: u16 = 0xDFFF;
+const RAM_SIZE: usize = 0x8000;
+const BIOS: u16 = 0xE000;
+
+/// How many CPU cycles the drive takes to read or write a byte
+const BYTE_CYCLES: u32 = 150;
+/// How many CPU cycles the head takes to get back to the start of the disk
+const REWIND_CYCLES: u32 = 50000;
+/// How many CPU cycles a disk stays out of the drive when switching sides,
+/// so the game notices (about a second)
+const SWAP_CYCLES: u32 = 1_789_773;
+/// What `inserted` is saved as while the drive is empty
+const NO_SIDE: u8 = 0xFF;
+
+// $4022 bits
+const TIMER_REPEAT: u8 = 0b01;
+const TIMER_ENABLE: u8 = 0b10;
+
+// $4023 bits
+const DISK_IO: u8 = 0b01;
+const SOUND_IO: u8 = 0b10;
+
+// $4025 bits
+const MOTOR_ON: u8 = 0b0000_0001;
+const TRANSFER_RESET: u8 = 0b0000_0010;
+const READ_MODE: u8 = 0b0000_0100;
+const MIRROR_HORIZONTAL: u8 = 0b0000_1000;
+const WRITE_CRC: u8 = 0b0001_0000;
+const TRANSFER_START: u8 = 0b0100_0000;
+const DISK_IRQ: u8 = 0b1000_0000;
+
+// $4030 bits
+const TIMER_IRQ_FLAG: u8 = 0b01;
+const TRANSFER_FLAG: u8 = 0b10;
+
+// $4032 bits
+const NO_DISK: u8 = 0b001;
+const NOT_READY: u8 = 0b010;
+const WRITE_PROTECTED: u8 = 0b100;
+
+// $4033 bits
+const BATTERY_GOOD: u8 = 0b1000_0000;
+
+/// The disk drive and the disk in it. The sides are kept as tracks, the
+/// way the drive sees them, and turned back into an image (or a diff
+/// against the disk as it was loaded) when asked
+pub(crate) struct DiskDrive {
+    /// the disk as it was loaded (with anything after the last block of a
+    /// side cleared), to diff against
+    original: DiskImage,
+    tracks: Vec<Vec<u8>>,
+    inserted: Option<usize>,
+    /// the side that goes in once `swap_timer` runs out
+    pending: Option<usize>,
+    swap_timer: u32,
+}
+
+impl DiskDrive {
+    fn new(disk: &DiskImage) -> Self {
+        let tracks: Vec<Vec<u8>> = (0..disk.sides.len())
+            .map(|side| disk.to_track(side))
+            .collect();
+        Self {
+            original: DiskImage::from_tracks(&tracks),
+            tracks,
+            inserted: Some(0),
+            pending: None,
+            swap_timer: 0,
+        }
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// The side in the drive (None while it's empty)
+    pub fn inserted(&self) -> Option<usize> {
+        self.inserted
+    }
+
+    pub fn eject(&mut self) {
+        (self.inserted, self.pending) = (None, None);
+    }
+
+    /// Puts `side` in the drive. When another one is in there it's ejected
+    /// first, and the new one goes in a second later
+    pub fn insert(&mut self, side: usize) -> Result<(), String> {
+        if side >= self.side_count() {
+            return Err(format!(
+                "Side {side} doesn't exist, the disk has {} sides",
+                self.side_count()
+            ));
+        }
+        if self.inserted.is_some() {
+            (self.inserted, self.pending) = (None, Some(side));
+            self.swap_timer = SWAP_CYCLES;
+        } else {
+            (self.inserted, self.pending) = (Some(side), None);
+        }
+        Ok(())
+    }
+
+    /// The disk with everything that's been written to it
+    pub fn image(&self) -> DiskImage {
+        DiskImage::from_tracks(&self.tracks)
+    }
+
+    /// An IPS patch of everything that's been written to the disk
+    pub fn diff(&self) -> Result<Vec<u8>, String> {
+        self.image().diff(&self.original)
+    }
+
+    /// Applies a patch made by `diff` to the disk as it was loaded
+    pub fn apply_diff(&mut self, patch: &[u8]) -> Result<(), String> {
+        let disk = self.original.apply_diff(patch)?;
+        if disk.sides.len() != self.side_count() {
+            return Err(format!(
+                "The patch is for a disk with {} sides, not {}",
+                disk.sides.len(),
+                self.side_count()
+            ));
+        }
+        self.tracks = (0..disk.sides.len())
+            .map(|side| disk.to_track(side))
+            .collect();
+        Ok(())
+    }
+
+    fn clock(&mut self) {
+        if let Some(side) = self.pending {
+            self.swap_timer = self.swap_timer.saturating_sub(1);
+            if self.swap_timer == 0 {
+                (self.inserted, self.pending) = (Some(side), None);
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for track in &self.tracks {
+            state.vec(track);
+        }
+        state.u8(self.inserted.map_or(NO_SIDE, |side| side as u8));
+        state.u8(self.pending.map_or(NO_SIDE, |side| side as u8));
+        state.u64(self.swap_timer as u64);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for track in self.tracks.iter_mut() {
+            *track = state.vec(track.len())?;
+        }
+        let side = |value: u8| (value != NO_SIDE).then_some(value as usize);
+        self.inserted = side(state.u8()?);
+        self.pending = side(state.u8()?);
+        if self
+            .inserted
+            .iter()
+            .chain(&self.pending)
+            .any(|&side| side >= self.tracks.len())
+        {
+            return Err("Savestate has a disk side that doesn't exist".to_string());
+        }
+        self.swap_timer = state.u64()? as u32;
+        Ok(())
+    }
+}
+
+/// The Famicom Disk System's RAM adapter (mapper 20)
+pub(super) struct Fds {
+    cartridge: Cartridge,
+    drive: DiskDrive,
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+    /// $4023
+    io_enable: u8,
+    /// $4025
+    control: u8,
+    write_data: u8,
+    read_data: u8,
+    /// whether a byte was read or written since the game last checked
+    transferred: bool,
+    disk_irq: bool,
+    /// where the head is on the track
+    position: usize,
+    /// CPU cycles until the head reaches the next byte
+    delay: u32,
+    /// whether the head has to go back to the start of the disk
+    end_of_head: bool,
+    /// whether the head is moving along the disk
+    scanning: bool,
+    /// whether the start mark of the block being read has gone by
+    gap_ended: bool,
+    audio: FdsAudio,
+}
+
+impl Fds {
+    pub fn new(rom: &Rom, disk: &DiskImage) -> Self {
+        let mut fds = Self {
+            cartridge: Cartridge::new(rom, RAM_SIZE),
+            drive: DiskDrive::new(disk),
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+            io_enable: 0,
+            control: 0,
+            write_data: 0,
+            read_data: 0,
+            transferred: false,
+            disk_irq: false,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            audio: FdsAudio::default(),
+        };
+        fds.power_cycle();
+        fds
+    }
+
+    fn disk_io(&self) -> bool {
+        self.io_enable & DISK_IO != 0
+    }
+
+    fn sound_io(&self) -> bool {
+        self.io_enable & SOUND_IO != 0
+    }
+
+    fn drive_status(&self) -> u8 {
+        let mut status = 0;
+        if self.drive.inserted.is_none() {
+            status |= NO_DISK | WRITE_PROTECTED;
+        }
+        if self.drive.inserted.is_none() || !self.scanning {
+            status |= NOT_READY;
+        }
+        status
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.control = data;
+        self.disk_irq = false;
+        self.cartridge
+            .set_mirroring(if data & MIRROR_HORIZONTAL != 0 {
+                Mirroring::Horizontal
+            } else {
+                Mirroring::Vertical
+            });
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            self.timer_enabled = self.timer_repeat;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    /// Moves the head along the disk by a CPU cycle, reading or writing a
+    /// byte when it gets to one
+    fn clock_disk(&mut self) {
+        let Some(side) = self.drive.inserted else {
+            (self.end_of_head, self.scanning) = (true, false);
+            return;
+        };
+        if self.control & MOTOR_ON == 0 {
+            (self.end_of_head, self.scanning) = (true, false);
+            return;
+        }
+        if self.control & TRANSFER_RESET != 0 && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            (self.end_of_head, self.gap_ended) = (false, false);
+            (self.position, self.delay) = (0, REWIND_CYCLES);
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let track = &mut self.drive.tracks[side];
+        let irq = self.control & DISK_IRQ != 0;
+        if self.control & READ_MODE != 0 {
+            let data = track[self.position];
+            if self.control & TRANSFER_START == 0 {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                // the start mark isn't handed over
+                self.gap_ended = true;
+            } else if self.gap_ended {
+                self.read_data = data;
+                self.transferred = true;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let data = if self.control & WRITE_CRC != 0 {
+                0
+            } else {
+                self.transferred = true;
+                self.disk_irq |= irq;
+                if self.control & TRANSFER_START != 0 {
+                    self.write_data
+                } else {
+                    0
+                }
+            };
+            track[self.position] = data;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= track.len() {
+            // the motor stops at the end of the disk
+            self.control &= !MOTOR_ON;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn power_cycle(&mut self) {
+        (self.timer_reload, self.timer_counter) = (0, 0);
+        (self.timer_repeat, self.timer_enabled, self.timer_irq) = (false, false, false);
+        self.io_enable = DISK_IO | SOUND_IO;
+        self.write_control(0);
+        (self.write_data, self.read_data) = (0, 0);
+        (self.transferred, self.disk_irq) = (false, false);
+        (self.position, self.delay) = (0, 0);
+        (self.end_of_head, self.scanning, self.gap_ended) = (true, false, false);
+        self.audio = FdsAudio::default();
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        let value = self.peek(addr);
+        if self.disk_io() {
+            match addr {
+                0x4030 => {
+                    self.transferred = false;
+                    (self.timer_irq, self.disk_irq) = (false, false);
+                }
+                0x4031 => (self.transferred, self.disk_irq) = (false, false),
+                _ => {}
+            }
+        }
+        value
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4030..=0x4033 if !self.disk_io() => None,
+            0x4030 => {
+                let mut status = 0;
+                if self.timer_irq {
+                    status |= TIMER_IRQ_FLAG;
+                }
+                if self.transferred {
+                    status |= TRANSFER_FLAG;
+                }
+                Some(status)
+            }
+            0x4031 => Some(self.read_data),
+            0x4032 => Some(self.drive_status()),
+            0x4033 => Some(BATTERY_GOOD),
+            0x4040..=0x4097 if self.sound_io() => self.audio.read(addr),
+            RAM..=RAM_END => Some(self.cartridge.prg_ram[(addr - RAM) as usize]),
+            BIOS.. => self.cartridge.read(addr),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | data as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0xFF) | (data as u16) << 8,
+            0x4022 => {
+                self.timer_repeat = data & TIMER_REPEAT != 0;
+                self.timer_enabled = data & TIMER_ENABLE != 0 && self.disk_io();
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.io_enable = data;
+                if !self.disk_io() {
+                    self.timer_enabled = false;
+                    (self.timer_irq, self.disk_irq) = (false, false);
+                }
+            }
+            0x4024..=0x4026 if !self.disk_io() => {}
+            0x4024 => {
+                self.write_data = data;
+                (self.transferred, self.disk_irq) = (false, false);
+            }
+            0x4025 => self.write_control(data),
+            0x4040..=0x4097 if self.sound_io() => self.audio.write(addr, data),
+            RAM..=RAM_END => self.cartridge.prg_ram[(addr - RAM) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        if addr >= BIOS {
+            self.cartridge.prg_offset(addr)
+        } else {
+            None
+        }
+    }
+
+    fn clock(&mut self) {
+        self.drive.clock();
+        self.clock_timer();
+        self.clock_disk();
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn disk_drive(&self) -> Option<&DiskDrive> {
+        Some(&self.drive)
+    }
+
+    fn disk_drive_mut(&mut self) -> Option<&mut DiskDrive> {
+        Some(&mut self.drive)
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        self.drive.save_state(state);
+        state.u16(self.timer_reload);
+        state.u16(self.timer_counter);
+        state.bool(self.timer_repeat);
+        state.bool(self.timer_enabled);
+        state.bool(self.timer_irq);
+        state.u8(self.io_enable);
+        state.u8(self.control);
+        state.u8(self.write_data);
+        state.u8(self.read_data);
+        state.bool(self.transferred);
+        state.bool(self.disk_irq);
+        state.u64(self.position as u64);
+        state.u64(self.delay as u64);
+        state.bool(self.end_of_head);
+        state.bool(self.scanning);
+        state.bool(self.gap_ended);
+        self.audio.save_state(state);
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.drive.load_state(state)?;
+        self.timer_reload = state.u16()?;
+        self.timer_counter = state.u16()?;
+        self.timer_repeat = state.bool()?;
+        self.timer_enabled = state.bool()?;
+        self.timer_irq = state.bool()?;
+        self.io_enable = state.u8()?;
+        self.control = state.u8()?;
+        self.write_data = state.u8()?;
+        self.read_data = state.u8()?;
+        self.transferred = state.bool()?;
+        self.disk_irq = state.bool()?;
+        let track_len = self.drive.tracks.iter().map(Vec::len).min().unwrap_or(0);
+        self.position = (state.u64()? as usize).min(track_len.saturating_sub(1));
+        self.delay = state.u64()? as u32;
+        self.end_of_head = state.bool()?;
+        self.scanning = state.bool()?;
+        self.gap_ended = state.bool()?;
+        self.audio.load_state(state)
+    }
+}
@@ -0,0 +1,372 @@
+//! Contains the Famicom Disk System's audio: a single channel that plays a
+//! 64 step waveform of 6 bit samples the game writes, with a volume
+//! envelope, and a modulator that bends its pitch with a second table of
+//! 64 steps, with an envelope of its own.
+//!
+//! The registers:
+//! * $4040 - $407F: the waveform (writable while $4089 bit 7 is set, which
+//!   holds the channel's output)
+//! * $4080: volume envelope: direct gain (7), increase (6), speed or gain
+//!   (0 - 5)
+//! * $4082 / $4083: 12 bit pitch, plus halt the waveform (7) and the
+//!   envelopes (6) in $4083
+//! * $4084: modulator envelope, like $4080
+//! * $4085: the modulator's 7 bit signed counter
+//! * $4086 / $4087: the modulator's 12 bit pitch, plus halt it (7)
+//! * $4088: the modulation table, two steps at a time while it's halted
+//!   (0: +0, 1: +1, 2: +2, 3: +4, 4: reset, 5: -4, 6: -2, 7: -1)
+//! * $4089: waveform write enable (7), master volume (0 - 1: 2/2, 2/3, 2/4
+//!   or 2/5)
+//! * $408A: how fast the envelopes go (0 stops them)
+//! * $4090 / $4092 (read): the volume and modulator gains
+//!
+//! Both pitches are added to an accumulator every CPU cycle, which moves
+//! its table a step along when it overflows 16 bits.
+
+use crate::savestate::{StateReader, StateWriter};
+
+/// How loud a step of the channel's 0 - 63 output is, so it's about as
+/// loud as on a real Famicom next to the APU
+const VOLUME: f32 = 0.0035;
+/// What each master volume multiplies the output by (out of 36)
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 17, 14];
+/// How far each modulation table entry moves the counter (None resets it)
+const MODULATION_STEPS: [Option<i8>; 8] = [
+    Some(0),
+    Some(1),
+    Some(2),
+    Some(4),
+    None,
+    Some(-4),
+    Some(-2),
+    Some(-1),
+];
+const DEFAULT_ENVELOPE_SPEED: u8 = 0xE8;
+
+// $4080 / $4084 bits
+const DIRECT_GAIN: u8 = 0b1000_0000;
+const INCREASE: u8 = 0b0100_0000;
+
+// $4083 bits
+const HALT_WAVE: u8 = 0b1000_0000;
+const HALT_ENVELOPES: u8 = 0b0100_0000;
+
+// $4087 bits
+const HALT_MODULATOR: u8 = 0b1000_0000;
+
+// $4089 bits
+const WAVE_WRITE: u8 = 0b1000_0000;
+
+/// The envelopes of the volume and the modulator: every 8 * (speed + 1)
+/// * the master speed CPU cycles they move the gain a step up or down
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+    speed: u8,
+    increase: bool,
+    /// whether the gain is set directly instead
+    off: bool,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8, master_speed: u8) {
+        self.speed = value & 0b11_1111;
+        self.increase = value & INCREASE != 0;
+        self.off = value & DIRECT_GAIN != 0;
+        if self.off {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    /// Advances the envelope by a CPU cycle, returning whether its gain
+    /// got a step
+    fn clock(&mut self, master_speed: u8) -> bool {
+        if self.off || master_speed == 0 {
+            return false;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return false;
+        }
+        self.reset_timer(master_speed);
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+        true
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.speed);
+        state.bool(self.increase);
+        state.bool(self.off);
+        state.u8(self.gain);
+        state.u64(self.timer as u64);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.speed = state.u8()? & 0b11_1111;
+        self.increase = state.bool()?;
+        self.off = state.bool()?;
+        self.gain = state.u8()?;
+        self.timer = state.u64()? as u32;
+        Ok(())
+    }
+}
+
+/// FDS audio
+#[derive(Debug, Clone)]
+pub(super) struct FdsAudio {
+    wave: [u8; 64],
+    wave_position: u8,
+    wave_accumulator: u16,
+    pitch: u16,
+    wave_halted: bool,
+    envelopes_halted: bool,
+    wave_write: bool,
+    master_volume: u8,
+    envelope_speed: u8,
+    volume: Envelope,
+    modulator: Envelope,
+    modulation_table: [u8; 64],
+    modulation_position: u8,
+    modulation_accumulator: u16,
+    modulation_pitch: u16,
+    modulator_halted: bool,
+    /// 7 bit signed
+    counter: i8,
+    /// how far the modulator bends the pitch right now
+    modulation: i32,
+    /// the output, which holds while the waveform is being written
+    output: u8,
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        Self {
+            wave: [0; 64],
+            wave_position: 0,
+            wave_accumulator: 0,
+            pitch: 0,
+            wave_halted: true,
+            envelopes_halted: true,
+            wave_write: false,
+            master_volume: 0,
+            envelope_speed: DEFAULT_ENVELOPE_SPEED,
+            volume: Envelope::default(),
+            modulator: Envelope::default(),
+            modulation_table: [0; 64],
+            modulation_position: 0,
+            modulation_accumulator: 0,
+            modulation_pitch: 0,
+            modulator_halted: true,
+            counter: 0,
+            modulation: 0,
+            output: 0,
+        }
+    }
+}
+
+impl FdsAudio {
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            // while the waveform isn't writable it reads where it's playing
+            0x4040..=0x407F if self.wave_write => Some(self.wave[(addr & 0x3F) as usize]),
+            0x4040..=0x407F => Some(self.wave[self.wave_position as usize]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.modulator.gain),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => {
+                self.wave[(addr & 0x3F) as usize] = value & 0b11_1111
+            }
+            0x4080 => self.volume.write(value, self.envelope_speed),
+            0x4082 => self.pitch = (self.pitch & 0xF00) | value as u16,
+            0x4083 => {
+                self.pitch = (self.pitch & 0xFF) | ((value & 0xF) as u16) << 8;
+                self.wave_halted = value & HALT_WAVE != 0;
+                self.envelopes_halted = value & HALT_ENVELOPES != 0;
+                if self.wave_halted {
+                    (self.wave_position, self.wave_accumulator) = (0, 0);
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.envelope_speed);
+                    self.modulator.reset_timer(self.envelope_speed);
+                }
+            }
+            0x4084 => self.modulator.write(value, self.envelope_speed),
+            0x4085 => {
+                self.counter = sign_extend_7(value);
+                self.update_modulation();
+            }
+            0x4086 => self.modulation_pitch = (self.modulation_pitch & 0xF00) | value as u16,
+            0x4087 => {
+                self.modulation_pitch =
+                    (self.modulation_pitch & 0xFF) | ((value & 0xF) as u16) << 8;
+                self.modulator_halted = value & HALT_MODULATOR != 0;
+                if self.modulator_halted {
+                    self.modulation_accumulator = 0;
+                }
+            }
+            0x4088 if self.modulator_halted => {
+                for _ in 0..2 {
+                    self.modulation_table[self.modulation_position as usize] = value & 0b111;
+                    self.modulation_position = (self.modulation_position + 1) % 64;
+                }
+            }
+            0x4089 => {
+                self.wave_write = value & WAVE_WRITE != 0;
+                self.master_volume = value & 0b11;
+            }
+            0x408A => {
+                self.envelope_speed = value;
+                self.volume.reset_timer(value);
+                self.modulator.reset_timer(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn modulator_running(&self) -> bool {
+        !self.modulator_halted && self.modulation_pitch > 0
+    }
+
+    /// Works out how far the counter and the modulator's gain bend the
+    /// pitch, the way the chip does (rounding included, from the NESdev
+    /// wiki)
+    fn update_modulation(&mut self) {
+        let counter = self.counter as i32;
+        let mut bend = counter * self.modulator.gain as i32;
+        let remainder = bend & 0xF;
+        bend >>= 4;
+        if remainder > 0 && bend & 0x80 == 0 {
+            bend += if counter < 0 { -1 } else { 2 };
+        }
+        if bend >= 192 {
+            bend -= 256;
+        } else if bend < -64 {
+            bend += 256;
+        }
+        let mut pitch = self.pitch as i32 * bend;
+        let remainder = pitch & 0x3F;
+        pitch >>= 6;
+        if remainder >= 32 {
+            pitch += 1;
+        }
+        self.modulation = pitch;
+    }
+
+    /// Advances the channel by a CPU cycle
+    pub fn clock(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.clock(self.envelope_speed);
+            if self.modulator.clock(self.envelope_speed) {
+                self.update_modulation();
+            }
+        }
+
+        if self.modulator_running() {
+            let (accumulator, overflow) = self
+                .modulation_accumulator
+                .overflowing_add(self.modulation_pitch);
+            self.modulation_accumulator = accumulator;
+            if overflow {
+                let entry = self.modulation_table[self.modulation_position as usize];
+                self.counter = match MODULATION_STEPS[entry as usize] {
+                    Some(step) => sign_extend_7(self.counter.wrapping_add(step) as u8),
+                    None => 0,
+                };
+                self.modulation_position = (self.modulation_position + 1) % 64;
+                self.update_modulation();
+            }
+        }
+
+        if !self.wave_halted && !self.wave_write {
+            let modulation = if self.modulator_running() {
+                self.modulation
+            } else {
+                0
+            };
+            let pitch = self.pitch as i32 + modulation;
+            if pitch > 0 {
+                let (accumulator, overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+                self.wave_accumulator = accumulator;
+                if overflow {
+                    self.wave_position = (self.wave_position + 1) % 64;
+                }
+            }
+        }
+        if !self.wave_write {
+            let level =
+                self.volume.gain.min(32) as u32 * MASTER_VOLUMES[self.master_volume as usize];
+            self.output = (self.wave[self.wave_position as usize] as u32 * level / 1152) as u8;
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output as f32 * VOLUME
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.wave);
+        state.u8(self.wave_position);
+        state.u16(self.wave_accumulator);
+        state.u16(self.pitch);
+        state.bool(self.wave_halted);
+        state.bool(self.envelopes_halted);
+        state.bool(self.wave_write);
+        state.u8(self.master_volume);
+        state.u8(self.envelope_speed);
+        self.volume.save_state(state);
+        self.modulator.save_state(state);
+        state.bytes(&self.modulation_table);
+        state.u8(self.modulation_position);
+        state.u16(self.modulation_accumulator);
+        state.u16(self.modulation_pitch);
+        state.bool(self.modulator_halted);
+        state.u8(self.counter as u8);
+        state.u8(self.output);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.wave)?;
+        self.wave_position = state.u8()? % 64;
+        self.wave_accumulator = state.u16()?;
+        self.pitch = state.u16()? & 0xFFF;
+        self.wave_halted = state.bool()?;
+        self.envelopes_halted = state.bool()?;
+        self.wave_write = state.bool()?;
+        self.master_volume = state.u8()? & 0b11;
+        self.envelope_speed = state.u8()?;
+        self.volume.load_state(state)?;
+        self.modulator.load_state(state)?;
+        state.bytes(&mut self.modulation_table)?;
+        for entry in self.modulation_table.iter_mut() {
+            *entry &= 0b111;
+        }
+        self.modulation_position = state.u8()? % 64;
+        self.modulation_accumulator = state.u16()?;
+        self.modulation_pitch = state.u16()? & 0xFFF;
+        self.modulator_halted = state.bool()?;
+        self.counter = sign_extend_7(state.u8()?);
+        self.output = state.u8()?;
+        self.update_modulation();
+        Ok(())
+    }
+}
+
+/// Turns the low 7 bits of `value` into a signed number (-64 - 63)
+fn sign_extend_7(value: u8) -> i8 {
+    ((value << 1) as i8) >> 1
+}
@@ -0,0 +1,194 @@
+//! Contains Sunsoft's FME-7 (mapper 69), made for Batman: Return of the
+//! Joker, Gimmick! and a dozen others. It switches four 8 KiB PRG banks (the
+//! one at 0x6000 can be RAM instead) and eight 1 KiB CHR banks, and has a
+//! 16 bit IRQ counter that counts down every CPU cycle. Gimmick!'s version
+//! of the chip, the 5B, adds three channels of audio (see the `sunsoft5b`
+//! module), which is emulated on every board since the games made for the
+//! other versions never write to its ports.
+//!
+//! The banks and the IRQ are written through a command register ($8000)
+//! that picks one of 16 parameters, followed by the value ($A000):
+//! * 0 - 7: the CHR banks
+//! * 8: the bank at 0x6000: RAM enable (7), RAM instead of ROM (6), bank
+//!   (0 - 5)
+//! * 9 - 11: PRG banks at 0x8000 ... 0xC000 (0xE000 is fixed to the last
+//!   bank)
+//! * 12: mirroring (vertical, horizontal, one screen lower, upper)
+//! * 13: IRQ control: count (7), raise the IRQ at the wrap around (0);
+//!   writing it acknowledges the IRQ
+//! * 14 - 15: the counter's low and high byte
+//!
+//! $C000 and $E000 are the audio's address and data ports.
+
+use super::sunsoft5b::Sunsoft5b;
+use super::{Cartridge, Mapper, PRG_BANK_SIZE, PRG_RAM, PRG_RAM_END, PRG_RAM_SIZE};
+use crate::ppu::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+// command 8 bits
+const RAM_ENABLE: u8 = 0b1000_0000;
+const RAM_SELECT: u8 = 0b0100_0000;
+
+// command 13 bits
+const IRQ_COUNT: u8 = 0b1000_0000;
+const IRQ_ENABLE: u8 = 0b0000_0001;
+
+/// FME-7 / 5B (mapper 69)
+pub(super) struct Fme7 {
+    cartridge: Cartridge,
+    command: u8,
+    chr_registers: [u8; 8],
+    /// the banks at 0x6000, 0x8000, 0xA000 and 0xC000
+    prg_registers: [u8; 4],
+    irq_control: u8,
+    irq_counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5b,
+}
+
+impl Fme7 {
+    pub fn new(rom: &Rom) -> Self {
+        let mut fme7 = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+            command: 0,
+            chr_registers: [0; 8],
+            prg_registers: [0; 4],
+            irq_control: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::default(),
+        };
+        fme7.power_cycle();
+        fme7
+    }
+
+    fn update_banks(&mut self) {
+        for (slot, bank) in self.prg_registers[1..].iter().enumerate() {
+            self.cartridge
+                .map_prg(slot, PRG_BANK_SIZE, (bank & 0b11_1111) as usize);
+        }
+        let last = self.cartridge.prg_bank_count(PRG_BANK_SIZE) - 1;
+        self.cartridge.map_prg(3, PRG_BANK_SIZE, last);
+        for (slot, bank) in self.chr_registers.iter().enumerate() {
+            self.cartridge.map_chr(slot, 0x400, *bank as usize);
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0..=7 => self.chr_registers[self.command as usize] = data,
+            8..=11 => self.prg_registers[(self.command - 8) as usize] = data,
+            12 => self.cartridge.set_mirroring(match data & 0b11 {
+                0 => Mirroring::Vertical,
+                1 => Mirroring::Horizontal,
+                2 => Mirroring::SingleScreenLower,
+                _ => Mirroring::SingleScreenUpper,
+            }),
+            13 => {
+                self.irq_control = data;
+                self.irq_pending = false;
+            }
+            14 => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0xFF) | (data as u16) << 8,
+        }
+        self.update_banks();
+    }
+
+    /// Where a CPU address in [0x6000 ... 0x7FFF] lands in PRG ROM, when
+    /// ROM is banked in there
+    fn low_rom_offset(&self, addr: u16) -> usize {
+        let bank = (self.prg_registers[0] & 0b11_1111) as usize;
+        let start = bank % self.cartridge.prg_bank_count(PRG_BANK_SIZE) * PRG_BANK_SIZE;
+        (start + (addr - PRG_RAM) as usize) % self.cartridge.prg_len()
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn power_cycle(&mut self) {
+        self.command = 0;
+        self.chr_registers = [0, 1, 2, 3, 4, 5, 6, 7];
+        self.prg_registers = [0, 0, 1, 2];
+        (self.irq_control, self.irq_counter, self.irq_pending) = (0, 0, false);
+        self.audio = Sunsoft5b::default();
+        self.cartridge
+            .set_mirroring(self.cartridge.header_mirroring);
+        self.update_banks();
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        let low_bank = self.prg_registers[0];
+        match addr {
+            PRG_RAM..=PRG_RAM_END if low_bank & RAM_SELECT == 0 => {
+                let offset = self.low_rom_offset(addr);
+                self.cartridge.prg_rom.get(offset).copied()
+            }
+            // disabled RAM is open bus
+            PRG_RAM..=PRG_RAM_END if low_bank & RAM_ENABLE == 0 => None,
+            _ => self.cartridge.read(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            PRG_RAM..=PRG_RAM_END
+                if self.prg_registers[0] & (RAM_ENABLE | RAM_SELECT) == RAM_ENABLE | RAM_SELECT =>
+            {
+                self.cartridge.write_ram(addr, data);
+            }
+            0x8000..=0x9FFF => self.command = data & 0xF,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.write_address(data),
+            0xE000..=0xFFFF => self.audio.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.irq_control & IRQ_COUNT != 0 {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_control & IRQ_ENABLE != 0 {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        state.u8(self.command);
+        state.bytes(&self.chr_registers);
+        state.bytes(&self.prg_registers);
+        state.u8(self.irq_control);
+        state.u16(self.irq_counter);
+        state.bool(self.irq_pending);
+        self.audio.save_state(state);
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.command = state.u8()? & 0xF;
+        state.bytes(&mut self.chr_registers)?;
+        state.bytes(&mut self.prg_registers)?;
+        self.irq_control = state.u8()?;
+        self.irq_counter = state.u16()?;
+        self.irq_pending = state.bool()?;
+        self.audio.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
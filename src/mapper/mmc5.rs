@@ -0,0 +1,557 @@
+//! Contains MMC5 (mapper 5, ExROM), Nintendo's biggest mapper ASIC. On top
+//! of banking it has:
+//! * 1 KiB of its own RAM (ExRAM), which is either an extra name table, the
+//!   extended attributes (a CHR bank and palette for every background tile),
+//!   plain RAM or read only RAM, picked by $5104
+//! * a fill mode name table (one tile and palette everywhere)
+//! * a vertical split: a column of tiles on the left or right of the screen
+//!   drawn from ExRAM with its own vertical scroll and CHR bank
+//! * a scanline IRQ, which it drives by watching the PPU render (the bus
+//!   tells it about every scanline)
+//! * an 8 x 8 bit multiplier at $5205 / $5206
+//! * two more pulse channels and an 8 bit PCM channel
+//!
+//! The registers:
+//!
+//! | Address         | What                                                  |
+//! |-----------------|-------------------------------------------------------|
+//! | $5000 - $5007   | pulse 1 and 2 (like $4000 - $4007, minus the sweep)   |
+//! | $5010 / $5011   | PCM mode and IRQ / raw PCM                            |
+//! | $5015           | pulse enables and status                              |
+//! | $5100 / $5101   | PRG mode (0 - 3) / CHR mode (0 - 3)                   |
+//! | $5102 / $5103   | PRG RAM protect (writes need 2 and 1)                 |
+//! | $5104           | ExRAM mode                                            |
+//! | $5105           | name table sources (2 bits per name table)            |
+//! | $5106 / $5107   | fill tile / fill palette                              |
+//! | $5113 - $5117   | PRG banks for 0x6000 ... 0xE000 (bit 7 picks ROM)     |
+//! | $5120 - $512B   | CHR banks (A set for sprites, B set for backgrounds)  |
+//! | $5130           | upper CHR bank bits                                   |
+//! | $5200 - $5202   | split control, scroll and CHR bank                    |
+//! | $5203 / $5204   | IRQ scanline / IRQ enable and status                  |
+//! | $5205 / $5206   | multiplier                                            |
+//! | $5C00 - $5FFF   | ExRAM                                                 |
+//!
+//! The B set of CHR banks is only used for the background with 8x16
+//! sprites; with 8x8 sprites the A set is used for everything.
+
+use super::{CHR_BANK_SIZE, Cartridge, Mapper, PRG_BANK_SIZE, PRG_RAM, PRG_RAM_END, PRG_ROM};
+use crate::apu::pulse::Pulse;
+use crate::apu::{mix_pulses, mix_tnd};
+use crate::ppu::{ExtendedVideo, Mirroring, NameTableSource, Split};
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+/// 64 KiB of PRG RAM (the most any board has)
+const PRG_RAM_SIZE: usize = 0x10000;
+/// The expansion audio's frame sequencer steps at 240 Hz, clocking the
+/// envelopes and the length counters
+const FRAME_STEP: u16 = 7457;
+const VISIBLE_SCANLINES: u16 = 240;
+
+const EX_RAM: u16 = 0x5C00;
+const EX_RAM_END: u16 = 0x5FFF;
+
+// $5104 ExRAM modes
+const EX_RAM_NAME_TABLE: u8 = 0;
+const EX_RAM_EXTENDED_ATTRIBUTES: u8 = 1;
+const EX_RAM_READ_ONLY: u8 = 3;
+
+// $5204 bits
+const IRQ_PENDING: u8 = 0b1000_0000;
+const IRQ_IN_FRAME: u8 = 0b0100_0000;
+// $5010 bits
+const PCM_IRQ: u8 = 0b1000_0000;
+const PCM_READ_MODE: u8 = 0b0000_0001;
+// $5200 bits
+const SPLIT_ENABLE: u8 = 0b1000_0000;
+const SPLIT_RIGHT: u8 = 0b0100_0000;
+
+/// MMC5 (mapper 5)
+pub(super) struct Mmc5 {
+    cartridge: Cartridge,
+    prg_mode: u8,
+    chr_mode: u8,
+    ram_protect: [u8; 2],
+    ex_ram_mode: u8,
+    /// $5113 - $5117
+    prg_registers: [u8; 5],
+    /// $5120 - $512B, with the upper bits from $5130 at the time of the write
+    chr_registers: [u16; 12],
+    chr_upper: u8,
+    /// the offset into PRG RAM of the 8 KiB at 0x8000, 0xA000 and 0xC000
+    /// when RAM is banked in there instead of ROM
+    ram_slots: [Option<usize>; 4],
+    name_tables: u8,
+    split_control: u8,
+    split_scroll: u8,
+    split_chr_bank: u8,
+    /// holds ExRAM, and everything the PPU needs to draw with it
+    video: ExtendedVideo,
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline_counter: u8,
+    multiplicand: u8,
+    multiplier: u8,
+    pulses: [Pulse; 2],
+    pcm_control: u8,
+    pcm: u8,
+    pcm_irq: bool,
+    /// CPU cycles since the audio frame sequencer last stepped
+    frame_cycle: u16,
+    cycle: u64,
+}
+
+impl Mmc5 {
+    pub fn new(rom: &Rom) -> Self {
+        let mut mmc5 = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            ex_ram_mode: 0,
+            prg_registers: [0; 5],
+            chr_registers: [0; 12],
+            chr_upper: 0,
+            ram_slots: [None; 4],
+            name_tables: 0,
+            split_control: 0,
+            split_scroll: 0,
+            split_chr_bank: 0,
+            video: ExtendedVideo::default(),
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline_counter: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            pulses: [Pulse::without_sweep(), Pulse::without_sweep()],
+            pcm_control: 0,
+            pcm: 0,
+            pcm_irq: false,
+            frame_cycle: 0,
+            cycle: 0,
+        };
+        mmc5.power_cycle();
+        mmc5
+    }
+
+    /// Banks in what the PRG and CHR registers say
+    fn update_banks(&mut self) {
+        for slot in 0..4 {
+            let (register, bank) = self.prg_bank(slot);
+            // $5117 is always ROM
+            if register == 4 || bank & 0x80 != 0 {
+                self.cartridge
+                    .map_prg(slot, PRG_BANK_SIZE, (bank & 0x7F) as usize);
+                self.ram_slots[slot] = None;
+            } else {
+                self.ram_slots[slot] = Some(self.ram_offset(bank));
+            }
+        }
+
+        let (size, registers) = self.chr_layout(&self.chr_registers[..8]);
+        for (index, &bank) in registers.iter().enumerate() {
+            self.cartridge
+                .map_chr(index * size / CHR_BANK_SIZE, size, bank);
+        }
+        // the B set only covers 4 KiB (or 8 KiB in mode 0), repeated
+        let (size, registers) = self.chr_layout(&self.chr_registers[8..]);
+        let repeat = size.max(0x1000);
+        for slot in 0..8 {
+            let piece = slot * CHR_BANK_SIZE % repeat;
+            self.video.background_chr_banks[slot] = self
+                .cartridge
+                .chr_bank_offset(size, registers[piece / size])
+                + piece % size;
+        }
+        self.video.chr_upper = (self.chr_upper & 0b11) as usize;
+    }
+
+    /// Which of $5113 - $5117 the 8 KiB at 8 KiB `slot` of [0x8000 ...
+    /// 0xFFFF] comes from in the PRG mode, and the 8 KiB bank it picks
+    fn prg_bank(&self, slot: usize) -> (usize, u8) {
+        let register = match (self.prg_mode, slot) {
+            (0, _) => 4,
+            (1, 0..=1) | (2, 0..=1) => 2,
+            (1, _) => 4,
+            (_, slot) => slot + 1,
+        };
+        let value = self.prg_registers[register];
+        // bigger banks ignore the low bits and take the pieces in order
+        let bank = match (self.prg_mode, register) {
+            (0, _) => (value & !0b11) + slot as u8,
+            (1, _) | (2, 2) => (value & !0b1) + (slot % 2) as u8,
+            _ => value,
+        };
+        (register, bank | (value & 0x80))
+    }
+
+    /// The offset into PRG RAM of an 8 KiB RAM bank
+    fn ram_offset(&self, bank: u8) -> usize {
+        let banks = self.cartridge.prg_ram.len() / PRG_BANK_SIZE;
+        (bank & 0x7F) as usize % banks * PRG_BANK_SIZE
+    }
+
+    /// The size of the CHR banks in the CHR mode, and which of a set of
+    /// registers pick them
+    fn chr_layout(&self, registers: &[u16]) -> (usize, Vec<usize>) {
+        let last = registers.len() - 1;
+        let picked: Vec<usize> = match self.chr_mode {
+            0 => vec![last],
+            1 => (0..=last).filter(|i| i % 4 == 3).collect(),
+            2 => (0..=last).filter(|i| i % 2 == 1).collect(),
+            _ => (0..=last).collect(),
+        };
+        let size = 0x2000 >> self.chr_mode.min(3);
+        let banks = picked.iter().map(|&i| registers[i] as usize).collect();
+        (size, banks)
+    }
+
+    /// Hands the ExRAM mode, the name tables and the split to the video
+    fn update_video(&mut self) {
+        self.video.ex_ram_name_table = self.ex_ram_mode <= EX_RAM_EXTENDED_ATTRIBUTES;
+        self.video.extended_attributes = self.ex_ram_mode == EX_RAM_EXTENDED_ATTRIBUTES;
+        for (index, source) in self.video.name_tables.iter_mut().enumerate() {
+            *source = match (self.name_tables >> (index * 2)) & 0b11 {
+                0 => NameTableSource::VramA,
+                1 => NameTableSource::VramB,
+                2 => NameTableSource::ExRam,
+                _ => NameTableSource::Fill,
+            };
+        }
+        // the plain mirroring modes are what the PPU shows anyway
+        let mirroring = match self.name_tables {
+            0x44 => Some(Mirroring::Vertical),
+            0x50 => Some(Mirroring::Horizontal),
+            0x00 => Some(Mirroring::SingleScreenLower),
+            0x55 => Some(Mirroring::SingleScreenUpper),
+            _ => None,
+        };
+        if let Some(mirroring) = mirroring {
+            self.cartridge.set_mirroring(mirroring);
+        }
+        self.video.split = (self.split_control & SPLIT_ENABLE != 0).then_some(Split {
+            right: self.split_control & SPLIT_RIGHT != 0,
+            tile: (self.split_control & 0b1_1111) as usize,
+            scroll: self.split_scroll,
+            chr_bank: self.split_chr_bank as usize,
+        });
+    }
+
+    fn write_audio(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulses[0].write(addr - 0x5000, data),
+            0x5004..=0x5007 => self.pulses[1].write(addr - 0x5004, data),
+            0x5010 => {
+                self.pcm_control = data & (PCM_IRQ | PCM_READ_MODE);
+                if data & PCM_IRQ == 0 {
+                    self.pcm_irq = false;
+                }
+            }
+            // a 0 can't be written, it's what raises the IRQ in read mode
+            0x5011 if self.pcm_control & PCM_READ_MODE == 0 && data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulses[0].set_enabled(data & 0b01 != 0);
+                self.pulses[1].set_enabled(data & 0b10 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    /// Where a CPU address in [0x6000 ... 0xDFFF] lands in PRG RAM, if RAM
+    /// is banked in there
+    fn prg_ram_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            PRG_RAM..=PRG_RAM_END => {
+                Some(self.ram_offset(self.prg_registers[0]) + (addr - PRG_RAM) as usize)
+            }
+            PRG_ROM.. => {
+                let addr = (addr - PRG_ROM) as usize;
+                self.ram_slots[addr / PRG_BANK_SIZE].map(|offset| offset + addr % PRG_BANK_SIZE)
+            }
+            _ => None,
+        }
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_protect == [0b10, 0b01]
+    }
+
+    fn irq_status(&self) -> u8 {
+        let mut status = 0;
+        if self.irq_pending {
+            status |= IRQ_PENDING;
+        }
+        if self.in_frame {
+            status |= IRQ_IN_FRAME;
+        }
+        status
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn power_cycle(&mut self) {
+        let ex_ram = std::mem::take(&mut self.video.ex_ram);
+        self.prg_mode = 3;
+        self.chr_mode = 0;
+        self.ram_protect = [0; 2];
+        self.ex_ram_mode = 0;
+        self.prg_registers = [0, 0, 0, 0, 0xFF];
+        self.chr_registers = [0; 12];
+        self.chr_upper = 0;
+        self.name_tables = 0;
+        (self.split_control, self.split_scroll, self.split_chr_bank) = (0, 0, 0);
+        self.video = ExtendedVideo {
+            ex_ram,
+            ..ExtendedVideo::default()
+        };
+        (self.irq_scanline, self.irq_enabled, self.irq_pending) = (0, false, false);
+        (self.in_frame, self.scanline_counter) = (false, 0);
+        (self.multiplicand, self.multiplier) = (0xFF, 0xFF);
+        self.pulses = [Pulse::without_sweep(), Pulse::without_sweep()];
+        (self.pcm_control, self.pcm, self.pcm_irq) = (0, 0, false);
+        (self.frame_cycle, self.cycle) = (0, 0);
+        self.update_banks();
+        self.update_video();
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        let value = self.peek(addr);
+        match addr {
+            0x5010 => self.pcm_irq = false,
+            0x5204 => self.irq_pending = false,
+            // in read mode the PCM channel plays what the CPU reads
+            0x8000..=0xBFFF if self.pcm_control & PCM_READ_MODE != 0 => match value {
+                Some(0) => self.pcm_irq = self.pcm_control & PCM_IRQ != 0,
+                Some(value) => self.pcm = value,
+                None => {}
+            },
+            _ => {}
+        }
+        value
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => Some(if self.pcm_irq { PCM_IRQ } else { 0 }),
+            0x5015 => Some(self.pulses[0].active() as u8 | (self.pulses[1].active() as u8) << 1),
+            0x5204 => Some(self.irq_status()),
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            // ExRAM can't be read while it's feeding the PPU
+            EX_RAM..=EX_RAM_END if self.ex_ram_mode > EX_RAM_EXTENDED_ATTRIBUTES => {
+                Some(self.video.ex_ram[(addr - EX_RAM) as usize])
+            }
+            PRG_RAM.. => match self.prg_ram_offset(addr) {
+                Some(offset) => Some(self.cartridge.prg_ram[offset]),
+                None => self.cartridge.read(addr),
+            },
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000..=0x5015 => self.write_audio(addr, data),
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 => self.ram_protect[0] = data & 0b11,
+            0x5103 => self.ram_protect[1] = data & 0b11,
+            0x5104 => self.ex_ram_mode = data & 0b11,
+            0x5105 => self.name_tables = data,
+            0x5106 => self.video.fill_tile = data,
+            0x5107 => self.video.fill_palette = data & 0b11,
+            0x5113..=0x5117 => self.prg_registers[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512B => {
+                self.chr_registers[(addr - 0x5120) as usize] =
+                    data as u16 | (self.chr_upper as u16 & 0b11) << 8
+            }
+            0x5130 => self.chr_upper = data & 0b11,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_chr_bank = data,
+            0x5203 => self.irq_scanline = data,
+            0x5204 => self.irq_enabled = data & 0b1000_0000 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            EX_RAM..=EX_RAM_END => {
+                let index = (addr - EX_RAM) as usize;
+                match self.ex_ram_mode {
+                    EX_RAM_READ_ONLY => {}
+                    // while the PPU isn't rendering, writes in the name
+                    // table modes store a 0
+                    EX_RAM_NAME_TABLE | EX_RAM_EXTENDED_ATTRIBUTES if !self.in_frame => {
+                        self.video.ex_ram[index] = 0
+                    }
+                    _ => self.video.ex_ram[index] = data,
+                }
+            }
+            PRG_RAM.. => {
+                if self.ram_writable()
+                    && let Some(offset) = self.prg_ram_offset(addr)
+                {
+                    self.cartridge.prg_ram[offset] = data;
+                }
+            }
+            _ => {}
+        }
+        if (0x5100..=0x5202).contains(&addr) {
+            self.update_banks();
+            self.update_video();
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match self.prg_ram_offset(addr) {
+            Some(_) => None,
+            None => self.cartridge.prg_offset(addr),
+        }
+    }
+
+    fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle.is_multiple_of(2) {
+            for pulse in &mut self.pulses {
+                pulse.clock_timer();
+            }
+        }
+        self.frame_cycle += 1;
+        if self.frame_cycle == FRAME_STEP {
+            self.frame_cycle = 0;
+            for pulse in &mut self.pulses {
+                pulse.clock_envelope();
+                pulse.clock_length();
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let pulse = self.pulses[0].output() + self.pulses[1].output();
+        mix_pulses(pulse as f32) + mix_tnd(0.0, 0.0, self.pcm as f32 / 2.0)
+    }
+
+    /// The first visible scanline the PPU renders puts MMC5 "in frame" and
+    /// resets its counter, every one after counts up and raises the IRQ when
+    /// it reaches $5203. It drops out of frame once the PPU stops rendering
+    fn scanline(&mut self, scanline: u16, rendering: bool) {
+        if !rendering || scanline >= VISIBLE_SCANLINES {
+            self.in_frame = false;
+        } else if !self.in_frame {
+            self.in_frame = true;
+            self.irq_pending = false;
+            self.scanline_counter = 0;
+        } else {
+            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+            if self.scanline_counter == self.irq_scanline && self.irq_scanline != 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.pcm_irq
+    }
+
+    fn video(&self) -> Option<&ExtendedVideo> {
+        Some(&self.video)
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        for value in [
+            self.prg_mode,
+            self.chr_mode,
+            self.ram_protect[0],
+            self.ram_protect[1],
+            self.ex_ram_mode,
+            self.chr_upper,
+            self.name_tables,
+            self.video.fill_tile,
+            self.video.fill_palette,
+            self.split_control,
+            self.split_scroll,
+            self.split_chr_bank,
+            self.irq_scanline,
+            self.scanline_counter,
+            self.multiplicand,
+            self.multiplier,
+            self.pcm_control,
+            self.pcm,
+        ] {
+            state.u8(value);
+        }
+        state.bytes(&self.prg_registers);
+        for register in &self.chr_registers {
+            state.u16(*register);
+        }
+        state.bytes(&self.video.ex_ram);
+        for flag in [
+            self.irq_enabled,
+            self.irq_pending,
+            self.in_frame,
+            self.pcm_irq,
+        ] {
+            state.bool(flag);
+        }
+        for pulse in &self.pulses {
+            pulse.save_state(state);
+        }
+        state.u16(self.frame_cycle);
+        state.u64(self.cycle);
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let mut values = [0; 18];
+        state.bytes(&mut values)?;
+        [
+            self.prg_mode,
+            self.chr_mode,
+            self.ram_protect[0],
+            self.ram_protect[1],
+            self.ex_ram_mode,
+            self.chr_upper,
+            self.name_tables,
+            self.video.fill_tile,
+            self.video.fill_palette,
+            self.split_control,
+            self.split_scroll,
+            self.split_chr_bank,
+            self.irq_scanline,
+            self.scanline_counter,
+            self.multiplicand,
+            self.multiplier,
+            self.pcm_control,
+            self.pcm,
+        ] = values;
+        self.prg_mode &= 0b11;
+        self.chr_mode &= 0b11;
+        self.ex_ram_mode &= 0b11;
+        state.bytes(&mut self.prg_registers)?;
+        for register in self.chr_registers.iter_mut() {
+            *register = state.u16()?;
+        }
+        state.bytes(&mut self.video.ex_ram)?;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        self.in_frame = state.bool()?;
+        self.pcm_irq = state.bool()?;
+        for pulse in self.pulses.iter_mut() {
+            pulse.load_state(state)?;
+        }
+        self.frame_cycle = state.u16()? % FRAME_STEP;
+        self.cycle = state.u64()?;
+        self.update_banks();
+        self.update_video();
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+//! Contains the cartridge boards ("mappers"), which decide what the CPU sees
+//! at [0x4020 ... 0xFFFF] and the PPU sees in its pattern tables. The NES
+//! can only address 32 KiB of PRG ROM and 8 KiB of CHR at a time, so bigger
+//! games come on boards that switch banks of ROM in and out when the game
+//! writes to registers in cartridge space, and some of them switch the
+//! name table mirroring too.
+//!
+//! Every board keeps its memory and what's banked in where in a `Cartridge`;
+//! a `Mapper` only has to say what its registers do to it. The PPU gets the
+//! CHR banks and the mirroring handed over by the bus after every write to
+//! cartridge space.
+//!
+//! Supported boards (iNES mapper numbers):
+//! * 0: NROM, no banking at all
+//! * 5: MMC5, with its ExRAM, extended video, scanline IRQ and expansion
+//!   audio (see the `mmc5` module)
+//! * 7, 11, 34, 66, 71, 79, 87, 140: the discrete logic boards (see the
+//!   `discrete` module)
+//! * 19: Namco 163, with its wavetable synthesizer
+//! * 20: the Famicom Disk System's RAM adapter, with its disk drive and
+//!   wavetable channel (see the `fds` and `fds_audio` modules)
+//! * 21, 22, 23, 25: Konami VRC2 and VRC4, in all their wirings (see the
+//!   `vrc` module)
+//! * 24, 26: Konami VRC6, with its pulse and sawtooth channels
+//! * 69: Sunsoft FME-7 and 5B, with the 5B's three channels (see the
+//!   `sunsoft5b` module)
+//! * 85: Konami VRC7, with its FM synthesizer
+//!
+//! NSF music rips play on a board of their own, made of the expansion
+//! audio of the others (see the `nsf` module).
+//!
+//! Boards made of discrete logic chips don't keep the ROM from driving the
+//! data bus while the CPU writes to a register that overlaps it, so the
+//! value that lands in the register is the written value ANDed with the ROM
+//! byte at that address (a "bus conflict"). Games work around it by writing
+//! to an address that holds the same value, and the boards that have the
+//! problem emulate it so the ones that don't work around it misbehave like
+//! they do on the real thing.
+
+mod discrete;
+mod fds;
+mod fds_audio;
+mod fme7;
+mod mmc5;
+mod n163;
+mod nsf;
+mod opll;
+mod sunsoft5b;
+mod vrc;
+mod vrc6;
+mod vrc7;
+
+pub(crate) use fds::DiskDrive;
+
+use crate::nsf::Nsf;
+use crate::ppu::{ExtendedVideo, Mirroring};
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+/// PRG ROM is banked in 8 KiB pieces
+const PRG_BANK_SIZE: usize = 0x2000;
+/// CHR is banked in 1 KiB pieces
+const CHR_BANK_SIZE: usize = 0x400;
+const PRG_RAM_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
+const PRG_ROM: u16 = 0x8000;
+
+/// The memory on a cartridge and which parts of it are banked in right now
+pub(crate) struct Cartridge {
+    prg_rom: Vec<u8>,
+    /// RAM at [0x6000 ... 0x7FFF] (empty on boards that have registers there)
+    prg_ram: Vec<u8>,
+    /// the size of CHR ROM (or CHR RAM), which lives in the PPU
+    chr_len: usize,
+    /// the offset into PRG ROM of the 8 KiB at 0x8000, 0xA000, 0xC000 and
+    /// 0xE000
+    prg_banks: [usize; 4],
+    /// the offset into CHR of every KiB of the pattern tables
+    chr_banks: [usize; 8],
+    mirroring: Mirroring,
+    /// the mirroring the iNES header asks for, for boards that can't change it
+    header_mirroring: Mirroring,
+}
+
+impl Cartridge {
+    /// Loads a ROM onto a board with `prg_ram_size` bytes of PRG RAM
+    fn new(rom: &Rom, prg_ram_size: usize) -> Self {
+        let chr_len = if rom.chr_rom.is_empty() {
+            CHR_RAM_SIZE
+        } else {
+            rom.chr_rom.len()
+        };
+        let mut cartridge = Self {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: vec![0; prg_ram_size],
+            chr_len,
+            prg_banks: [0; 4],
+            chr_banks: [0; 8],
+            mirroring: rom.screen_mirroring,
+            header_mirroring: rom.screen_mirroring,
+        };
+        cartridge.map_prg_32k(0);
+        cartridge.map_chr_8k(0);
+        cartridge
+    }
+
+    /// How many banks of `size` bytes PRG ROM has
+    pub fn prg_bank_count(&self, size: usize) -> usize {
+        (self.prg_rom.len() / size).max(1)
+    }
+
+    /// The size of PRG ROM for wrapping offsets around (a ROM without any
+    /// PRG ROM doesn't have anything to wrap around)
+    fn prg_len(&self) -> usize {
+        self.prg_rom.len().max(1)
+    }
+
+    /// How many banks of `size` bytes CHR has
+    pub fn chr_bank_count(&self, size: usize) -> usize {
+        (self.chr_len / size).max(1)
+    }
+
+    /// Switches `bank` (of `size` bytes, wrapping around the end of PRG
+    /// ROM) in at 8 KiB slot `slot` and the ones after it it covers
+    fn map_prg(&mut self, slot: usize, size: usize, bank: usize) {
+        let start = bank % self.prg_bank_count(size) * size;
+        for piece in 0..size / PRG_BANK_SIZE {
+            self.prg_banks[slot + piece] = (start + piece * PRG_BANK_SIZE) % self.prg_len();
+        }
+    }
+
+    /// Switches a 32 KiB bank in at [0x8000 ... 0xFFFF]
+    pub fn map_prg_32k(&mut self, bank: usize) {
+        self.map_prg(0, 0x8000, bank);
+    }
+
+    /// Switches a 16 KiB bank in at 0x8000 (`slot` 0) or 0xC000 (`slot` 1)
+    pub fn map_prg_16k(&mut self, slot: usize, bank: usize) {
+        self.map_prg(slot * 2, 0x4000, bank);
+    }
+
+    /// Switches `bank` (of `size` bytes, wrapping around the end of CHR) in
+    /// at 1 KiB slot `slot` and the ones after it it covers
+    fn map_chr(&mut self, slot: usize, size: usize, bank: usize) {
+        let start = self.chr_bank_offset(size, bank);
+        for piece in 0..size / CHR_BANK_SIZE {
+            self.chr_banks[slot + piece] = (start + piece * CHR_BANK_SIZE) % self.chr_len;
+        }
+    }
+
+    /// The offset into CHR of `bank` (of `size` bytes, wrapping around the
+    /// end of CHR)
+    fn chr_bank_offset(&self, size: usize, bank: usize) -> usize {
+        bank % self.chr_bank_count(size) * size % self.chr_len
+    }
+
+    /// Switches an 8 KiB bank in at [0x0000 ... 0x1FFF]
+    pub fn map_chr_8k(&mut self, bank: usize) {
+        self.map_chr(0, 0x2000, bank);
+    }
+
+    /// Switches a 4 KiB bank in at 0x0000 (`slot` 0) or 0x1000 (`slot` 1)
+    pub fn map_chr_4k(&mut self, slot: usize, bank: usize) {
+        self.map_chr(slot * 4, 0x1000, bank);
+    }
+
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    /// Maps a CPU address in [0x8000 ... 0xFFFF] onto an offset into PRG ROM
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        if self.prg_rom.is_empty() || addr < PRG_ROM {
+            return None;
+        }
+        let addr = (addr - PRG_ROM) as usize;
+        Some(self.prg_banks[addr / PRG_BANK_SIZE] + addr % PRG_BANK_SIZE)
+    }
+
+    /// Reads PRG RAM or PRG ROM (None for anything else, which is open bus)
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            PRG_RAM..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr - PRG_RAM) as usize])
+            }
+            _ => self.prg_offset(addr).map(|offset| self.prg_rom[offset]),
+        }
+    }
+
+    /// What a write of `data` to `addr` on a board with bus conflicts puts
+    /// into the register: the ROM at `addr` drives the bus at the same time
+    pub fn bus_conflict(&self, addr: u16, data: u8) -> u8 {
+        data & self.read(addr).unwrap_or(0xFF)
+    }
+
+    /// Writes to PRG RAM, returning whether there was RAM at `addr`
+    fn write_ram(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            PRG_RAM..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - PRG_RAM) as usize] = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.vec(&self.prg_ram);
+        for bank in self.prg_banks.iter().chain(&self.chr_banks) {
+            state.u64(*bank as u64);
+        }
+        state.u8(mirroring_to_u8(self.mirroring));
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.prg_ram = state.vec(self.prg_ram.len())?;
+        let prg_len = self.prg_len();
+        for bank in self.prg_banks.iter_mut() {
+            *bank = state.u64()? as usize % prg_len;
+        }
+        for bank in self.chr_banks.iter_mut() {
+            *bank = state.u64()? as usize % self.chr_len;
+        }
+        self.mirroring = mirroring_from_u8(state.u8()?)?;
+        Ok(())
+    }
+}
+
+/// A cartridge board. Everything but the registers is the same for all of
+/// them, so a board only has to hand over its `Cartridge` and say what a
+/// write to one of its registers does
+pub(crate) trait Mapper: Send {
+    fn cartridge(&self) -> &Cartridge;
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge;
+
+    /// Handles a write to a register at [0x8000 ... 0xFFFF] (after the bus
+    /// conflict, on boards that have them)
+    fn write_register(&mut self, _addr: u16, _data: u8) {}
+
+    /// Whether writes to registers that overlap PRG ROM get ANDed with it
+    fn bus_conflicts(&self) -> bool {
+        false
+    }
+
+    /// Puts the registers (and so the banks) back into their power on state
+    fn power_cycle(&mut self);
+
+    /// Reads cartridge space [0x4020 ... 0xFFFF], None where nothing answers
+    /// (open bus)
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        self.peek(addr)
+    }
+
+    /// Returns what reading `addr` would return without any side effects
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self.cartridge().read(addr)
+    }
+
+    /// Writes to cartridge space [0x4020 ... 0xFFFF]. Boards with registers
+    /// below 0x8000 override this
+    fn write(&mut self, addr: u16, data: u8) {
+        if self.cartridge_mut().write_ram(addr, data) || addr < PRG_ROM {
+            return;
+        }
+        let data = if self.bus_conflicts() {
+            self.cartridge().bus_conflict(addr, data)
+        } else {
+            data
+        };
+        self.write_register(addr, data);
+    }
+
+    /// Maps a CPU address in [0x8000 ... 0xFFFF] onto an offset into PRG ROM
+    /// (None where the board has something else banked in)
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        self.cartridge().prg_offset(addr)
+    }
+
+    /// Advances the board by a CPU cycle, for boards with timers or audio
+    fn clock(&mut self) {}
+
+    /// What the board's own audio puts out right now, on the same scale as
+    /// the APU's mixed output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Tells the board the PPU has started `scanline` (0 - 261, or 311 on
+    /// PAL and Dendy), and whether
+    /// it's rendering (boards that count scanlines watch for this)
+    fn scanline(&mut self, _scanline: u16, _rendering: bool) {}
+
+    /// Whether the board is asserting the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// The board's extended video (see `ppu::ExtendedVideo`), if it has any
+    fn video(&self) -> Option<&ExtendedVideo> {
+        None
+    }
+
+    /// The disk drive, on the Famicom Disk System
+    fn disk_drive(&self) -> Option<&DiskDrive> {
+        None
+    }
+
+    fn disk_drive_mut(&mut self) -> Option<&mut DiskDrive> {
+        None
+    }
+
+    /// What the battery keeps, which is what goes in a save file: the PRG
+    /// RAM, plus RAM of the board's own on boards that back that up too
+    fn battery_ram(&self) -> Vec<u8> {
+        self.cartridge().prg_ram.clone()
+    }
+
+    /// Puts back what `battery_ram` returned
+    fn load_battery_ram(&mut self, ram: &[u8]) -> Result<(), String> {
+        let prg_ram = &mut self.cartridge_mut().prg_ram;
+        check_save_size(ram, prg_ram.len())?;
+        prg_ram.copy_from_slice(ram);
+        Ok(())
+    }
+
+    /// Board specific state that isn't banks or mirroring (the banks are
+    /// saved with the cartridge)
+    fn save_registers(&self, _state: &mut StateWriter) {}
+
+    fn load_registers(&mut self, _state: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl dyn Mapper {
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        self.cartridge().save_state(state);
+        self.save_registers(state);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.cartridge_mut().load_state(state)?;
+        self.load_registers(state)
+    }
+
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.cartridge().prg_rom
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.cartridge().prg_ram
+    }
+
+    pub(crate) fn clear_prg_ram(&mut self) {
+        self.cartridge_mut().prg_ram.fill(0);
+    }
+
+    pub fn chr_banks(&self) -> [usize; 8] {
+        self.cartridge().chr_banks
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.cartridge().mirroring
+    }
+}
+
+/// Refuses saves that aren't the size of the battery backed RAM
+fn check_save_size(save: &[u8], size: usize) -> Result<(), String> {
+    if save.len() != size {
+        return Err(format!(
+            "The save is {} bytes, but the cartridge keeps {size}",
+            save.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Instantiates the board a ROM was made for
+pub(crate) fn create(rom: &Rom) -> Result<Box<dyn Mapper>, String> {
+    let mapper: Box<dyn Mapper> = match rom.mapper {
+        0 => Box::new(discrete::Nrom::new(rom)),
+        5 => Box::new(mmc5::Mmc5::new(rom)),
+        7 => Box::new(discrete::Axrom::new(rom)),
+        11 => Box::new(discrete::ColorDreams::new(rom)),
+        19 => Box::new(n163::N163::new(rom)),
+        20 => match &rom.disk {
+            Some(disk) => Box::new(fds::Fds::new(rom, disk)),
+            None => return Err("The Famicom Disk System needs a disk image".to_string()),
+        },
+        21..=23 | 25 => Box::new(vrc::Vrc::new(rom)),
+        24 | 26 => Box::new(vrc6::Vrc6::new(rom)),
+        // NES 2.0 names the board (1 = NINA-001, 2 = BNROM), iNES headers
+        // leave it to be guessed from the CHR size
+        34 => match rom.submapper {
+            1 => Box::new(discrete::Nina001::new(rom)),
+            2 => Box::new(discrete::Bnrom::new(rom)),
+            _ if rom.chr_rom.len() > CHR_RAM_SIZE => Box::new(discrete::Nina001::new(rom)),
+            _ => Box::new(discrete::Bnrom::new(rom)),
+        },
+        66 => Box::new(discrete::Gxrom::new(rom)),
+        69 => Box::new(fme7::Fme7::new(rom)),
+        71 => Box::new(discrete::Camerica::new(rom)),
+        79 => Box::new(discrete::Nina03::new(rom)),
+        85 => Box::new(vrc7::Vrc7::new(rom)),
+        87 => Box::new(discrete::Mapper87::new(rom)),
+        140 => Box::new(discrete::Mapper140::new(rom)),
+        mapper => return Err(format!("Mapper {mapper} is not supported yet")),
+    };
+    Ok(mapper)
+}
+
+/// Instantiates the board an NSF tune plays on
+pub(crate) fn create_nsf(tune: &Nsf) -> Box<dyn Mapper> {
+    Box::new(nsf::NsfBoard::new(tune))
+}
+
+fn mirroring_to_u8(mirroring: Mirroring) -> u8 {
+    match mirroring {
+        Mirroring::Horizontal => 0,
+        Mirroring::Vertical => 1,
+        Mirroring::SingleScreenLower => 2,
+        Mirroring::SingleScreenUpper => 3,
+    }
+}
+
+fn mirroring_from_u8(value: u8) -> Result<Mirroring, String> {
+    match value {
+        0 => Ok(Mirroring::Horizontal),
+        1 => Ok(Mirroring::Vertical),
+        2 => Ok(Mirroring::SingleScreenLower),
+        3 => Ok(Mirroring::SingleScreenUpper),
+        _ => Err(format!("Savestate has an invalid mirroring ({value})")),
+    }
+}
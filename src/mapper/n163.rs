@@ -0,0 +1,336 @@
+//! Contains Namco's 163 (mapper 19), made for Megami Tensei II, King of
+//! Kings, Erika to Satoru no Yume Bouken and a few dozen others. It switches
+//! three 8 KiB PRG banks and eight 1 KiB CHR banks, has a 15 bit IRQ counter
+//! that counts up every CPU cycle and a wavetable synthesizer.
+//!
+//! The synthesizer lives in 128 bytes of internal RAM, which hold both the
+//! waveforms (as 4 bit samples, low nibble first) and the settings of up to
+//! 8 channels, 8 bytes each from 0x78 down:
+//! * 0, 2, 4 (bits 0 - 1): the 18 bit frequency, added to the phase
+//! * 1, 3, 5: the 24 bit phase, the top 8 bits of which index the waveform
+//! * 4 (bits 2 - 7): the waveform's length (256 - this many samples)
+//! * 6: where in RAM the waveform starts (in samples)
+//! * 7: volume (0 - 3), plus how many channels are on (4 - 6, only at 0x7F)
+//!
+//! It updates one channel every 15 CPU cycles and only has one DAC, so the
+//! channels take turns on it: the more are on, the lower each one's pitch
+//! and the louder the whine at the switching rate. Games that don't play
+//! music through it often keep saves in its RAM, which is battery backed
+//! on the boards with a battery.
+//!
+//! The registers:
+//! * $4800: the RAM at the address port
+//! * $5000 / $5800: IRQ counter low 8 bits / high 7 bits and enable (7)
+//! * $8000 - $B800: the CHR banks (0xE0 and up pick the console's name
+//!   table RAM on the real thing, which isn't emulated)
+//! * $C000 - $D800: the name tables (0xE0 and up picks the console's RAM by
+//!   bit 0, the CHR ROM name tables the rest pick aren't emulated)
+//! * $E000: PRG bank at 0x8000, sound disable (6)
+//! * $E800: PRG bank at 0xA000 (and the CHR RAM disables, not emulated)
+//! * $F000: PRG bank at 0xC000 (0xE000 is fixed to the last bank)
+//! * $F800: the address port: address (0 - 6), auto increment (7). The
+//!   same write unlocks PRG RAM when bits 4 - 7 are 0b0100, minus the
+//!   2 KiB pieces bits 0 - 3 protect
+
+use super::{
+    Cartridge, Mapper, PRG_BANK_SIZE, PRG_RAM, PRG_RAM_END, PRG_RAM_SIZE, check_save_size,
+};
+use crate::ppu::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+/// How loud a step of a channel's -120 - 105 output is, so a channel is
+/// about as loud as an APU pulse at the same volume
+const VOLUME: f32 = 0.001;
+/// How many CPU cycles a channel update takes
+const CHANNEL_CYCLES: u8 = 15;
+const SOUND_RAM_SIZE: usize = 0x80;
+/// Where the settings of channel 0 (the last one to turn on) start
+const CHANNELS: usize = 0x40;
+
+const IRQ_ENABLE: u16 = 0x8000;
+const IRQ_COUNTER_MAX: u16 = 0x7FFF;
+
+// $E000 bits
+const SOUND_DISABLE: u8 = 0b0100_0000;
+
+// $F800 bits
+const AUTO_INCREMENT: u8 = 0b1000_0000;
+const RAM_UNLOCK: u8 = 0b0100_0000;
+
+/// Namco 163 (mapper 19)
+pub(super) struct N163 {
+    cartridge: Cartridge,
+    /// whether the sound RAM survives a power cycle
+    battery: bool,
+    chr_registers: [u8; 8],
+    name_table_registers: [u8; 4],
+    prg_registers: [u8; 3],
+    /// the last write to $F800 (it's both the address port and the PRG RAM
+    /// protection)
+    port: u8,
+    /// the sound RAM address the port points at
+    address: u8,
+    /// the counter (0 - 14) and enable (15)
+    irq_counter: u16,
+    irq_pending: bool,
+    sound_ram: [u8; SOUND_RAM_SIZE],
+    /// counts the CPU cycles of the current channel update
+    channel_cycle: u8,
+    /// the channel (7 down to 8 - the number on) that updates next
+    channel: u8,
+    /// what the channel that updated last puts out
+    output: i16,
+}
+
+impl N163 {
+    pub fn new(rom: &Rom) -> Self {
+        let mut n163 = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+            battery: rom.battery,
+            chr_registers: [0; 8],
+            name_table_registers: [0; 4],
+            prg_registers: [0; 3],
+            port: 0,
+            address: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            sound_ram: [0; SOUND_RAM_SIZE],
+            channel_cycle: 0,
+            channel: 7,
+            output: 0,
+        };
+        n163.power_cycle();
+        n163
+    }
+
+    fn update_banks(&mut self) {
+        for (slot, bank) in self.prg_registers.iter().enumerate() {
+            self.cartridge
+                .map_prg(slot, PRG_BANK_SIZE, (bank & 0b11_1111) as usize);
+        }
+        let last = self.cartridge.prg_bank_count(PRG_BANK_SIZE) - 1;
+        self.cartridge.map_prg(3, PRG_BANK_SIZE, last);
+        for (slot, bank) in self.chr_registers.iter().enumerate() {
+            self.cartridge.map_chr(slot, 0x400, *bank as usize);
+        }
+        // only the console's 2 KiB of name tables can be picked, in the
+        // arrangements the PPU knows
+        if self.name_table_registers.iter().all(|bank| *bank >= 0xE0) {
+            let pages = self.name_table_registers.map(|bank| bank & 1);
+            let mirroring = match pages {
+                [0, 1, 0, 1] => Some(Mirroring::Vertical),
+                [0, 0, 1, 1] => Some(Mirroring::Horizontal),
+                [0, 0, 0, 0] => Some(Mirroring::SingleScreenLower),
+                [1, 1, 1, 1] => Some(Mirroring::SingleScreenUpper),
+                _ => None,
+            };
+            if let Some(mirroring) = mirroring {
+                self.cartridge.set_mirroring(mirroring);
+            }
+        }
+    }
+
+    fn sound_enabled(&self) -> bool {
+        self.prg_registers[0] & SOUND_DISABLE == 0
+    }
+
+    /// Moves the address port on after an access, if it auto increments
+    fn advance_address(&mut self) {
+        if self.port & AUTO_INCREMENT != 0 {
+            self.address = (self.address + 1) % SOUND_RAM_SIZE as u8;
+        }
+    }
+
+    /// Whether a write to `addr` in PRG RAM goes through
+    fn ram_writable(&self, addr: u16) -> bool {
+        let piece = (addr - PRG_RAM) / 0x800;
+        self.port & 0xF0 == RAM_UNLOCK && self.port & 1 << piece == 0
+    }
+
+    /// How many channels are on (1 - 8)
+    fn channel_count(&self) -> u8 {
+        (self.sound_ram[SOUND_RAM_SIZE - 1] >> 4 & 0b111) + 1
+    }
+
+    /// Adds `channel`'s frequency to its phase, wraps it around the end of
+    /// its waveform and puts out the sample it lands on
+    fn update_channel(&mut self, channel: u8) {
+        let base = CHANNELS + channel as usize * 8;
+        let ram = &mut self.sound_ram;
+        let frequency =
+            ram[base] as u32 | (ram[base + 2] as u32) << 8 | (ram[base + 4] as u32 & 0b11) << 16;
+        let phase =
+            ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let length = 256 - (ram[base + 4] & 0b1111_1100) as u32;
+        let phase = (phase + frequency) % (length << 16);
+        (ram[base + 1], ram[base + 3], ram[base + 5]) =
+            (phase as u8, (phase >> 8) as u8, (phase >> 16) as u8);
+
+        let sample_address = (ram[base + 6] as u32 + (phase >> 16)) as u8;
+        let byte = ram[sample_address as usize / 2];
+        let sample = if sample_address.is_multiple_of(2) {
+            byte & 0xF
+        } else {
+            byte >> 4
+        };
+        let volume = ram[base + 7] & 0xF;
+        self.output = (sample as i16 - 8) * volume as i16;
+    }
+}
+
+impl Mapper for N163 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn power_cycle(&mut self) {
+        self.chr_registers = [0, 1, 2, 3, 4, 5, 6, 7];
+        self.name_table_registers = [0; 4];
+        self.prg_registers = [0, 1, 2];
+        (self.port, self.address) = (0, 0);
+        (self.irq_counter, self.irq_pending) = (0, false);
+        if !self.battery {
+            self.sound_ram = [0; SOUND_RAM_SIZE];
+        }
+        (self.channel_cycle, self.channel, self.output) = (0, 7, 0);
+        self.cartridge
+            .set_mirroring(self.cartridge.header_mirroring);
+        self.update_banks();
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        let value = self.peek(addr);
+        if (0x4800..=0x4FFF).contains(&addr) {
+            self.advance_address();
+        }
+        value
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => Some(self.sound_ram[self.address as usize]),
+            0x5000..=0x57FF => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF => Some((self.irq_counter >> 8) as u8),
+            _ => self.cartridge.read(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => {
+                self.sound_ram[self.address as usize] = data;
+                self.advance_address();
+            }
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0xFF00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0xFF) | (data as u16) << 8;
+                self.irq_pending = false;
+            }
+            PRG_RAM..=PRG_RAM_END if self.ram_writable(addr) => {
+                self.cartridge.write_ram(addr, data);
+            }
+            0x8000..=0xBFFF => self.chr_registers[(addr - 0x8000) as usize / 0x800] = data,
+            0xC000..=0xDFFF => self.name_table_registers[(addr - 0xC000) as usize / 0x800] = data,
+            0xE000..=0xF7FF => self.prg_registers[(addr - 0xE000) as usize / 0x800] = data,
+            0xF800..=0xFFFF => {
+                self.port = data;
+                self.address = data & 0b111_1111;
+            }
+            _ => {}
+        }
+        if addr >= 0x8000 {
+            self.update_banks();
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.irq_counter & IRQ_ENABLE != 0 {
+            let count = self.irq_counter & IRQ_COUNTER_MAX;
+            if count == IRQ_COUNTER_MAX {
+                self.irq_pending = true;
+            } else {
+                self.irq_counter += 1;
+            }
+        }
+
+        if !self.sound_enabled() {
+            return;
+        }
+        self.channel_cycle += 1;
+        if self.channel_cycle < CHANNEL_CYCLES {
+            return;
+        }
+        self.channel_cycle = 0;
+        self.update_channel(self.channel);
+        self.channel = if self.channel <= 8 - self.channel_count() {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_enabled() {
+            self.output as f32 * VOLUME
+        } else {
+            0.0
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// The sound RAM is battery backed along with the PRG RAM, and comes
+    /// after it in saves
+    fn battery_ram(&self) -> Vec<u8> {
+        [&self.cartridge.prg_ram[..], &self.sound_ram].concat()
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) -> Result<(), String> {
+        let prg_ram_len = self.cartridge.prg_ram.len();
+        check_save_size(ram, prg_ram_len + SOUND_RAM_SIZE)?;
+        let (prg_ram, sound_ram) = ram.split_at(prg_ram_len);
+        self.cartridge.prg_ram.copy_from_slice(prg_ram);
+        self.sound_ram.copy_from_slice(sound_ram);
+        Ok(())
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        state.bytes(&self.chr_registers);
+        state.bytes(&self.name_table_registers);
+        state.bytes(&self.prg_registers);
+        state.u8(self.port);
+        state.u8(self.address);
+        state.u16(self.irq_counter);
+        state.bool(self.irq_pending);
+        state.bytes(&self.sound_ram);
+        state.u8(self.channel_cycle);
+        state.u8(self.channel);
+        state.u16(self.output as u16);
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.chr_registers)?;
+        state.bytes(&mut self.name_table_registers)?;
+        state.bytes(&mut self.prg_registers)?;
+        self.port = state.u8()?;
+        self.address = state.u8()? % SOUND_RAM_SIZE as u8;
+        self.irq_counter = state.u16()?;
+        self.irq_pending = state.bool()?;
+        state.bytes(&mut self.sound_ram)?;
+        self.channel_cycle = state.u8()? % CHANNEL_CYCLES;
+        self.channel = state.u8()? & 0b111;
+        self.output = state.u16()? as i16;
+        self.update_banks();
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+//! Contains the board NSF music rips play on (see the `nsf` module). It
+//! doesn't exist as such: it's what an NSF player's hardware looks like to
+//! the tune.
+//!
+//! The tune's data is split into 4 KiB banks, which the registers at
+//! $5FF8 - $5FFF switch in at 0x8000, 0x9000 ... 0xF000. There's 8 KiB of
+//! RAM at [0x6000 ... 0x7FFF].
+//!
+//! Tunes that use the Famicom Disk System's audio get its RAM too: all of
+//! [0x6000 ... 0xDFFF] is RAM, with two more registers at $5FF6 / $5FF7
+//! for 0x6000 / 0x7000. Switching a bank in copies it into the RAM (which
+//! is how the BIOS would have loaded it off the disk), and [0xE000 ...
+//! 0xFFFF] can be switched but not written.
+//!
+//! The expansion audio chips sit at the same addresses as on the boards
+//! they came on, and they're emulated by those boards (with no ROM of
+//! their own); only the writes to their audio registers reach them.
+
+use super::fds_audio::FdsAudio;
+use super::sunsoft5b::Sunsoft5b;
+use super::{Cartridge, Mapper, mmc5, n163, vrc6, vrc7};
+use crate::nsf::{self, Nsf};
+use crate::ppu::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+const BANK_SIZE: usize = 0x1000;
+/// The register for the bank at 0x6000 (FDS tunes only); the ones for
+/// 0x7000 ... 0xF000 follow it
+const BANK_REGISTERS: u16 = 0x5FF6;
+const BANK_REGISTERS_END: u16 = 0x5FFF;
+const RAM: u16 = 0x6000;
+const RAM_END: u16 = 0x7FFF;
+const FDS_RAM_END: u16 = 0xDFFF;
+const ROM: u16 = 0x8000;
+const RAM_SIZE: usize = 0x2000;
+/// [0x6000 ... 0xFFFF], all of it kept in RAM for FDS tunes
+const FDS_RAM_SIZE: usize = 0xA000;
+/// MMC5's ExRAM mode that makes it plain RAM
+const EX_RAM_AS_RAM: u8 = 2;
+
+/// An expansion chip that's emulated by the board it came on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Chip {
+    Vrc6,
+    Vrc7,
+    Mmc5,
+    N163,
+}
+
+impl Chip {
+    /// The iNES mapper number of the board the chip came on
+    fn mapper(&self) -> u8 {
+        match self {
+            Chip::Vrc6 => 24,
+            Chip::Vrc7 => 85,
+            Chip::Mmc5 => 5,
+            Chip::N163 => 19,
+        }
+    }
+
+    fn reads(&self, addr: u16) -> bool {
+        match self {
+            Chip::Mmc5 => matches!(addr, 0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5),
+            Chip::N163 => matches!(addr, 0x4800..=0x4FFF),
+            Chip::Vrc6 | Chip::Vrc7 => false,
+        }
+    }
+
+    fn writes(&self, addr: u16) -> bool {
+        match self {
+            Chip::Vrc6 => matches!(addr, 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002),
+            Chip::Vrc7 => matches!(addr, 0x9010 | 0x9030),
+            Chip::Mmc5 => matches!(addr, 0x5000..=0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5),
+            Chip::N163 => matches!(addr, 0x4800..=0x4FFF | 0xF800..=0xFFFF),
+        }
+    }
+
+    fn board(&self) -> Box<dyn Mapper> {
+        let rom = Rom {
+            prg_rom: vec![0; 0x8000],
+            chr_rom: Vec::new(),
+            mapper: self.mapper(),
+            submapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+            battery: false,
+            region: None,
+            expansion: None,
+            title: None,
+            disk: None,
+        };
+        let mut board: Box<dyn Mapper> = match self {
+            Chip::Vrc6 => Box::new(vrc6::Vrc6::new(&rom)),
+            Chip::Vrc7 => Box::new(vrc7::Vrc7::new(&rom)),
+            Chip::Mmc5 => Box::new(mmc5::Mmc5::new(&rom)),
+            Chip::N163 => Box::new(n163::N163::new(&rom)),
+        };
+        if *self == Chip::Mmc5 {
+            board.write(0x5104, EX_RAM_AS_RAM);
+        }
+        board
+    }
+}
+
+/// The board NSF tunes play on
+pub(super) struct NsfBoard {
+    cartridge: Cartridge,
+    /// the banks at 0x6000, 0x7000 ... 0xF000 (the first two only matter
+    /// for FDS tunes)
+    banks: [u8; 10],
+    initial_banks: [u8; 10],
+    /// the FDS audio, which also turns [0x6000 ... 0xDFFF] into RAM
+    fds: Option<FdsAudio>,
+    sunsoft5b: Option<Sunsoft5b>,
+    chips: Vec<(Chip, Box<dyn Mapper>)>,
+}
+
+impl NsfBoard {
+    pub fn new(tune: &Nsf) -> Self {
+        let fds = tune.expansion & nsf::FDS != 0;
+        // without banking the data goes straight where it's loaded, which
+        // is the same as banks in order from the first one
+        let (padding, initial_banks) = match tune.banks {
+            Some(banks) => {
+                let mut initial_banks = [0; 10];
+                initial_banks[2..].copy_from_slice(&banks);
+                // FDS tunes start with the last two banks at 0x6000 too
+                initial_banks[..2].copy_from_slice(&banks[6..]);
+                (tune.load_addr as usize % BANK_SIZE, initial_banks)
+            }
+            None if fds => (
+                (tune.load_addr - RAM) as usize,
+                [0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            ),
+            None => (
+                (tune.load_addr - ROM) as usize,
+                [0, 0, 0, 1, 2, 3, 4, 5, 6, 7],
+            ),
+        };
+        let mut prg_rom = vec![0; padding];
+        prg_rom.extend_from_slice(&tune.data);
+        prg_rom.resize(prg_rom.len().div_ceil(BANK_SIZE) * BANK_SIZE, 0);
+        let rom = Rom {
+            prg_rom,
+            chr_rom: Vec::new(),
+            mapper: 0,
+            submapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+            battery: false,
+            region: None,
+            expansion: None,
+            title: None,
+            disk: None,
+        };
+
+        let chips = [
+            (nsf::VRC6, Chip::Vrc6),
+            (nsf::VRC7, Chip::Vrc7),
+            (nsf::MMC5, Chip::Mmc5),
+            (nsf::N163, Chip::N163),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| tune.expansion & flag != 0)
+        .map(|(_, chip)| (chip, chip.board()))
+        .collect();
+        let mut board = Self {
+            cartridge: Cartridge::new(&rom, if fds { FDS_RAM_SIZE } else { RAM_SIZE }),
+            banks: initial_banks,
+            initial_banks,
+            fds: fds.then(FdsAudio::default),
+            sunsoft5b: (tune.expansion & nsf::SUNSOFT_5B != 0).then(Sunsoft5b::default),
+            chips,
+        };
+        board.power_cycle();
+        board
+    }
+
+    fn bank_count(&self) -> usize {
+        self.cartridge.prg_rom.len() / BANK_SIZE
+    }
+
+    /// Where `addr` (0x6000 and up) lands in the tune's data
+    fn rom_offset(&self, addr: u16) -> usize {
+        let slot = (addr - RAM) as usize / BANK_SIZE;
+        let bank = self.banks[slot] as usize % self.bank_count();
+        bank * BANK_SIZE + addr as usize % BANK_SIZE
+    }
+
+    /// Switches a bank in, which FDS tunes copy into their RAM
+    fn switch_bank(&mut self, slot: usize, bank: u8) {
+        self.banks[slot] = bank;
+        if self.fds.is_some() {
+            let start = self.rom_offset(RAM + (slot * BANK_SIZE) as u16);
+            let ram = &mut self.cartridge.prg_ram[slot * BANK_SIZE..(slot + 1) * BANK_SIZE];
+            ram.copy_from_slice(&self.cartridge.prg_rom[start..start + BANK_SIZE]);
+        }
+    }
+}
+
+impl Mapper for NsfBoard {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn power_cycle(&mut self) {
+        for (slot, bank) in self.initial_banks.into_iter().enumerate() {
+            self.switch_bank(slot, bank);
+        }
+        if let Some(fds) = &mut self.fds {
+            *fds = FdsAudio::default();
+        }
+        if let Some(sunsoft5b) = &mut self.sunsoft5b {
+            *sunsoft5b = Sunsoft5b::default();
+        }
+        for (chip, board) in &mut self.chips {
+            *board = chip.board();
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        if let Some((_, board)) = self.chips.iter_mut().find(|(chip, _)| chip.reads(addr)) {
+            return board.read(addr);
+        }
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        if let Some((_, board)) = self.chips.iter().find(|(chip, _)| chip.reads(addr)) {
+            return board.peek(addr);
+        }
+        match addr {
+            0x4040..=0x4097 => self.fds.as_ref()?.read(addr),
+            RAM.. if self.fds.is_some() => Some(self.cartridge.prg_ram[(addr - RAM) as usize]),
+            RAM..=RAM_END => Some(self.cartridge.prg_ram[(addr - RAM) as usize]),
+            ROM.. => Some(self.cartridge.prg_rom[self.rom_offset(addr)]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        for (chip, board) in &mut self.chips {
+            if chip.writes(addr) {
+                board.write(addr, data);
+            }
+        }
+        if let Some(sunsoft5b) = &mut self.sunsoft5b {
+            match addr {
+                0xC000..=0xDFFF => sunsoft5b.write_address(data),
+                0xE000..=0xFFFF => sunsoft5b.write_data(data),
+                _ => {}
+            }
+        }
+        match addr {
+            0x4040..=0x4097 => {
+                if let Some(fds) = &mut self.fds {
+                    fds.write(addr, data);
+                }
+            }
+            // 0x6000 and 0x7000 only switch for FDS tunes
+            BANK_REGISTERS..=BANK_REGISTERS_END
+                if addr >= BANK_REGISTERS + 2 || self.fds.is_some() =>
+            {
+                self.switch_bank((addr - BANK_REGISTERS) as usize, data);
+            }
+            RAM..=FDS_RAM_END if self.fds.is_some() => {
+                self.cartridge.prg_ram[(addr - RAM) as usize] = data;
+            }
+            RAM..=RAM_END => self.cartridge.prg_ram[(addr - RAM) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            ROM.. if self.fds.is_none() => Some(self.rom_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if let Some(fds) = &mut self.fds {
+            fds.clock();
+        }
+        if let Some(sunsoft5b) = &mut self.sunsoft5b {
+            sunsoft5b.clock();
+        }
+        for (_, board) in &mut self.chips {
+            board.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let fds = self.fds.as_ref().map_or(0.0, FdsAudio::output);
+        let sunsoft5b = self.sunsoft5b.as_ref().map_or(0.0, Sunsoft5b::output);
+        let chips: f32 = self
+            .chips
+            .iter()
+            .map(|(_, board)| board.audio_output())
+            .sum();
+        fds + sunsoft5b + chips
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        state.bytes(&self.banks);
+        if let Some(fds) = &self.fds {
+            fds.save_state(state);
+        }
+        if let Some(sunsoft5b) = &self.sunsoft5b {
+            sunsoft5b.save_state(state);
+        }
+        for (_, board) in &self.chips {
+            board.save_state(state);
+        }
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.banks)?;
+        if let Some(fds) = &mut self.fds {
+            fds.load_state(state)?;
+        }
+        if let Some(sunsoft5b) = &mut self.sunsoft5b {
+            sunsoft5b.load_state(state)?;
+        }
+        for (_, board) in &mut self.chips {
+            board.load_state(state)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+//! Contains the FM synthesizer in VRC7, a cut down Yamaha YM2413 (OPLL): 6
+//! channels of 2 operators each (a modulator that bends the phase of a
+//! carrier, whose output is the channel's), playing one of 15 instruments
+//! baked into the chip or 1 custom one.
+//!
+//! The registers (written through the address and data ports):
+//! * $00 - $07: the custom instrument (same layout as `PATCHES`)
+//! * $10 - $15: F-number low 8 bits
+//! * $20 - $25: sustain (5), key on (4), octave (1 - 3), F-number bit 8 (0)
+//! * $30 - $35: instrument (4 - 7), volume (0 - 3, in 3 dB steps down)
+//!
+//! This isn't a bit exact emulation of the chip's log-sin tables, but it
+//! follows its structure: the phase generators run at the chip's sample
+//! rate, the envelopes go through attack, decay, sustain and release in dB
+//! at the chip's rates, and the tremolo (AM) and vibrato LFOs run at their
+//! frequencies, so the instruments sound like themselves.
+
+use std::f32::consts::TAU;
+
+use crate::savestate::{StateReader, StateWriter};
+
+/// The chip puts out a sample every 72 clocks of its 3.58 MHz crystal,
+/// which is twice the CPU's clock
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+/// The chip's sample rate (in Hz)
+const RATE: f32 = 3_579_545.0 / 72.0;
+/// How loud a channel at full volume is on the mixer's scale
+const VOLUME: f32 = 0.05;
+
+/// The built-in instruments 1 - 15 (instrument 0 is the custom one),
+/// 8 bytes each:
+/// * 0, 1: modulator, carrier: tremolo (7), vibrato (6), sustained (5), key
+///   scale rate (4), frequency multiplier (0 - 3)
+/// * 2: modulator key scale level (6 - 7), modulator total level (0 - 5)
+/// * 3: carrier key scale level (6 - 7), carrier rectified (4), modulator
+///   rectified (3), feedback (0 - 2)
+/// * 4, 5: modulator, carrier: attack rate (4 - 7), decay rate (0 - 3)
+/// * 6, 7: modulator, carrier: sustain level (4 - 7), release rate (0 - 3)
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+/// The frequency multipliers bits 0 - 3 of bytes 0 and 1 pick from
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+/// The key scale level (in dB, at 6 dB an octave) by the top 4 bits of the
+/// F-number, in the highest octave
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+/// The envelope is silent at this attenuation (in dB)
+const SILENT: f32 = 48.0;
+/// How far the modulator bends the carrier's phase at full volume (in
+/// cycles)
+const MODULATION: f32 = 2.0;
+const TREMOLO_HZ: f32 = 3.7;
+const TREMOLO_DB: f32 = 4.8;
+const VIBRATO_HZ: f32 = 6.4;
+/// How far vibrato bends the pitch (in cents)
+const VIBRATO_CENTS: f32 = 14.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+impl Stage {
+    fn from_u8(value: u8) -> Result<Self, String> {
+        match value {
+            0 => Ok(Stage::Attack),
+            1 => Ok(Stage::Decay),
+            2 => Ok(Stage::Sustain),
+            3 => Ok(Stage::Release),
+            4 => Ok(Stage::Off),
+            _ => Err(format!("Savestate has an invalid envelope stage ({value})")),
+        }
+    }
+}
+
+/// The settings of one operator (the modulator or the carrier) of an
+/// instrument
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    /// Decodes operator `op` (0 is the modulator, 1 the carrier) of an
+    /// instrument
+    fn new(patch: &[u8; 8], op: usize) -> Self {
+        Self {
+            tremolo: patch[op] & 0b1000_0000 != 0,
+            vibrato: patch[op] & 0b100_0000 != 0,
+            sustained: patch[op] & 0b10_0000 != 0,
+            key_scale_rate: patch[op] & 0b1_0000 != 0,
+            multiplier: MULTIPLIERS[(patch[op] & 0b1111) as usize],
+            key_scale_level: patch[2 + op] >> 6,
+            rectified: patch[3] & (0b1000 << op) != 0,
+            attack: patch[4 + op] >> 4,
+            decay: patch[4 + op] & 0b1111,
+            sustain_level: patch[6 + op] >> 4,
+            release: patch[6 + op] & 0b1111,
+        }
+    }
+}
+
+/// Where a channel is playing: its pitch and what it means for the rates
+/// and levels of its operators
+struct Pitch {
+    /// cycles per sample at a multiplier of 1
+    step: f32,
+    /// 0 - 15, the octave and the top bit of the F-number
+    key_code: u8,
+    /// the key scale level at 6 dB an octave
+    key_scale_level: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Operator {
+    /// where in the sine wave the operator is (in cycles)
+    phase: f32,
+    /// the envelope's attenuation (in dB)
+    attenuation: f32,
+    stage: Stage,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            phase: 0.0,
+            attenuation: SILENT,
+            stage: Stage::Off,
+        }
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.stage = Stage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != Stage::Off {
+            self.stage = Stage::Release;
+        }
+    }
+
+    /// Steps the envelope by a sample
+    fn clock_envelope(&mut self, patch: &OperatorPatch, pitch: &Pitch, sustain: bool) {
+        let rate = |value: u8| {
+            if value == 0 {
+                0
+            } else {
+                let shift = if patch.key_scale_rate { 0 } else { 2 };
+                (value * 4 + (pitch.key_code >> shift)).min(63)
+            }
+        };
+        match self.stage {
+            Stage::Attack => {
+                let rate = rate(patch.attack);
+                if rate >= 60 {
+                    self.attenuation = 0.0;
+                } else if rate >= 4 {
+                    // the attack is exponential: fast at first, slowing
+                    // down as it gets close to full volume
+                    let time = 2.826 / 2f32.powf((rate - 4) as f32 / 4.0);
+                    self.attenuation -= self.attenuation * (6.2 / (time * RATE)).min(1.0);
+                }
+                if self.attenuation < 0.1 {
+                    self.attenuation = 0.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                let sustain_level = patch.sustain_level as f32 * 3.0;
+                self.attenuation += decay_step(rate(patch.decay));
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            // percussive instruments keep decaying while the key is held
+            Stage::Sustain if !patch.sustained => {
+                self.attenuation += decay_step(rate(patch.release))
+            }
+            Stage::Sustain | Stage::Off => {}
+            Stage::Release => {
+                let release = if sustain { 5 } else { patch.release };
+                self.attenuation += decay_step(rate(release));
+            }
+        }
+        if self.attenuation >= SILENT {
+            self.attenuation = SILENT;
+            self.stage = Stage::Off;
+        }
+    }
+
+    /// The operator's wave at `phase` (in cycles), at an attenuation of `db`
+    fn output(&self, phase: f32, rectified: bool, db: f32) -> f32 {
+        let wave = (phase * TAU).sin();
+        if (rectified && wave < 0.0) || self.stage == Stage::Off || db >= 2.0 * SILENT {
+            0.0
+        } else {
+            wave * 10f32.powf(-db / 20.0)
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.f32(self.phase);
+        state.f32(self.attenuation);
+        state.u8(self.stage as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.phase = state.f32()?.fract();
+        self.attenuation = state.f32()?.clamp(0.0, SILENT);
+        self.stage = Stage::from_u8(state.u8()?)?;
+        Ok(())
+    }
+}
+
+/// How many dB a decay at `rate` (0 - 63) drops per sample
+fn decay_step(rate: u8) -> f32 {
+    if rate < 4 {
+        return 0.0;
+    }
+    let time = 19.64 / 2f32.powf((rate - 4) as f32 / 4.0);
+    SILENT / (time * RATE)
+}
+
+#[derive(Debug, Clone, Default)]
+struct Channel {
+    modulator: Operator,
+    carrier: Operator,
+    /// the modulator's last two outputs, which it feeds back into itself
+    feedback: [f32; 2],
+}
+
+/// The synthesizer
+#[derive(Debug, Clone)]
+pub(super) struct Opll {
+    registers: [u8; 0x40],
+    address: u8,
+    channels: [Channel; 6],
+    /// CPU cycles until the next sample
+    divider: u8,
+    /// where the tremolo and the vibrato LFOs are (in cycles)
+    tremolo: f32,
+    vibrato: f32,
+    output: f32,
+}
+
+impl Default for Opll {
+    fn default() -> Self {
+        Self {
+            registers: [0; 0x40],
+            address: 0,
+            channels: Default::default(),
+            divider: CPU_CYCLES_PER_SAMPLE,
+            tremolo: 0.0,
+            vibrato: 0.0,
+            output: 0.0,
+        }
+    }
+}
+
+impl Opll {
+    /// Picks the register the next data write goes to
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    /// Writes to the register last picked
+    pub fn write_data(&mut self, value: u8) {
+        let register = self.address as usize;
+        if register >= self.registers.len() {
+            return;
+        }
+        let old = self.registers[register];
+        self.registers[register] = value;
+        // key on and key off happen on the edges of bit 4 of $20 - $25
+        if let 0x20..=0x25 = register {
+            let channel = &mut self.channels[register - 0x20];
+            match (old & 0x10 != 0, value & 0x10 != 0) {
+                (false, true) => {
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                }
+                (true, false) => {
+                    channel.modulator.key_off();
+                    channel.carrier.key_off();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Advances the chip by a CPU cycle
+    pub fn clock(&mut self) {
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = CPU_CYCLES_PER_SAMPLE;
+            self.output = self.sample();
+        }
+    }
+
+    /// The last sample, on the mixer's scale
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+
+    /// Steps every channel by a sample and mixes them
+    fn sample(&mut self) -> f32 {
+        self.tremolo = (self.tremolo + TREMOLO_HZ / RATE).fract();
+        self.vibrato = (self.vibrato + VIBRATO_HZ / RATE).fract();
+        let tremolo = (1.0 - (self.tremolo * TAU).cos()) / 2.0 * TREMOLO_DB;
+        let vibrato = 2f32.powf((self.vibrato * TAU).sin() * VIBRATO_CENTS / 1200.0);
+
+        let mut sum = 0.0;
+        for index in 0..self.channels.len() {
+            sum += self.sample_channel(index, tremolo, vibrato);
+        }
+        sum * VOLUME
+    }
+
+    fn sample_channel(&mut self, index: usize, tremolo: f32, vibrato: f32) -> f32 {
+        let control = self.registers[0x20 + index];
+        let fnum = self.registers[0x10 + index] as u16 | (control as u16 & 1) << 8;
+        let block = (control >> 1) & 0b111;
+        let sustain = control & 0b10_0000 != 0;
+        let instrument = self.registers[0x30 + index] >> 4;
+        let volume = self.registers[0x30 + index] & 0b1111;
+        let patch = match instrument {
+            0 => self.registers[..8].try_into().unwrap(),
+            instrument => PATCHES[instrument as usize - 1],
+        };
+        let pitch = Pitch {
+            step: fnum as f32 * (1 << block) as f32 / (1 << 19) as f32,
+            key_code: block << 1 | (fnum >> 8) as u8,
+            key_scale_level: (KEY_SCALE_LEVELS[(fnum >> 5) as usize] - 6.0 * (7 - block) as f32)
+                .max(0.0),
+        };
+        let operators = [OperatorPatch::new(&patch, 0), OperatorPatch::new(&patch, 1)];
+        // the attenuation on top of the envelope: key scaling, tremolo and
+        // the modulator's total level or the carrier's volume
+        let levels = [(patch[2] & 0b11_1111) as f32 * 0.75, volume as f32 * 3.0];
+        let attenuation = |op: &OperatorPatch, level: f32| {
+            let key_scale = [0.0, 0.25, 0.5, 1.0][op.key_scale_level as usize];
+            level + pitch.key_scale_level * key_scale + if op.tremolo { tremolo } else { 0.0 }
+        };
+        let step = |op: &OperatorPatch| {
+            pitch.step * op.multiplier * if op.vibrato { vibrato } else { 1.0 }
+        };
+
+        let channel = &mut self.channels[index];
+        let feedback = match patch[3] & 0b111 {
+            0 => 0.0,
+            feedback => {
+                (channel.feedback[0] + channel.feedback[1]) / 2.0 * 2f32.powf(feedback as f32 - 6.0)
+            }
+        };
+        let (modulator, carrier) = (&operators[0], &operators[1]);
+        channel.modulator.clock_envelope(modulator, &pitch, sustain);
+        channel.carrier.clock_envelope(carrier, &pitch, sustain);
+        channel.modulator.phase = (channel.modulator.phase + step(modulator)).fract();
+        channel.carrier.phase = (channel.carrier.phase + step(carrier)).fract();
+
+        let db = channel.modulator.attenuation + attenuation(modulator, levels[0]);
+        let modulation =
+            channel
+                .modulator
+                .output(channel.modulator.phase + feedback, modulator.rectified, db);
+        channel.feedback = [channel.feedback[1], modulation];
+        let db = channel.carrier.attenuation + attenuation(carrier, levels[1]);
+        channel.carrier.output(
+            channel.carrier.phase + modulation * MODULATION,
+            carrier.rectified,
+            db,
+        )
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.registers);
+        state.u8(self.address);
+        for channel in &self.channels {
+            channel.modulator.save_state(state);
+            channel.carrier.save_state(state);
+            state.f32(channel.feedback[0]);
+            state.f32(channel.feedback[1]);
+        }
+        state.u8(self.divider);
+        state.f32(self.tremolo);
+        state.f32(self.vibrato);
+        state.f32(self.output);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.registers)?;
+        self.address = state.u8()?;
+        for channel in self.channels.iter_mut() {
+            channel.modulator.load_state(state)?;
+            channel.carrier.load_state(state)?;
+            channel.feedback = [state.f32()?, state.f32()?];
+        }
+        self.divider = state.u8()?.clamp(1, CPU_CYCLES_PER_SAMPLE);
+        self.tremolo = state.f32()?.fract();
+        self.vibrato = state.f32()?.fract();
+        self.output = state.f32()?;
+        Ok(())
+    }
+}
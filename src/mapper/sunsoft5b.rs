@@ -0,0 +1,247 @@
+//! Contains the Sunsoft 5B's audio, a licensed copy of Yamaha's YM2149 (the
+//! AY-3-8910 with a finer envelope) inside the FME-7 on Gimmick!'s board.
+//! It has three square wave channels, a noise generator any of them can mix
+//! in and an envelope any of them can take its volume from.
+//!
+//! It's written through an address port ($C000) and a data port ($E000),
+//! which reach 16 registers:
+//! * 0 - 5: the channels' 12 bit periods (low 8 bits, high 4 bits)
+//! * 6: the noise period (5 bits)
+//! * 7: disable the tones (bits 0 - 2) and the noise (bits 3 - 5)
+//! * 8 - 10: the channels' volumes (0 - 3), or the envelope instead (4)
+//! * 11 - 12: the envelope's 16 bit period
+//! * 13: the envelope's shape: hold (0), alternate (1), attack (2),
+//!   continue (3)
+//!
+//! The tones and the noise count in units of 16 CPU cycles and the
+//! envelope in units of 8, so a tone plays at 1.79 MHz / (32 * period) and
+//! the 32 step envelope repeats at 1.79 MHz / (256 * period). Its DAC is
+//! logarithmic: every volume step is 3 dB and every envelope step 1.5 dB.
+
+use crate::savestate::{StateReader, StateWriter};
+
+/// How loud a channel at full volume is, so a channel is a little louder
+/// than an APU pulse at full volume (like on the real thing)
+const VOLUME: f32 = 0.12;
+/// The attenuation of an envelope step (a volume step is two)
+const STEP_DB: f32 = 1.5;
+
+const NOISE_PERIOD: usize = 6;
+const MIXER: usize = 7;
+const VOLUMES: usize = 8;
+const ENVELOPE_PERIOD: usize = 11;
+const ENVELOPE_SHAPE: usize = 13;
+
+// envelope shape bits
+const HOLD: u8 = 0b0001;
+const ALTERNATE: u8 = 0b0010;
+const ATTACK: u8 = 0b0100;
+const CONTINUE: u8 = 0b1000;
+
+/// The envelope volume bit of registers 8 - 10
+const USE_ENVELOPE: u8 = 0b1_0000;
+
+/// Sunsoft 5B audio
+#[derive(Debug, Clone)]
+pub(super) struct Sunsoft5b {
+    address: u8,
+    registers: [u8; 16],
+    /// counts CPU cycles, the tones and the noise step every 16 of them and
+    /// the envelope every 8
+    divider: u8,
+    tone_timers: [u16; 3],
+    tone_high: [bool; 3],
+    noise_timer: u8,
+    /// a 17 bit LFSR, bit 0 is the noise's output
+    noise_shift: u32,
+    envelope_timer: u16,
+    /// 0 - 31 through the current ramp
+    envelope_step: u8,
+    /// whether the current ramp goes up
+    envelope_attack: bool,
+    /// whether the envelope is done and sits at its last level
+    envelope_holding: bool,
+    /// the amplitude of each of the 32 levels (0 is silent)
+    levels: [f32; 32],
+}
+
+impl Default for Sunsoft5b {
+    fn default() -> Self {
+        let mut levels = [0.0; 32];
+        for (level, amplitude) in levels.iter_mut().enumerate().skip(1) {
+            *amplitude = 10f32.powf((level as f32 - 31.0) * STEP_DB / 20.0);
+        }
+        Self {
+            address: 0,
+            registers: [0; 16],
+            divider: 0,
+            tone_timers: [0; 3],
+            tone_high: [false; 3],
+            noise_timer: 0,
+            noise_shift: 1,
+            envelope_timer: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+            levels,
+        }
+    }
+}
+
+impl Sunsoft5b {
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        // the upper 4 bits of the address have to be 0
+        if self.address > 0xF {
+            return;
+        }
+        self.registers[self.address as usize] = value;
+        if self.address as usize == ENVELOPE_SHAPE {
+            self.envelope_step = 0;
+            self.envelope_attack = value & ATTACK != 0;
+            self.envelope_holding = false;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let low = self.registers[channel * 2] as u16;
+        let high = (self.registers[channel * 2 + 1] & 0xF) as u16;
+        (high << 8 | low).max(1)
+    }
+
+    fn envelope_period(&self) -> u16 {
+        let low = self.registers[ENVELOPE_PERIOD] as u16;
+        let high = self.registers[ENVELOPE_PERIOD + 1] as u16;
+        (high << 8 | low).max(1)
+    }
+
+    /// Advances the chip by a CPU cycle
+    pub fn clock(&mut self) {
+        self.divider = (self.divider + 1) % 16;
+        if self.divider.is_multiple_of(8) {
+            self.clock_envelope();
+        }
+        if self.divider != 0 {
+            return;
+        }
+        for channel in 0..3 {
+            self.tone_timers[channel] += 1;
+            if self.tone_timers[channel] >= self.tone_period(channel) {
+                self.tone_timers[channel] = 0;
+                self.tone_high[channel] = !self.tone_high[channel];
+            }
+        }
+        self.noise_timer += 1;
+        if self.noise_timer >= (self.registers[NOISE_PERIOD] & 0b1_1111).max(1) {
+            self.noise_timer = 0;
+            let feedback = (self.noise_shift ^ self.noise_shift >> 3) & 1;
+            self.noise_shift = self.noise_shift >> 1 | feedback << 16;
+        }
+    }
+
+    /// Moves the envelope a step along, and at the end of a ramp either
+    /// holds it, turns it around or starts it over as the shape says
+    fn clock_envelope(&mut self) {
+        self.envelope_timer += 1;
+        if self.envelope_timer < self.envelope_period() {
+            return;
+        }
+        self.envelope_timer = 0;
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+        let shape = self.registers[ENVELOPE_SHAPE];
+        if shape & CONTINUE == 0 {
+            // drops to silence
+            (
+                self.envelope_holding,
+                self.envelope_attack,
+                self.envelope_step,
+            ) = (true, false, 31);
+        } else if shape & HOLD != 0 {
+            if shape & ALTERNATE != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            (self.envelope_holding, self.envelope_step) = (true, 31);
+        } else {
+            if shape & ALTERNATE != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    /// The level (0 - 31) `channel` plays at right now
+    fn channel_level(&self, channel: usize) -> u8 {
+        let mixer = self.registers[MIXER];
+        let tone = self.tone_high[channel] || mixer & 1 << channel != 0;
+        let noise = self.noise_shift & 1 != 0 || mixer & 1 << (channel + 3) != 0;
+        if !(tone && noise) {
+            return 0;
+        }
+        let volume = self.registers[VOLUMES + channel];
+        if volume & USE_ENVELOPE != 0 {
+            self.envelope_level()
+        } else if volume & 0xF == 0 {
+            0
+        } else {
+            (volume & 0xF) * 2 + 1
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let sum: f32 = (0..3)
+            .map(|channel| self.levels[self.channel_level(channel) as usize])
+            .sum();
+        sum * VOLUME
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.address);
+        state.bytes(&self.registers);
+        state.u8(self.divider);
+        for (timer, high) in self.tone_timers.iter().zip(&self.tone_high) {
+            state.u16(*timer);
+            state.bool(*high);
+        }
+        state.u8(self.noise_timer);
+        state.u64(self.noise_shift as u64);
+        state.u16(self.envelope_timer);
+        state.u8(self.envelope_step);
+        state.bool(self.envelope_attack);
+        state.bool(self.envelope_holding);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.address = state.u8()?;
+        state.bytes(&mut self.registers)?;
+        self.divider = state.u8()? % 16;
+        for (timer, high) in self.tone_timers.iter_mut().zip(self.tone_high.iter_mut()) {
+            *timer = state.u16()?;
+            *high = state.bool()?;
+        }
+        self.noise_timer = state.u8()?;
+        // an LFSR of all zeros would never make noise again
+        self.noise_shift = (state.u64()? as u32 & 0x1_FFFF).max(1);
+        self.envelope_timer = state.u16()?;
+        self.envelope_step = state.u8()? & 0b1_1111;
+        self.envelope_attack = state.bool()?;
+        self.envelope_holding = state.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+//! Contains Konami's VRC2 and VRC4 (mappers 21, 22, 23 and 25), and the IRQ
+//! counter VRC4, VRC6 and VRC7 share.
+//!
+//! VRC2 and VRC4 switch two 8 KiB PRG banks and eight 1 KiB CHR banks. Every
+//! register is at the start of a 4 KiB block ($8000, $9000, ..., $F000)
+//! plus a register number 0 - 3, which comes from two CPU address lines.
+//! Which two is down to how the chip is wired on the board, and the mapper
+//! numbers lump several wirings together:
+//!
+//! | Mapper | Submapper | Board  | Register bits |
+//! |--------|-----------|--------|---------------|
+//! | 21     | 1         | VRC4a  | A1, A2        |
+//! | 21     | 2         | VRC4c  | A6, A7        |
+//! | 22     | -         | VRC2a  | A1, A0        |
+//! | 23     | 1         | VRC4f  | A0, A1        |
+//! | 23     | 2         | VRC4e  | A2, A3        |
+//! | 23     | 3         | VRC2b  | A0, A1        |
+//! | 25     | 1         | VRC4b  | A1, A0        |
+//! | 25     | 2         | VRC4d  | A3, A2        |
+//! | 25     | 3         | VRC2c  | A1, A0        |
+//!
+//! Without a submapper (iNES headers) both wirings of a mapper number are
+//! listened to at once, which works because games only ever write to the
+//! addresses their wiring uses. VRC2a drops the low bit of the CHR banks.
+//!
+//! The registers (by block and register number):
+//! * $8000: PRG bank at 0x8000 (or 0xC000 in swap mode)
+//! * $9000 - $9001: mirroring (VRC2 only has vertical and horizontal)
+//! * $9002: PRG swap mode (bit 1, VRC4 only)
+//! * $A000: PRG bank at 0xA000 (0xE000 and 0xC000 or 0x8000 are fixed to
+//!   the last two banks)
+//! * $B000 - $E003: the CHR banks, two per block (low nibble in register 0
+//!   or 2, high bits in 1 or 3)
+//! * $F000 - $F003: the IRQ (VRC4 only, see `VrcIrq`)
+
+use super::{Cartridge, Mapper, PRG_RAM_SIZE};
+use crate::ppu::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+/// The IRQ counter of VRC4, VRC6 and VRC7. It counts up from a reloadable
+/// value to 0xFF and raises the IRQ when it wraps around, either every CPU
+/// cycle or every scanline. Scanlines are approximated with a prescaler
+/// that counts 341 PPU dots (3 per CPU cycle), so it keeps counting when
+/// rendering is off, like on the real thing.
+/// * latch: the value the counter reloads
+/// * control: bit 0 re-enables the IRQ on acknowledge, bit 1 enables it
+///   (and reloads the counter), bit 2 picks cycle mode
+/// * acknowledge: clears the IRQ and copies bit 0 of control to bit 1
+#[derive(Debug, Clone, Default)]
+pub(super) struct VrcIrq {
+    pub latch: u8,
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    counter: u8,
+    prescaler: i16,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0b001 != 0;
+        self.enabled = value & 0b010 != 0;
+        self.cycle_mode = value & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Advances the counter by a CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += 341;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.latch);
+        state.bool(self.enable_after_ack);
+        state.bool(self.enabled);
+        state.bool(self.cycle_mode);
+        state.u8(self.counter);
+        state.u16(self.prescaler as u16);
+        state.bool(self.pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.latch = state.u8()?;
+        self.enable_after_ack = state.bool()?;
+        self.enabled = state.bool()?;
+        self.cycle_mode = state.bool()?;
+        self.counter = state.u8()?;
+        self.prescaler = (state.u16()? as i16).clamp(-2, 341);
+        self.pending = state.bool()?;
+        Ok(())
+    }
+}
+
+/// Turns the 2 bit mirroring value VRC4, VRC6 and VRC7 use into a mirroring
+pub(super) fn vrc_mirroring(value: u8) -> Mirroring {
+    match value & 0b11 {
+        0 => Mirroring::Vertical,
+        1 => Mirroring::Horizontal,
+        2 => Mirroring::SingleScreenLower,
+        _ => Mirroring::SingleScreenUpper,
+    }
+}
+
+/// VRC2 / VRC4 (mappers 21, 22, 23 and 25)
+pub(super) struct Vrc {
+    cartridge: Cartridge,
+    /// the address lines that make up bit 0 and bit 1 of the register
+    /// number (several when the wiring isn't known)
+    register_lines: [u16; 2],
+    vrc2: bool,
+    /// VRC2a wires the CHR bank lines shifted by one
+    chr_shift: u8,
+    prg_registers: [u8; 2],
+    swap_mode: bool,
+    chr_registers: [u16; 8],
+    irq: VrcIrq,
+}
+
+impl Vrc {
+    pub fn new(rom: &Rom) -> Self {
+        let (register_lines, vrc2) = match (rom.mapper, rom.submapper) {
+            (21, 1) => ([0x02, 0x04], false),
+            (21, 2) => ([0x40, 0x80], false),
+            (21, _) => ([0x42, 0x84], false),
+            (22, _) => ([0x02, 0x01], true),
+            (23, 1) => ([0x01, 0x02], false),
+            (23, 2) => ([0x04, 0x08], false),
+            (23, 3) => ([0x01, 0x02], true),
+            (23, _) => ([0x05, 0x0A], false),
+            (25, 1) => ([0x02, 0x01], false),
+            (25, 2) => ([0x08, 0x04], false),
+            (25, 3) => ([0x02, 0x01], true),
+            _ => ([0x0A, 0x05], false),
+        };
+        let mut vrc = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+            register_lines,
+            vrc2,
+            chr_shift: if rom.mapper == 22 { 1 } else { 0 },
+            prg_registers: [0; 2],
+            swap_mode: false,
+            chr_registers: [0; 8],
+            irq: VrcIrq::default(),
+        };
+        vrc.power_cycle();
+        vrc
+    }
+
+    /// The register number (0 - 3) a write to `addr` is for
+    fn register(&self, addr: u16) -> u16 {
+        let [low, high] = self.register_lines;
+        (addr & low != 0) as u16 | ((addr & high != 0) as u16) << 1
+    }
+
+    fn update_banks(&mut self) {
+        let second_last = self.cartridge.prg_bank_count(0x2000).saturating_sub(2);
+        let [first, second] = self.prg_registers.map(|bank| (bank & 0b1_1111) as usize);
+        let (bank_8000, bank_c000) = if self.swap_mode {
+            (second_last, first)
+        } else {
+            (first, second_last)
+        };
+        self.cartridge.map_prg(0, 0x2000, bank_8000);
+        self.cartridge.map_prg(1, 0x2000, second);
+        self.cartridge.map_prg(2, 0x2000, bank_c000);
+        self.cartridge.map_prg(3, 0x2000, second_last + 1);
+        for (slot, bank) in self.chr_registers.iter().enumerate() {
+            let bank = (*bank >> self.chr_shift) as usize;
+            self.cartridge.map_chr(slot, 0x400, bank);
+        }
+    }
+}
+
+impl Mapper for Vrc {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let register = self.register(addr);
+        match (addr & 0xF000, register) {
+            (0x8000, _) => self.prg_registers[0] = data,
+            (0x9000, 0..=1) if self.vrc2 => self.cartridge.set_mirroring(vrc_mirroring(data & 0b1)),
+            (0x9000, 0..=1) => self.cartridge.set_mirroring(vrc_mirroring(data)),
+            (0x9000, _) if !self.vrc2 => self.swap_mode = data & 0b10 != 0,
+            (0xA000, _) => self.prg_registers[1] = data,
+            (0xB000..=0xE000, _) => {
+                let slot = ((addr & 0xF000) - 0xB000) as usize / 0x800 + (register >> 1) as usize;
+                let bank = &mut self.chr_registers[slot];
+                *bank = if register & 1 == 0 {
+                    (*bank & !0xF) | (data & 0xF) as u16
+                } else {
+                    (*bank & 0xF) | ((data & 0b1_1111) as u16) << 4
+                };
+            }
+            (0xF000, _) if self.vrc2 => {}
+            (0xF000, 0) => self.irq.latch = (self.irq.latch & 0xF0) | (data & 0xF),
+            (0xF000, 1) => self.irq.latch = (self.irq.latch & 0xF) | data << 4,
+            (0xF000, 2) => self.irq.write_control(data),
+            (0xF000, _) => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+
+    fn power_cycle(&mut self) {
+        self.prg_registers = [0, 1];
+        self.swap_mode = false;
+        self.chr_registers = [0; 8];
+        self.irq = VrcIrq::default();
+        self.cartridge
+            .set_mirroring(self.cartridge.header_mirroring);
+        self.update_banks();
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_registers);
+        state.bool(self.swap_mode);
+        for bank in &self.chr_registers {
+            state.u16(*bank);
+        }
+        self.irq.save_state(state);
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.prg_registers)?;
+        self.swap_mode = state.bool()?;
+        for bank in self.chr_registers.iter_mut() {
+            *bank = state.u16()?;
+        }
+        self.irq.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
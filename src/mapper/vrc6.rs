@@ -0,0 +1,321 @@
+//! Contains Konami's VRC6 (mappers 24 and 26, the latter with address lines
+//! A0 and A1 swapped), made for Akumajou Densetsu, Madara and Esper Dream 2.
+//! It switches a 16 KiB and an 8 KiB PRG bank and eight 1 KiB CHR banks, has
+//! the VRC IRQ counter and adds three channels to the audio: two pulses with
+//! 8 duty cycles and a sawtooth.
+//!
+//! The registers (by block and register number):
+//! * $8000: 16 KiB PRG bank at 0x8000
+//! * $9000 - $9002, $A000 - $A002: pulse 1 and 2
+//! * $9003: audio halt (bit 0) and frequency shift (bits 1, 2)
+//! * $B000 - $B002: sawtooth
+//! * $B003: PPU banking mode, mirroring in bits 2 - 3 (only the 1 KiB CHR
+//!   bank mode the three games use is emulated)
+//! * $C000: 8 KiB PRG bank at 0xC000 (0xE000 is fixed to the last bank)
+//! * $D000 - $E003: the CHR banks
+//! * $F000 - $F002: the IRQ latch, control and acknowledge (see `VrcIrq`)
+
+use super::vrc::{VrcIrq, vrc_mirroring};
+use super::{Cartridge, Mapper, PRG_RAM_SIZE};
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+/// How loud a step of the channels' 0 - 61 output is, so a VRC6 pulse is
+/// about as loud as an APU pulse at the same volume
+const VOLUME: f32 = 0.0075;
+
+/// A VRC6 pulse, written through 3 registers:
+/// * 0: mode (7, a constant volume), duty cycle (4 - 6), volume (0 - 3)
+/// * 1: period low 8 bits
+/// * 2: enable (7), period high 4 bits (0 - 3)
+#[derive(Debug, Clone, Default)]
+struct Pulse {
+    constant: bool,
+    duty: u8,
+    volume: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    /// counts down from 15, the channel is high while it's <= the duty
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.constant = value & 0b1000_0000 != 0;
+                self.duty = (value >> 4) & 0b111;
+                self.volume = value & 0b1111;
+            }
+            1 => self.period = (self.period & 0xF00) | value as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((value & 0b1111) as u16) << 8;
+                self.enabled = value & 0b1000_0000 != 0;
+                // disabling resets the duty cycle
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.checked_sub(1).unwrap_or(15);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.constant);
+        state.u8(self.duty);
+        state.u8(self.volume);
+        state.bool(self.enabled);
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u8(self.step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.constant = state.bool()?;
+        self.duty = state.u8()? & 0b111;
+        self.volume = state.u8()? & 0b1111;
+        self.enabled = state.bool()?;
+        self.period = state.u16()? & 0xFFF;
+        self.timer = state.u16()?;
+        self.step = state.u8()? & 0b1111;
+        Ok(())
+    }
+}
+
+/// The VRC6 sawtooth, written through 3 registers:
+/// * 0: accumulator rate (0 - 5)
+/// * 1: period low 8 bits
+/// * 2: enable (7), period high 4 bits (0 - 3)
+///
+/// Every other timer clock it adds the rate to an accumulator, whose top 5
+/// bits are the output, and after 7 additions it starts over from 0
+#[derive(Debug, Clone, Default)]
+struct Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    /// 0 - 13, the accumulator grows on the odd steps
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0b11_1111,
+            1 => self.period = (self.period & 0xF00) | value as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((value & 0b1111) as u16) << 8;
+                self.enabled = value & 0b1000_0000 != 0;
+                if !self.enabled {
+                    (self.step, self.accumulator) = (0, 0);
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            (self.step, self.accumulator) = (0, 0);
+        } else if !self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.rate);
+        state.bool(self.enabled);
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u8(self.step);
+        state.u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.rate = state.u8()? & 0b11_1111;
+        self.enabled = state.bool()?;
+        self.period = state.u16()? & 0xFFF;
+        self.timer = state.u16()?;
+        self.step = state.u8()? % 14;
+        self.accumulator = state.u8()?;
+        Ok(())
+    }
+}
+
+/// VRC6 (mappers 24 and 26)
+pub(super) struct Vrc6 {
+    cartridge: Cartridge,
+    /// mapper 26 swaps the two address lines the register number comes from
+    swapped_lines: bool,
+    prg_registers: [u8; 2],
+    chr_registers: [u8; 8],
+    irq: VrcIrq,
+    pulses: [Pulse; 2],
+    sawtooth: Sawtooth,
+    halted: bool,
+    /// how far the periods are shifted right (4 or 8 in the test modes)
+    shift: u8,
+}
+
+impl Vrc6 {
+    pub fn new(rom: &Rom) -> Self {
+        let mut vrc6 = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+            swapped_lines: rom.mapper == 26,
+            prg_registers: [0; 2],
+            chr_registers: [0; 8],
+            irq: VrcIrq::default(),
+            pulses: Default::default(),
+            sawtooth: Sawtooth::default(),
+            halted: false,
+            shift: 0,
+        };
+        vrc6.power_cycle();
+        vrc6
+    }
+
+    fn update_banks(&mut self) {
+        self.cartridge
+            .map_prg_16k(0, (self.prg_registers[0] & 0b1111) as usize);
+        self.cartridge
+            .map_prg(2, 0x2000, (self.prg_registers[1] & 0b1_1111) as usize);
+        let last = self.cartridge.prg_bank_count(0x2000) - 1;
+        self.cartridge.map_prg(3, 0x2000, last);
+        for (slot, bank) in self.chr_registers.iter().enumerate() {
+            self.cartridge.map_chr(slot, 0x400, *bank as usize);
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let register = if self.swapped_lines {
+            (addr & 0b01) << 1 | (addr & 0b10) >> 1
+        } else {
+            addr & 0b11
+        };
+        match (addr & 0xF000, register) {
+            (0x8000, _) => self.prg_registers[0] = data,
+            (0x9000, 3) => {
+                self.halted = data & 0b001 != 0;
+                self.shift = match data & 0b110 {
+                    0 => 0,
+                    0b010 => 4,
+                    _ => 8,
+                };
+            }
+            (0x9000, register) => self.pulses[0].write(register, data),
+            (0xA000, 3) => {}
+            (0xA000, register) => self.pulses[1].write(register, data),
+            (0xB000, 3) => self.cartridge.set_mirroring(vrc_mirroring(data >> 2)),
+            (0xB000, register) => self.sawtooth.write(register, data),
+            (0xC000, _) => self.prg_registers[1] = data,
+            (0xD000, register) => self.chr_registers[register as usize] = data,
+            (0xE000, register) => self.chr_registers[4 + register as usize] = data,
+            (0xF000, 0) => self.irq.latch = data,
+            (0xF000, 1) => self.irq.write_control(data),
+            (0xF000, 2) => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+
+    fn power_cycle(&mut self) {
+        self.prg_registers = [0, 0];
+        self.chr_registers = [0, 1, 2, 3, 4, 5, 6, 7];
+        self.irq = VrcIrq::default();
+        self.pulses = Default::default();
+        self.sawtooth = Sawtooth::default();
+        (self.halted, self.shift) = (false, 0);
+        self.cartridge
+            .set_mirroring(self.cartridge.header_mirroring);
+        self.update_banks();
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        if !self.halted {
+            for pulse in &mut self.pulses {
+                pulse.clock(self.shift);
+            }
+            self.sawtooth.clock(self.shift);
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let sum = self.pulses[0].output() + self.pulses[1].output() + self.sawtooth.output();
+        sum as f32 * VOLUME
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_registers);
+        state.bytes(&self.chr_registers);
+        self.irq.save_state(state);
+        for pulse in &self.pulses {
+            pulse.save_state(state);
+        }
+        self.sawtooth.save_state(state);
+        state.bool(self.halted);
+        state.u8(self.shift);
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.prg_registers)?;
+        state.bytes(&mut self.chr_registers)?;
+        self.irq.load_state(state)?;
+        for pulse in self.pulses.iter_mut() {
+            pulse.load_state(state)?;
+        }
+        self.sawtooth.load_state(state)?;
+        self.halted = state.bool()?;
+        self.shift = state.u8()?.min(8);
+        self.update_banks();
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Contains Konami's VRC7 (mapper 85), made for Lagrange Point and Tiny Toon
+//! Adventures 2. It switches three 8 KiB PRG banks and eight 1 KiB CHR
+//! banks, has the VRC IRQ counter and an FM synthesizer (see the `opll`
+//! module) on top of the NES's audio.
+//!
+//! Registers come in pairs, the second of which is picked by an address
+//! line that depends on the board: A3 on VRC7b (submapper 1), A4 on VRC7a
+//! (submapper 2), either one without a submapper:
+//! * $8000 / $8010: PRG banks at 0x8000 / 0xA000
+//! * $9000: PRG bank at 0xC000 (0xE000 is fixed to the last bank)
+//! * $9010 / $9030: the synthesizer's address / data port
+//! * $A000 - $D010: the CHR banks
+//! * $E000: mirroring (0 - 1), silence the synthesizer (6)
+//! * $E010 / $F000 / $F010: the IRQ latch, control and acknowledge (see
+//!   `VrcIrq`)
+
+use super::opll::Opll;
+use super::vrc::{VrcIrq, vrc_mirroring};
+use super::{Cartridge, Mapper, PRG_RAM_SIZE};
+use crate::rom::Rom;
+use crate::savestate::{StateReader, StateWriter};
+
+/// VRC7 (mapper 85)
+pub(super) struct Vrc7 {
+    cartridge: Cartridge,
+    /// the address line(s) that pick the second register of a pair
+    second_register: u16,
+    prg_registers: [u8; 3],
+    chr_registers: [u8; 8],
+    control: u8,
+    irq: VrcIrq,
+    opll: Opll,
+}
+
+impl Vrc7 {
+    pub fn new(rom: &Rom) -> Self {
+        let mut vrc7 = Self {
+            cartridge: Cartridge::new(rom, PRG_RAM_SIZE),
+            second_register: match rom.submapper {
+                1 => 0x08,
+                2 => 0x10,
+                _ => 0x18,
+            },
+            prg_registers: [0; 3],
+            chr_registers: [0; 8],
+            control: 0,
+            irq: VrcIrq::default(),
+            opll: Opll::default(),
+        };
+        vrc7.power_cycle();
+        vrc7
+    }
+
+    fn update_banks(&mut self) {
+        for (slot, bank) in self.prg_registers.iter().enumerate() {
+            self.cartridge
+                .map_prg(slot, 0x2000, (bank & 0b11_1111) as usize);
+        }
+        let last = self.cartridge.prg_bank_count(0x2000) - 1;
+        self.cartridge.map_prg(3, 0x2000, last);
+        for (slot, bank) in self.chr_registers.iter().enumerate() {
+            self.cartridge.map_chr(slot, 0x400, *bank as usize);
+        }
+        self.cartridge.set_mirroring(vrc_mirroring(self.control));
+    }
+
+    fn silenced(&self) -> bool {
+        self.control & 0b0100_0000 != 0
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        // the synthesizer's ports are at the same addresses on both boards
+        match addr & 0xF030 {
+            0x9010 => return self.opll.write_address(data),
+            0x9030 => return self.opll.write_data(data),
+            _ => {}
+        }
+        let second = addr & self.second_register != 0;
+        match (addr & 0xF000, second) {
+            (0x8000, false) => self.prg_registers[0] = data,
+            (0x8000, true) => self.prg_registers[1] = data,
+            (0x9000, false) => self.prg_registers[2] = data,
+            (0xA000..=0xD000, second) => {
+                let slot = ((addr & 0xF000) - 0xA000) as usize / 0x800 + second as usize;
+                self.chr_registers[slot] = data;
+            }
+            (0xE000, false) => self.control = data,
+            (0xE000, true) => self.irq.latch = data,
+            (0xF000, false) => self.irq.write_control(data),
+            (0xF000, true) => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+
+    fn power_cycle(&mut self) {
+        self.prg_registers = [0, 1, 2];
+        self.chr_registers = [0, 1, 2, 3, 4, 5, 6, 7];
+        self.control = 0;
+        self.irq = VrcIrq::default();
+        self.opll = Opll::default();
+        self.update_banks();
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.opll.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.silenced() {
+            0.0
+        } else {
+            self.opll.output()
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn save_registers(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_registers);
+        state.bytes(&self.chr_registers);
+        state.u8(self.control);
+        self.irq.save_state(state);
+        self.opll.save_state(state);
+    }
+
+    fn load_registers(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.prg_registers)?;
+        state.bytes(&mut self.chr_registers)?;
+        self.control = state.u8()?;
+        self.irq.load_state(state)?;
+        self.opll.load_state(state)?;
+        self.update_banks();
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Contains the importer for BizHawk `.bk2` movies. A `.bk2` file is a ZIP
+//! archive holding (amongst others):
+//!
+//! * `Header.txt`: `key value` lines, i.e. `Platform NES`, `GameName ...`,
+//!   `SHA1 ...` (of the ROM without its iNES header), `rerecordCount 12`
+//! * `Input Log.txt`: the input, between `[Input]` and `[/Input]`:
+//!
+//! ```text
+//! [Input]
+//! LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|...
+//! |..|........|........|
+//! |r.|....S...|........|
+//! [/Input]
+//! ```
+//!
+//! The log key names the columns: groups start with `#` and every button in
+//! a group is one character of the matching `|` field on the input lines,
+//! `.` when it isn't pressed. For the NES the first group holds the console's
+//! Reset and Power buttons, followed by a group per controller: Up, Down,
+//! Left, Right, Start, Select, B, A (written `UDLRSsBA`). Columns are mapped
+//! by the names in the log key, not by the letters, so the order doesn't
+//! matter as long as the key lists it.
+//!
+//! Imported movies turn into regular `Movie`s, so they play back through
+//! `MoviePlayer` like `.fm2` movies do.
+
+use std::path::Path;
+
+use super::{COMMAND_HARD_RESET, COMMAND_SOFT_RESET, FrameInput, Movie, hex};
+use crate::gamepad::{BUTTON_A, BUTTON_B, DOWN, LEFT, RIGHT, SELECT, START, UP};
+use crate::rom::Rom;
+use crate::zip::ZipArchive;
+
+/// The columns NES movies have when the log key is missing
+const DEFAULT_LOG_KEY: &str = "#Reset|Power|\
+    #P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|\
+    #P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|";
+
+/// What a column of the input log does
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Column {
+    Command(u8),
+    Button { port: usize, button: u8 },
+}
+
+impl Movie {
+    /// Imports the contents of a BizHawk `.bk2` file. The movie has to be for
+    /// the NES and start from power on, and if it names the ROM it was
+    /// recorded with (by SHA-1) that has to be `rom`
+    pub fn from_bk2(archive: &[u8], rom: &Rom) -> Result<Self, String> {
+        let archive = ZipArchive::new(archive)?;
+        let text = |name: &str| {
+            archive
+                .read(name)
+                .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+        };
+
+        let mut movie = Movie::new("", rom.checksum());
+        let mut platform = None;
+        for line in text("Header.txt")?.lines() {
+            let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match key {
+                "Platform" => platform = Some(value.to_string()),
+                "GameName" => movie.rom_filename = value.to_string(),
+                "Author" => movie.comments.push(format!("author {value}")),
+                "rerecordCount" => {
+                    movie.rerecord_count = value
+                        .parse()
+                        .map_err(|_| format!("Invalid rerecordCount {value}"))?;
+                }
+                "PAL" => movie.pal = value.eq_ignore_ascii_case("true"),
+                "SHA1" => {
+                    let expected = value.trim_start_matches("SHA1:").to_ascii_lowercase();
+                    let actual = hex(&rom.sha1());
+                    if expected != actual {
+                        return Err(format!(
+                            "Movie desync: it was recorded with ROM {} (SHA-1 {expected}) but \
+                             the ROM has SHA-1 {actual}",
+                            movie.rom_filename
+                        ));
+                    }
+                }
+                "StartsFromSavestate" | "StartsFromSaveRam"
+                    if value.eq_ignore_ascii_case("true") =>
+                {
+                    return Err(format!(
+                        "BizHawk movies that start from a {} are not supported",
+                        if key == "StartsFromSavestate" {
+                            "savestate"
+                        } else {
+                            "SaveRAM"
+                        }
+                    ));
+                }
+                // everything else doesn't matter to us
+                _ => {}
+            }
+        }
+        match platform.as_deref() {
+            Some("NES") => {}
+            Some(platform) => return Err(format!("Not an NES movie (platform {platform})")),
+            None => return Err("Movie header has no Platform".to_string()),
+        }
+
+        if let Some(entry) = archive.entry("Comments.txt") {
+            let comments = String::from_utf8_lossy(&archive.read_entry(entry)?).to_string();
+            movie.comments.extend(
+                comments
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(String::from),
+            );
+        }
+
+        let log = text("Input Log.txt")?;
+        let mut groups = parse_log_key(DEFAULT_LOG_KEY)?;
+        let mut in_input = false;
+        for (line_num, line) in log.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let error = |msg: String| format!("Input Log.txt line {}: {msg}", line_num + 1);
+            if line == "[Input]" {
+                in_input = true;
+            } else if line == "[/Input]" {
+                in_input = false;
+            } else if let Some(key) = line.strip_prefix("LogKey:") {
+                groups = parse_log_key(key).map_err(error)?;
+            } else if in_input && line.starts_with('|') {
+                movie
+                    .frames
+                    .push(parse_input(line, &groups).map_err(error)?);
+            }
+        }
+
+        for port in 0..2 {
+            movie.gamepads[port] = groups.iter().flatten().any(|column| {
+                matches!(column, Column::Button { port: button_port, .. } if *button_port == port)
+            });
+        }
+        Ok(movie)
+    }
+
+    /// Imports a BizHawk `.bk2` file
+    pub fn load_bk2<P: AsRef<Path>>(path: P, rom: &Rom) -> Result<Self, String> {
+        let path = path.as_ref();
+        let archive = std::fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::from_bk2(&archive, rom).map_err(|err| format!("{}: {err}", path.display()))
+    }
+}
+
+/// Parses the column groups of a log key (`#Reset|Power|#P1 Up|...`)
+fn parse_log_key(key: &str) -> Result<Vec<Vec<Column>>, String> {
+    key.split('#')
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            group
+                .split('|')
+                .filter(|name| !name.is_empty())
+                .map(parse_column)
+                .collect()
+        })
+        .collect()
+}
+
+fn parse_column(name: &str) -> Result<Column, String> {
+    let unsupported = || Err(format!("Unsupported input {name}"));
+    match name {
+        "Reset" => return Ok(Column::Command(COMMAND_SOFT_RESET)),
+        "Power" => return Ok(Column::Command(COMMAND_HARD_RESET)),
+        _ => {}
+    }
+    let Some((player, button)) = name.split_once(' ') else {
+        return unsupported();
+    };
+    let port = match player {
+        "P1" => 0,
+        "P2" => 1,
+        _ => return unsupported(),
+    };
+    let button = match button {
+        "Up" => UP,
+        "Down" => DOWN,
+        "Left" => LEFT,
+        "Right" => RIGHT,
+        "Start" => START,
+        "Select" => SELECT,
+        "B" => BUTTON_B,
+        "A" => BUTTON_A,
+        _ => return unsupported(),
+    };
+    Ok(Column::Button { port, button })
+}
+
+/// Parses an input line, which has a `|` separated field per column group
+fn parse_input(line: &str, groups: &[Vec<Column>]) -> Result<FrameInput, String> {
+    let fields = line
+        .trim_end()
+        .trim_matches('|')
+        .split('|')
+        .collect::<Vec<_>>();
+    if fields.len() != groups.len() {
+        return Err(format!("Invalid input line {line}"));
+    }
+    let mut input = FrameInput::default();
+    for (field, columns) in fields.iter().zip(groups) {
+        if field.chars().count() != columns.len() {
+            return Err(format!("Invalid input {field}"));
+        }
+        for (letter, column) in field.chars().zip(columns) {
+            if letter == '.' || letter == ' ' {
+                continue;
+            }
+            match *column {
+                Column::Command(command) => input.commands |= command,
+                Column::Button { port, button } => input.gamepads[port] |= button,
+            }
+        }
+    }
+    Ok(input)
+}
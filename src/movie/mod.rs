@@ -0,0 +1,528 @@
+//! Contains input movies: recordings of what was pressed on the controllers
+//! every frame, which replay a game exactly when played back from the same
+//! starting point. They're saved in the FCEUX `.fm2` text format:
+//!
+//! ```text
+//! version 3
+//! romFilename game.nes
+//! romChecksum base64:jmY2nqnWe4GoHO6bIQiVKA==
+//! guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+//! port0 1
+//! port1 1
+//! |0|....T..A|........||
+//! |1|........|........||
+//! ```
+//!
+//! A header of `key value` lines is followed by one line per frame,
+//! `|commands|port 1|port 2|expansion port|`. Each controller field lists the
+//! buttons `RLDUTSBA` (Right, Left, Down, Up, sTart, Select, B, A) with a `.`
+//! for every button that isn't pressed. Commands are flags: 1 presses the
+//! reset button and 2 power cycles the console before the frame runs.
+//!
+//! Movies start from power on, or from a savestate that's embedded in the
+//! header (`savestate base64:...`). The savestate is one made by this
+//! emulator, so movies FCEUX recorded from one of its own savestates can't be
+//! played back.
+//!
+//! BizHawk `.bk2` movies can be imported too (see `Movie::from_bk2`).
+//!
+//! Input only gets picked up at frame boundaries, so anything driving the
+//! controllers while recording should change them between frames: between
+//! runs stopped by a frame hook, or from a frame hook that's registered after
+//! the recording started (hooks run in the order they were added, and the
+//! recorder has to see what was held during the frame that just ended).
+
+mod bk2;
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::cpu::CPU;
+use crate::cpu::hooks::{HookAction, HookId};
+use crate::region::Region;
+
+/// Presses the reset button before the frame runs
+pub const COMMAND_SOFT_RESET: u8 = 0b01;
+/// Power cycles the console before the frame runs
+pub const COMMAND_HARD_RESET: u8 = 0b10;
+
+/// The controller buttons as they appear in the input lines, from bit 7 down
+/// to bit 0 (see the `gamepad` module)
+const BUTTON_LETTERS: &[u8; 8] = b"RLDUTSBA";
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// What happens during a single frame
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FrameInput {
+    /// `COMMAND_SOFT_RESET` and/or `COMMAND_HARD_RESET`
+    pub commands: u8,
+    /// the buttons held on the controllers in port 1 and 2
+    pub gamepads: [u8; 2],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_filename: String,
+    /// the MD5 of the ROM the movie was recorded with
+    pub rom_checksum: [u8; 16],
+    /// identifies the movie (across rerecords)
+    pub guid: String,
+    pub rerecord_count: u32,
+    /// whether the movie was recorded on a PAL console
+    pub pal: bool,
+    /// whether there's a controller in port 1 and 2
+    pub gamepads: [bool; 2],
+    /// the `comment` lines of the header (i.e. `author Someone`)
+    pub comments: Vec<String>,
+    /// the savestate the movie starts from (None to start from power on)
+    pub savestate: Option<Vec<u8>>,
+    pub frames: Vec<FrameInput>,
+}
+
+impl Movie {
+    /// Instantiates an empty movie that starts from power on, with
+    /// controllers in both ports
+    pub fn new(rom_filename: &str, rom_checksum: [u8; 16]) -> Self {
+        Self {
+            rom_filename: rom_filename.to_string(),
+            rom_checksum,
+            guid: new_guid(),
+            rerecord_count: 0,
+            pal: false,
+            gamepads: [true, true],
+            comments: Vec::new(),
+            savestate: None,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Parses the contents of an `.fm2` file
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut movie = Movie {
+            guid: String::new(),
+            ..Movie::new("", [0; 16])
+        };
+        let mut version = None;
+
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let error = |msg: String| format!("line {}: {msg}", line_num + 1);
+            if line.starts_with('|') {
+                movie.frames.push(parse_input(line).map_err(error)?);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let number = || {
+                value
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| error(format!("Invalid {key} {value}")))
+            };
+            match key {
+                "version" => version = Some(number()?),
+                "rerecordCount" => movie.rerecord_count = number()?,
+                "palFlag" => movie.pal = number()? != 0,
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let checksum = decode_binary(value).map_err(error)?;
+                    movie.rom_checksum = checksum
+                        .try_into()
+                        .map_err(|_| error("ROM checksum isn't an MD5".to_string()))?;
+                }
+                "guid" => movie.guid = value.to_string(),
+                "comment" => movie.comments.push(value.to_string()),
+                "savestate" => movie.savestate = Some(decode_binary(value).map_err(error)?),
+                "port0" | "port1" => {
+                    let port = (key == "port1") as usize;
+                    movie.gamepads[port] = match number()? {
+                        0 => false,
+                        1 => true,
+                        _ => return Err(error(format!("Only controllers are supported in {key}"))),
+                    };
+                }
+                "fourscore" | "binary" if number()? != 0 => {
+                    return Err(error(format!("{key} movies are not supported")));
+                }
+                // everything else doesn't matter to us
+                _ => {}
+            }
+        }
+
+        match version {
+            Some(3) => Ok(movie),
+            Some(version) => Err(format!("Unsupported movie version {version}")),
+            None => Err("Movie has no version line".to_string()),
+        }
+    }
+
+    /// Reads an `.fm2` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::parse(&text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// Formats the movie as an `.fm2` file
+    pub fn to_fm2(&self) -> String {
+        let mut text = format!(
+            "version 3\n\
+             emuVersion 22020\n\
+             rerecordCount {}\n\
+             palFlag {}\n\
+             romFilename {}\n\
+             romChecksum base64:{}\n\
+             guid {}\n\
+             fourscore 0\n\
+             microphone 0\n\
+             port0 {}\n\
+             port1 {}\n\
+             port2 0\n\
+             FDS 0\n\
+             NewPPU 0\n",
+            self.rerecord_count,
+            self.pal as u8,
+            self.rom_filename,
+            base64_encode(&self.rom_checksum),
+            self.guid,
+            self.gamepads[0] as u8,
+            self.gamepads[1] as u8,
+        );
+        for comment in &self.comments {
+            text += &format!("comment {comment}\n");
+        }
+        if let Some(savestate) = &self.savestate {
+            text += &format!("savestate base64:{}\n", base64_encode(savestate));
+        }
+        for input in &self.frames {
+            text += &format!("|{}|", input.commands);
+            for port in 0..2 {
+                if self.gamepads[port] {
+                    text.extend(BUTTON_LETTERS.iter().enumerate().map(|(i, &letter)| {
+                        if input.gamepads[port] & (0x80 >> i) != 0 {
+                            letter as char
+                        } else {
+                            '.'
+                        }
+                    }));
+                }
+                text.push('|');
+            }
+            text += "|\n";
+        }
+        text
+    }
+
+    /// Writes the movie out as an `.fm2` file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_fm2()).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// Checks that the movie was recorded with the ROM that has this
+    /// checksum. Playing a movie back on a different ROM (even a slightly
+    /// different revision of the same game) makes it desync
+    pub fn check_rom(&self, rom_checksum: Option<[u8; 16]>) -> Result<(), String> {
+        match rom_checksum {
+            Some(checksum) if checksum == self.rom_checksum => Ok(()),
+            Some(checksum) => Err(format!(
+                "Movie desync: it was recorded with ROM {} (checksum {}) but the ROM \
+                 has checksum {}",
+                self.rom_filename,
+                hex(&self.rom_checksum),
+                hex(&checksum)
+            )),
+            None => Err("Movies need a cartridge".to_string()),
+        }
+    }
+}
+
+/// Plays a movie back by setting the controllers at the start of every frame
+pub struct MoviePlayer {
+    playback: Arc<Mutex<Playback>>,
+    hook: HookId,
+}
+
+struct Playback {
+    movie: Movie,
+    /// the movie frame that's running
+    frame: usize,
+    finished: bool,
+}
+
+impl MoviePlayer {
+    /// Starts playing a movie: checks that it was made with the cartridge
+    /// that's inserted, puts the machine into the state (and region) the
+    /// movie starts from and hooks into the CPU to replay the input. Once
+    /// the movie runs out, the CPU is stopped (once) and the controllers
+    /// are left alone
+    pub fn start(cpu: &mut CPU, movie: Movie) -> Result<Self, String> {
+        movie.check_rom(cpu.bus.rom_checksum())?;
+        // movies only tell PAL apart from the rest, so a Dendy stays one
+        match (movie.pal, cpu.bus.region()) {
+            (true, _) => cpu.bus.set_region(Region::Pal),
+            (false, Region::Pal) => cpu.bus.set_region(Region::Ntsc),
+            _ => {}
+        }
+        match &movie.savestate {
+            Some(savestate) => cpu.load_state(savestate)?,
+            None => cpu.power_cycle(),
+        }
+        if let Some(&input) = movie.frames.first() {
+            apply_input(cpu, &movie, input);
+        }
+
+        let playback = Arc::new(Mutex::new(Playback {
+            finished: movie.frames.is_empty(),
+            movie,
+            frame: 0,
+        }));
+        let hooked = Arc::clone(&playback);
+        let hook = cpu.add_frame_hook(move |cpu, _| {
+            let mut playback = hooked.lock().unwrap();
+            if playback.finished {
+                return HookAction::Continue;
+            }
+            playback.frame += 1;
+            match playback.movie.frames.get(playback.frame) {
+                Some(&input) => {
+                    apply_input(cpu, &playback.movie, input);
+                    HookAction::Continue
+                }
+                None => {
+                    playback.finished = true;
+                    HookAction::Stop
+                }
+            }
+        });
+        Ok(Self { playback, hook })
+    }
+
+    /// The movie frame that's running (which equals the number of frames
+    /// played once the movie is finished)
+    pub fn frame(&self) -> usize {
+        self.playback.lock().unwrap().frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.playback.lock().unwrap().finished
+    }
+
+    /// Stops playing, unhooking from the CPU, and returns the movie
+    pub fn stop(self, cpu: &mut CPU) -> Movie {
+        cpu.remove_hook(self.hook);
+        let playback = Arc::try_unwrap(self.playback)
+            .ok()
+            .expect("the hook was removed");
+        playback.into_inner().unwrap().movie
+    }
+}
+
+/// Records a movie by taking down what the controllers hold at the end of
+/// every frame
+pub struct MovieRecorder {
+    recording: Arc<Mutex<Recording>>,
+    hook: HookId,
+}
+
+struct Recording {
+    movie: Movie,
+    /// the commands that ran at the start of the current frame
+    commands: u8,
+    /// the commands to run at the start of the next frame
+    pending: u8,
+}
+
+impl MovieRecorder {
+    /// Starts recording a movie of the game in the cartridge that's inserted,
+    /// either from power on (the machine is power cycled) or from the state
+    /// the machine is in right now (which gets saved into the movie)
+    pub fn start(cpu: &mut CPU, rom_filename: &str, from_power_on: bool) -> Result<Self, String> {
+        let rom_checksum = cpu.bus.rom_checksum().ok_or("Movies need a cartridge")?;
+        let mut movie = Movie::new(rom_filename, rom_checksum);
+        movie.pal = cpu.bus.region() == Region::Pal;
+        if from_power_on {
+            cpu.power_cycle();
+        } else {
+            movie.savestate = Some(cpu.save_state());
+        }
+
+        let recording = Arc::new(Mutex::new(Recording {
+            movie,
+            commands: 0,
+            pending: 0,
+        }));
+        let hooked = Arc::clone(&recording);
+        let hook = cpu.add_frame_hook(move |cpu, _| {
+            let mut recording = hooked.lock().unwrap();
+            let input = FrameInput {
+                commands: recording.commands,
+                gamepads: [cpu.bus.gamepad(0).buttons(), cpu.bus.gamepad(1).buttons()],
+            };
+            recording.movie.frames.push(input);
+
+            recording.commands = std::mem::take(&mut recording.pending);
+            run_commands(cpu, recording.commands);
+            HookAction::Continue
+        });
+        Ok(Self { recording, hook })
+    }
+
+    /// Presses the reset button at the start of the next frame
+    pub fn soft_reset(&self) {
+        self.recording.lock().unwrap().pending |= COMMAND_SOFT_RESET;
+    }
+
+    /// Power cycles the console at the start of the next frame
+    pub fn hard_reset(&self) {
+        self.recording.lock().unwrap().pending |= COMMAND_HARD_RESET;
+    }
+
+    /// How many frames have been recorded
+    pub fn frames(&self) -> usize {
+        self.recording.lock().unwrap().movie.frames.len()
+    }
+
+    /// Stops recording, unhooking from the CPU, and returns the movie. The
+    /// frame that's running isn't part of it
+    pub fn finish(self, cpu: &mut CPU) -> Movie {
+        cpu.remove_hook(self.hook);
+        let recording = Arc::try_unwrap(self.recording)
+            .ok()
+            .expect("the hook was removed");
+        recording.into_inner().unwrap().movie
+    }
+}
+
+/// Runs a frame's commands and sets the controllers
+fn apply_input(cpu: &mut CPU, movie: &Movie, input: FrameInput) {
+    run_commands(cpu, input.commands);
+    for port in 0..2 {
+        let buttons = if movie.gamepads[port] {
+            input.gamepads[port]
+        } else {
+            0
+        };
+        cpu.bus.gamepad_mut(port).set_buttons(buttons);
+    }
+}
+
+fn run_commands(cpu: &mut CPU, commands: u8) {
+    if commands & COMMAND_HARD_RESET != 0 {
+        cpu.power_cycle();
+    } else if commands & COMMAND_SOFT_RESET != 0 {
+        cpu.reset();
+    }
+}
+
+/// Parses an input line (`|commands|port 1|port 2|expansion port|`)
+fn parse_input(line: &str) -> Result<FrameInput, String> {
+    let fields = line.split('|').collect::<Vec<_>>();
+    if fields.len() < 4 {
+        return Err(format!("Invalid input line {line}"));
+    }
+    let mut input = FrameInput {
+        commands: fields[1]
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid commands {}", fields[1]))?,
+        ..FrameInput::default()
+    };
+    for (port, field) in fields[2..4].iter().enumerate() {
+        if field.is_empty() {
+            continue;
+        }
+        if field.len() != 8 {
+            return Err(format!("Invalid controller input {field}"));
+        }
+        for (i, button) in field.bytes().enumerate() {
+            if button != b'.' && button != b' ' {
+                input.gamepads[port] |= 0x80 >> i;
+            }
+        }
+    }
+    Ok(input)
+}
+
+/// Decodes binary header values, which are either `base64:` prefixed or hex
+/// (with an optional `0x` prefix)
+fn decode_binary(value: &str) -> Result<Vec<u8>, String> {
+    let value = value.trim();
+    if let Some(base64) = value.strip_prefix("base64:") {
+        return base64_decode(base64);
+    }
+    let hex_digits = value.strip_prefix("0x").unwrap_or(value);
+    if !hex_digits.len().is_multiple_of(2) {
+        return Err(format!("Invalid binary value {value}"));
+    }
+    (0..hex_digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex_digits[i..i + 2], 16)
+                .map_err(|_| format!("Invalid binary value {value}"))
+        })
+        .collect()
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_ALPHABET[(group >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim().trim_end_matches('=');
+    let mut bytes = Vec::new();
+    let mut group = 0u32;
+    let mut bits = 0;
+    for letter in text.bytes() {
+        let value = BASE64_ALPHABET
+            .iter()
+            .position(|&c| c == letter)
+            .ok_or_else(|| format!("Invalid base64 {text}"))?;
+        group = (group << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Makes up a random GUID for a new movie
+fn new_guid() -> String {
+    let random = |seed: u64| RandomState::new().hash_one(seed);
+    let (high, low) = (random(0), random(1));
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:04X}-{:012X}",
+        high >> 32,
+        (high >> 16) & 0xFFFF,
+        high & 0xFFFF,
+        low >> 48,
+        low & 0xFFFF_FFFF_FFFF
+    )
+}
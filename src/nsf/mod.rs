@@ -0,0 +1,333 @@
+//! Contains NSF music rips (`.nsf` and `.nsfe` files) and a player for
+//! them (see the `player` module). An NSF is a game's music code and data
+//! with the rest of the game taken out, plus the addresses of two
+//! routines: INIT, which starts a song, and PLAY, which is called at a
+//! fixed rate (every frame, usually) to keep it going.
+//!
+//! An `.nsf` file is a 128 byte header followed by the data:
+//!
+//! | Byte      | Contents                                               |
+//! |-----------|--------------------------------------------------------|
+//! | 0-4       | `NESM` followed by 0x1A                                |
+//! | 5         | version                                                |
+//! | 6         | number of songs                                        |
+//! | 7         | the song to start with (from 1)                        |
+//! | 8-9       | load address                                           |
+//! | 10-11     | INIT address                                           |
+//! | 12-13     | PLAY address                                           |
+//! | 14-45     | title                                                  |
+//! | 46-77     | artist                                                 |
+//! | 78-109    | copyright                                              |
+//! | 110-111   | how often PLAY is called on NTSC (in microseconds)     |
+//! | 112-119   | the initial banks (all 0 when there's no banking)      |
+//! | 120-121   | how often PLAY is called on PAL (in microseconds)      |
+//! | 122       | TV system: PAL (0), both (1)                           |
+//! | 123       | expansion audio (see `VRC6` and the rest)              |
+//!
+//! The data is loaded at the load address. Tunes bigger than 32 KiB bank
+//! it in 4 KiB pieces with the registers at $5FF8 - $5FFF, in which case
+//! the data is laid out in banks from the start of a 4 KiB page (see
+//! `mapper::nsf`).
+//!
+//! `.nsfe` files hold the same things in chunks (a little endian length, a
+//! four letter id and the contents) after `NSFE`, along with the names,
+//! lengths and fades of the tracks. Chunks with an id that starts with a
+//! capital letter have to be understood to play the tune, the others can
+//! be skipped.
+
+pub mod player;
+
+/// Expansion audio flags (byte 123 of the header)
+pub const VRC6: u8 = 0b0000_0001;
+pub const VRC7: u8 = 0b0000_0010;
+pub const FDS: u8 = 0b0000_0100;
+pub const MMC5: u8 = 0b0000_1000;
+pub const N163: u8 = 0b0001_0000;
+pub const SUNSOFT_5B: u8 = 0b0010_0000;
+
+const NSF_TAG: &[u8] = b"NESM\x1A";
+const NSFE_TAG: &[u8] = b"NSFE";
+const HEADER_SIZE: usize = 0x80;
+const TEXT_SIZE: usize = 32;
+
+// TV system bits
+const PAL: u8 = 0b01;
+const DUAL: u8 = 0b10;
+
+/// How often PLAY is called when the file doesn't say (in microseconds)
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+/// How long a song plays and fades out for when the tune doesn't say (in
+/// milliseconds)
+pub const DEFAULT_LENGTH: u32 = 150_000;
+pub const DEFAULT_FADE: u32 = 5_000;
+
+/// Where the data can go at the lowest (FDS tunes can load into its RAM)
+const LOWEST_LOAD: u16 = 0x8000;
+const LOWEST_FDS_LOAD: u16 = 0x6000;
+
+/// What's known about a song besides its number (NSFe only)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub name: Option<String>,
+    /// how long it plays before fading out, in milliseconds
+    pub length: Option<u32>,
+    /// how long it takes to fade out, in milliseconds
+    pub fade: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    /// how many songs there are
+    pub songs: u8,
+    /// the song to start with (from 0)
+    pub starting_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// who ripped the music out of the game (NSFe only)
+    pub ripper: String,
+    /// how often PLAY is called on NTSC and PAL, in microseconds
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    /// the banks at $8000, $9000 ... $F000 when the tune starts, or None
+    /// when it doesn't bank
+    pub banks: Option<[u8; 8]>,
+    /// whether the tune was made for NTSC and for PAL consoles
+    pub ntsc: bool,
+    pub pal: bool,
+    /// the expansion audio chips the tune plays on (see `VRC6` and the
+    /// rest)
+    pub expansion: u8,
+    /// the names, lengths and fades of the songs (all None without NSFe)
+    pub tracks: Vec<Track>,
+    /// the music code and data
+    pub data: Vec<u8>,
+}
+
+impl Nsf {
+    /// Parses the contents of an `.nsf` or `.nsfe` file
+    pub fn new(raw: &[u8]) -> Result<Nsf, String> {
+        let nsf = if raw.starts_with(NSF_TAG) {
+            Nsf::parse_nsf(raw)?
+        } else if raw.starts_with(NSFE_TAG) {
+            Nsf::parse_nsfe(raw)?
+        } else {
+            return Err("File is not an NSF or NSFe file".to_string());
+        };
+        nsf.validate()?;
+        Ok(nsf)
+    }
+
+    /// Reads and parses an `.nsf` or `.nsfe` file
+    pub fn load(path: &str) -> Result<Nsf, String> {
+        let raw = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+        Nsf::new(&raw)
+    }
+
+    fn parse_nsf(raw: &[u8]) -> Result<Nsf, String> {
+        if raw.len() < HEADER_SIZE {
+            return Err("NSF file is truncated".to_string());
+        }
+        let word = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let banks: [u8; 8] = raw[0x70..0x78].try_into().unwrap();
+        let songs = raw[6];
+        Ok(Nsf {
+            songs,
+            starting_song: raw[7].saturating_sub(1),
+            load_addr: word(0x08),
+            init_addr: word(0x0A),
+            play_addr: word(0x0C),
+            title: text(&raw[0x0E..0x0E + TEXT_SIZE]),
+            artist: text(&raw[0x2E..0x2E + TEXT_SIZE]),
+            copyright: text(&raw[0x4E..0x4E + TEXT_SIZE]),
+            ripper: String::new(),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            banks: banks.iter().any(|&bank| bank != 0).then_some(banks),
+            ntsc: raw[0x7A] & (PAL | DUAL) != PAL,
+            pal: raw[0x7A] & (PAL | DUAL) != 0,
+            expansion: raw[0x7B],
+            tracks: vec![Track::default(); songs as usize],
+            data: raw[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    fn parse_nsfe(raw: &[u8]) -> Result<Nsf, String> {
+        let truncated = || "NSFe file is truncated".to_string();
+        let mut nsf: Option<Nsf> = None;
+        let mut data = None;
+        let mut rest = &raw[NSFE_TAG.len()..];
+        loop {
+            if rest.len() < 8 {
+                return Err(truncated());
+            }
+            let len = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let id = &rest[4..8];
+            let chunk = rest.get(8..8 + len).ok_or_else(truncated)?;
+            rest = &rest[8 + len..];
+            match id {
+                b"INFO" => nsf = Some(Nsf::parse_info(chunk)?),
+                b"DATA" => data = Some(chunk.to_vec()),
+                b"NEND" => break,
+                b"BANK" | b"RATE" | b"auth" | b"tlbl" | b"time" | b"fade" => {
+                    // everything else comes after INFO
+                    nsf.as_mut()
+                        .ok_or("NSFe file has no INFO chunk before its other chunks")?
+                        .parse_chunk(id, chunk);
+                }
+                [first, ..] if first.is_ascii_uppercase() => {
+                    return Err(format!(
+                        "NSFe file has a {} chunk, which is needed to play it but isn't \
+                         supported",
+                        String::from_utf8_lossy(id)
+                    ));
+                }
+                _ => {}
+            }
+        }
+        let mut nsf = nsf.ok_or("NSFe file has no INFO chunk")?;
+        nsf.data = data.ok_or("NSFe file has no DATA chunk")?;
+        Ok(nsf)
+    }
+
+    /// The chunks that add to what the INFO chunk says
+    fn parse_chunk(&mut self, id: &[u8], chunk: &[u8]) {
+        let word = |offset: usize| {
+            chunk
+                .get(offset..offset + 2)
+                .map_or(0, |word| u16::from_le_bytes([word[0], word[1]]))
+        };
+        match id {
+            b"BANK" => {
+                let mut banks = [0; 8];
+                for (bank, value) in banks.iter_mut().zip(chunk) {
+                    *bank = *value;
+                }
+                self.banks = Some(banks);
+            }
+            b"RATE" => (self.ntsc_speed, self.pal_speed) = (word(0), word(2)),
+            b"auth" => {
+                let mut strings = chunk.split(|&byte| byte == 0).map(text);
+                self.title = strings.next().unwrap_or_default();
+                self.artist = strings.next().unwrap_or_default();
+                self.copyright = strings.next().unwrap_or_default();
+                self.ripper = strings.next().unwrap_or_default();
+            }
+            b"tlbl" => {
+                let names = chunk.split(|&byte| byte == 0).map(text);
+                for (track, name) in self.tracks.iter_mut().zip(names) {
+                    track.name = Some(name);
+                }
+            }
+            b"time" | b"fade" => {
+                let times = chunk
+                    .chunks_exact(4)
+                    .map(|time| i32::from_le_bytes(time.try_into().unwrap()));
+                for (track, time) in self.tracks.iter_mut().zip(times) {
+                    // a negative time means the player's default
+                    let time = u32::try_from(time).ok();
+                    if id == b"time" {
+                        track.length = time;
+                    } else {
+                        track.fade = time;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The INFO chunk: the addresses, the TV system, the expansion audio
+    /// and the songs
+    fn parse_info(chunk: &[u8]) -> Result<Nsf, String> {
+        if chunk.len() < 8 {
+            return Err("NSFe file's INFO chunk is truncated".to_string());
+        }
+        let word = |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+        let songs = chunk.get(8).copied().unwrap_or(1);
+        Ok(Nsf {
+            songs,
+            starting_song: chunk.get(9).copied().unwrap_or(0),
+            load_addr: word(0),
+            init_addr: word(2),
+            play_addr: word(4),
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            ntsc_speed: 0,
+            pal_speed: 0,
+            banks: None,
+            ntsc: chunk[6] & (PAL | DUAL) != PAL,
+            pal: chunk[6] & (PAL | DUAL) != 0,
+            expansion: chunk[7],
+            tracks: vec![Track::default(); songs as usize],
+            data: Vec::new(),
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.songs == 0 {
+            return Err("NSF file has no songs".to_string());
+        }
+        if self.data.is_empty() {
+            return Err("NSF file has no data".to_string());
+        }
+        let lowest = if self.expansion & FDS != 0 {
+            LOWEST_FDS_LOAD
+        } else {
+            LOWEST_LOAD
+        };
+        if self.load_addr < lowest {
+            return Err(format!(
+                "NSF file loads at ${:04X}, below ${lowest:04X}",
+                self.load_addr
+            ));
+        }
+        Ok(())
+    }
+
+    /// How often PLAY is called, in microseconds
+    pub fn play_speed(&self, pal: bool) -> u16 {
+        match (pal, self.ntsc_speed, self.pal_speed) {
+            (false, 0, _) => DEFAULT_NTSC_SPEED,
+            (false, speed, _) => speed,
+            (true, _, 0) => DEFAULT_PAL_SPEED,
+            (true, _, speed) => speed,
+        }
+    }
+
+    /// How long a song (from 0) plays for and then fades out for, in
+    /// milliseconds
+    pub fn track_length(&self, song: u8) -> (u32, u32) {
+        let track = self.tracks.get(song as usize);
+        (
+            track
+                .and_then(|track| track.length)
+                .unwrap_or(DEFAULT_LENGTH),
+            track.and_then(|track| track.fade).unwrap_or(DEFAULT_FADE),
+        )
+    }
+
+    /// The name of a song (from 0), or "Song n" (from 1) when it has none
+    pub fn track_name(&self, song: u8) -> String {
+        self.tracks
+            .get(song as usize)
+            .and_then(|track| track.name.clone())
+            .unwrap_or_else(|| format!("Song {}", song as u16 + 1))
+    }
+}
+
+/// A null padded string (which is supposed to be ASCII, but isn't always)
+fn text(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
@@ -0,0 +1,178 @@
+//! Contains the NSF player, which does what the driver in a real NSF
+//! player's ROM would, from the outside: it calls the tune's INIT routine
+//! to start a song, then its PLAY routine at the rate the tune asks for,
+//! letting the APU and the expansion audio run in between. Songs can be
+//! rendered headless too, i.e. to write them to a WAV file (see the `wav`
+//! module).
+//!
+//! A routine is called by pushing a return address onto the stack the way
+//! JSR does and pointing the PC at it; once it returns there, the CPU sits
+//! idle (while the rest of the machine keeps running) until the next call
+//! is due. A PLAY that runs past the next call makes it get skipped.
+//!
+//! Tunes play on an NTSC machine unless they're PAL only, but any region
+//! can be picked (see `set_region`). On PAL and Dendy PLAY gets called at
+//! the PAL rate; the Dendy is told it's NTSC though (in X), since its APU
+//! plays the same notes as an NTSC one for the same periods.
+
+use super::Nsf;
+use crate::Mem;
+use crate::apu::SAMPLE_RATE;
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::region::Region;
+
+/// Where the routines return to, somewhere a tune doesn't have code
+const RETURN_ADDR: u16 = 0x4100;
+const STACK: u16 = 0x0100;
+const STACK_TOP: u8 = 0xFF;
+
+pub struct NsfPlayer {
+    /// the machine the tune plays on
+    pub cpu: CPU,
+    nsf: Nsf,
+    song: u8,
+    /// the cycle the song started on, and how many times PLAY was due since
+    start_cycle: u64,
+    plays: u64,
+    /// whether INIT or PLAY is running
+    running: bool,
+}
+
+impl NsfPlayer {
+    /// Loads a tune and starts its first song
+    pub fn new(nsf: Nsf) -> Self {
+        let mut cpu = CPU::with_bus(Bus::with_nsf(&nsf));
+        if !nsf.ntsc {
+            cpu.bus.set_region(Region::Pal);
+        }
+        let mut player = Self {
+            cpu,
+            song: nsf.starting_song,
+            nsf,
+            start_cycle: 0,
+            plays: 0,
+            running: false,
+        };
+        let song = player.song.min(player.nsf.songs - 1);
+        player.start(song).unwrap();
+        player
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    /// The region the tune plays on
+    pub fn region(&self) -> Region {
+        self.cpu.bus.region()
+    }
+
+    /// Switches the machine to another region and starts the song that's
+    /// playing over on it
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.bus.set_region(region);
+        self.start(self.song).unwrap();
+    }
+
+    /// The song that's playing (from 0)
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    /// Starts a song (from 0): the machine is powered on again, the APU
+    /// set up the way the NSF spec says and INIT called with the song in A
+    /// and the TV system (0 for NTSC, 1 for PAL) in X
+    pub fn start(&mut self, song: u8) -> Result<(), String> {
+        if song >= self.nsf.songs {
+            return Err(format!(
+                "Song {} doesn't exist, the tune has {} songs",
+                song as u16 + 1,
+                self.nsf.songs
+            ));
+        }
+        self.cpu.power_cycle();
+        for addr in 0x4000..=0x4013 {
+            self.cpu.mem_write(addr, 0);
+        }
+        self.cpu.mem_write(0x4015, 0x00);
+        self.cpu.mem_write(0x4015, 0x0F);
+        self.cpu.mem_write(0x4017, 0x40);
+
+        self.song = song;
+        self.cpu.register_a = song;
+        self.cpu.register_x = (self.region() == Region::Pal) as u8;
+        self.call(self.nsf.init_addr);
+        (self.start_cycle, self.plays) = (self.cycles(), 0);
+        Ok(())
+    }
+
+    /// Whether INIT or PLAY is running right now
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    fn cycles(&self) -> u64 {
+        self.cpu.bus.cycles() as u64
+    }
+
+    /// The cycle PLAY is due on next
+    fn next_play(&self) -> u64 {
+        let region = self.region();
+        let speed = self.nsf.play_speed(region != Region::Ntsc) as u64;
+        self.start_cycle + (self.plays + 1) * speed * region.cpu_clock() / 1_000_000
+    }
+
+    /// Calls the routine at `addr`, the way JSR would from `RETURN_ADDR`
+    fn call(&mut self, addr: u16) {
+        let [low, high] = (RETURN_ADDR - 1).to_le_bytes();
+        self.cpu.mem_write(STACK + STACK_TOP as u16, high);
+        self.cpu.mem_write(STACK + STACK_TOP as u16 - 1, low);
+        self.cpu.stack_pointer = STACK_TOP - 2;
+        self.cpu.program_counter = addr;
+        self.running = true;
+    }
+
+    /// Runs the machine for `cycles` CPU cycles, calling PLAY whenever it's
+    /// due
+    pub fn run(&mut self, cycles: u64) {
+        let end = self.cycles() + cycles;
+        while self.cycles() < end {
+            if self.running {
+                // BRK ends a routine too, since there's nowhere to go, and so
+                // does an opcode the CPU doesn't know (rips that use the
+                // unofficial ones play what they can instead of crashing)
+                if self.cpu.program_counter == RETURN_ADDR || self.cpu.try_step() != Ok(true) {
+                    self.running = false;
+                }
+                continue;
+            }
+            let next = self.next_play();
+            if self.cycles() >= next {
+                while self.next_play() <= self.cycles() {
+                    self.plays += 1;
+                }
+                self.call(self.nsf.play_addr);
+            } else {
+                self.cpu.bus.tick((next.min(end) - self.cycles()) as usize);
+            }
+        }
+    }
+
+    /// Plays the song on for `length` milliseconds and then fades it out
+    /// over `fade` more, returning the samples (at `apu::SAMPLE_RATE`)
+    pub fn render(&mut self, length: u32, fade: u32) -> Vec<f32> {
+        self.cpu.bus.record_audio(true);
+        self.cpu.bus.take_audio();
+        self.run((length + fade) as u64 * self.region().cpu_clock() / 1000);
+        let mut samples = self.cpu.bus.take_audio();
+        self.cpu.bus.record_audio(false);
+
+        let fade_samples = (fade as u64 * SAMPLE_RATE / 1000) as usize;
+        let fade_start = samples.len().saturating_sub(fade_samples);
+        for (index, sample) in samples[fade_start..].iter_mut().enumerate() {
+            *sample *= 1.0 - index as f32 / fade_samples as f32;
+        }
+        samples
+    }
+}
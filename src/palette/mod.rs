@@ -0,0 +1,144 @@
+//! Contains the palettes that turn the NES palette indices the PPU draws
+//! with (0x00 - 0x3F) into RGB colors.
+//!
+//! The NES doesn't output RGB at all (it generates an NTSC signal directly),
+//! so there's no one true palette, just ones that look more or less like a
+//! TV does. The color emphasis bits of PPUMASK darken the colors that aren't
+//! emphasized, so a palette has 8 variants of the 64 colors, one for every
+//! combination of emphasis bits.
+//!
+//! Palettes come from:
+//! * the built-in ones (see `BUILTIN_PALETTES`)
+//! * `.pal` files, which are raw RGB triplets: either the 64 colors (192
+//!   bytes) or all 8 variants (1536 bytes, emphasis bits 0 first)
+//! * the NTSC generator (see the `ntsc` module), which works the colors out
+//!   from the signal the PPU generates
+
+mod builtin;
+pub(crate) mod ntsc;
+
+pub use ntsc::NtscSettings;
+
+use std::path::Path;
+
+/// RGB color
+pub type Rgb = [u8; 3];
+
+/// The names of the built-in palettes
+pub const BUILTIN_PALETTES: [&str; 4] = ["default", "2c02", "fceux", "nestopia-yuv"];
+
+/// How much the color channels that aren't emphasized get darkened in
+/// palettes that only have the 64 colors
+const EMPHASIS_ATTENUATION: f64 = 0.816;
+
+/// A full palette: the 64 colors for every combination of emphasis bits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    /// 8 * 64 colors, the ones for emphasis bits `e` starting at `e * 64`
+    colors: Vec<Rgb>,
+}
+
+impl Palette {
+    /// Builds a palette out of its 64 colors, darkening them for the
+    /// emphasis bits the way the PPU does
+    pub fn from_colors(colors: &[Rgb; 64]) -> Self {
+        let mut all = Vec::with_capacity(8 * 64);
+        for emphasis in 0..8u8 {
+            for color in colors {
+                all.push(std::array::from_fn(|channel| {
+                    // any emphasis darkens the channels that aren't emphasized
+                    if emphasis != 0 && emphasis & (1 << channel) == 0 {
+                        (color[channel] as f64 * EMPHASIS_ATTENUATION).round() as u8
+                    } else {
+                        color[channel]
+                    }
+                }));
+            }
+        }
+        Self { colors: all }
+    }
+
+    /// One of the built-in palettes, by name (see `BUILTIN_PALETTES`)
+    pub fn builtin(name: &str) -> Result<Self, String> {
+        let colors = match name.to_ascii_lowercase().as_str() {
+            "default" => &builtin::DEFAULT,
+            "2c02" => &builtin::MEASURED_2C02,
+            "fceux" => &builtin::FCEUX,
+            "nestopia-yuv" => &builtin::NESTOPIA_YUV,
+            _ => {
+                return Err(format!(
+                    "Unknown palette {name} (expected a .pal file, ntsc or one of {})",
+                    BUILTIN_PALETTES.join(", ")
+                ));
+            }
+        };
+        Ok(Self::from_colors(colors))
+    }
+
+    /// Generates a palette from the NTSC signal, emphasis variants included
+    pub fn generate_ntsc(settings: &NtscSettings) -> Self {
+        let colors = (0..8u8)
+            .flat_map(|emphasis| (0..64u8).map(move |index| (index, emphasis)))
+            .map(|(index, emphasis)| ntsc::generate(index, emphasis, settings))
+            .collect();
+        Self { colors }
+    }
+
+    /// Reads the contents of a `.pal` file. Files with only the 64 colors
+    /// get the emphasis variants worked out like `from_colors` does
+    pub fn from_pal(bytes: &[u8]) -> Result<Self, String> {
+        let colors = bytes
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect::<Vec<Rgb>>();
+        match bytes.len() {
+            192 => Ok(Self::from_colors(colors.as_slice().try_into().unwrap())),
+            1536 => Ok(Self { colors }),
+            len => Err(format!(
+                "Invalid palette file ({len} bytes, expected 192 or 1536)"
+            )),
+        }
+    }
+
+    /// Reads a `.pal` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::from_pal(&bytes).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// A built-in palette, `ntsc` for a generated one (with the default
+    /// settings), or a `.pal` file if `name` is neither
+    pub fn find(name: &str) -> Result<Self, String> {
+        if name.ends_with(".pal") || Path::new(name).exists() {
+            Self::load(name)
+        } else if name.eq_ignore_ascii_case("ntsc") {
+            Ok(Self::generate_ntsc(&NtscSettings::default()))
+        } else {
+            Self::builtin(name)
+        }
+    }
+
+    /// Returns the palette in the 512 color `.pal` format
+    pub fn to_pal(&self) -> Vec<u8> {
+        self.colors.concat()
+    }
+
+    /// Writes the palette out as a 512 color `.pal` file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_pal()).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// The color of a palette index with the emphasis bits (PPUMASK bits
+    /// 5 - 7 shifted down to 0 - 2) applied
+    pub fn rgb(&self, index: u8, emphasis: u8) -> Rgb {
+        self.colors[(emphasis & 0b111) as usize * 64 + (index & 0x3F) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::from_colors(&builtin::DEFAULT)
+    }
+}
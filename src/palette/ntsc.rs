@@ -0,0 +1,137 @@
+//! Contains the NTSC palette generator. Instead of storing colors, it
+//! recreates the signal the PPU puts out for every palette index and decodes
+//! it the way a TV would:
+//!
+//! * the PPU outputs a square wave between two voltage levels for 12 master
+//!   clocks per pixel; the hue (bits 0 - 3 of the index) is the phase of the
+//!   wave and the brightness (bits 4 - 5) picks the levels
+//! * hues 0 and 13 - 15 don't alternate (greys and blacks)
+//! * each emphasis bit lowers the signal during the half of the wave that's
+//!   in phase with its color
+//! * the TV averages the signal into luma (Y) and demodulates the two chroma
+//!   components (I and Q) against the color burst, then turns YIQ into RGB
+//!
+//! The levels are the ones measured on a 2C02, relative to the 0.518 V black
+//! and the 1.962 V white.
+
+use super::Rgb;
+
+/// Voltage of the low part of the wave, per brightness
+const SIGNAL_LOW: [f64; 4] = [0.350, 0.518, 0.962, 1.550];
+/// Voltage of the high part of the wave, per brightness
+const SIGNAL_HIGH: [f64; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f64 = 0.518;
+const WHITE: f64 = 1.962;
+/// How much an emphasis bit lowers the signal
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+/// The hues each emphasis bit is in phase with (red, green, blue)
+const EMPHASIS_HUES: [u8; 3] = [0xC, 0x4, 0x8];
+
+/// The knobs of the generator. The defaults look like a TV that was left
+/// at its factory settings
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NtscSettings {
+    /// rotates every hue by this many degrees
+    pub hue: f64,
+    /// scales the chroma (0 gives greys)
+    pub saturation: f64,
+    /// scales the luma
+    pub contrast: f64,
+    /// is added to the luma (-1.0 - 1.0)
+    pub brightness: f64,
+    /// the gamma of the display the colors are for (the NES assumed a TV
+    /// with 2.2, so anything else adjusts for the difference)
+    pub gamma: f64,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 1.8,
+        }
+    }
+}
+
+/// Whether the square wave of a hue is high at a point of the wave
+fn in_phase(hue: u8, phase: usize) -> bool {
+    (hue as usize + phase + 8) % 12 < 6
+}
+
+/// The level of the signal (0.0 is black, 1.0 white) a pixel puts out at a
+/// phase (0 - 11) of the color subcarrier. The pixel is a palette index in
+/// bits 0 - 5 with the emphasis bits above it
+pub(crate) fn signal(pixel: u16, phase: usize) -> f64 {
+    let hue = (pixel & 0x0F) as u8;
+    let emphasis = (pixel >> 6) as u8;
+    // hues 14 and 15 are black whatever the brightness
+    let level = if hue > 13 {
+        1
+    } else {
+        (pixel >> 4) as usize & 3
+    };
+    let low = if hue == 0 {
+        SIGNAL_HIGH[level]
+    } else {
+        SIGNAL_LOW[level]
+    };
+    let high = if hue < 13 {
+        SIGNAL_HIGH[level]
+    } else {
+        SIGNAL_LOW[level]
+    };
+
+    let mut signal = if in_phase(hue, phase) { high } else { low };
+    let emphasized = EMPHASIS_HUES
+        .iter()
+        .enumerate()
+        .any(|(bit, &color)| emphasis & (1 << bit) != 0 && in_phase(color, phase));
+    if hue < 14 && emphasized {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+/// The cosine and sine a TV demodulates the chroma with at a phase of the
+/// color subcarrier (the samples are 30 degrees apart)
+pub(crate) fn carrier(phase: usize, settings: &NtscSettings) -> (f64, f64) {
+    let angle = (phase as f64 + settings.hue / 30.0) * std::f64::consts::PI / 6.0;
+    (angle.cos(), angle.sin())
+}
+
+/// Turns decoded luma and chroma into RGB, applying the settings
+pub(crate) fn yiq_to_rgb(y: f64, i: f64, q: f64, settings: &NtscSettings) -> Rgb {
+    let y = y * settings.contrast + settings.brightness;
+    let (i, q) = (i * settings.saturation, q * settings.saturation);
+    let rgb = [
+        y + 0.946882 * i + 0.623557 * q,
+        y - 0.274788 * i - 0.635691 * q,
+        y - 1.108545 * i + 1.709007 * q,
+    ];
+    rgb.map(|channel| {
+        let corrected = if channel <= 0.0 {
+            0.0
+        } else {
+            channel.powf(2.2 / settings.gamma)
+        };
+        (corrected * 255.0).round().clamp(0.0, 255.0) as u8
+    })
+}
+
+/// Generates the color of a palette index with the emphasis bits applied,
+/// by decoding a whole cycle of its signal
+pub(super) fn generate(index: u8, emphasis: u8, settings: &NtscSettings) -> Rgb {
+    let pixel = index as u16 | (emphasis as u16) << 6;
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let value = signal(pixel, phase) / 12.0;
+        let (cos, sin) = carrier(phase, settings);
+        y += value;
+        i += value * cos;
+        q += value * sin;
+    }
+    yiq_to_rgb(y, i, q, settings)
+}
@@ -0,0 +1,159 @@
+//! Contains the extended video some boards (MMC5) add: they take over the
+//! PPU's name table and pattern fetches to draw things the PPU can't do on
+//! its own. Like the CHR banks, the cartridge's mapper describes it and the
+//! bus hands it over after every write to cartridge space:
+//! * every name table can come from either KiB of VRAM, the board's own RAM
+//!   (ExRAM) or a fill pattern (a single tile and palette everywhere)
+//! * extended attributes: every background tile picks its own 4 KiB CHR
+//!   bank and palette from ExRAM
+//! * a vertical split: a column of tiles on the left or right of the screen
+//!   drawn from ExRAM with its own vertical scroll and CHR bank
+//! * separate CHR banks for the background when sprites are 8x16
+
+use super::NesPPU;
+
+/// Where the bytes of a name table come from
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub(crate) enum NameTableSource {
+    /// the first KiB of VRAM
+    #[default]
+    VramA,
+    /// the second KiB of VRAM
+    VramB,
+    /// the board's own RAM (reads as 0 when the board uses it for
+    /// something else)
+    ExRam,
+    /// the fill tile everywhere, with the fill palette
+    Fill,
+}
+
+/// A vertical split (see the module docs)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Split {
+    /// whether the split is on the right side of the screen
+    pub right: bool,
+    /// where the split starts (in tiles from the left)
+    pub tile: usize,
+    /// the vertical scroll of the split
+    pub scroll: u8,
+    /// the 4 KiB CHR bank the split is drawn with
+    pub chr_bank: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExtendedVideo {
+    pub name_tables: [NameTableSource; 4],
+    /// 1 KiB of the board's own RAM
+    pub ex_ram: Vec<u8>,
+    /// whether ExRAM can be read as a name table (and so as a split)
+    pub ex_ram_name_table: bool,
+    pub fill_tile: u8,
+    pub fill_palette: u8,
+    /// whether background tiles get their CHR bank and palette from ExRAM
+    pub extended_attributes: bool,
+    /// the CHR bank bits above the 6 extended attributes have room for
+    pub chr_upper: usize,
+    /// the offset into CHR of every KiB of the background's pattern tables
+    /// when sprites are 8x16
+    pub background_chr_banks: [usize; 8],
+    pub split: Option<Split>,
+}
+
+impl Default for ExtendedVideo {
+    fn default() -> Self {
+        Self {
+            name_tables: [NameTableSource::VramA; 4],
+            ex_ram: vec![0; 0x400],
+            ex_ram_name_table: false,
+            fill_tile: 0,
+            fill_palette: 0,
+            extended_attributes: false,
+            chr_upper: 0,
+            background_chr_banks: [0; 8],
+            split: None,
+        }
+    }
+}
+
+impl ExtendedVideo {
+    /// The byte of ExRAM at `index`, as a name table
+    fn ex_ram_name_table_byte(&self, index: usize) -> u8 {
+        if self.ex_ram_name_table {
+            self.ex_ram[index]
+        } else {
+            0
+        }
+    }
+}
+
+impl NesPPU {
+    /// Reads a name table address [0x2000 ... 0x3EFF], going through the
+    /// extended video's name table sources when there are any
+    pub(crate) fn read_name_table(&self, addr: u16) -> u8 {
+        let Some(extended) = &self.extended else {
+            return self.vram[self.mirror_vram_addr(addr) as usize];
+        };
+        let index = (addr & 0x3FF) as usize;
+        match extended.name_tables[((addr & 0xFFF) / 0x400) as usize] {
+            NameTableSource::VramA => self.vram[index],
+            NameTableSource::VramB => self.vram[0x400 + index],
+            NameTableSource::ExRam => extended.ex_ram_name_table_byte(index),
+            // attribute bytes repeat the palette for all 4 quadrants
+            NameTableSource::Fill if index >= 0x3C0 => extended.fill_palette * 0b0101_0101,
+            NameTableSource::Fill => extended.fill_tile,
+        }
+    }
+
+    /// Maps a name table address onto an index into VRAM, None when the
+    /// extended video puts something else there (writes to which are lost)
+    pub(crate) fn name_table_vram_index(&self, addr: u16) -> Option<usize> {
+        let Some(extended) = &self.extended else {
+            return Some(self.mirror_vram_addr(addr) as usize);
+        };
+        let index = (addr & 0x3FF) as usize;
+        match extended.name_tables[((addr & 0xFFF) / 0x400) as usize] {
+            NameTableSource::VramA => Some(index),
+            NameTableSource::VramB => Some(0x400 + index),
+            _ => None,
+        }
+    }
+
+    /// Where the byte at a background pattern table address comes from when
+    /// sprites are 8x16 and the extended video has separate banks for the
+    /// background
+    pub(crate) fn background_chr_offset(&self, addr: u16) -> usize {
+        match &self.extended {
+            Some(extended) if self.ctrl & super::CTRL_SPRITE_SIZE != 0 => {
+                let addr = addr as usize & 0x1FFF;
+                (extended.background_chr_banks[addr / 0x400] + addr % 0x400) % self.chr_rom.len()
+            }
+            _ => self.chr_offset(addr),
+        }
+    }
+
+    /// The offset into CHR of a row of a tile in a 4 KiB CHR bank
+    pub(crate) fn chr_bank_offset(&self, bank: usize, tile: u8, row: usize) -> usize {
+        (bank * 0x1000 + tile as usize * 16 + row) % self.chr_rom.len()
+    }
+
+    /// The extended attribute of a background tile (its 4 KiB CHR bank and
+    /// palette), if the extended video has them on
+    pub(crate) fn extended_attribute(&self, tile_x: usize, tile_y: usize) -> Option<(usize, u8)> {
+        let extended = self.extended.as_ref()?;
+        if !extended.extended_attributes {
+            return None;
+        }
+        let value = extended.ex_ram[tile_y * 32 + tile_x];
+        let bank = (value & 0b11_1111) as usize | extended.chr_upper << 6;
+        Some((bank, value >> 6))
+    }
+
+    /// The split the extended video draws over the background, if any
+    pub(crate) fn split(&self) -> Option<(Split, &[u8])> {
+        let extended = self.extended.as_ref()?;
+        let split = extended.split?;
+        extended
+            .ex_ram_name_table
+            .then_some((split, extended.ex_ram.as_slice()))
+    }
+}
@@ -0,0 +1,543 @@
+//! Contains the definition of the PPU (Picture Processing Unit), which is
+//! responsible for rendering graphics and keeping track of the state of
+//! the screen.
+//!
+//! What's emulated:
+//! * the memory mapped registers at [0x2000 ... 0x2007] (and 0x4014 through the bus)
+//! * its own memory (pattern tables, name tables, palettes and OAM)
+//! * scanline timing, the VBlank flag and the NMI it raises at the start of
+//!   VBlank, for the region's frame (see the `region` module)
+//! * a frame buffer the whole frame is drawn into when VBlank starts (see the
+//!   `render` module)
+
+mod extended;
+mod render;
+
+pub(crate) use extended::{ExtendedVideo, NameTableSource, Split};
+pub use render::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
+
+/// How the 2 KiB of name table VRAM is mapped onto the 4 name tables
+/// the PPU can address (the cartridge decides this, and boards with a mapper
+/// can change it while the game runs)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    /// all 4 name tables are the first KiB of VRAM
+    SingleScreenLower,
+    /// all 4 name tables are the second KiB of VRAM
+    SingleScreenUpper,
+}
+
+// PPUCTRL ($2000) bits
+const CTRL_NAME_TABLE: u8 = 0b0000_0011;
+const CTRL_VRAM_ADD_INCREMENT: u8 = 0b0000_0100;
+const CTRL_SPRITE_PATTERN_TABLE: u8 = 0b0000_1000;
+const CTRL_BACKGROUND_PATTERN_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_SIZE: u8 = 0b0010_0000;
+const CTRL_GENERATE_NMI: u8 = 0b1000_0000;
+
+// PPUMASK ($2001) bits
+const MASK_SHOW_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SHOW_SPRITES: u8 = 0b0001_0000;
+
+// PPUSTATUS ($2002) bits
+const STATUS_VBLANK_STARTED: u8 = 0b1000_0000;
+
+/// Number of PPU dots in a single scanline
+const DOTS_PER_SCANLINE: usize = 341;
+/// The pattern tables are banked in 1 KiB pieces
+const CHR_BANK_SIZE: usize = 0x400;
+/// Where the pattern tables come from when nothing switches banks: CHR
+/// in order, from the start
+const CHR_BANKS_IDENTITY: [usize; 8] = [0, 0x400, 0x800, 0xC00, 0x1000, 0x1400, 0x1800, 0x1C00];
+
+pub struct NesPPU {
+    /// the TV system, which decides how long a frame is and when VBlank
+    /// starts
+    region: Region,
+    /// all of the cartridge's CHR ROM (or its 8 KiB of CHR RAM), which the
+    /// pattern tables are banked in from
+    pub chr_rom: Vec<u8>,
+    /// the offset into `chr_rom` of every KiB of the pattern tables
+    /// [0x0000 ... 0x1FFF] (the cartridge's mapper switches these)
+    chr_banks: [usize; 8],
+    /// background and sprite palettes
+    pub palette_table: [u8; 32],
+    /// 2 KiB of name table memory
+    pub vram: [u8; 2048],
+    /// object attribute memory (sprite data)
+    pub oam_data: [u8; 256],
+    pub mirroring: Mirroring,
+
+    /// PPUCTRL ($2000)
+    ctrl: u8,
+    /// PPUMASK ($2001)
+    mask: u8,
+    /// PPUSTATUS ($2002)
+    status: u8,
+    /// OAMADDR ($2003)
+    oam_addr: u8,
+    /// PPUSCROLL ($2005) x and y
+    scroll: (u8, u8),
+    /// the VRAM address that PPUDATA ($2007) reads from and writes to
+    addr: u16,
+    /// the shared "w" latch that decides whether a write to PPUSCROLL or
+    /// PPUADDR is the first or second write (cleared by reading PPUSTATUS)
+    write_latch: bool,
+    /// reads from PPUDATA outside of palette memory are delayed by one read
+    internal_data_buf: u8,
+
+    scanline: u16,
+    cycles: usize,
+    frame: u64,
+    nmi_interrupt: bool,
+    /// palette indices of the last frame drawn
+    frame_buffer: Vec<u8>,
+    /// the color emphasis bits (PPUMASK bits 5 - 7) the last frame was
+    /// drawn with
+    frame_emphasis: u8,
+    /// the cartridge's extended video, if it has any (see the `extended`
+    /// module)
+    extended: Option<ExtendedVideo>,
+}
+
+impl NesPPU {
+    /// Instantiates the PPU with the given pattern table data
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        Self {
+            region: Region::Ntsc,
+            chr_rom,
+            chr_banks: CHR_BANKS_IDENTITY,
+            palette_table: [0; 32],
+            vram: [0; 2048],
+            oam_data: [0; 256],
+            mirroring,
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            scroll: (0, 0),
+            addr: 0,
+            write_latch: false,
+            internal_data_buf: 0,
+            scanline: 0,
+            cycles: 0,
+            frame: 0,
+            nmi_interrupt: false,
+            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_emphasis: 0,
+            extended: None,
+        }
+    }
+
+    /// Instantiates the PPU with 8 KiB of CHR RAM (for when there's no cartridge)
+    pub fn new_empty() -> Self {
+        Self::new(vec![0; 0x2000], Mirroring::Horizontal)
+    }
+
+    /// Points the pattern tables at other parts of CHR (the offset of every
+    /// KiB, wrapping around the end of CHR)
+    pub(crate) fn set_chr_banks(&mut self, banks: [usize; 8]) {
+        self.chr_banks = banks;
+    }
+
+    /// Hands over the cartridge's extended video (None for boards without)
+    pub(crate) fn set_extended_video(&mut self, video: Option<&ExtendedVideo>) {
+        match (video, &mut self.extended) {
+            (Some(video), Some(extended)) => extended.clone_from(video),
+            (video, extended) => *extended = video.cloned(),
+        }
+    }
+
+    /// Maps a pattern table address [0x0000 ... 0x1FFF] onto an offset into
+    /// `chr_rom`, going through the banks the cartridge has switched in
+    pub fn chr_offset(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        (self.chr_banks[addr / CHR_BANK_SIZE] + addr % CHR_BANK_SIZE) % self.chr_rom.len()
+    }
+
+    /// Puts the PPU back into its power on state. The pattern tables are only
+    /// cleared when they're RAM, and the region stays the same
+    pub(crate) fn power_cycle(&mut self, chr_ram: bool) {
+        let mut chr = std::mem::take(&mut self.chr_rom);
+        if chr_ram {
+            chr.fill(0);
+        }
+        *self = Self {
+            region: self.region,
+            ..Self::new(chr, self.mirroring)
+        };
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switches to another region's frame. If the PPU is past the end of
+    /// the new one, the next frame starts right away
+    pub(crate) fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.scanline = self.scanline.min(region.scanlines() - 1);
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter, chr_ram: bool) {
+        if chr_ram {
+            state.vec(&self.chr_rom);
+        }
+        state.bytes(&self.palette_table);
+        state.bytes(&self.vram);
+        state.bytes(&self.oam_data);
+        state.u8(self.ctrl);
+        state.u8(self.mask);
+        state.u8(self.status);
+        state.u8(self.oam_addr);
+        state.u8(self.scroll.0);
+        state.u8(self.scroll.1);
+        state.u16(self.addr);
+        state.bool(self.write_latch);
+        state.u8(self.internal_data_buf);
+        state.u16(self.scanline);
+        state.u64(self.cycles as u64);
+        state.u64(self.frame);
+        state.bool(self.nmi_interrupt);
+    }
+
+    pub(crate) fn load_state(
+        &mut self,
+        state: &mut StateReader,
+        chr_ram: bool,
+    ) -> Result<(), String> {
+        if chr_ram {
+            self.chr_rom = state.vec(self.chr_rom.len())?;
+        }
+        state.bytes(&mut self.palette_table)?;
+        state.bytes(&mut self.vram)?;
+        state.bytes(&mut self.oam_data)?;
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        self.oam_addr = state.u8()?;
+        self.scroll = (state.u8()?, state.u8()?);
+        self.addr = state.u16()?;
+        self.write_latch = state.bool()?;
+        self.internal_data_buf = state.u8()?;
+        self.scanline = state.u16()?;
+        self.cycles = state.u64()? as usize;
+        self.frame = state.u64()?;
+        self.nmi_interrupt = state.bool()?;
+        // the frame buffer isn't saved, it's drawn again from what was loaded
+        self.render_frame();
+        Ok(())
+    }
+
+    /// The scanline the PPU is currently on (0 - 261 on NTSC, 0 - 311 on PAL
+    /// and Dendy)
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// The dot within the current scanline (0 - 340)
+    pub fn dot(&self) -> usize {
+        self.cycles
+    }
+
+    /// The last value written to PPUCTRL ($2000)
+    pub fn ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    /// The last value written to PPUMASK ($2001)
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    /// Whether PPUMASK has the background or the sprites turned on
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES) != 0
+    }
+
+    /// The scroll position (x, y) written through PPUSCROLL ($2005)
+    pub fn scroll(&self) -> (u8, u8) {
+        self.scroll
+    }
+
+    /// The VRAM address PPUDATA ($2007) currently points to
+    pub fn vram_addr(&self) -> u16 {
+        self.addr
+    }
+
+    /// How many frames have been completed since power on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Advances the PPU by a number of dots (3 per CPU cycle)
+    pub fn tick(&mut self, dots: usize) {
+        self.cycles += dots;
+        while self.cycles >= DOTS_PER_SCANLINE {
+            self.cycles -= DOTS_PER_SCANLINE;
+            self.scanline += 1;
+
+            if self.scanline == self.region.vblank_scanline() {
+                self.render_frame();
+                self.status |= STATUS_VBLANK_STARTED;
+                if self.ctrl & CTRL_GENERATE_NMI != 0 {
+                    self.nmi_interrupt = true;
+                }
+            }
+
+            if self.scanline >= self.region.scanlines() {
+                self.scanline = 0;
+                self.frame += 1;
+                self.status &= !STATUS_VBLANK_STARTED;
+                self.nmi_interrupt = false;
+            }
+        }
+    }
+
+    /// Returns whether the PPU has raised an NMI, acknowledging it
+    pub fn poll_nmi_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_interrupt)
+    }
+
+    /// Returns whether the PPU has raised an NMI without acknowledging it
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_interrupt
+    }
+
+    /// Writes to PPUCTRL ($2000). Turning on NMI generation while already in
+    /// VBlank immediately raises an NMI
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        let nmi_was_enabled = self.ctrl & CTRL_GENERATE_NMI != 0;
+        self.ctrl = value;
+        if !nmi_was_enabled
+            && self.ctrl & CTRL_GENERATE_NMI != 0
+            && self.status & STATUS_VBLANK_STARTED != 0
+        {
+            self.nmi_interrupt = true;
+        }
+    }
+
+    /// Writes to PPUMASK ($2001)
+    pub fn write_to_mask(&mut self, value: u8) {
+        self.mask = value;
+    }
+
+    /// Reads PPUSTATUS ($2002). This clears the VBlank flag and the w latch
+    pub fn read_status(&mut self) -> u8 {
+        let data = self.status;
+        self.status &= !STATUS_VBLANK_STARTED;
+        self.write_latch = false;
+        data
+    }
+
+    /// Returns what reading PPUSTATUS would return without clearing anything
+    pub fn peek_status(&self) -> u8 {
+        self.status
+    }
+
+    /// Writes to OAMADDR ($2003)
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.oam_addr = value;
+    }
+
+    /// Writes to OAMDATA ($2004), incrementing OAMADDR
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    /// Reads OAMDATA ($2004). Reads don't increment OAMADDR
+    pub fn read_oam_data(&self) -> u8 {
+        self.oam_data[self.oam_addr as usize]
+    }
+
+    /// Writes to PPUSCROLL ($2005), x first and then y
+    pub fn write_to_scroll(&mut self, value: u8) {
+        if self.write_latch {
+            self.scroll.1 = value;
+        } else {
+            self.scroll.0 = value;
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    /// Writes to PPUADDR ($2006), high byte first and then low byte
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        if self.write_latch {
+            self.addr = (self.addr & 0xFF00) | value as u16;
+        } else {
+            self.addr = (self.addr & 0x00FF) | ((value as u16) << 8);
+        }
+        // PPU addresses above 0x3FFF are mirrored down
+        self.addr &= 0x3FFF;
+        self.write_latch = !self.write_latch;
+    }
+
+    /// Copies a whole page of CPU memory into OAM (OAMDMA through $4014)
+    pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+        for value in data {
+            self.write_to_oam_data(*value);
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        let step = if self.ctrl & CTRL_VRAM_ADD_INCREMENT != 0 {
+            32
+        } else {
+            1
+        };
+        self.addr = self.addr.wrapping_add(step) & 0x3FFF;
+    }
+
+    /// Writes to PPUDATA ($2007) at the current VRAM address
+    pub fn write_to_data(&mut self, value: u8) {
+        let addr = self.addr;
+        match addr {
+            0..=0x1FFF => {
+                let offset = self.chr_offset(addr);
+                self.chr_rom[offset] = value;
+            }
+            0x2000..=0x3EFF => {
+                if let Some(index) = self.name_table_vram_index(addr) {
+                    self.vram[index] = value;
+                }
+            }
+            _ => self.palette_table[mirror_palette_addr(addr)] = value,
+        }
+        self.increment_vram_addr();
+    }
+
+    /// Reads PPUDATA ($2007) at the current VRAM address. Reads from pattern
+    /// tables and name tables return the contents of an internal buffer (which
+    /// then gets refilled) so a dummy read is needed after setting PPUADDR
+    pub fn read_data(&mut self) -> u8 {
+        let addr = self.addr;
+        self.increment_vram_addr();
+        match addr {
+            0..=0x1FFF => {
+                let value = self.chr_rom[self.chr_offset(addr)];
+                std::mem::replace(&mut self.internal_data_buf, value)
+            }
+            0x2000..=0x3EFF => {
+                let value = self.read_name_table(addr);
+                std::mem::replace(&mut self.internal_data_buf, value)
+            }
+            _ => self.palette_table[mirror_palette_addr(addr)],
+        }
+    }
+
+    /// Returns what reading PPUDATA would return without touching the
+    /// buffer or the VRAM address
+    pub fn peek_data(&self) -> u8 {
+        match self.addr {
+            0..=0x3EFF => self.internal_data_buf,
+            addr => self.palette_table[mirror_palette_addr(addr)],
+        }
+    }
+
+    /// Returns the pattern table address (before banking, see `chr_offset`)
+    /// of every tile that is currently on
+    /// screen (without duplicates): the background tiles of the name tables
+    /// the scroll position makes visible and the tiles of the sprites that
+    /// aren't hidden below the screen. Only what PPUMASK enables counts
+    pub fn visible_tiles(&self) -> Vec<u16> {
+        let mut tiles = Vec::new();
+
+        if self.mask & MASK_SHOW_BACKGROUND != 0 {
+            let bank = if self.ctrl & CTRL_BACKGROUND_PATTERN_TABLE != 0 {
+                0x1000
+            } else {
+                0
+            };
+            let base = (self.ctrl & CTRL_NAME_TABLE) as u16;
+            let mut name_tables = vec![base];
+            if self.scroll.0 != 0 {
+                name_tables.push(base ^ 1);
+            }
+            if self.scroll.1 != 0 {
+                name_tables.push(base ^ 2);
+            }
+            if self.scroll.0 != 0 && self.scroll.1 != 0 {
+                name_tables.push(base ^ 3);
+            }
+            for name_table in name_tables {
+                // the last 64 bytes of a name table are attributes, not tiles
+                for index in 0..960 {
+                    let addr = 0x2000 + name_table * 0x400 + index;
+                    let tile = self.read_name_table(addr) as u16;
+                    tiles.push(bank + tile * 16);
+                }
+            }
+        }
+
+        if self.mask & MASK_SHOW_SPRITES != 0 {
+            for sprite in self.oam_data.chunks_exact(4) {
+                // sprites at Y >= 0xEF are below the screen
+                if sprite[0] >= 0xEF {
+                    continue;
+                }
+                let tile = sprite[1] as u16;
+                if self.ctrl & CTRL_SPRITE_SIZE != 0 {
+                    // 8x16 sprites pick their pattern table with bit 0
+                    let bank = (tile & 1) * 0x1000;
+                    tiles.push(bank + (tile & 0xFE) * 16);
+                    tiles.push(bank + (tile | 1) * 16);
+                } else {
+                    let bank = if self.ctrl & CTRL_SPRITE_PATTERN_TABLE != 0 {
+                        0x1000
+                    } else {
+                        0
+                    };
+                    tiles.push(bank + tile * 16);
+                }
+            }
+        }
+
+        tiles.sort_unstable();
+        tiles.dedup();
+        tiles
+    }
+
+    /// Maps a name table address [0x2000 ... 0x3EFF] onto an index into VRAM
+    ///
+    /// Horizontal:
+    ///   [ A ] [ a ]
+    ///   [ B ] [ b ]
+    ///
+    /// Vertical:
+    ///   [ A ] [ B ]
+    ///   [ a ] [ b ]
+    ///
+    /// Single screen:
+    ///   [ A ] [ a ]
+    ///   [ a ] [ a ]
+    fn mirror_vram_addr(&self, addr: u16) -> u16 {
+        // 0x3000 - 0x3EFF mirrors 0x2000 - 0x2EFF
+        let vram_index = (addr & 0x2FFF) - 0x2000;
+        let name_table = vram_index / 0x400;
+        match (self.mirroring, name_table) {
+            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
+            (Mirroring::Horizontal, 1) | (Mirroring::Horizontal, 2) => vram_index - 0x400,
+            (Mirroring::Horizontal, 3) => vram_index - 0x800,
+            (Mirroring::SingleScreenLower, _) => vram_index % 0x400,
+            (Mirroring::SingleScreenUpper, _) => 0x400 + vram_index % 0x400,
+            _ => vram_index,
+        }
+    }
+}
+
+/// Maps a palette address [0x3F00 ... 0x3FFF] onto an index into palette
+/// memory. 0x3F10/0x3F14/0x3F18/0x3F1C mirror the background colors
+/// 0x3F00/0x3F04/0x3F08/0x3F0C
+fn mirror_palette_addr(addr: u16) -> usize {
+    let index = (addr & 0x1F) as usize;
+    match index {
+        0x10 | 0x14 | 0x18 | 0x1C => index - 0x10,
+        _ => index,
+    }
+}
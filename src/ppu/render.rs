@@ -0,0 +1,228 @@
+//! Contains the renderer, which draws a whole frame at once from the state
+//! the PPU is in when VBlank starts: the background of the name tables at
+//! the scroll position, then the sprites in OAM on top of (or behind) it.
+//!
+//! The frame buffer holds an NES palette index (0x00 - 0x3F) per pixel, so
+//! it can be turned into colors with any palette (along with the color
+//! emphasis bits the frame was drawn with). Since it's drawn in one
+//! go, mid-frame changes (split scrolling, palette swaps) aren't visible, and
+//! neither the 8 sprites per scanline limit nor sprite 0 hits are emulated.
+//! The extended video of boards like MMC5 (see the `extended` module) is
+//! drawn though, including its vertical split.
+
+use super::extended::Split;
+use super::{
+    CTRL_BACKGROUND_PATTERN_TABLE, CTRL_NAME_TABLE, CTRL_SPRITE_PATTERN_TABLE, CTRL_SPRITE_SIZE,
+    MASK_SHOW_BACKGROUND, MASK_SHOW_SPRITES, NesPPU,
+};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// PPUMASK ($2001) bits only the renderer cares about
+const MASK_GREYSCALE: u8 = 0b0000_0001;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_EMPHASIS: u8 = 0b1110_0000;
+
+// sprite attribute bits
+const SPRITE_PALETTE: u8 = 0b0000_0011;
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const SPRITE_FLIP_VERTICAL: u8 = 0b1000_0000;
+
+impl NesPPU {
+    /// The last frame drawn, as SCREEN_WIDTH * SCREEN_HEIGHT palette indices
+    /// row by row from the top left
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame_buffer
+    }
+
+    /// The color emphasis bits the last frame was drawn with, shifted down
+    /// to bits 0 - 2 (red, green, blue). PAL and Dendy PPUs have red and
+    /// green the other way around in PPUMASK, which is undone here so the
+    /// palettes work the same everywhere
+    pub fn frame_emphasis(&self) -> u8 {
+        self.frame_emphasis
+    }
+
+    /// Draws the frame into the frame buffer
+    pub(crate) fn render_frame(&mut self) {
+        let backdrop = self.palette_table[0];
+        let mut frame = vec![backdrop; SCREEN_WIDTH * SCREEN_HEIGHT];
+        // whether the background is opaque, for sprites behind it
+        let mut opaque = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        if self.mask & MASK_SHOW_BACKGROUND != 0 {
+            self.render_background(&mut frame, &mut opaque);
+        }
+        if self.mask & MASK_SHOW_SPRITES != 0 {
+            self.render_sprites(&mut frame, &opaque);
+        }
+
+        let greyscale = if self.mask & MASK_GREYSCALE != 0 {
+            0x30
+        } else {
+            0x3F
+        };
+        for pixel in frame.iter_mut() {
+            *pixel &= greyscale;
+        }
+        self.frame_buffer = frame;
+        let emphasis = (self.mask & MASK_EMPHASIS) >> 5;
+        self.frame_emphasis = if self.region.swaps_emphasis() {
+            emphasis & 0b100 | (emphasis & 0b01) << 1 | (emphasis & 0b10) >> 1
+        } else {
+            emphasis
+        };
+    }
+
+    fn render_background(&self, frame: &mut [u8], opaque: &mut [bool]) {
+        let bank = if self.ctrl & CTRL_BACKGROUND_PATTERN_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        };
+        // the scroll position is relative to the name table PPUCTRL selects
+        let base = self.ctrl & CTRL_NAME_TABLE;
+        let scroll_x = self.scroll.0 as usize + (base & 1) as usize * SCREEN_WIDTH;
+        let scroll_y = self.scroll.1 as usize + (base >> 1) as usize * SCREEN_HEIGHT;
+        let first_x = if self.mask & MASK_BACKGROUND_LEFT != 0 {
+            0
+        } else {
+            8
+        };
+        let split = self.split();
+
+        for y in 0..SCREEN_HEIGHT {
+            let world_y = (y + scroll_y) % (SCREEN_HEIGHT * 2);
+            let (name_table_y, tile_y) = (world_y / SCREEN_HEIGHT, world_y % SCREEN_HEIGHT / 8);
+            for x in first_x..SCREEN_WIDTH {
+                let in_split = split.filter(|(split, _)| (x / 8 < split.tile) != split.right);
+                let (value, palette) = if let Some((split, ex_ram)) = in_split {
+                    split_pixel(self, &split, ex_ram, x, y)
+                } else {
+                    let world_x = (x + scroll_x) % (SCREEN_WIDTH * 2);
+                    let (name_table_x, tile_x) =
+                        (world_x / SCREEN_WIDTH, world_x % SCREEN_WIDTH / 8);
+                    let name_table = 0x2000 + (name_table_y * 2 + name_table_x) as u16 * 0x400;
+
+                    let tile = self.read_name_table(name_table + (tile_y * 32 + tile_x) as u16);
+                    let row = world_y % SCREEN_HEIGHT % 8;
+                    if let Some((chr_bank, palette)) = self.extended_attribute(tile_x, tile_y) {
+                        let offset = self.chr_bank_offset(chr_bank, tile, row);
+                        (self.chr_pixel(offset, world_x % 8), palette)
+                    } else {
+                        let addr = bank + tile as u16 * 16 + row as u16;
+                        let value = self.chr_pixel(self.background_chr_offset(addr), world_x % 8);
+                        // every attribute byte covers 4x4 tiles, 2 bits per 2x2
+                        let attribute_addr =
+                            name_table + 0x3C0 + (tile_y / 4 * 8 + tile_x / 4) as u16;
+                        let attribute = self.read_name_table(attribute_addr);
+                        let shift = (tile_y % 4 / 2) * 4 + (tile_x % 4 / 2) * 2;
+                        (value, (attribute >> shift) & 0b11)
+                    }
+                };
+                if value == 0 {
+                    continue;
+                }
+
+                let pixel = y * SCREEN_WIDTH + x;
+                frame[pixel] = self.palette_table[(palette * 4 + value) as usize];
+                opaque[pixel] = true;
+            }
+        }
+    }
+
+    fn render_sprites(&self, frame: &mut [u8], opaque: &[bool]) {
+        let tall = self.ctrl & CTRL_SPRITE_SIZE != 0;
+        let height = if tall { 16 } else { 8 };
+        let first_x = if self.mask & MASK_SPRITES_LEFT != 0 {
+            0
+        } else {
+            8
+        };
+        // lower OAM indices have priority, so the first sprite with an opaque
+        // pixel at a position decides it
+        let mut covered = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        for sprite in self.oam_data.chunks_exact(4) {
+            // sprites show up a scanline below their Y
+            let top = sprite[0] as usize + 1;
+            let (tile, attributes, left) = (sprite[1] as u16, sprite[2], sprite[3] as usize);
+            for row in 0..height {
+                let y = top + row;
+                if y >= SCREEN_HEIGHT {
+                    break;
+                }
+                let row = if attributes & SPRITE_FLIP_VERTICAL != 0 {
+                    height - 1 - row
+                } else {
+                    row
+                };
+                let pattern = if tall {
+                    // 8x16 sprites pick their pattern table with bit 0
+                    (tile & 1) * 0x1000 + ((tile & 0xFE) + (row / 8) as u16) * 16
+                } else if self.ctrl & CTRL_SPRITE_PATTERN_TABLE != 0 {
+                    0x1000 + tile * 16
+                } else {
+                    tile * 16
+                };
+
+                for column in 0..8 {
+                    let x = left + column;
+                    if x >= SCREEN_WIDTH || x < first_x {
+                        continue;
+                    }
+                    let pixel = y * SCREEN_WIDTH + x;
+                    if covered[pixel] {
+                        continue;
+                    }
+                    let column = if attributes & SPRITE_FLIP_HORIZONTAL != 0 {
+                        7 - column
+                    } else {
+                        column
+                    };
+                    let value = self.pattern_pixel(pattern, column, row % 8);
+                    if value == 0 {
+                        continue;
+                    }
+                    covered[pixel] = true;
+                    if attributes & SPRITE_BEHIND_BACKGROUND != 0 && opaque[pixel] {
+                        continue;
+                    }
+                    let palette = 4 + (attributes & SPRITE_PALETTE);
+                    frame[pixel] = self.palette_table[(palette * 4 + value) as usize];
+                }
+            }
+        }
+    }
+
+    /// The 2 bit value of a pixel of the 8x8 tile at `addr` in the pattern
+    /// tables (0 = transparent)
+    fn pattern_pixel(&self, addr: u16, x: usize, y: usize) -> u8 {
+        self.chr_pixel(self.chr_offset(addr + y as u16), x)
+    }
+
+    /// The 2 bit value of pixel `x` of the tile row whose low bits are at
+    /// `offset` into CHR (the high bits are 8 bytes later, in the same bank)
+    fn chr_pixel(&self, offset: usize, x: usize) -> u8 {
+        let low = self.chr_rom[offset];
+        let high = self.chr_rom[(offset + 8) % self.chr_rom.len()];
+        let bit = 7 - x;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+}
+
+/// The value and palette of the pixel at (x, y) of a split: the split is a
+/// name table in ExRAM that scrolls vertically on its own (and not at all
+/// horizontally), drawn with its own CHR bank
+fn split_pixel(ppu: &NesPPU, split: &Split, ex_ram: &[u8], x: usize, y: usize) -> (u8, u8) {
+    let split_y = (y + split.scroll as usize) % SCREEN_HEIGHT;
+    let (tile_x, tile_y) = (x / 8, split_y / 8);
+    let tile = ex_ram[tile_y * 32 + tile_x];
+    let offset = ppu.chr_bank_offset(split.chr_bank, tile, split_y % 8);
+    let attribute = ex_ram[0x3C0 + tile_y / 4 * 8 + tile_x / 4];
+    let shift = (tile_y % 4 / 2) * 4 + (tile_x % 4 / 2) * 2;
+    (ppu.chr_pixel(offset, x % 8), (attribute >> shift) & 0b11)
+}
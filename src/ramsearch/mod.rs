@@ -0,0 +1,194 @@
+//! Contains the RAM search tool, which finds where a game keeps a variable
+//! (lives, health, a position, ...) by narrowing down candidate addresses the
+//! way the RAM search windows of other emulators do:
+//!
+//! 1. start a search, which snapshots the 2 KiB of internal RAM and the
+//!    cartridge's PRG RAM at [0x6000 ... 0x7FFF], with every address being a
+//!    candidate
+//! 2. play a bit (i.e. lose a life)
+//! 3. filter the candidates by how their values compare to the snapshot (or
+//!    to a known value). Every filter takes a new snapshot to compare the
+//!    next filter against
+//! 4. repeat until only a handful of candidates are left
+//!
+//! Values can be 8 or 16 bit (little-endian, like the 6502 stores them) and
+//! signed or unsigned.
+
+use crate::bus::Bus;
+
+const RAM_SIZE: usize = 0x0800;
+const PRG_RAM_START: u16 = 0x6000;
+/// How much PRG RAM gets searched: the 8 KiB at [0x6000 ... 0x7FFF] (boards
+/// like MMC5 and the FDS have more, banked in there or elsewhere)
+const PRG_RAM_WINDOW: usize = 0x2000;
+
+/// How many bytes each value spans
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValueSize {
+    Byte,
+    Word,
+}
+
+/// How a candidate's current value has to compare with what it's compared
+/// to in order to stay a candidate
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(self, lhs: i32, rhs: i32) -> bool {
+        match self {
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::Less => lhs < rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::LessOrEqual => lhs <= rhs,
+            Comparison::GreaterOrEqual => lhs >= rhs,
+        }
+    }
+}
+
+/// What a candidate's current value gets compared to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompareTo {
+    /// its value in the last snapshot (`NotEqual` finds values that changed,
+    /// `Equal` values that didn't)
+    Previous,
+    /// a specific value
+    Value(i32),
+    /// the value in the last snapshot plus this (`Equal` finds values that
+    /// changed by exactly this much)
+    ChangedBy(i32),
+}
+
+pub struct RamSearch {
+    size: ValueSize,
+    signed: bool,
+    /// the internal RAM followed by the PRG RAM, as of the last snapshot
+    snapshot: Vec<u8>,
+    /// the addresses still in the running, in ascending order
+    candidates: Vec<u16>,
+}
+
+impl RamSearch {
+    /// Starts a new search over the bus' RAM, with every address a candidate
+    pub fn new(bus: &Bus, size: ValueSize, signed: bool) -> Self {
+        let mut search = RamSearch {
+            size,
+            signed,
+            snapshot: Vec::new(),
+            candidates: Vec::new(),
+        };
+        search.reset(bus);
+        search
+    }
+
+    /// Makes every address a candidate again and takes a new snapshot
+    pub fn reset(&mut self, bus: &Bus) {
+        self.update(bus);
+        let prg_ram_end = PRG_RAM_START as usize + prg_ram(bus).len();
+        // 16 bit values can't start on the last byte of a region
+        let word = (self.size == ValueSize::Word) as usize;
+        self.candidates = (0..RAM_SIZE - word)
+            .chain(PRG_RAM_START as usize..prg_ram_end.saturating_sub(word))
+            .map(|addr| addr as u16)
+            .collect();
+    }
+
+    /// Takes a new snapshot for the next filter to compare against, without
+    /// touching the candidates
+    pub fn update(&mut self, bus: &Bus) {
+        self.snapshot = [bus.ram(), prg_ram(bus)].concat();
+    }
+
+    /// Keeps only the candidates whose current value compares to `compare_to`
+    /// as asked, then takes a new snapshot. Returns how many are left
+    pub fn filter(&mut self, bus: &Bus, comparison: Comparison, compare_to: CompareTo) -> usize {
+        let current = [bus.ram(), prg_ram(bus)].concat();
+        let previous = std::mem::replace(&mut self.snapshot, current);
+        let (size, signed, current) = (self.size, self.signed, &self.snapshot);
+        self.candidates.retain(|&addr| {
+            let (Some(value), Some(previous)) = (
+                value_in(current, addr, size, signed),
+                value_in(&previous, addr, size, signed),
+            ) else {
+                return false;
+            };
+            // a change that would overflow can't have happened
+            let rhs = match compare_to {
+                CompareTo::Previous => Some(previous),
+                CompareTo::Value(value) => Some(value),
+                CompareTo::ChangedBy(delta) => previous.checked_add(delta),
+            };
+            rhs.is_some_and(|rhs| comparison.holds(value, rhs))
+        });
+        self.candidates.len()
+    }
+
+    /// The addresses still in the running, in ascending order
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    pub fn size(&self) -> ValueSize {
+        self.size
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signed
+    }
+
+    /// The value at `addr` in the last snapshot, or None if the address isn't
+    /// RAM
+    pub fn value(&self, addr: u16) -> Option<i32> {
+        let word = (self.size == ValueSize::Word) as u16;
+        let index = snapshot_index(addr)?;
+        // both bytes have to be in the same region (and the PRG RAM has to
+        // exist)
+        let last = snapshot_index(addr.wrapping_add(word))?;
+        if last != index + word as usize {
+            return None;
+        }
+        value_in(&self.snapshot, addr, self.size, self.signed)
+    }
+}
+
+/// Where an address' byte is kept in a snapshot
+fn snapshot_index(addr: u16) -> Option<usize> {
+    match addr {
+        0..=0x07FF => Some(addr as usize),
+        PRG_RAM_START..=0x7FFF => Some(RAM_SIZE + (addr - PRG_RAM_START) as usize),
+        _ => None,
+    }
+}
+
+/// The part of the cartridge's PRG RAM that gets searched
+fn prg_ram(bus: &Bus) -> &[u8] {
+    let prg_ram = bus.prg_ram();
+    &prg_ram[..prg_ram.len().min(PRG_RAM_WINDOW)]
+}
+
+/// Reads the value at an address out of a snapshot, or None if the snapshot
+/// doesn't have it
+fn value_in(snapshot: &[u8], addr: u16, size: ValueSize, signed: bool) -> Option<i32> {
+    let index = snapshot_index(addr)?;
+    Some(match (size, signed) {
+        (ValueSize::Byte, false) => *snapshot.get(index)? as i32,
+        (ValueSize::Byte, true) => *snapshot.get(index)? as i8 as i32,
+        (ValueSize::Word, signed) => {
+            let bytes = snapshot.get(index..index + 2)?;
+            let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+            if signed {
+                value as i16 as i32
+            } else {
+                value as i32
+            }
+        }
+    })
+}
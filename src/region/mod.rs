@@ -0,0 +1,109 @@
+//! Contains the TV systems the NES was made for, and everything about the
+//! machine's timing that differs between them:
+//!
+//! |                      | NTSC      | PAL       | Dendy     |
+//! |----------------------|-----------|-----------|-----------|
+//! | CPU clock (Hz)       | 1_789_773 | 1_662_607 | 1_773_448 |
+//! | PPU dots per cycle   | 3         | 3.2       | 3         |
+//! | scanlines per frame  | 262       | 312       | 312       |
+//! | VBlank (NMI) starts  | 241       | 241       | 291       |
+//! | VBlank scanlines     | 20        | 70        | 20        |
+//! | frames per second    | ~60.1     | ~50.0     | ~50.0     |
+//!
+//! PAL consoles have an APU of their own too, with longer frame counter
+//! steps and noise/DMC periods that are tuned to their slower clock. The
+//! Dendy (a Famiclone sold in Russia) keeps NTSC's APU but runs it at its
+//! own clock, and puts its extra scanlines before VBlank so games that
+//! count cycles from the NMI still work. PAL and Dendy PPUs have the red
+//! and green emphasis bits of PPUMASK the other way around.
+//!
+//! Which one a game wants comes from its header (see `Rom::region`), NTSC
+//! when it doesn't say.
+
+use std::fmt;
+
+/// Number of PPU dots in a single scanline (in every region)
+const DOTS_PER_SCANLINE: u64 = 341;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    pub const ALL: [Region; 3] = [Region::Ntsc, Region::Pal, Region::Dendy];
+
+    /// Parses a region's name (ntsc, pal or dendy, in any case)
+    pub fn parse(name: &str) -> Result<Region, String> {
+        Region::ALL
+            .into_iter()
+            .find(|region| region.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown region {name} (expected ntsc, pal or dendy)"))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        }
+    }
+
+    /// The rate the CPU (and so the APU) runs at (in Hz)
+    pub fn cpu_clock(self) -> u64 {
+        match self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_448,
+        }
+    }
+
+    /// How many PPU dots go by in how many CPU cycles, (3, 1) or PAL's
+    /// (16, 5)
+    pub fn ppu_dots_per_cycles(self) -> (u64, u64) {
+        match self {
+            Region::Pal => (16, 5),
+            Region::Ntsc | Region::Dendy => (3, 1),
+        }
+    }
+
+    /// Number of scanlines in a frame
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// The scanline VBlank starts (and the NMI is raised) on, which lasts
+    /// until the end of the frame
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// How many frames the machine puts out a second, for frontends to
+    /// pace themselves with
+    pub fn frame_rate(self) -> f64 {
+        let (dots, cycles) = self.ppu_dots_per_cycles();
+        let dots_per_second = self.cpu_clock() as f64 * dots as f64 / cycles as f64;
+        dots_per_second / (DOTS_PER_SCANLINE * self.scanlines() as u64) as f64
+    }
+
+    /// Whether bits 5 and 6 of PPUMASK emphasize green and red, instead of
+    /// red and green
+    pub fn swaps_emphasis(self) -> bool {
+        self != Region::Ntsc
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
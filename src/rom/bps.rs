@@ -0,0 +1,170 @@
+//! Contains BPS patches, which most translations are distributed as these
+//! days. Instead of overwriting bytes in place like IPS, a BPS patch builds
+//! the patched file from scratch out of pieces of the original, of the
+//! patch and of what it has built so far, so it can move data around:
+//!
+//! | Bytes  | Contents                                                 |
+//! |--------|----------------------------------------------------------|
+//! | 4      | `BPS1`                                                   |
+//! | number | the size of the original                                 |
+//! | number | the size of the patched file                             |
+//! | number | the size of the metadata that follows (skipped)          |
+//! | ...    | the actions, until the footer                            |
+//! | 4      | the CRC-32 of the original (little endian)               |
+//! | 4      | the CRC-32 of the patched file                           |
+//! | 4      | the CRC-32 of the patch, up to this point                |
+//!
+//! Every action is a number with what to do in its low 2 bits and how many
+//! bytes to do it for (minus one) in the rest:
+//! * 0: copy the original's bytes from where the patched file is up to
+//! * 1: copy bytes from the patch
+//! * 2: copy the original's bytes from an offset (a signed number, from
+//!   where the last of these left off)
+//! * 3: copy the patched file's own bytes from an offset (same)
+//!
+//! The CRCs make sure the patch is applied to the ROM it was made for and
+//! that it came out right, which is the big win over IPS.
+
+use crate::hash::crc32;
+
+const HEADER: &[u8] = b"BPS1";
+/// The 3 CRC-32s at the end
+const FOOTER_SIZE: usize = 12;
+/// The biggest file we'll patch to (way more than any NES game, but it
+/// keeps a broken patch from asking for all the memory there is)
+pub(super) const MAX_SIZE: usize = 0x1000_0000;
+
+// actions
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+const TARGET_COPY: u64 = 3;
+
+/// Reads one of the variable length numbers BPS and UPS patches use: 7
+/// bits a byte, least significant first, with the top bit set on the last
+/// one (and each byte after the first counting one more, so there's only
+/// one way to write a number). Advances `pos` past it
+pub(super) fn read_number(patch: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut number: u64 = 0;
+    let mut shift: u64 = 1;
+    loop {
+        let byte = *patch.get(*pos).ok_or("Patch is truncated")?;
+        *pos += 1;
+        number = (byte as u64 & 0x7F)
+            .checked_mul(shift)
+            .and_then(|value| number.checked_add(value))
+            .ok_or("Patch has a number that's too big")?;
+        if byte & 0x80 != 0 {
+            return Ok(number);
+        }
+        shift = shift
+            .checked_shl(7)
+            .ok_or("Patch has a number that's too big")?;
+        number = number
+            .checked_add(shift)
+            .ok_or("Patch has a number that's too big")?;
+    }
+}
+
+/// Checks the 3 CRC-32s at the end of a BPS or UPS patch: that it's
+/// intact, and that `original` is what it was made for
+pub(super) fn check_footer(original: &[u8], patch: &[u8]) -> Result<(u32, usize), String> {
+    let footer = patch.len() - FOOTER_SIZE;
+    let read_crc = |at: usize| u32::from_le_bytes(patch[at..at + 4].try_into().unwrap());
+    if crc32(&patch[..footer + 8]) != read_crc(footer + 8) {
+        return Err("Patch is corrupt (CRC mismatch)".to_string());
+    }
+    if crc32(original) != read_crc(footer) {
+        return Err("Patch was made for a different ROM (CRC mismatch)".to_string());
+    }
+    Ok((read_crc(footer + 4), footer))
+}
+
+/// Applies a patch to `original`, returning the patched copy
+pub fn apply(original: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(HEADER) {
+        return Err("File is not a BPS patch".to_string());
+    }
+    if patch.len() < HEADER.len() + FOOTER_SIZE {
+        return Err("Patch is truncated".to_string());
+    }
+    let (target_crc, end) = check_footer(original, patch)?;
+
+    let mut pos = HEADER.len();
+    let source_size = read_number(patch, &mut pos)? as usize;
+    let target_size = read_number(patch, &mut pos)? as usize;
+    let metadata_size = read_number(patch, &mut pos)? as usize;
+    pos = pos.saturating_add(metadata_size);
+    if source_size != original.len() {
+        return Err(format!(
+            "Patch is for a {source_size} byte file, not {} bytes",
+            original.len()
+        ));
+    }
+    if target_size > MAX_SIZE {
+        return Err(format!("Patch makes a {target_size} byte file"));
+    }
+
+    let out_of_bounds = || "Patch reads past the end of a file".to_string();
+    let mut patched = Vec::with_capacity(target_size);
+    let mut source_offset: i64 = 0;
+    let mut target_offset: i64 = 0;
+    while pos < end {
+        let action = read_number(patch, &mut pos)?;
+        let len = (action >> 2) as usize + 1;
+        if patched.len().saturating_add(len) > target_size {
+            return Err("Patch writes past the end of the patched file".to_string());
+        }
+        match action & 0b11 {
+            SOURCE_READ => {
+                let start = patched.len();
+                let bytes = original.get(start..start + len).ok_or_else(out_of_bounds)?;
+                patched.extend_from_slice(bytes);
+            }
+            TARGET_READ => {
+                let bytes = patch[..end].get(pos..pos.saturating_add(len));
+                patched.extend_from_slice(bytes.ok_or_else(out_of_bounds)?);
+                pos += len;
+            }
+            SOURCE_COPY => {
+                source_offset = source_offset.saturating_add(read_offset(patch, &mut pos)?);
+                let start = usize::try_from(source_offset).map_err(|_| out_of_bounds())?;
+                let bytes = original
+                    .get(start..start.saturating_add(len))
+                    .ok_or_else(out_of_bounds)?;
+                patched.extend_from_slice(bytes);
+                source_offset += len as i64;
+            }
+            TARGET_COPY => {
+                target_offset = target_offset.saturating_add(read_offset(patch, &mut pos)?);
+                let start = usize::try_from(target_offset).map_err(|_| out_of_bounds())?;
+                if start >= patched.len() {
+                    return Err(out_of_bounds());
+                }
+                // one byte at a time, since the copy can overlap what it's
+                // writing (that's how runs get repeated)
+                for index in start..start + len {
+                    patched.push(patched[index]);
+                }
+                target_offset += len as i64;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if patched.len() != target_size || crc32(&patched) != target_crc {
+        return Err("Patch didn't apply right (CRC mismatch)".to_string());
+    }
+    Ok(patched)
+}
+
+/// Reads a signed offset: a number with the sign in its low bit
+fn read_offset(patch: &[u8], pos: &mut usize) -> Result<i64, String> {
+    let number = read_number(patch, pos)?;
+    let magnitude = (number >> 1) as i64;
+    Ok(if number & 1 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
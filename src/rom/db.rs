@@ -0,0 +1,120 @@
+//! Contains the game database, which corrects the headers of iNES dumps:
+//! plenty of the ones going around have the wrong mapper, mirroring or
+//! battery flag (they predate NES 2.0, or were made by hand), and most
+//! don't say which region they're for at all.
+//!
+//! Games are looked up by the CRC-32 of their PRG ROM followed by their CHR
+//! ROM (the header and trainer aren't part of it), the checksum of the
+//! `<rom>` in the NES 2.0 XML database the entries are taken from. The build
+//! script turns `data/nes20db.xml` into a `phf` map like the CPU's opcodes,
+//! so a lookup costs nothing at startup. Dropping the whole of nes20db.xml
+//! in there (or building with `NES20DB` set to where it is) is all it takes
+//! to know every game it does.
+//!
+//! ```
+//! use nes_emulator::rom::db;
+//!
+//! let game = db::lookup(0x3337EC46).unwrap();
+//! assert_eq!(game.title, "Super Mario Bros.");
+//! assert_eq!(game.mapper, 0);
+//! ```
+
+use super::Rom;
+use crate::ppu::Mirroring;
+use crate::region::Region;
+
+/// What's plugged into the expansion port (or the controller ports) by
+/// default, as in byte 15 of an NES 2.0 header. The emulator itself only
+/// has standard controllers, this is for frontends to tell the player
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExpansionDevice {
+    StandardControllers,
+    /// the NES Four Score (4 controllers)
+    FourScore,
+    /// the Famicom's 4 players adapter
+    FourPlayersAdapter,
+    Zapper,
+    TwoZappers,
+    PowerPad,
+    ArkanoidVaus,
+    FamilyBasicKeyboard,
+    /// any of the others, by its NES 2.0 number
+    Other(u8),
+}
+
+impl ExpansionDevice {
+    /// The device for an NES 2.0 number (None for 0, which doesn't say)
+    pub const fn from_nes_2(number: u8) -> Option<ExpansionDevice> {
+        Some(match number {
+            0x00 => return None,
+            0x01 => ExpansionDevice::StandardControllers,
+            0x02 => ExpansionDevice::FourScore,
+            0x03 => ExpansionDevice::FourPlayersAdapter,
+            0x08 => ExpansionDevice::Zapper,
+            0x09 => ExpansionDevice::TwoZappers,
+            // sides A and B
+            0x0B | 0x0C => ExpansionDevice::PowerPad,
+            // the NES and Famicom versions
+            0x0F | 0x10 => ExpansionDevice::ArkanoidVaus,
+            0x23 => ExpansionDevice::FamilyBasicKeyboard,
+            number => ExpansionDevice::Other(number),
+        })
+    }
+}
+
+/// What the database knows about a game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameInfo {
+    pub title: &'static str,
+    pub mapper: u8,
+    pub submapper: u8,
+    /// None for boards with four screen VRAM, which no mirroring describes
+    pub mirroring: Option<Mirroring>,
+    pub battery: bool,
+    /// None for games that run on any region
+    pub region: Option<Region>,
+    pub expansion: ExpansionDevice,
+}
+
+impl GameInfo {
+    /// Puts what the database knows over what the header said. Where it
+    /// knows nothing better (a game for any region, a four screen board) the
+    /// header's guess stays
+    pub fn apply(&self, rom: &mut Rom) {
+        rom.title = Some(self.title);
+        rom.mapper = self.mapper;
+        rom.submapper = self.submapper;
+        if let Some(mirroring) = self.mirroring {
+            rom.screen_mirroring = mirroring;
+        }
+        rom.battery = self.battery;
+        if let Some(region) = self.region {
+            rom.region = Some(region);
+        }
+        rom.expansion = Some(self.expansion);
+    }
+}
+
+/// Looks a game up by the CRC-32 of its PRG ROM followed by its CHR ROM
+/// (see `Rom::crc32`)
+pub fn lookup(crc32: u32) -> Option<&'static GameInfo> {
+    GAMES.get(&crc32)
+}
+
+/// The number of games in the database
+pub fn len() -> usize {
+    GAMES.len()
+}
+
+/// The expansion device for an NES 2.0 number, standard controllers when
+/// it doesn't say (the generated table is made of these)
+const fn expansion(number: u8) -> ExpansionDevice {
+    match ExpansionDevice::from_nes_2(number) {
+        Some(device) => device,
+        None => ExpansionDevice::StandardControllers,
+    }
+}
+
+/// The games, by the CRC-32 of their PRG and CHR ROM (generated out of
+/// `data/nes20db.xml` by the build script)
+static GAMES: phf::Map<u32, GameInfo> = include!(concat!(env!("OUT_DIR"), "/games.rs"));
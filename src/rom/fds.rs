@@ -0,0 +1,181 @@
+//! Contains Famicom Disk System disk images (`.fds` files). A disk image is
+//! every side of a disk one after the other, 65500 bytes each, optionally
+//! behind fwNES's 16 byte header:
+//!
+//! | Byte | Contents                        |
+//! |------|---------------------------------|
+//! | 0-3  | `FDS` followed by 0x1A          |
+//! | 4    | number of sides                 |
+//! | 5-15 | 0                               |
+//!
+//! A side is a row of blocks, each starting with its type:
+//! * 1: the disk info (56 bytes, starting with `*NINTENDO-HVC*`)
+//! * 2: how many files there are (2 bytes)
+//! * 3: a file's header (16 bytes, its size is at 13 - 14)
+//! * 4: a file's contents (1 + the size from the header before it)
+//!
+//! On the real disk every block follows a gap and a start mark and is
+//! followed by a CRC, which `.fds` leaves out. `DiskImage::to_track` puts
+//! them back in for the drive (see `mapper::fds`), and `from_tracks` takes
+//! them out again so what games write can be saved as an image or a diff.
+
+use super::ips;
+
+const FDS_TAG: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+/// The size of a side in a `.fds` file
+pub const SIDE_SIZE: usize = 65500;
+/// Every side starts with the text `*NINTENDO-HVC*` after the block type
+const DISK_VERIFICATION: &[u8] = b"*NINTENDO-HVC*";
+
+// block types
+const DISK_INFO: u8 = 1;
+const FILE_AMOUNT: u8 = 2;
+const FILE_HEADER: u8 = 3;
+const FILE_DATA: u8 = 4;
+const DISK_INFO_SIZE: usize = 56;
+const FILE_AMOUNT_SIZE: usize = 2;
+const FILE_HEADER_SIZE: usize = 16;
+
+/// How many bytes of gap there are before the first block (28300 bits)
+const LEAD_IN: usize = 28300 / 8;
+/// How many bytes of gap there are between blocks (976 bits)
+const GAP: usize = 976 / 8;
+/// The byte with the bit that tells the drive a block starts
+const START_MARK: u8 = 0x80;
+/// What's written where the CRC of a block goes (the drive doesn't check
+/// the CRCs, see `mapper::fds`)
+const CRC: [u8; 2] = [0, 0];
+/// How long a side's track is at least, with room to spare after the last
+/// block for files the game writes
+pub const TRACK_SIZE: usize = 0x12000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskImage {
+    /// every side, `SIDE_SIZE` bytes each
+    pub sides: Vec<Vec<u8>>,
+}
+
+impl DiskImage {
+    /// Parses the contents of a `.fds` file, with or without its header
+    pub fn new(raw: &[u8]) -> Result<DiskImage, String> {
+        let body = if raw.starts_with(&FDS_TAG) {
+            &raw[HEADER_SIZE.min(raw.len())..]
+        } else {
+            raw
+        };
+        if body.is_empty() || !body.len().is_multiple_of(SIDE_SIZE) {
+            return Err(format!(
+                "File is not an FDS disk image: {} bytes isn't a whole number of {SIDE_SIZE} \
+                 byte sides",
+                body.len()
+            ));
+        }
+        let sides: Vec<Vec<u8>> = body.chunks(SIDE_SIZE).map(|side| side.to_vec()).collect();
+        for (number, side) in sides.iter().enumerate() {
+            if side[0] != DISK_INFO || !side[1..].starts_with(DISK_VERIFICATION) {
+                return Err(format!("Side {number} of the disk image has no disk info"));
+            }
+        }
+        Ok(DiskImage { sides })
+    }
+
+    /// Reads and parses a `.fds` file
+    pub fn load(path: &str) -> Result<DiskImage, String> {
+        let raw = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+        DiskImage::new(&raw)
+    }
+
+    /// The image as a `.fds` file (with fwNES's header, like most are)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = FDS_TAG.to_vec();
+        raw.push(self.sides.len() as u8);
+        raw.resize(HEADER_SIZE, 0);
+        for side in &self.sides {
+            raw.extend_from_slice(side);
+        }
+        raw
+    }
+
+    /// Creates an IPS patch (see `rom::ips`) that turns `original` into
+    /// this image, which is how what a game wrote to a disk gets saved
+    pub fn diff(&self, original: &DiskImage) -> Result<Vec<u8>, String> {
+        ips::create(&original.to_bytes(), &self.to_bytes())
+    }
+
+    /// Applies an IPS patch made by `diff`
+    pub fn apply_diff(&self, patch: &[u8]) -> Result<DiskImage, String> {
+        DiskImage::new(&ips::apply(&self.to_bytes(), patch)?)
+    }
+
+    /// The blocks of `side` the way they are on the disk: with the gaps,
+    /// the start marks and the CRCs, padded out to (at least) `TRACK_SIZE`
+    pub fn to_track(&self, side: usize) -> Vec<u8> {
+        let data = &self.sides[side];
+        let mut track = vec![0; LEAD_IN];
+        let mut offset = 0;
+        let mut file_size = 0;
+        while offset < data.len() {
+            let len = match data[offset] {
+                DISK_INFO => DISK_INFO_SIZE,
+                FILE_AMOUNT => FILE_AMOUNT_SIZE,
+                FILE_HEADER => FILE_HEADER_SIZE,
+                FILE_DATA => 1 + file_size,
+                // the rest of the side is unused
+                _ => break,
+            };
+            let Some(block) = data.get(offset..offset + len) else {
+                break;
+            };
+            if block[0] == FILE_HEADER {
+                file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+            }
+            track.push(START_MARK);
+            track.extend_from_slice(block);
+            track.extend_from_slice(&CRC);
+            track.resize(track.len() + GAP, 0);
+            offset += len;
+        }
+        track.resize(track.len().max(TRACK_SIZE), 0);
+        track
+    }
+
+    /// Turns the tracks of every side back into an image, the opposite of
+    /// `to_track`
+    pub fn from_tracks(tracks: &[Vec<u8>]) -> DiskImage {
+        let sides = tracks.iter().map(|track| side_from_track(track)).collect();
+        DiskImage { sides }
+    }
+}
+
+/// Picks the blocks out of a track, skipping the gaps and the CRCs
+fn side_from_track(track: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut offset = 0;
+    let mut file_size = 0;
+    // the gap ends with the start mark
+    while let Some(mark) = track[offset..].iter().position(|&byte| byte != 0) {
+        let start = offset + mark + 1;
+        let len = match track.get(start) {
+            Some(&DISK_INFO) => DISK_INFO_SIZE,
+            Some(&FILE_AMOUNT) => FILE_AMOUNT_SIZE,
+            Some(&FILE_HEADER) => FILE_HEADER_SIZE,
+            Some(&FILE_DATA) => 1 + file_size,
+            _ => break,
+        };
+        let Some(block) = track.get(start..start + len) else {
+            break;
+        };
+        if block[0] == FILE_HEADER {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        side.extend_from_slice(block);
+        offset = start + len + CRC.len();
+        if offset >= track.len() {
+            break;
+        }
+    }
+    side.resize(SIDE_SIZE, 0);
+    side.truncate(SIDE_SIZE);
+    side
+}
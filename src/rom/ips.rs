@@ -0,0 +1,104 @@
+//! Contains IPS patches, the simplest and oldest of the ROM patch formats.
+//! A patch is a list of records that each overwrite a run of bytes:
+//!
+//! | Bytes | Contents                                                  |
+//! |-------|-----------------------------------------------------------|
+//! | 5     | `PATCH`                                                   |
+//! | 3     | record: offset (big endian)                               |
+//! | 2     | record: length (big endian), 0 for a run of one value     |
+//! | n     | record: the bytes, or a 2 byte count and the value        |
+//! | 3     | `EOF`                                                     |
+//! | 3     | optional: the length to cut the patched file down to      |
+//!
+//! Offsets are 24 bit, so IPS can only patch the first 16 MiB of a file,
+//! and a record can't start at 0x454F46 since that reads as `EOF`.
+
+const HEADER: &[u8] = b"PATCH";
+const FOOTER: &[u8] = b"EOF";
+/// The offset that can't start a record
+const EOF_OFFSET: usize = 0x454F46;
+const MAX_OFFSET: usize = 0xFF_FFFF;
+const MAX_RECORD: usize = 0xFFFF;
+
+/// Creates a patch that turns `original` into `modified`
+pub fn create(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, String> {
+    if modified.len() > MAX_OFFSET + 1 {
+        return Err("IPS patches can't reach past 16 MiB".to_string());
+    }
+    let differs = |offset: usize| original.get(offset) != Some(&modified[offset]);
+    let mut patch = HEADER.to_vec();
+    let mut offset = 0;
+    while offset < modified.len() {
+        if !differs(offset) {
+            offset += 1;
+            continue;
+        }
+        // a record can't start where it would read as the footer
+        let start = if offset == EOF_OFFSET {
+            offset - 1
+        } else {
+            offset
+        };
+        let mut end = offset;
+        while end < modified.len() && end - start < MAX_RECORD && differs(end) {
+            end += 1;
+        }
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&modified[start..end]);
+        offset = end;
+    }
+    patch.extend_from_slice(FOOTER);
+    if modified.len() < original.len() {
+        patch.extend_from_slice(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+/// Whether a patch doesn't change anything
+pub fn is_empty(patch: &[u8]) -> bool {
+    patch.len() == HEADER.len() + FOOTER.len()
+        && patch.starts_with(HEADER)
+        && patch.ends_with(FOOTER)
+}
+
+/// Applies a patch to `original`, returning the patched copy
+pub fn apply(original: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "IPS patch is truncated".to_string();
+    if !patch.starts_with(HEADER) {
+        return Err("File is not an IPS patch".to_string());
+    }
+    let mut patched = original.to_vec();
+    let mut rest = &patch[HEADER.len()..];
+    loop {
+        if rest.starts_with(FOOTER) {
+            rest = &rest[FOOTER.len()..];
+            break;
+        }
+        if rest.len() < 5 {
+            return Err(truncated());
+        }
+        let offset = u32::from_be_bytes([0, rest[0], rest[1], rest[2]]) as usize;
+        let len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        rest = &rest[5..];
+        let (bytes, record_len) = if len == 0 {
+            // a run of one value
+            if rest.len() < 3 {
+                return Err(truncated());
+            }
+            let count = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            (vec![rest[2]; count], 3)
+        } else {
+            (rest.get(..len).ok_or_else(truncated)?.to_vec(), len)
+        };
+        rest = &rest[record_len..];
+        if patched.len() < offset + bytes.len() {
+            patched.resize(offset + bytes.len(), 0);
+        }
+        patched[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    if let [a, b, c, ..] = *rest {
+        patched.truncate(u32::from_be_bytes([0, a, b, c]) as usize);
+    }
+    Ok(patched)
+}
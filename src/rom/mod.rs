@@ -0,0 +1,322 @@
+//! Contains the definition of a cartridge ROM and the parsing of the iNES
+//! file format (`.nes` files) most NES games are distributed in.
+//!
+//! An iNES file is laid out as:
+//! * a 16 byte header
+//! * an optional 512 byte trainer (which we skip)
+//! * PRG ROM (in 16 KiB pages), the program the CPU runs
+//! * CHR ROM (in 8 KiB pages), the pattern tables the PPU draws with.
+//!   Cartridges without CHR ROM have 8 KiB of CHR RAM instead
+//!
+//! Header layout:
+//!
+//! | Byte | Contents                                                     |
+//! |------|--------------------------------------------------------------|
+//! | 0-3  | `NES` followed by 0x1A                                       |
+//! | 4    | number of 16 KiB PRG ROM pages                               |
+//! | 5    | number of 8 KiB CHR ROM pages                                |
+//! | 6    | mirroring, battery, trainer, four screen, mapper low nibble  |
+//! | 7    | VS/Playchoice, NES 2.0 marker, mapper high nibble            |
+//! | 8    | NES 2.0 only: submapper (high nibble), mapper bits 8-11      |
+//! | 9    | iNES only: TV system (bit 0, 1 for PAL)                      |
+//! | 10-11| rarely used extensions (ignored)                             |
+//! | 12   | NES 2.0 only: timing (bits 0-1, NTSC/PAL/multi-region/Dendy) |
+//! | 13-14| rarely used extensions (ignored)                             |
+//! | 15   | NES 2.0 only: default expansion device (bits 0-5)            |
+//!
+//! NES 2.0 headers are marked by bits 2-3 of byte 7 being `10`. The
+//! submapper tells apart boards that share a mapper number but are wired
+//! differently (i.e. the Konami VRCs). Old iNES headers are often filled
+//! with junk past byte 7 (i.e. `DiskDude!`), so their TV system bit only
+//! counts when bytes 12-15 are clean. Either way, games the game database
+//! (see the `db` module) knows get their header corrected from it, unless
+//! it's turned off (see `Rom::parse`).
+//!
+//! Famicom Disk System games come as disk images instead (see the `fds`
+//! module), which run on the BIOS of the RAM adapter plugged into the
+//! cartridge slot. They're loaded as a `Rom` with the BIOS as its PRG ROM
+//! and the disk on the side, and the mapper number iNES set aside for it.
+//!
+//! Some boards' games only come as UNIF files (see the `unif` module),
+//! which name the board instead of numbering it, and get loaded as the
+//! iNES mapper the board is the same as.
+//!
+//! Whatever the format, the file can come out of a ZIP archive and get
+//! soft patched with an IPS, UPS or BPS patch on the way in (see
+//! `read_file` and `apply_patch`), without anything being written to disk.
+
+pub mod bps;
+pub mod db;
+pub mod fds;
+pub mod ips;
+pub mod unif;
+pub mod ups;
+
+use crate::hash::{crc32, md5, sha1};
+use crate::ppu::Mirroring;
+use crate::region::Region;
+use crate::zip::ZipArchive;
+use db::ExpansionDevice;
+use fds::DiskImage;
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+pub const PRG_ROM_PAGE_SIZE: usize = 0x4000;
+pub const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+/// The iNES mapper number of the Famicom Disk System
+pub const FDS_MAPPER: u8 = 20;
+/// The size of the Famicom Disk System's BIOS (`disksys.rom`)
+const FDS_BIOS_SIZE: usize = 0x2000;
+/// What the files we can load end with, for picking them out of a ZIP
+/// archive
+const ROM_EXTENSIONS: [&str; 6] = [".nes", ".fds", ".nsf", ".nsfe", ".unf", ".unif"];
+
+// flags 7 bits
+const FLAGS_NES_2: u8 = 0b0000_1100;
+const NES_2: u8 = 0b0000_1000;
+
+// flags 6 bits
+const FLAG_VERTICAL_MIRRORING: u8 = 0b0000_0001;
+const FLAG_BATTERY: u8 = 0b0000_0010;
+const FLAG_TRAINER: u8 = 0b0000_0100;
+const FLAG_FOUR_SCREEN: u8 = 0b0000_1000;
+
+// flags 9 (iNES) and timing (NES 2.0) bits
+const FLAG_PAL: u8 = 0b0000_0001;
+const TIMING: u8 = 0b0000_0011;
+// NES 2.0 byte 15 bits
+const EXPANSION_DEVICE: u8 = 0b0011_1111;
+
+#[derive(Debug, Clone)]
+pub struct Rom {
+    /// the program the CPU runs
+    pub prg_rom: Vec<u8>,
+    /// the pattern tables (empty when the cartridge has CHR RAM instead)
+    pub chr_rom: Vec<u8>,
+    /// the iNES mapper number (which board the game was made for)
+    pub mapper: u8,
+    /// the NES 2.0 submapper number (0 for iNES headers)
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    /// whether the PRG RAM is battery backed (the game saves progress there)
+    pub battery: bool,
+    /// the TV system the game was made for, None when the header doesn't
+    /// say (or the game runs on any)
+    pub region: Option<Region>,
+    /// what the game expects in the expansion port, when the header or the
+    /// game database says
+    pub expansion: Option<ExpansionDevice>,
+    /// the game's title, when the game database knows it
+    pub title: Option<&'static str>,
+    /// the disk in the drive, for Famicom Disk System games
+    pub disk: Option<DiskImage>,
+}
+
+impl Rom {
+    /// Parses the contents of an iNES file, correcting the header with the
+    /// game database
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        Rom::parse(raw, true)
+    }
+
+    /// Parses the contents of an iNES file, taking the header as it is when
+    /// `use_database` is off
+    pub fn parse(raw: &[u8], use_database: bool) -> Result<Rom, String> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        let flags_6 = raw[6];
+        let flags_7 = raw[7];
+        let nes_2 = flags_7 & FLAGS_NES_2 == NES_2;
+        // old tools left junk (like "DiskDude!") in bytes 7 - 15 of iNES
+        // headers, which shows in bytes 12 - 15 that are meant to be zero.
+        // Nothing past byte 6 can be trusted then, mapper number included
+        let junk = !nes_2 && raw[12..16].iter().any(|&byte| byte != 0);
+        let mapper_high = if junk { 0 } else { flags_7 & 0b1111_0000 };
+        let mapper = mapper_high | (flags_6 >> 4);
+        let submapper = if nes_2 { raw[8] >> 4 } else { 0 };
+        let region = if nes_2 {
+            match raw[12] & TIMING {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                3 => Some(Region::Dendy),
+                _ => None,
+            }
+        } else if !junk && raw[9] & FLAG_PAL != 0 {
+            Some(Region::Pal)
+        } else {
+            None
+        };
+        let expansion = if nes_2 {
+            ExpansionDevice::from_nes_2(raw[15] & EXPANSION_DEVICE)
+        } else {
+            None
+        };
+
+        let screen_mirroring = if flags_6 & FLAG_VERTICAL_MIRRORING != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let skip_trainer = flags_6 & FLAG_TRAINER != 0;
+        let prg_rom_start = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if prg_rom_size == 0 {
+            return Err("ROM has no PRG ROM".to_string());
+        }
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(format!(
+                "ROM is truncated: header says {} bytes of PRG ROM and {} bytes of CHR ROM \
+                 but the file only has {} bytes",
+                prg_rom_size,
+                chr_rom_size,
+                raw.len()
+            ));
+        }
+
+        let mut rom = Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec(),
+            mapper,
+            submapper,
+            screen_mirroring,
+            battery: flags_6 & FLAG_BATTERY != 0,
+            region,
+            expansion,
+            title: None,
+            disk: None,
+        };
+        // the database knows better than a four screen flag set by mistake
+        let four_screen = match db::lookup(rom.crc32()).filter(|_| use_database) {
+            Some(game) => {
+                game.apply(&mut rom);
+                game.mirroring.is_none()
+            }
+            None => flags_6 & FLAG_FOUR_SCREEN != 0,
+        };
+        if four_screen {
+            return Err("Four screen mirroring is not supported yet".to_string());
+        }
+        Ok(rom)
+    }
+
+    /// Puts a Famicom Disk System disk together with the BIOS it runs on
+    pub fn from_fds(disk: DiskImage, bios: &[u8]) -> Result<Rom, String> {
+        if bios.len() != FDS_BIOS_SIZE {
+            return Err(format!(
+                "The FDS BIOS has to be {FDS_BIOS_SIZE} bytes, not {}",
+                bios.len()
+            ));
+        }
+        Ok(Rom {
+            prg_rom: bios.to_vec(),
+            chr_rom: Vec::new(),
+            mapper: FDS_MAPPER,
+            submapper: 0,
+            // the RAM adapter picks it (see `mapper::fds`)
+            screen_mirroring: Mirroring::Horizontal,
+            battery: false,
+            // the Famicom was only ever sold in Japan
+            region: Some(Region::Ntsc),
+            expansion: None,
+            title: None,
+            disk: Some(disk),
+        })
+    }
+
+    /// Parses the contents of a UNIF file
+    pub fn from_unif(raw: &[u8]) -> Result<Rom, String> {
+        unif::parse(raw)
+    }
+
+    /// The bytes that identify the game: the disk for Famicom Disk System
+    /// games (which all share the BIOS), PRG ROM followed by CHR ROM for
+    /// the rest
+    fn identity(&self) -> Vec<u8> {
+        match &self.disk {
+            Some(disk) => disk.sides.concat(),
+            None => [self.prg_rom.as_slice(), self.chr_rom.as_slice()].concat(),
+        }
+    }
+
+    /// The MD5 of the PRG ROM followed by the CHR ROM (or of the disk's
+    /// sides), which is how FCEUX identifies a game (i.e. in movie files)
+    pub fn checksum(&self) -> [u8; 16] {
+        md5(&self.identity())
+    }
+
+    /// The CRC-32 of the same bytes, which is how the game database (see
+    /// the `db` module) identifies a game
+    pub fn crc32(&self) -> u32 {
+        crc32(&self.identity())
+    }
+
+    /// The SHA-1 of the same bytes, which is how BizHawk identifies a game
+    pub fn sha1(&self) -> [u8; 20] {
+        sha1(&self.identity())
+    }
+
+    /// Reads and parses an iNES file
+    pub fn load(path: &str) -> Result<Rom, String> {
+        let raw = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+        Rom::new(&raw)
+    }
+
+    /// Reads and parses a UNIF file
+    pub fn load_unif(path: &str) -> Result<Rom, String> {
+        let raw = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+        Rom::from_unif(&raw)
+    }
+
+    /// Reads a `.fds` disk image and the FDS BIOS it runs on
+    pub fn load_fds(path: &str, bios_path: &str) -> Result<Rom, String> {
+        let bios = std::fs::read(bios_path).map_err(|err| format!("{bios_path}: {err}"))?;
+        Rom::from_fds(DiskImage::load(path)?, &bios)
+    }
+}
+
+/// Reads a file to load, or the first ROM (`.nes`, `.fds`, `.nsf` and so on)
+/// in it if it's a `.zip` archive. Returns the file's name along with its
+/// contents, which for archives is the one it has in there (so the format
+/// can be told from it)
+pub fn read_file(path: &str) -> Result<(String, Vec<u8>), String> {
+    let raw = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+    if !path.to_ascii_lowercase().ends_with(".zip") {
+        return Ok((path.to_string(), raw));
+    }
+    let archive = ZipArchive::new(&raw).map_err(|err| format!("{path}: {err}"))?;
+    let entry = archive
+        .entries()
+        .iter()
+        .find(|entry| {
+            let name = entry.name.to_ascii_lowercase();
+            ROM_EXTENSIONS
+                .iter()
+                .any(|extension| name.ends_with(extension))
+        })
+        .ok_or_else(|| format!("{path}: there's no ROM in the archive"))?;
+    let contents = archive
+        .read_entry(entry)
+        .map_err(|err| format!("{path}: {err}"))?;
+    Ok((entry.name.clone(), contents))
+}
+
+/// Applies an IPS, UPS or BPS patch to `original` (told apart by how they
+/// start), returning the patched copy
+pub fn apply_patch(original: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"BPS1") {
+        bps::apply(original, patch)
+    } else if patch.starts_with(b"UPS1") {
+        ups::apply(original, patch)
+    } else if patch.starts_with(b"PATCH") {
+        ips::apply(original, patch)
+    } else {
+        Err("File is not an IPS, UPS or BPS patch".to_string())
+    }
+}
@@ -0,0 +1,239 @@
+//! Contains the parsing of UNIF files (`.unf`), the format some dumps of
+//! boards iNES had no mapper number for at the time only exist in. Instead
+//! of a number, a UNIF file names the board the game came on, and
+//! everything else comes in tagged chunks after a 32 byte header:
+//!
+//! | Bytes | Contents                                              |
+//! |-------|-------------------------------------------------------|
+//! | 4     | `UNIF`                                                |
+//! | 4     | revision (little endian)                              |
+//! | 24    | 0                                                     |
+//! | 4     | chunk: its ID                                         |
+//! | 4     | chunk: its length (little endian)                     |
+//! | n     | chunk: its data                                       |
+//!
+//! The chunks we read:
+//! * `MAPR`: the board's name, zero terminated (i.e. `NES-NROM-256`)
+//! * `PRG0` - `PRGF`, `CHR0` - `CHRF`: the ROM chips, in the order of the
+//!   hex digit at the end of the ID (no `CHR` chunks means CHR RAM)
+//! * `MIRR`: the mirroring (0 horizontal, 1 vertical, 2 and 3 single
+//!   screen, 4 four screen, 5 up to the mapper)
+//! * `BATR`: there's a battery (its data doesn't matter)
+//! * `TVCI`: the TV system (0 NTSC, 1 PAL, 2 either)
+//! * `CTRL`: the controllers the game takes, a bit for each
+//!
+//! The rest (the name, the dumper's notes, the checksums) are skipped. The
+//! board names get mapped onto the iNES mapper (and submapper) they're the
+//! same as, so the result is a `Rom` like any other.
+
+use super::Rom;
+use super::db::ExpansionDevice;
+use crate::ppu::Mirroring;
+use crate::region::Region;
+use phf::phf_map;
+
+const UNIF_TAG: &[u8] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+// CTRL bits
+const CTRL_STANDARD: u8 = 0b0000_0001;
+const CTRL_ZAPPER: u8 = 0b0000_0010;
+const CTRL_ARKANOID: u8 = 0b0000_1000;
+const CTRL_POWER_PAD: u8 = 0b0001_0000;
+const CTRL_FOUR_SCORE: u8 = 0b0010_0000;
+
+/// The prefixes board names come with, which say who made the board
+/// rather than which one it is
+const PREFIXES: [&str; 4] = ["NES-", "HVC-", "UNL-", "BTL-"];
+
+/// The iNES mapper and NES 2.0 submapper of every board we know the name
+/// of (without the prefix). The submapper is 0 unless the mapper number
+/// lumps boards together that are wired differently (like the VRCs, see
+/// `mapper::vrc`)
+static BOARDS: phf::Map<&'static str, (u8, u8)> = phf_map! {
+    // NROM
+    "NROM" => (0, 0),
+    "NROM-128" => (0, 0),
+    "NROM-256" => (0, 0),
+    "RROM" => (0, 0),
+    "RROM-128" => (0, 0),
+    // MMC5
+    "EKROM" => (5, 0),
+    "ELROM" => (5, 0),
+    "ETROM" => (5, 0),
+    "EWROM" => (5, 0),
+    "EXROM" => (5, 0),
+    // AxROM, where AN1ROM is the one without bus conflicts
+    "AMROM" => (7, 2),
+    "ANROM" => (7, 2),
+    "AN1ROM" => (7, 1),
+    "AOROM" => (7, 2),
+    "COLORDREAMS-74*377" => (11, 0),
+    "NAMCOT-163" => (19, 0),
+    // Konami's VRCs, by the chip's letter (which says how it's wired)
+    "KONAMI-VRC-2A" => (22, 0),
+    "KONAMI-VRC-2B" => (23, 3),
+    "KONAMI-VRC-2C" => (25, 3),
+    "KONAMI-VRC-4A" => (21, 1),
+    "KONAMI-VRC-4B" => (25, 1),
+    "KONAMI-VRC-4C" => (21, 2),
+    "KONAMI-VRC-4D" => (25, 2),
+    "KONAMI-VRC-4E" => (23, 2),
+    "KONAMI-VRC-4F" => (23, 1),
+    "KONAMI-VRC-6A" => (24, 0),
+    "KONAMI-VRC-6B" => (26, 0),
+    "KONAMI-VRC-7" => (85, 0),
+    "KONAMI-VRC-7A" => (85, 2),
+    "KONAMI-VRC-7B" => (85, 1),
+    "AVE-NINA-01" => (34, 0),
+    "BNROM" => (34, 0),
+    "GNROM" => (66, 0),
+    "MHROM" => (66, 0),
+    // Sunsoft FME-7 and the 5B
+    "JLROM" => (69, 0),
+    "JSROM" => (69, 0),
+    "SUNSOFT-FME-7" => (69, 0),
+    "SUNSOFT-5B" => (69, 0),
+    "CAMERICA-BF9093" => (71, 0),
+    "CAMERICA-BF9097" => (71, 1),
+    "AVE-NINA-03" => (79, 0),
+    "AVE-NINA-06" => (79, 0),
+    // the 74*139/74 boards
+    "JALECO-JF-05" => (87, 0),
+    "JALECO-JF-06" => (87, 0),
+    "JALECO-JF-07" => (87, 0),
+    "JALECO-JF-08" => (87, 0),
+    "JALECO-JF-09" => (87, 0),
+    "JALECO-JF-10" => (87, 0),
+    "KONAMI-74*139/74" => (87, 0),
+    "TAITO-74*139/74" => (87, 0),
+    "JALECO-JF-11" => (140, 0),
+    "JALECO-JF-14" => (140, 0),
+};
+
+/// The iNES mapper number and NES 2.0 submapper of a board, by its UNIF
+/// name
+pub fn board_mapper(board: &str) -> Option<(u8, u8)> {
+    let name = PREFIXES
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    BOARDS.get(name).copied()
+}
+
+/// Parses the contents of a UNIF file
+pub fn parse(raw: &[u8]) -> Result<Rom, String> {
+    if raw.len() < HEADER_SIZE || !raw.starts_with(UNIF_TAG) {
+        return Err("File is not in UNIF file format".to_string());
+    }
+
+    let mut board = None;
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = 0;
+    let mut battery = false;
+    let mut region = None;
+    let mut expansion = None;
+
+    let mut offset = HEADER_SIZE;
+    while offset < raw.len() {
+        if raw.len() < offset + CHUNK_HEADER_SIZE {
+            return Err(format!("UNIF file has a truncated chunk at {offset:#X}"));
+        }
+        let id = &raw[offset..offset + 4];
+        let len = u32::from_le_bytes(raw[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let start = offset + CHUNK_HEADER_SIZE;
+        let Some(data) = raw.get(start..start.saturating_add(len)) else {
+            return Err(format!(
+                "UNIF chunk {} is {len} bytes long, but the file ends before that",
+                String::from_utf8_lossy(id)
+            ));
+        };
+        offset = start + len;
+
+        match id {
+            b"MAPR" => {
+                let name = data.split(|&byte| byte == 0).next().unwrap_or_default();
+                board = Some(String::from_utf8_lossy(name).trim().to_string());
+            }
+            [b'P', b'R', b'G', digit] | [b'C', b'H', b'R', digit] => {
+                let Some(index) = (*digit as char).to_digit(16) else {
+                    continue;
+                };
+                let chunks = if id.starts_with(b"PRG") {
+                    &mut prg_chunks
+                } else {
+                    &mut chr_chunks
+                };
+                chunks[index as usize] = Some(data);
+            }
+            b"MIRR" => mirroring = data.first().copied().unwrap_or_default(),
+            b"BATR" => battery = true,
+            b"TVCI" => {
+                region = match data.first() {
+                    Some(0) => Some(Region::Ntsc),
+                    Some(1) => Some(Region::Pal),
+                    _ => None,
+                }
+            }
+            b"CTRL" => expansion = data.first().and_then(|&ctrl| controllers(ctrl)),
+            _ => {}
+        }
+    }
+
+    let board = board.ok_or("UNIF file doesn't name its board (no MAPR chunk)")?;
+    let (mapper, submapper) =
+        board_mapper(&board).ok_or(format!("UNIF board {board} is not supported yet"))?;
+    let screen_mirroring = match mirroring {
+        1 => Mirroring::Vertical,
+        2 => Mirroring::SingleScreenLower,
+        3 => Mirroring::SingleScreenUpper,
+        4 => return Err("Four screen mirroring is not supported yet".to_string()),
+        // the boards that pick it start out with whatever they like
+        _ => Mirroring::Horizontal,
+    };
+    let prg_rom: Vec<u8> = prg_chunks
+        .iter()
+        .flatten()
+        .flat_map(|chunk| chunk.iter())
+        .copied()
+        .collect();
+    let chr_rom: Vec<u8> = chr_chunks
+        .iter()
+        .flatten()
+        .flat_map(|chunk| chunk.iter())
+        .copied()
+        .collect();
+    if prg_rom.is_empty() {
+        return Err("ROM has no PRG ROM".to_string());
+    }
+
+    Ok(Rom {
+        prg_rom,
+        chr_rom,
+        mapper,
+        submapper,
+        screen_mirroring,
+        battery,
+        region,
+        expansion,
+        title: None,
+        disk: None,
+    })
+}
+
+/// The expansion device for the CTRL bits, the most particular one when
+/// more than one is set
+fn controllers(ctrl: u8) -> Option<ExpansionDevice> {
+    [
+        (CTRL_FOUR_SCORE, ExpansionDevice::FourScore),
+        (CTRL_ZAPPER, ExpansionDevice::Zapper),
+        (CTRL_ARKANOID, ExpansionDevice::ArkanoidVaus),
+        (CTRL_POWER_PAD, ExpansionDevice::PowerPad),
+        (CTRL_STANDARD, ExpansionDevice::StandardControllers),
+    ]
+    .into_iter()
+    .find(|(bit, _)| ctrl & bit != 0)
+    .map(|(_, device)| device)
+}
@@ -0,0 +1,73 @@
+//! Contains UPS patches, IPS's successor before BPS came along. A UPS patch
+//! is a list of runs of bytes to XOR into the original, each after a gap:
+//!
+//! | Bytes  | Contents                                                 |
+//! |--------|----------------------------------------------------------|
+//! | 4      | `UPS1`                                                   |
+//! | number | the size of the original                                 |
+//! | number | the size of the patched file                             |
+//! | number | hunk: how many bytes to leave alone before it            |
+//! | ...    | hunk: the bytes to XOR in, up to and including a 0       |
+//! | 4      | the CRC-32 of the original (little endian)               |
+//! | 4      | the CRC-32 of the patched file                           |
+//! | 4      | the CRC-32 of the patch, up to this point                |
+//!
+//! The numbers are the same as BPS's (see `bps::read_number`). Files that
+//! grow are padded out with zeros before XORing, and ones that shrink get
+//! cut down afterwards.
+
+use super::bps::{MAX_SIZE, check_footer, read_number};
+use crate::hash::crc32;
+
+const HEADER: &[u8] = b"UPS1";
+/// The 3 CRC-32s at the end
+const FOOTER_SIZE: usize = 12;
+
+/// Applies a patch to `original`, returning the patched copy
+pub fn apply(original: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if !patch.starts_with(HEADER) {
+        return Err("File is not a UPS patch".to_string());
+    }
+    if patch.len() < HEADER.len() + FOOTER_SIZE {
+        return Err("Patch is truncated".to_string());
+    }
+    let (target_crc, end) = check_footer(original, patch)?;
+
+    let mut pos = HEADER.len();
+    let source_size = read_number(patch, &mut pos)? as usize;
+    let target_size = read_number(patch, &mut pos)? as usize;
+    if source_size != original.len() {
+        return Err(format!(
+            "Patch is for a {source_size} byte file, not {} bytes",
+            original.len()
+        ));
+    }
+    if target_size > MAX_SIZE {
+        return Err(format!("Patch makes a {target_size} byte file"));
+    }
+
+    let mut patched = original.to_vec();
+    patched.resize(source_size.max(target_size), 0);
+    let mut offset: usize = 0;
+    while pos < end {
+        offset = offset.saturating_add(read_number(patch, &mut pos)? as usize);
+        loop {
+            let byte = *patch[..end].get(pos).ok_or("Patch is truncated")?;
+            pos += 1;
+            // the 0 at the end of a hunk counts as a byte left alone
+            offset = offset.saturating_add(1);
+            if byte == 0 {
+                break;
+            }
+            *patched
+                .get_mut(offset - 1)
+                .ok_or("Patch writes past the end of the patched file")? ^= byte;
+        }
+    }
+    patched.truncate(target_size);
+
+    if crc32(&patched) != target_crc {
+        return Err("Patch didn't apply right (CRC mismatch)".to_string());
+    }
+    Ok(patched)
+}
@@ -0,0 +1,182 @@
+//! Contains savestates, snapshots of the whole machine (CPU registers, RAM,
+//! PPU state, controllers, ...) that it can be put back into later. Movies
+//! that don't start from power on start from one.
+//!
+//! A savestate is a flat little-endian byte string:
+//! * `NESS` followed by a format version byte
+//! * the MD5 of the cartridge's ROM (zeros without a cartridge), so a state
+//!   can't be loaded into a different game
+//! * the CPU registers, then the bus and everything connected to it, in a
+//!   fixed order
+//!
+//! Debugging state (hooks, cheats, the code/data log) is not part of it.
+
+use crate::cpu::CPU;
+
+const MAGIC: &[u8; 4] = b"NESS";
+const VERSION: u8 = 4;
+
+/// Appends the parts of a savestate
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Appends bytes whose length the reader knows
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Appends bytes preceded by their length
+    pub fn vec(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.bytes(bytes);
+    }
+}
+
+/// Reads the parts of a savestate back in the order they were written
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("Savestate is truncated".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads an f32, which has to be finite (a savestate is untrusted)
+    pub fn f32(&mut self) -> Result<f32, String> {
+        let value = f32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        if value.is_finite() {
+            Ok(value)
+        } else {
+            Err("Savestate has an invalid number".to_string())
+        }
+    }
+
+    /// Fills `bytes` (which has to be as long as what was written)
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> Result<(), String> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    /// Reads bytes preceded by their length, which has to be `expected_len`
+    pub fn vec(&mut self, expected_len: usize) -> Result<Vec<u8>, String> {
+        let len = self.u64()?;
+        if len != expected_len as u64 {
+            return Err(format!(
+                "Savestate has {len} bytes where {expected_len} were expected"
+            ));
+        }
+        Ok(self.take(expected_len)?.to_vec())
+    }
+}
+
+impl CPU {
+    /// Takes a savestate of the whole machine
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        state.bytes(MAGIC);
+        state.u8(VERSION);
+        state.bytes(&self.bus.rom_checksum().unwrap_or_default());
+
+        state.u8(self.register_a);
+        state.u8(self.register_x);
+        state.u8(self.register_y);
+        state.u8(self.stack_pointer);
+        state.u8(self.status);
+        state.u16(self.program_counter);
+        self.bus.save_state(&mut state);
+        state.bytes
+    }
+
+    /// Puts the machine back into the state a savestate was taken in. If the
+    /// savestate is invalid (or is for a different game) the machine is left
+    /// untouched
+    pub fn load_state(&mut self, savestate: &[u8]) -> Result<(), String> {
+        let mut state = StateReader { bytes: savestate };
+        let mut magic = [0; 4];
+        state.bytes(&mut magic)?;
+        if &magic != MAGIC {
+            return Err("Not a savestate made by this emulator".to_string());
+        }
+        let version = state.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported savestate version {version}"));
+        }
+        let mut checksum = [0; 16];
+        state.bytes(&mut checksum)?;
+        if checksum != self.bus.rom_checksum().unwrap_or_default() {
+            return Err("Savestate is for a different ROM".to_string());
+        }
+
+        let backup = self.save_state();
+        let result = self.load_registers_and_bus(&mut state).and_then(|_| {
+            if state.bytes.is_empty() {
+                Ok(())
+            } else {
+                Err("Savestate has trailing data".to_string())
+            }
+        });
+        if result.is_err() {
+            let mut backup = StateReader {
+                bytes: &backup[MAGIC.len() + 1 + 16..],
+            };
+            self.load_registers_and_bus(&mut backup)
+                .expect("a savestate we just took loads");
+        }
+        result
+    }
+
+    fn load_registers_and_bus(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.register_a = state.u8()?;
+        self.register_x = state.u8()?;
+        self.register_y = state.u8()?;
+        self.stack_pointer = state.u8()?;
+        self.status = state.u8()?;
+        self.program_counter = state.u16()?;
+        self.bus.load_state(state)
+    }
+}
@@ -0,0 +1,82 @@
+//! Contains screenshots: the last frame the PPU drew, turned into RGB with a
+//! palette (color emphasis included) or the NTSC filter, and saved as a PNG
+//! image, so a bug report can show exactly what a frame looked like.
+//!
+//! ```no_run
+//! # use nes_emulator::cpu::CPU;
+//! # use nes_emulator::palette::Palette;
+//! # use nes_emulator::screenshot::Screenshot;
+//! # let cpu = CPU::new();
+//! Screenshot::capture(&cpu, &Palette::default()).save("frame.png")?;
+//! # Ok::<(), String>(())
+//! ```
+
+mod png;
+
+pub use png::encode_png;
+
+use std::path::Path;
+
+use crate::cpu::CPU;
+use crate::filter::NtscFilter;
+use crate::palette::Palette;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// A frame in RGB
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: usize,
+    pub height: usize,
+    /// 3 bytes per pixel, row by row from the top left
+    pub pixels: Vec<u8>,
+    /// the PPU frame the screenshot was taken at
+    pub frame: u64,
+}
+
+impl Screenshot {
+    /// Takes a screenshot of the last frame the machine drew
+    pub fn capture(cpu: &CPU, palette: &Palette) -> Self {
+        let ppu = cpu.bus.ppu();
+        let emphasis = ppu.frame_emphasis();
+        let pixels = ppu
+            .frame_buffer()
+            .iter()
+            .flat_map(|&index| palette.rgb(index, emphasis))
+            .collect();
+        Self {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels,
+            frame: ppu.frame(),
+        }
+    }
+
+    /// Takes a screenshot of the last frame the machine drew through the
+    /// NTSC filter (which makes it wider)
+    pub fn capture_ntsc(cpu: &CPU, filter: &NtscFilter) -> Self {
+        let ppu = cpu.bus.ppu();
+        let emphasis = (ppu.frame_emphasis() as u16) << 6;
+        let pixels = ppu
+            .frame_buffer()
+            .iter()
+            .map(|&index| index as u16 | emphasis)
+            .collect::<Vec<_>>();
+        Self {
+            width: filter.output_width(SCREEN_WIDTH),
+            height: SCREEN_HEIGHT,
+            pixels: filter.filter(&pixels, SCREEN_WIDTH, ppu.frame()),
+            frame: ppu.frame(),
+        }
+    }
+
+    /// Encodes the screenshot as a PNG image
+    pub fn to_png(&self) -> Vec<u8> {
+        encode_png(self.width, self.height, &self.pixels)
+    }
+
+    /// Writes the screenshot out as a PNG file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_png()).map_err(|err| format!("{}: {err}", path.display()))
+    }
+}
@@ -0,0 +1,67 @@
+//! Contains a minimal PNG encoder: 8-bit RGB images, no filtering, and the
+//! image data wrapped in a zlib stream of stored (uncompressed) DEFLATE
+//! blocks. The files come out big, but any PNG reader opens them and the
+//! encoder stays a page long.
+
+use crate::hash::{adler32, crc32};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/// The most a stored DEFLATE block can hold
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// Encodes `rgb` (3 bytes per pixel, row by row from the top left) as a PNG
+/// image
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "wrong amount of image data");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filtering, not interlaced
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // every row starts with its filter type (0 = none)
+    let mut scanlines = Vec::with_capacity(height * (width * 3 + 1));
+    for row in 0..height {
+        scanlines.push(0);
+        scanlines.extend_from_slice(&rgb[row * width * 3..(row + 1) * width * 3]);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Appends a chunk: its length, type, data, and the CRC of type and data
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream without compressing it
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32 KiB window, no preset dictionary, fastest level (the
+    // header has to be a multiple of 31)
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        // an empty stream still needs its final block
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        // BFINAL, then BTYPE 00 (stored), padded to the byte
+        stream.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
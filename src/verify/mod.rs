@@ -0,0 +1,296 @@
+//! Contains the determinism checker. Playing the same movie from the same
+//! starting point has to give exactly the same result every time, or movies
+//! (and anything else that replays input) desync. To check that, the state
+//! of the machine is captured at the end of every frame of the movie:
+//! * the CPU registers and the cycle count
+//! * a hash of the RAM (internal RAM followed by the cartridge's RAM)
+//! * a hash of the frame buffer
+//!
+//! and compared to the states of a second run, or to a hash log saved
+//! earlier, which is a text file with a line per frame:
+//!
+//! ```text
+//! # frame a x y sp p pc cycles ram frame_buffer
+//! 1 00 00 00 FD 24 C00A 29781 8a3f0c61d2b7e450 5f0d1c33e96aa4b2
+//! ```
+//!
+//! The first state that differs is reported, with the part of the machine
+//! it differs in (the CPU is compared first, then the RAM, then the frame
+//! buffer).
+
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::cpu::CPU;
+use crate::cpu::hooks::HookAction;
+use crate::hash::fnv1a;
+use crate::movie::{Movie, MoviePlayer};
+
+/// The state of the machine at the end of a frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameState {
+    /// the frame of the movie (the first frame is 1)
+    pub frame: u64,
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub stack_pointer: u8,
+    pub status: u8,
+    pub program_counter: u16,
+    pub cycles: usize,
+    pub ram_hash: u64,
+    pub frame_hash: u64,
+}
+
+impl FrameState {
+    /// Captures the state the machine is in
+    pub fn capture(cpu: &CPU, frame: u64) -> Self {
+        Self {
+            frame,
+            register_a: cpu.register_a,
+            register_x: cpu.register_x,
+            register_y: cpu.register_y,
+            stack_pointer: cpu.stack_pointer,
+            status: cpu.status,
+            program_counter: cpu.program_counter,
+            cycles: cpu.bus.cycles(),
+            ram_hash: fnv1a(&[cpu.bus.ram(), cpu.bus.prg_ram()].concat()),
+            frame_hash: fnv1a(cpu.bus.ppu().frame_buffer()),
+        }
+    }
+
+    fn cpu_state(&self) -> String {
+        format!(
+            "A=${:02X} X=${:02X} Y=${:02X} SP=${:02X} P=${:02X} PC=${:04X} cycles={}",
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.stack_pointer,
+            self.status,
+            self.program_counter,
+            self.cycles
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 10 {
+            return Err(format!("Invalid state {line}"));
+        }
+        let invalid = |_| format!("Invalid state {line}");
+        let byte = |field: &str| u8::from_str_radix(field, 16).map_err(invalid);
+        Ok(Self {
+            frame: fields[0].parse().map_err(invalid)?,
+            register_a: byte(fields[1])?,
+            register_x: byte(fields[2])?,
+            register_y: byte(fields[3])?,
+            stack_pointer: byte(fields[4])?,
+            status: byte(fields[5])?,
+            program_counter: u16::from_str_radix(fields[6], 16).map_err(invalid)?,
+            cycles: fields[7].parse().map_err(invalid)?,
+            ram_hash: u64::from_str_radix(fields[8], 16).map_err(invalid)?,
+            frame_hash: u64::from_str_radix(fields[9], 16).map_err(invalid)?,
+        })
+    }
+}
+
+impl fmt::Display for FrameState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:02X} {:02X} {:02X} {:02X} {:02X} {:04X} {} {:016x} {:016x}",
+            self.frame,
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.stack_pointer,
+            self.status,
+            self.program_counter,
+            self.cycles,
+            self.ram_hash,
+            self.frame_hash
+        )
+    }
+}
+
+/// The part of the machine a divergence was found in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Subsystem {
+    /// the CPU registers or cycle count
+    Cpu,
+    Ram,
+    FrameBuffer,
+    /// one run has fewer frames than the other
+    Length,
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Subsystem::Cpu => "CPU",
+            Subsystem::Ram => "RAM",
+            Subsystem::FrameBuffer => "frame buffer",
+            Subsystem::Length => "length",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The first difference between two runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub frame: u64,
+    pub subsystem: Subsystem,
+    /// what the first run (or the hash log) had
+    pub expected: String,
+    /// what the second run had
+    pub actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Frame {}: {} diverged (expected {}, got {})",
+            self.frame, self.subsystem, self.expected, self.actual
+        )
+    }
+}
+
+/// The states of every frame of a run
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HashLog {
+    pub frames: Vec<FrameState>,
+}
+
+impl HashLog {
+    /// Parses the contents of a hash log file
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut log = HashLog::default();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let state =
+                FrameState::parse(line).map_err(|err| format!("line {}: {err}", line_num + 1))?;
+            log.frames.push(state);
+        }
+        Ok(log)
+    }
+
+    /// Reads a hash log file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Self::parse(&text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// Formats the log as a hash log file
+    pub fn to_text(&self) -> String {
+        let mut text = "# frame a x y sp p pc cycles ram frame_buffer\n".to_string();
+        for state in &self.frames {
+            text += &format!("{state}\n");
+        }
+        text
+    }
+
+    /// Writes the log out as a hash log file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_text()).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    /// Finds the first frame `actual` differs from this log in (None when
+    /// both runs match)
+    pub fn compare(&self, actual: &HashLog) -> Option<Divergence> {
+        for (expected, actual) in self.frames.iter().zip(&actual.frames) {
+            if expected.cpu_state() != actual.cpu_state() {
+                return Some(Divergence {
+                    frame: expected.frame,
+                    subsystem: Subsystem::Cpu,
+                    expected: expected.cpu_state(),
+                    actual: actual.cpu_state(),
+                });
+            }
+            if expected.ram_hash != actual.ram_hash {
+                return Some(Divergence {
+                    frame: expected.frame,
+                    subsystem: Subsystem::Ram,
+                    expected: format!("hash {:016x}", expected.ram_hash),
+                    actual: format!("hash {:016x}", actual.ram_hash),
+                });
+            }
+            if expected.frame_hash != actual.frame_hash {
+                return Some(Divergence {
+                    frame: expected.frame,
+                    subsystem: Subsystem::FrameBuffer,
+                    expected: format!("hash {:016x}", expected.frame_hash),
+                    actual: format!("hash {:016x}", actual.frame_hash),
+                });
+            }
+        }
+        if self.frames.len() != actual.frames.len() {
+            let frame = self.frames.len().min(actual.frames.len()) as u64 + 1;
+            return Some(Divergence {
+                frame,
+                subsystem: Subsystem::Length,
+                expected: format!("{} frames", self.frames.len()),
+                actual: format!("{} frames", actual.frames.len()),
+            });
+        }
+        None
+    }
+}
+
+/// Plays a movie to its end, capturing the state of the machine at the end
+/// of every frame
+pub fn hash_movie(cpu: &mut CPU, movie: Movie) -> Result<HashLog, String> {
+    let frames = Arc::new(Mutex::new(HashLog::default()));
+    let hooked = Arc::clone(&frames);
+    // registered before the player's hook, so the state is captured before
+    // the next frame's input (and resets) get applied
+    let hook = cpu.add_frame_hook(move |cpu, _| {
+        let mut log = hooked.lock().unwrap();
+        let frame = log.frames.len() as u64 + 1;
+        log.frames.push(FrameState::capture(cpu, frame));
+        HookAction::Continue
+    });
+
+    let player = match MoviePlayer::start(cpu, movie) {
+        Ok(player) => player,
+        Err(err) => {
+            cpu.remove_hook(hook);
+            return Err(err);
+        }
+    };
+    if !player.is_finished() {
+        cpu.run();
+    }
+    let finished = player.is_finished();
+    let frame = player.frame();
+    player.stop(cpu);
+    cpu.remove_hook(hook);
+    if !finished {
+        return Err(format!(
+            "The movie stopped at frame {frame} before it ended"
+        ));
+    }
+
+    Ok(std::mem::take(&mut *frames.lock().unwrap()))
+}
+
+/// Plays a movie on two machines (which should have the same cartridge
+/// inserted) and compares them frame by frame. Returns the first divergence,
+/// or None when both runs match
+pub fn check_determinism(
+    first: &mut CPU,
+    second: &mut CPU,
+    movie: &Movie,
+) -> Result<Option<Divergence>, String> {
+    let expected = hash_movie(first, movie.clone())?;
+    let actual = hash_movie(second, movie.clone())?;
+    Ok(expected.compare(&actual))
+}
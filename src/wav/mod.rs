@@ -0,0 +1,76 @@
+//! Contains a writer for WAV files, so audio the emulator puts out (i.e. a
+//! song rendered by the NSF player) can be listened to elsewhere.
+//!
+//! ```no_run
+//! # use nes_emulator::apu::SAMPLE_RATE;
+//! # use nes_emulator::nsf::Nsf;
+//! # use nes_emulator::nsf::player::NsfPlayer;
+//! let mut player = NsfPlayer::new(Nsf::load("music.nsf")?);
+//! let samples = player.render(60_000, 5_000);
+//! nes_emulator::wav::save("song.wav", &samples, SAMPLE_RATE as u32)?;
+//! # Ok::<(), String>(())
+//! ```
+//!
+//! The samples are written as 16 bit mono PCM. The APU's output sits
+//! between 0.0 and 1.0 (with silence at 0.0), so it goes through a high
+//! pass filter first, like the one on the NES's audio output, which centers
+//! it around 0 the way a WAV file expects.
+
+use std::path::Path;
+
+/// The cutoff of the high pass filter (in Hz)
+const HIGH_PASS: f32 = 90.0;
+const CHANNELS: u16 = 1;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Turns samples into the contents of a WAV file
+pub fn encode(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let data_len = (samples.len() * block_align as usize) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in high_pass(samples, sample_rate) {
+        let sample = (sample * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32);
+        wav.extend_from_slice(&(sample as i16).to_le_bytes());
+    }
+    wav
+}
+
+/// Writes samples to a WAV file
+pub fn save<P: AsRef<Path>>(path: P, samples: &[f32], sample_rate: u32) -> Result<(), String> {
+    let path = path.as_ref();
+    std::fs::write(path, encode(samples, sample_rate))
+        .map_err(|err| format!("{}: {err}", path.display()))
+}
+
+/// A first order high pass filter, starting from the first sample so it
+/// doesn't pop
+fn high_pass(samples: &[f32], sample_rate: u32) -> Vec<f32> {
+    let rc = 1.0 / (2.0 * std::f32::consts::PI * HIGH_PASS);
+    let alpha = rc / (rc + 1.0 / sample_rate as f32);
+    let mut previous_input = samples.first().copied().unwrap_or(0.0);
+    let mut previous_output = 0.0;
+    samples
+        .iter()
+        .map(|&sample| {
+            previous_output = alpha * (previous_output + sample - previous_input);
+            previous_input = sample;
+            previous_output
+        })
+        .collect()
+}
@@ -0,0 +1,266 @@
+//! Contains a DEFLATE (RFC 1951) decompressor, which is how ZIP archives
+//! compress their files.
+//!
+//! A DEFLATE stream is a sequence of blocks, each either stored as is or
+//! compressed with Huffman codes (the fixed ones from the RFC or ones listed
+//! at the start of the block). Compressed blocks are made of literal bytes
+//! and (length, distance) pairs that repeat earlier output.
+
+/// Base lengths of length symbols 257..=285, and how many extra bits follow
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances of distance symbols 0..=29, and how many extra bits follow
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order the code lengths of the code length code are listed in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const MAX_CODE_LENGTH: usize = 15;
+
+/// Reads a stream least significant bit first, which is how DEFLATE packs
+/// everything but Huffman codes
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or("Compressed data is truncated")?;
+            self.pos += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary and takes `len` bytes
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("Compressed data is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code, kept as the number of codes of every length and
+/// the symbols sorted by code
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the code length of every symbol (0 = unused).
+    /// Incomplete codes are allowed, since a distance code may only have
+    /// one symbol
+    fn new(lengths: &[u8]) -> Result<Self, String> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err("Invalid Huffman code".to_string());
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 1];
+        for len in 1..MAX_CODE_LENGTH {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    /// Reads one symbol, a bit at a time (Huffman codes are packed most
+    /// significant bit first)
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code in compressed data".to_string())
+    }
+}
+
+/// Decompresses a raw DEFLATE stream (without a zlib or gzip wrapper)
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bit_buffer: 0,
+        bit_count: 0,
+    };
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                let header = reader.bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let complement = u16::from_le_bytes([header[2], header[3]]);
+                if len != !complement {
+                    return Err("Corrupt stored block in compressed data".to_string());
+                }
+                output.extend_from_slice(reader.bytes(len as usize)?);
+            }
+            1 => {
+                let (literals, distances) = fixed_codes()?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err("Invalid block type in compressed data".to_string()),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// The codes compressed blocks of type 1 use
+fn fixed_codes() -> Result<(Huffman, Huffman), String> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+/// Reads the codes listed at the start of a compressed block of type 2
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("Invalid code lengths in compressed data".to_string());
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths)?;
+
+    // the literal/length and distance code lengths are one run-length
+    // encoded list
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_lengths.decode(reader)?;
+        let (len, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or("Invalid code lengths in compressed data")?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        if lengths.len() + repeat as usize > literal_count + distance_count {
+            return Err("Invalid code lengths in compressed data".to_string());
+        }
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err("Compressed block has no end code".to_string());
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+/// Decompresses the literals and (length, distance) pairs of a block up to
+/// its end code
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASES.len() {
+                    return Err("Invalid length in compressed data".to_string());
+                }
+                let len = LENGTH_BASES[index] as usize
+                    + reader.bits(LENGTH_EXTRA_BITS[index] as u32)? as usize;
+
+                let index = distances.decode(reader)? as usize;
+                if index >= DISTANCE_BASES.len() {
+                    return Err("Invalid distance in compressed data".to_string());
+                }
+                let distance = DISTANCE_BASES[index] as usize
+                    + reader.bits(DISTANCE_EXTRA_BITS[index] as u32)? as usize;
+                if distance > output.len() {
+                    return Err("Compressed data refers back past its start".to_string());
+                }
+
+                // the copy may overlap what it's producing, so go byte by byte
+                let start = output.len() - distance;
+                for i in 0..len {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Contains a reader for ZIP archives, which BizHawk movies (and plenty of
+//! ROM collections) come in.
+//!
+//! The archive ends with a central directory that lists every file: its
+//! name, how it's compressed (stored or DEFLATE), its CRC-32 and where its
+//! local header is. The file's data follows the local header. Encrypted
+//! archives and ZIP64 (files over 4 GiB) aren't supported.
+
+mod inflate;
+
+pub use inflate::inflate;
+
+use crate::hash::crc32;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const END_OF_DIRECTORY_LEN: usize = 22;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// A file in a ZIP archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// the path of the file in the archive (`/` separated)
+    pub name: String,
+    /// the size of the file once decompressed
+    pub size: usize,
+    method: u16,
+    crc32: u32,
+    compressed_size: usize,
+    header_offset: usize,
+}
+
+/// A ZIP archive in memory, whose files get decompressed when read
+pub struct ZipArchive<'a> {
+    data: &'a [u8],
+    entries: Vec<ZipEntry>,
+}
+
+impl<'a> ZipArchive<'a> {
+    /// Reads the archive's central directory
+    pub fn new(data: &'a [u8]) -> Result<Self, String> {
+        // the end of central directory record is followed by a comment of up
+        // to 64 KiB, so look for it from the end
+        let end = (0..=data.len().saturating_sub(END_OF_DIRECTORY_LEN))
+            .rev()
+            .take(0x10000)
+            .find(|&pos| read_u32(data, pos) == Some(END_OF_DIRECTORY_SIGNATURE))
+            .ok_or("Not a ZIP archive")?;
+        let count = read_u16(data, end + 10).ok_or("Not a ZIP archive")?;
+        let mut pos = read_u32(data, end + 16).ok_or("Not a ZIP archive")? as usize;
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let truncated = || "ZIP directory is truncated".to_string();
+            if read_u32(data, pos) != Some(CENTRAL_HEADER_SIGNATURE) {
+                return Err("Corrupt ZIP directory".to_string());
+            }
+            let flags = read_u16(data, pos + 8).ok_or_else(truncated)?;
+            let name_len = read_u16(data, pos + 28).ok_or_else(truncated)? as usize;
+            let extra_len = read_u16(data, pos + 30).ok_or_else(truncated)? as usize;
+            let comment_len = read_u16(data, pos + 32).ok_or_else(truncated)? as usize;
+            let name = data
+                .get(pos + 46..pos + 46 + name_len)
+                .ok_or_else(truncated)?;
+            let entry = ZipEntry {
+                name: String::from_utf8_lossy(name).to_string(),
+                method: read_u16(data, pos + 10).ok_or_else(truncated)?,
+                crc32: read_u32(data, pos + 16).ok_or_else(truncated)?,
+                compressed_size: read_u32(data, pos + 20).ok_or_else(truncated)? as usize,
+                size: read_u32(data, pos + 24).ok_or_else(truncated)? as usize,
+                header_offset: read_u32(data, pos + 42).ok_or_else(truncated)? as usize,
+            };
+            if flags & 1 != 0 {
+                return Err(format!("{} is encrypted", entry.name));
+            }
+            if entry.size == u32::MAX as usize || entry.compressed_size == u32::MAX as usize {
+                return Err(format!("{} is a ZIP64 entry", entry.name));
+            }
+            entries.push(entry);
+            pos += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(Self { data, entries })
+    }
+
+    /// The files in the archive, in the order of its directory
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Finds a file by its path in the archive
+    pub fn entry(&self, name: &str) -> Option<&ZipEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Decompresses a file, checking it against its CRC-32
+    pub fn read_entry(&self, entry: &ZipEntry) -> Result<Vec<u8>, String> {
+        let pos = entry.header_offset;
+        let truncated = || format!("{} is truncated", entry.name);
+        if read_u32(self.data, pos) != Some(LOCAL_HEADER_SIGNATURE) {
+            return Err(format!("Corrupt ZIP header for {}", entry.name));
+        }
+        let name_len = read_u16(self.data, pos + 26).ok_or_else(truncated)? as usize;
+        let extra_len = read_u16(self.data, pos + 28).ok_or_else(truncated)? as usize;
+        let start = pos + 30 + name_len + extra_len;
+        let compressed = self
+            .data
+            .get(start..start + entry.compressed_size)
+            .ok_or_else(truncated)?;
+
+        let contents = match entry.method {
+            METHOD_STORED => compressed.to_vec(),
+            METHOD_DEFLATE => {
+                inflate(compressed).map_err(|err| format!("{}: {err}", entry.name))?
+            }
+            method => {
+                return Err(format!(
+                    "{} uses unsupported compression method {method}",
+                    entry.name
+                ));
+            }
+        };
+        if contents.len() != entry.size || crc32(&contents) != entry.crc32 {
+            return Err(format!("{} is corrupt (CRC mismatch)", entry.name));
+        }
+        Ok(contents)
+    }
+
+    /// Decompresses the file with this path
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, String> {
+        let entry = self
+            .entry(name)
+            .ok_or_else(|| format!("{name} is not in the archive"))?;
+        self.read_entry(entry)
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
@@ -0,0 +1,167 @@
+//! APU (channels, frame counter, IRQs and audio output) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{ines, load_cartridge};
+    use std::sync::{Arc, Mutex};
+
+    use nes_emulator::Mem;
+    use nes_emulator::apu::{CPU_CLOCK, SAMPLE_RATE};
+    use nes_emulator::asm;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::cpu::hooks::{HookAction, Interrupt};
+
+    /// An NROM cartridge whose PRG ROM is filled with `fill`
+    fn cartridge(fill: u8) -> Bus {
+        load_cartridge(&ines(0, 0, &[fill; 0x8000], &[0; 0x2000]))
+    }
+
+    #[test]
+    fn test_length_counters_and_status() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x4017, 0x40);
+        // loading a length counter does nothing while the channel is off
+        bus.mem_write(0x4003, 0x08);
+        assert_eq!(bus.mem_read(0x4015), 0);
+
+        bus.mem_write(0x4015, 0b0000_1111);
+        bus.mem_write(0x4000, 0b0011_0000);
+        // a length of 254 (index 1) on pulse 1, 2 (index 3) on the noise
+        bus.mem_write(0x4003, 0b0000_1000);
+        bus.mem_write(0x400F, 0b0001_1000);
+        bus.mem_write(0x400B, 0b0001_1000);
+        assert_eq!(bus.mem_read(0x4015), 0b0000_1101);
+        // two half frames later only the halted pulse is still playing
+        bus.tick(30_000);
+        assert_eq!(bus.mem_read(0x4015), 0b0000_0001);
+        // and disabling a channel silences it right away
+        bus.mem_write(0x4015, 0);
+        assert_eq!(bus.mem_read(0x4015), 0);
+        // the channels are write only
+        assert_eq!(bus.mem_read(0x4000), 0);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut bus = Bus::new();
+        assert!(!bus.irq_pending());
+        bus.tick(29_830);
+        assert!(bus.irq_pending());
+        assert_eq!(bus.mem_peek(0x4015) & 0x40, 0x40);
+        // reading the status acknowledges it
+        assert_eq!(bus.mem_read(0x4015) & 0x40, 0x40);
+        assert!(!bus.irq_pending());
+
+        // no IRQ when inhibited, or in 5 step mode
+        bus.mem_write(0x4017, 0x40);
+        bus.tick(40_000);
+        assert!(!bus.irq_pending());
+        bus.mem_write(0x4017, 0x80);
+        bus.tick(40_000);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_cpu_services_irqs() {
+        // a cartridge of zeros, so the IRQ vector reads as 0x0000
+        let mut cpu = CPU::with_bus(cartridge(0));
+        cpu.load_at(0x0000, &asm!("INC $10", "LDA $4015", "RTI"));
+        cpu.load_at(0x0600, &asm!(".org $0600", "CLI", "loop: JMP loop"));
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        let interrupts = Arc::new(Mutex::new(Vec::new()));
+        let seen = interrupts.clone();
+        cpu.add_interrupt_hook(move |_, interrupt| {
+            seen.lock().unwrap().push(interrupt);
+            HookAction::Continue
+        });
+        cpu.add_frame_hook(|_, frame| {
+            if frame == 4 {
+                HookAction::Stop
+            } else {
+                HookAction::Continue
+            }
+        });
+        cpu.run();
+        // an IRQ for every 4 step sequence (29830 cycles) in 4 frames
+        assert_eq!(cpu.mem_peek(0x10), 3);
+        assert!(interrupts.lock().unwrap().contains(&Interrupt::Irq));
+    }
+
+    #[test]
+    fn test_irqs_are_masked() {
+        let mut cpu = CPU::new();
+        cpu.load_at(0x0000, &asm!("INC $10", "LDA $4015", "RTI"));
+        cpu.load_at(0x0600, &asm!(".org $0600", "SEI", "loop: JMP loop"));
+        cpu.reset();
+        cpu.add_frame_hook(|_, frame| {
+            if frame == 2 {
+                HookAction::Stop
+            } else {
+                HookAction::Continue
+            }
+        });
+        cpu.run();
+        assert_eq!(cpu.mem_peek(0x10), 0);
+        assert!(cpu.bus.irq_pending());
+    }
+
+    #[test]
+    fn test_recording_samples() {
+        let mut bus = Bus::new();
+        bus.tick(10_000);
+        // nothing is kept unless recording
+        assert!(bus.take_audio().is_empty());
+
+        bus.record_audio(true);
+        bus.tick(CPU_CLOCK as usize / 10);
+        let samples = bus.take_audio();
+        let expected = SAMPLE_RATE as usize / 10;
+        assert!(samples.len().abs_diff(expected) <= 1);
+        // silence is flat (the triangle holds its step, so it isn't 0)
+        assert!(samples.iter().all(|&sample| sample == samples[0]));
+
+        // the triangle at a high pitch
+        bus.mem_write(0x4015, 0b0000_0100);
+        bus.mem_write(0x4008, 0b1111_1111);
+        bus.mem_write(0x400A, 0x40);
+        bus.mem_write(0x400B, 0x08);
+        bus.tick(CPU_CLOCK as usize / 10);
+        let samples = bus.take_audio();
+        let loudest = samples.iter().cloned().fold(0.0, f32::max);
+        let quietest = samples.iter().cloned().fold(1.0, f32::min);
+        assert!(loudest > 0.1 && loudest <= 1.0);
+        assert!(quietest < loudest);
+        assert!(bus.take_audio().is_empty());
+
+        bus.record_audio(false);
+        bus.tick(10_000);
+        assert!(bus.take_audio().is_empty());
+    }
+
+    #[test]
+    fn test_dmc_plays_from_the_cartridge() {
+        let mut bus = cartridge(0xFF);
+        bus.mem_write(0x4017, 0x40);
+        bus.record_audio(true);
+        // the fastest rate with the IRQ on, a 17 byte sample at 0xC000
+        bus.mem_write(0x4010, 0b1000_1111);
+        bus.mem_write(0x4011, 0);
+        bus.mem_write(0x4012, 0);
+        bus.mem_write(0x4013, 1);
+        bus.mem_write(0x4015, 0b0001_0000);
+        assert_eq!(bus.mem_read(0x4015), 0b0001_0000);
+        bus.tick(17 * 8 * 54);
+        // every bit of 0xFF moves the level up
+        assert!(bus.apu().output() > 0.2);
+        assert_eq!(bus.mem_read(0x4015), 0b1000_0000);
+        assert!(bus.irq_pending());
+        // writing $4015 acknowledges the DMC's IRQ
+        bus.mem_write(0x4015, 0);
+        assert!(!bus.irq_pending());
+    }
+}
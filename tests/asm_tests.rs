@@ -0,0 +1,184 @@
+//! Assembler tests reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::Mem;
+    use nes_emulator::asm;
+    use nes_emulator::asm::assemble;
+    use nes_emulator::cpu::{CPU, processor_status::ProcessorStatus};
+    use nes_emulator::disasm::disassemble;
+
+    #[test]
+    fn test_asm_all_addressing_modes() {
+        let program = asm!(
+            "    LDA #$10",
+            "    LDA $10",
+            "    LDA $10,X",
+            "    LDX $10,Y",
+            "    LDA $1234",
+            "    LDA $1234,X",
+            "    LDA $1234,Y",
+            "    LDA ($10,X)",
+            "    LDA ($10),Y",
+            "    JMP ($FFFC)",
+            "    ASL A",
+            "    LSR",
+            "    INX",
+        );
+
+        assert_eq!(
+            program,
+            vec![
+                0xa9, 0x10, 0xa5, 0x10, 0xb5, 0x10, 0xb6, 0x10, 0xad, 0x34, 0x12, 0xbd, 0x34, 0x12,
+                0xb9, 0x34, 0x12, 0xa1, 0x10, 0xb1, 0x10, 0x6c, 0xfc, 0xff, 0x0a, 0x4a, 0xe8,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_asm_labels_and_branches() {
+        let program = asm!(
+            "start:  JSR init",
+            "        BEQ done      ; forward branch",
+            "        BNE start     ; backward branch",
+            "init:   RTS",
+            "done:   BRK",
+        );
+
+        assert_eq!(
+            program,
+            vec![0x20, 0x07, 0x00, 0xf0, 0x03, 0xd0, 0xf9, 0x60, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_asm_forward_reference_uses_absolute_mode() {
+        // `var` isn't known yet when the LDA is sized, so it can't
+        // be squeezed into the zero page
+        let program = asm!("LDA var", "BRK", "var: .byte 7");
+        assert_eq!(program, vec![0xad, 0x04, 0x00, 0x00, 0x07]);
+
+        // while a constant defined up front can be
+        let program = asm!("var = $10", "LDA var");
+        assert_eq!(program, vec![0xa5, 0x10]);
+    }
+
+    #[test]
+    fn test_asm_expressions() {
+        let program = asm!(
+            "SCREEN = $0200",
+            "        .org $0600",
+            "        LDA #<SCREEN + 1",
+            "        LDA #>SCREEN",
+            "        LDA #(2 + 3) * 4",
+            "        LDA #%1010",
+            "        LDA #'A'",
+            "        LDA #-1",
+            "        STA SCREEN + 32 * 2,X",
+            "here:   JMP *",
+        );
+
+        assert_eq!(
+            program,
+            vec![
+                0xa9, 0x01, 0xa9, 0x02, 0xa9, 0x14, 0xa9, 0x0a, 0xa9, 0x41, 0xa9, 0xff, 0x9d, 0x40,
+                0x02, 0x4c, 0x0f, 0x06,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_asm_directives() {
+        let program = assemble(
+            ".org $8000\n\
+             reset: .word reset, $1234\n\
+             .byte 1, $02, \"Hi\"\n\
+             .org $8010\n\
+             .db $ff\n",
+        )
+        .unwrap();
+
+        assert_eq!(program.origin, 0x8000);
+        assert_eq!(program.bytes.len(), 0x11);
+        assert_eq!(
+            &program.bytes[..8],
+            &[0x00, 0x80, 0x34, 0x12, 0x01, 0x02, b'H', b'i']
+        );
+        assert_eq!(program.bytes[0x10], 0xff);
+        assert_eq!(program.symbols.addr_of("reset"), Some(0x8000));
+    }
+
+    #[test]
+    fn test_asm_errors_report_the_line() {
+        let err = assemble("NOP\nLDA #$100").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{err}");
+
+        assert!(assemble("JMP nowhere").is_err());
+        assert!(assemble("LDA ($1234),Y").is_err());
+        assert!(assemble("STX $10,X").is_err());
+        assert!(assemble("FOO").is_err());
+        assert!(assemble("x: NOP\nx: NOP").is_err());
+        assert!(assemble(".org $10\nNOP\n.org $08\nNOP").is_err());
+
+        // expressions that overflow an i64 are errors, not panics
+        for expr in [
+            "$7FFFFFFFFFFFFFFF*2",
+            "$7FFFFFFFFFFFFFFF+1",
+            "-$7FFFFFFFFFFFFFFF-2",
+            "-(-$7FFFFFFFFFFFFFFF-1)",
+            "(-$7FFFFFFFFFFFFFFF-1)/-1",
+        ] {
+            let err = assemble(&format!("NOP\nLDA #{expr}")).unwrap_err();
+            assert_eq!(err, "line 2: Expression overflow", "{expr}");
+        }
+
+        let mut far_branch = String::from("BNE far\n");
+        far_branch.push_str(&".byte 0\n".repeat(200));
+        far_branch.push_str("far: BRK");
+        assert!(assemble(&far_branch).is_err());
+    }
+
+    #[test]
+    fn test_asm_round_trips_through_disassembler() {
+        let program = assemble(
+            ".org $0600\n\
+             LDX #$08\n\
+             loop: DEX\n\
+             STA $0200,Y\n\
+             BNE loop\n\
+             BRK\n",
+        )
+        .unwrap();
+
+        let texts = disassemble(&program.bytes, program.origin, None)
+            .into_iter()
+            .map(|line| line.text)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec!["LDX #$08", "DEX", "STA $0200,Y", "BNE $0602", "BRK"]
+        );
+    }
+
+    #[test]
+    fn test_asm_program_runs_on_cpu() {
+        let mut cpu = CPU::new();
+        cpu.load(&asm!(
+            "        LDX #$00",
+            "        LDA #$00",
+            "        CLC",
+            "loop:   ADC #$03",
+            "        INX",
+            "        CPX #$05",
+            "        BNE loop",
+            "        STA $20",
+            "        BRK",
+        ));
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(cpu.register_a, 15);
+        assert_eq!(cpu.mem_read(0x20), 15);
+        assert!(cpu.is_status_flag_set(ProcessorStatus::Zero));
+    }
+}
@@ -0,0 +1,292 @@
+//! ZIP archive, DEFLATE and BizHawk movie (BK2) import tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{controller_program, ines, zip};
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::cpu::hooks::HookAction;
+    use nes_emulator::gamepad::{BUTTON_A, BUTTON_B, DOWN, LEFT, RIGHT, SELECT, START, UP};
+    use nes_emulator::hash::{crc32, sha1};
+    use nes_emulator::movie::{
+        COMMAND_HARD_RESET, COMMAND_SOFT_RESET, FrameInput, Movie, MoviePlayer,
+    };
+    use nes_emulator::rom::Rom;
+    use nes_emulator::zip::{ZipArchive, inflate};
+
+    /// "hello hello hello hello!" compressed with the fixed Huffman codes
+    const FIXED_BLOCK: &str = "cb48cdc9c957c840271501";
+
+    /// `input_log()` compressed with its own Huffman codes
+    const DYNAMIC_BLOCK: &str = concat!(
+        "5593c16ac3301044eff98a42ee537aed2d4297521d82844fa18752d4b450e210bb84823ebeda5dcb",
+        "1a1b23ad90ccf3ec8c4e2f97ebeffcb60be3f935ff3def639ef25c8ee33ddfcafef8f4305c4b1dfd",
+        "78bfc81cf2e72c73fc3e7f6991e6f79b15f9277f68e56438945d01ea6b4f5b056042dbf340826b7b",
+        "3e204d6ed91b80584fa2ad424c68df0d1eb19e0413dca11352e70981782162e519a1f32aa1f3eabf",
+        "60e529807842705d43247d4258794248c413c2caf3da09d29088a79d00131c69e8fa9430515f40fa",
+        "94e04843229e7402ac817842a0be907f4a205e60ff94403e907f42207d3e907f4670a481f46926c8",
+        "07f2cf08ec03f967a1600d9d67a9eb1ad83f4b1d69e07c226ef2894d3eb1c927f96704f281fc83a5",
+        "aef7059b7c62934f703e41fe2981efc3e2dfe971b9daff",
+    );
+
+    /// The controller buttons in the order of BizHawk's `UDLRSsBA` columns
+    const BIZHAWK_BUTTONS: [u8; 8] = [UP, DOWN, LEFT, RIGHT, START, SELECT, BUTTON_B, BUTTON_A];
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// What's held on controller 1 during a frame, as BizHawk columns
+    fn pattern(frame: u64) -> u8 {
+        (frame * 37 % 256) as u8
+    }
+
+    /// The same, as gamepad bits
+    fn pattern_buttons(frame: u64) -> u8 {
+        (0..8)
+            .filter(|i| pattern(frame) & (0x80 >> i) != 0)
+            .fold(0, |buttons, i| buttons | BIZHAWK_BUTTONS[i])
+    }
+
+    /// A 64 frame input log for controller 1 only
+    fn input_log() -> String {
+        let mut log = "[Input]\nLogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start\
+                       |P1 Select|P1 B|P1 A|\n"
+            .to_string();
+        for frame in 0..64 {
+            let buttons = b"UDLRSsBA"
+                .iter()
+                .enumerate()
+                .map(|(i, &letter)| {
+                    if pattern(frame) & (0x80 >> i) != 0 {
+                        letter as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            log += &format!("|..|{buttons}|\n");
+        }
+        log + "[/Input]\n"
+    }
+
+    fn header(rom: &Rom) -> String {
+        let sha1 = sha1(&[rom.prg_rom.as_slice(), rom.chr_rom.as_slice()].concat());
+        let sha1 = sha1
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<String>();
+        format!(
+            "MovieVersion BizHawk v2.0.0\n\
+             Author Someone\n\
+             Platform NES\n\
+             GameName Controller Test\n\
+             SHA1 {sha1}\n\
+             Core NesHawk\n\
+             rerecordCount 42\n"
+        )
+    }
+
+    #[test]
+    fn test_checksums() {
+        let hex = |digest: [u8; 20]| {
+            digest
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+        };
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(sha1(b"The quick brown fox jumps over the lazy dog")),
+            "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12"
+        );
+        assert_eq!(
+            hex(sha1(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "50abf5706a150990a08b2c5ea40fa0e585554732"
+        );
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_inflate() {
+        assert_eq!(
+            inflate(&unhex(FIXED_BLOCK)).unwrap(),
+            b"hello hello hello hello!"
+        );
+        assert_eq!(
+            inflate(&unhex(DYNAMIC_BLOCK)).unwrap(),
+            input_log().as_bytes()
+        );
+
+        // a stored block that isn't the last, followed by a fixed block
+        let mut stored = vec![0x00, 0x05, 0x00, 0xfa, 0xff];
+        stored.extend(b"well ");
+        stored.extend(unhex(FIXED_BLOCK));
+        assert_eq!(inflate(&stored).unwrap(), b"well hello hello hello hello!");
+
+        let dynamic = unhex(DYNAMIC_BLOCK);
+        assert!(inflate(&dynamic[..dynamic.len() - 10]).is_err());
+        assert!(inflate(&[0x07]).is_err());
+        assert!(inflate(&[0x01, 0x05, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_zip_archive() {
+        let log = input_log();
+        let archive = zip(&[
+            ("Header.txt", b"Platform NES\n", None),
+            ("Input Log.txt", log.as_bytes(), Some(&unhex(DYNAMIC_BLOCK))),
+        ]);
+        let zip_archive = ZipArchive::new(&archive).unwrap();
+        let names = zip_archive
+            .entries()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Header.txt", "Input Log.txt"]);
+        assert_eq!(zip_archive.entries()[1].size, log.len());
+        assert_eq!(zip_archive.read("Header.txt").unwrap(), b"Platform NES\n");
+        assert_eq!(zip_archive.read("Input Log.txt").unwrap(), log.as_bytes());
+        assert!(zip_archive.read("Missing.txt").is_err());
+
+        // flipping a byte of the contents trips the CRC
+        let mut corrupt = archive.clone();
+        corrupt[30 + "Header.txt".len()] ^= 0x20;
+        assert_eq!(
+            ZipArchive::new(&corrupt)
+                .unwrap()
+                .read("Header.txt")
+                .unwrap_err(),
+            "Header.txt is corrupt (CRC mismatch)"
+        );
+
+        assert!(ZipArchive::new(b"not a zip").is_err());
+        assert!(ZipArchive::new(&archive[..archive.len() - 30]).is_err());
+    }
+
+    #[test]
+    fn test_bk2_import() {
+        let rom = Rom::new(&ines(0, 0, &controller_program(), &[0; 0x2000])).unwrap();
+        let log = "[Input]\n\
+                   LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|\
+                   #P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|\n\
+                   |..|...S...A|........|\n\
+                   |r.|U.......|.......A|\n\
+                   |.P|...RSsBA|..L.....|\n\
+                   [/Input]\n";
+        let archive = zip(&[
+            ("Header.txt", header(&rom).as_bytes(), None),
+            ("Input Log.txt", log.as_bytes(), None),
+            ("Comments.txt", b"first try\n", None),
+        ]);
+        let movie = Movie::from_bk2(&archive, &rom).unwrap();
+        assert_eq!(movie.rom_filename, "Controller Test");
+        assert_eq!(movie.rom_checksum, rom.checksum());
+        assert_eq!(movie.rerecord_count, 42);
+        assert_eq!(movie.comments, ["author Someone", "first try"]);
+        assert_eq!(movie.gamepads, [true, true]);
+        assert!(movie.savestate.is_none());
+        assert_eq!(
+            movie.frames,
+            [
+                FrameInput {
+                    commands: 0,
+                    gamepads: [RIGHT | BUTTON_A, 0]
+                },
+                FrameInput {
+                    commands: COMMAND_SOFT_RESET,
+                    gamepads: [UP, BUTTON_A]
+                },
+                FrameInput {
+                    commands: COMMAND_HARD_RESET,
+                    gamepads: [RIGHT | SELECT | START | BUTTON_B | BUTTON_A, LEFT]
+                },
+            ]
+        );
+
+        // a different ROM is caught before playing anything
+        let mut other = rom.clone();
+        other.prg_rom[0] ^= 0xff;
+        let err = Movie::from_bk2(&archive, &other).unwrap_err();
+        assert!(err.starts_with("Movie desync"), "{err}");
+
+        let import = |header: &str, log: &str| {
+            let archive = zip(&[
+                ("Header.txt", header.as_bytes(), None),
+                ("Input Log.txt", log.as_bytes(), None),
+            ]);
+            Movie::from_bk2(&archive, &rom)
+        };
+        assert_eq!(
+            import("Platform SNES\n", log).unwrap_err(),
+            "Not an NES movie (platform SNES)"
+        );
+        assert!(import("Platform NES\nStartsFromSavestate True\n", log).is_err());
+        assert_eq!(
+            import(
+                "Platform NES\n",
+                "[Input]\nLogKey:#Reset|Power|#P1 Up|P3 Up|\n|..|..|\n[/Input]\n"
+            )
+            .unwrap_err(),
+            "Input Log.txt line 2: Unsupported input P3 Up"
+        );
+        assert_eq!(
+            import(
+                "Platform NES\n",
+                "[Input]\n|..|.......|........|\n[/Input]\n"
+            )
+            .unwrap_err(),
+            "Input Log.txt line 2: Invalid input ......."
+        );
+    }
+
+    #[test]
+    fn test_bk2_playback() {
+        let rom = Rom::new(&ines(0, 0, &controller_program(), &[0; 0x2000])).unwrap();
+        let log = input_log();
+        let archive = zip(&[
+            ("Header.txt", header(&rom).as_bytes(), None),
+            ("Input Log.txt", log.as_bytes(), Some(&unhex(DYNAMIC_BLOCK))),
+        ]);
+        let movie = Movie::from_bk2(&archive, &rom).unwrap();
+        assert_eq!(movie.frames.len(), 64);
+        assert_eq!(movie.gamepads, [true, false]);
+
+        let mut replay = CPU::with_bus(Bus::with_rom(rom.clone()).unwrap());
+        replay.reset();
+        let player = MoviePlayer::start(&mut replay, movie).unwrap();
+        replay.run();
+        assert!(player.is_finished());
+
+        // the same input, pressed by hand from power on
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom).unwrap());
+        cpu.power_cycle();
+        cpu.bus.gamepad_mut(0).set_buttons(pattern_buttons(0));
+        let mut frame = 0;
+        cpu.add_frame_hook(move |cpu, _| {
+            frame += 1;
+            if frame == 64 {
+                return HookAction::Stop;
+            }
+            cpu.bus.gamepad_mut(0).set_buttons(pattern_buttons(frame));
+            HookAction::Continue
+        });
+        cpu.run();
+
+        assert_eq!(replay.bus.ram(), cpu.bus.ram());
+        assert_eq!(replay.bus.cycles(), cpu.bus.cycles());
+        assert_eq!(replay.bus.ram()[0x03], 1);
+    }
+}
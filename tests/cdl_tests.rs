@@ -0,0 +1,208 @@
+//! Cartridge loading and code/data logger tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::ines;
+    use nes_emulator::Mem;
+    use nes_emulator::asm;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cdl::{
+        CHR_READ, CHR_RENDERED, CodeDataLog, PRG_CODE, PRG_DATA, PRG_INDIRECT_CODE,
+        PRG_INDIRECT_DATA,
+    };
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::cpu::hooks::HookAction;
+    use nes_emulator::disasm::disassemble_logged;
+    use nes_emulator::ppu::Mirroring;
+    use nes_emulator::rom::Rom;
+
+    /// Builds an iNES file with 16 KiB of PRG ROM (`program` is assembled for
+    /// 0xC000 and the reset vector points there) and 8 KiB of CHR ROM
+    fn program_image(program: &[u8], flags_6: u8) -> Vec<u8> {
+        let mut prg = program.to_vec();
+        prg.resize(0x4000, 0);
+        prg[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0xc0]);
+        let chr = (0..0x2000).map(|i| i as u8).collect::<Vec<_>>();
+        ines(0, flags_6, &prg, &chr)
+    }
+
+    fn logging_program() -> Vec<u8> {
+        asm!(
+            "        .org $C000",
+            "reset:  LDA table", // 0xC000
+            "        LDA #<ptr_data",
+            "        STA $00",
+            "        LDA #>ptr_data",
+            "        STA $01",
+            "        LDY #$00",
+            "        LDA ($00),Y", // 0xC00D
+            "        JMP (vector)",
+            "target: LDA #$00", // 0xC012
+            "        STA $2006",
+            "        LDA #$10",
+            "        STA $2006",
+            "        LDA $2007", // fills the read buffer from 0x0010
+            "        LDA $2007",
+            "        LDA #$05", // put tile 5 in the top left corner
+            "        LDX #$20",
+            "        STX $2006",
+            "        LDX #$00",
+            "        STX $2006",
+            "        STA $2007",
+            "        LDA #$08", // show the background
+            "        STA $2001",
+            "loop:   JMP loop",
+            "table:  .db 1, 2, 3",
+            "ptr_data: .db 4",
+            "vector: .dw target",
+        )
+    }
+
+    /// Runs the cartridge with the code/data logger on for a couple of frames
+    fn run_logged(program: &[u8]) -> (Rom, CodeDataLog) {
+        let rom = Rom::new(&program_image(program, 0)).unwrap();
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom.clone()).unwrap());
+        cpu.reset();
+        cpu.bus.start_code_data_log();
+        cpu.add_frame_hook(|_, frame| {
+            if frame == 2 {
+                HookAction::Stop
+            } else {
+                HookAction::Continue
+            }
+        });
+        cpu.run();
+        (rom, cpu.bus.take_code_data_log().unwrap())
+    }
+
+    #[test]
+    fn test_rom_parsing() {
+        let rom = Rom::new(&program_image(&[0xea], 0b0000_0011)).unwrap();
+        assert_eq!(rom.prg_rom.len(), 0x4000);
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+
+        let mut with_mapper = program_image(&[0xea], 0b0001_0000);
+        with_mapper[7] = 0b0010_0000;
+        assert_eq!(Rom::new(&with_mapper).unwrap().mapper, 0x21);
+
+        assert!(Rom::new(b"NES").is_err());
+        assert!(Rom::new(&program_image(&[0xea], 0)[..0x100]).is_err());
+        assert!(Bus::with_rom(Rom::new(&with_mapper).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_cartridge_memory_map() {
+        let rom = Rom::new(&program_image(
+            &asm!(".org $C000", "LDA #$42", "STA $6000", "BRK"),
+            0,
+        ))
+        .unwrap();
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom).unwrap());
+        cpu.reset();
+        // the reset vector is read from the cartridge
+        assert_eq!(cpu.program_counter, 0xc000);
+        cpu.run();
+        // PRG RAM is writable, the 16 KiB PRG ROM is mirrored and read only
+        assert_eq!(cpu.mem_peek(0x6000), 0x42);
+        assert_eq!(cpu.mem_peek(0x8000), 0xa9);
+        cpu.mem_write(0x8000, 0);
+        assert_eq!(cpu.mem_peek(0xc000), 0xa9);
+    }
+
+    #[test]
+    fn test_cdl_prg_flags() {
+        let (_, log) = run_logged(&logging_program());
+        // 0xC000 is in the third 8 KiB window
+        let bank = 0b10 << 2;
+
+        assert_eq!(log.prg()[0x0000], PRG_CODE | bank);
+        assert!(log.is_opcode(0x0000));
+        assert!(log.is_operand(0x0001) && log.is_operand(0x0002));
+
+        let table = 0x0039;
+        assert_eq!(log.prg()[table], PRG_DATA | bank);
+        // only the first byte of the table was read
+        assert_eq!(log.prg()[table + 1], 0);
+        assert_eq!(log.prg()[table + 3], PRG_DATA | PRG_INDIRECT_DATA | bank);
+        // JMP ($nnnn) reads its pointer as data and lands on indirect code
+        assert_eq!(log.prg()[table + 4], PRG_DATA | bank);
+        assert_eq!(log.prg()[0x0012], PRG_CODE | PRG_INDIRECT_CODE | bank);
+
+        let (code, data, unused) = log.prg_coverage();
+        assert_eq!(code, 0x39);
+        assert_eq!(data, 4);
+        assert_eq!(code + data + unused, 0x4000);
+    }
+
+    #[test]
+    fn test_cdl_chr_flags() {
+        let (_, log) = run_logged(&logging_program());
+        assert_eq!(log.chr()[0x0010], CHR_READ);
+        assert_eq!(log.chr()[0x0011], CHR_READ);
+        assert_eq!(log.chr()[0x0012], 0);
+
+        // tile 5 and tile 0 (the rest of the name table) are on screen
+        assert!((0x50..0x60).all(|offset| log.is_rendered(offset)));
+        assert!((0x00..0x10).all(|offset| log.chr()[offset] & CHR_RENDERED != 0));
+        assert!(!log.is_rendered(0x60));
+        assert!(!log.is_rendered(0x1050));
+    }
+
+    #[test]
+    fn test_cdl_file_round_trip() {
+        let (rom, log) = run_logged(&logging_program());
+        let bytes = log.to_bytes();
+        assert_eq!(bytes.len(), 0x4000 + 0x2000);
+
+        let loaded = CodeDataLog::from_bytes(&bytes, rom.prg_rom.len()).unwrap();
+        assert_eq!(loaded.prg(), log.prg());
+        assert_eq!(loaded.chr(), log.chr());
+        // the file format doesn't know about opcodes and operands
+        assert!(!loaded.is_opcode(0) && !loaded.is_operand(1));
+
+        assert!(CodeDataLog::from_bytes(&bytes[..0x100], rom.prg_rom.len()).is_err());
+
+        // CHR RAM isn't part of the file
+        let raw = ines(0, 0, &[0; 0x4000], &[]);
+        let mut bus = Bus::with_rom(Rom::new(&raw).unwrap()).unwrap();
+        bus.start_code_data_log();
+        let log = bus.take_code_data_log().unwrap();
+        assert_eq!(log.to_bytes().len(), 0x4000);
+    }
+
+    #[test]
+    fn test_disassemble_with_cdl() {
+        let (rom, log) = run_logged(&logging_program());
+        let loaded = CodeDataLog::from_bytes(&log.to_bytes(), rom.prg_rom.len()).unwrap();
+
+        for log in [log, loaded] {
+            let lines = disassemble_logged(&rom.prg_rom[..0x50], 0xc000, &log, None);
+            let text = lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(text[0], "C000  AD 39 C0  LDA $C039");
+            assert!(
+                text.contains(&"C00F  6C 3D C0  JMP ($C03D)".to_string()),
+                "{text:#?}"
+            );
+            assert!(
+                text.contains(&"C036  4C 36 C0  JMP $C036".to_string()),
+                "{text:#?}"
+            );
+            // the table, pointer data and vector aren't decoded as instructions
+            assert!(
+                text.contains(
+                    &"C039  01 02 03 04 12 C0 00 00  .db $01,$02,$03,$04,$12,$C0,$00,$00"
+                        .to_string()
+                ),
+                "{text:#?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Cheat engine (Game Genie codes and RAM freezes) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{ines, load_cartridge};
+    use nes_emulator::Mem;
+    use nes_emulator::asm;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cheats::{Cheat, CheatKind, parse_cht, to_cht};
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::debugger::Debugger;
+
+    /// Builds a cartridge with 16 KiB of PRG ROM (mirrored at 0x8000 and
+    /// 0xC000) holding `prg` and 8 KiB of CHR ROM
+    fn cartridge(prg: &[u8]) -> Bus {
+        load_cartridge(&ines(0, 0, prg, &[0; 0x2000]))
+    }
+
+    #[test]
+    fn test_game_genie_decoding() {
+        let cheat = Cheat::game_genie("SXIOPO").unwrap();
+        assert_eq!(cheat.kind, CheatKind::GameGenie);
+        assert_eq!(cheat.addr, 0x91d9);
+        assert_eq!(cheat.value, 0xad);
+        assert_eq!(cheat.compare, None);
+
+        let cheat = Cheat::game_genie("gossipve").unwrap();
+        assert_eq!(cheat.name, "GOSSIPVE");
+        assert_eq!(cheat.addr, 0xd1dd);
+        assert_eq!(cheat.value, 0x1c);
+        assert_eq!(cheat.compare, Some(0x86));
+
+        assert!(Cheat::game_genie("SXIOP").is_err());
+        assert!(Cheat::game_genie("SXIOPB").is_err());
+    }
+
+    #[test]
+    fn test_cheat_parsing() {
+        assert_eq!(Cheat::parse("0x0075:09").unwrap(), Cheat::freeze(0x75, 9));
+        assert_eq!(Cheat::parse("$0075:$09").unwrap(), Cheat::freeze(0x75, 9));
+        assert_eq!(Cheat::parse("007509").unwrap(), Cheat::freeze(0x75, 9));
+        assert_eq!(Cheat::parse("SXIOPO").unwrap().addr, 0x91d9);
+        assert!(Cheat::parse("0075:100").is_err());
+        assert_eq!(
+            Cheat::freeze(0x75, 9).to_string(),
+            "[on] 0075:09 (freeze: $0075 = $09)"
+        );
+    }
+
+    #[test]
+    fn test_game_genie_patches_rom() {
+        let mut prg = vec![0; 0x4000];
+        prg[0x11d9] = 0x11;
+        prg[0x11dd] = 0x86;
+        let mut bus = cartridge(&prg);
+
+        bus.add_cheat(Cheat::game_genie("SXIOPO").unwrap());
+        let compared = bus.add_cheat(Cheat::game_genie("GOSSIPVE").unwrap());
+        assert_eq!(bus.mem_read(0x91d9), 0xad);
+        assert_eq!(bus.mem_peek(0x91d9), 0xad);
+        // the code is for the lower copy of the ROM only
+        assert_eq!(bus.mem_read(0xd1d9), 0x11);
+        assert_eq!(bus.mem_read(0xd1dd), 0x1c);
+
+        // codes with a compare value leave other values alone
+        bus.remove_cheat(compared);
+        bus.add_cheat(Cheat {
+            compare: Some(0x42),
+            ..Cheat::game_genie("GOSSIPVE").unwrap()
+        });
+        assert_eq!(bus.mem_read(0xd1dd), 0x86);
+
+        // Game Genie codes don't touch RAM
+        bus.add_cheat(Cheat {
+            addr: 0x0010,
+            ..Cheat::game_genie("SXIOPO").unwrap()
+        });
+        assert_eq!(bus.mem_read(0x0010), 0);
+    }
+
+    #[test]
+    fn test_ram_freeze() {
+        let mut cpu = CPU::new();
+        cpu.load_at(
+            0x0600,
+            &asm!(
+                ".org $0600",
+                "LDA #$03",
+                "STA $75",
+                "LDX $75",
+                "LDY $0875", // a mirror of $0075
+                "BRK",
+            ),
+        );
+        cpu.reset();
+        let index = cpu.bus.add_cheat(Cheat::parse("0075:09").unwrap());
+        cpu.run();
+        assert_eq!(cpu.register_x, 9);
+        assert_eq!(cpu.register_y, 9);
+
+        // the game's own value comes back once the cheat is off
+        assert!(cpu.bus.set_cheat_enabled(index, false));
+        assert_eq!(cpu.mem_peek(0x75), 3);
+        assert!(!cpu.bus.set_cheat_enabled(5, false));
+
+        cpu.bus.set_cheat_enabled(index, true);
+        assert_eq!(cpu.mem_peek(0x75), 9);
+        cpu.bus.clear_cheats();
+        assert!(cpu.bus.cheats().is_empty());
+        assert_eq!(cpu.mem_peek(0x75), 3);
+    }
+
+    #[test]
+    fn test_cht_round_trip() {
+        let text = "0075:09:Infinite lives\n\
+                    :0076:01:Disabled\n\
+                    S91d9:ad:SXIOPO\n\
+                    SCd1dd:1c:86:GOSSIPVE\n";
+        let cheats = parse_cht(text).unwrap();
+        assert_eq!(cheats.len(), 4);
+        assert_eq!(cheats[0].name, "Infinite lives");
+        assert_eq!(cheats[0].kind, CheatKind::Freeze);
+        assert!(!cheats[1].enabled);
+        assert_eq!(cheats[2], Cheat::game_genie("SXIOPO").unwrap());
+        assert_eq!(cheats[3], Cheat::game_genie("GOSSIPVE").unwrap());
+
+        assert_eq!(to_cht(&cheats), text);
+        assert_eq!(parse_cht("\n\n").unwrap(), vec![]);
+        assert_eq!(
+            parse_cht("0075:09:ok\n0075:zz:bad").unwrap_err(),
+            "line 2: Invalid value"
+        );
+        assert!(parse_cht("0075").is_err());
+    }
+
+    #[test]
+    fn test_debugger_cheat_commands() {
+        let mut debugger = Debugger::new(CPU::new());
+        assert_eq!(
+            debugger.execute("cheat 0010:42").unwrap(),
+            "0: [on] 0010:42 (freeze: $0010 = $42)"
+        );
+        assert_eq!(debugger.cpu.mem_peek(0x10), 0x42);
+        debugger.execute("cheat SXIOPO");
+        assert_eq!(
+            debugger.execute("cheats").unwrap(),
+            "0: [on] 0010:42 (freeze: $0010 = $42)\n\
+             1: [on] SXIOPO (Game Genie: $91D9 = $AD)"
+        );
+
+        assert_eq!(
+            debugger.execute("cheat off 0").unwrap(),
+            "Cheat 0 turned off"
+        );
+        assert_eq!(debugger.cpu.mem_peek(0x10), 0);
+        assert_eq!(debugger.execute("cheat del 1").unwrap(), "Deleted SXIOPO");
+        assert_eq!(debugger.execute("cheat on 1").unwrap(), "Error: No cheat 1");
+        assert!(debugger.execute("cheat QQQ").unwrap().starts_with("Error"));
+
+        let path = std::env::temp_dir().join("nes_emulator_cheats_test.cht");
+        let path = path.to_str().unwrap();
+        debugger.execute(&format!("cheat save {path}"));
+        debugger.cpu.bus.clear_cheats();
+        assert_eq!(
+            debugger.execute(&format!("cheat load {path}")).unwrap(),
+            "Loaded 1 cheats"
+        );
+        let disabled = Cheat {
+            enabled: false,
+            ..Cheat::freeze(0x10, 0x42)
+        };
+        assert_eq!(debugger.cpu.bus.cheats(), [disabled]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
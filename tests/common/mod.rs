@@ -0,0 +1,266 @@
+//! Helpers shared by the tests reside here (every test file is its own
+//! crate, so each one only uses some of them)
+#![allow(dead_code)]
+
+use nes_emulator::asm;
+use nes_emulator::bus::Bus;
+use nes_emulator::cpu::CPU;
+use nes_emulator::cpu::hooks::HookAction;
+use nes_emulator::hash::crc32;
+use nes_emulator::rom::Rom;
+
+/// Builds an iNES image for `mapper` with `flags_6` (mirroring, battery,
+/// ...). PRG ROM is padded out to 16 KiB pages and CHR ROM to 8 KiB ones
+/// (no CHR ROM at all means CHR RAM)
+pub fn ines(mapper: u8, flags_6: u8, prg: &[u8], chr: &[u8]) -> Vec<u8> {
+    let prg_pages = prg.len().div_ceil(0x4000);
+    let chr_pages = chr.len().div_ceil(0x2000);
+    let mut raw = vec![
+        0x4e,
+        0x45,
+        0x53,
+        0x1a,
+        prg_pages as u8,
+        chr_pages as u8,
+        flags_6 | mapper << 4,
+        mapper & 0xF0,
+    ];
+    raw.resize(16, 0);
+    raw.extend(prg);
+    raw.resize(16 + prg_pages * 0x4000, 0);
+    raw.extend(chr);
+    raw.resize(16 + prg_pages * 0x4000 + chr_pages * 0x2000, 0);
+    raw
+}
+
+/// `pages` 16 KiB pages of PRG ROM, every 8 KiB of which is filled with its
+/// bank number
+pub fn numbered_prg(pages: usize) -> Vec<u8> {
+    (0..pages * 0x4000)
+        .map(|offset| (offset / 0x2000) as u8)
+        .collect()
+}
+
+/// `pages` 8 KiB pages of CHR ROM, every KiB of which is filled with its
+/// number
+pub fn numbered_chr(pages: usize) -> Vec<u8> {
+    (0..pages * 0x2000)
+        .map(|offset| (offset / 0x400) as u8)
+        .collect()
+}
+
+/// Plugs an iNES image into a bus
+pub fn load_cartridge(raw: &[u8]) -> Bus {
+    Bus::with_rom(Rom::new(raw).unwrap()).unwrap()
+}
+
+/// Plugs an iNES image into a machine and resets it
+pub fn boot(raw: &[u8]) -> CPU {
+    let mut cpu = CPU::with_bus(load_cartridge(raw));
+    cpu.reset();
+    cpu
+}
+
+/// A machine running 16 KiB of NROM PRG ROM (vectors and all) with blank
+/// CHR ROM
+pub fn machine(prg: &[u8]) -> CPU {
+    boot(&ines(0, 0, prg, &[0; 0x2000]))
+}
+
+/// Runs the machine until `frames` more frames have been drawn
+pub fn run_frames(cpu: &mut CPU, frames: u64) {
+    let mut left = frames;
+    let hook = cpu.add_frame_hook(move |_, _| {
+        left -= 1;
+        if left == 0 {
+            HookAction::Stop
+        } else {
+            HookAction::Continue
+        }
+    });
+    cpu.run();
+    cpu.remove_hook(hook);
+}
+
+/// A game that reads controller 1 every NMI, keeping a running sum of what
+/// was pressed at 0x0001, a history of it at 0x0300 and counting resets at
+/// 0x0003
+pub fn controller_program() -> Vec<u8> {
+    asm!(
+        "        .org $C000",
+        "reset:  SEI",
+        "        LDX #$FF",
+        "        TXS",
+        "        INC $03",
+        "        LDA #$80",
+        "        STA $2000", // NMI on
+        "loop:   JMP loop",
+        "nmi:    LDA #$01",
+        "        STA $4016",
+        "        LDA #$00",
+        "        STA $4016",
+        "        LDX #$08",
+        "read:   LDA $4016",
+        "        LSR A",
+        "        ROL $00",
+        "        DEX",
+        "        BNE read",
+        "        LDA $00",
+        "        CLC",
+        "        ADC $01",
+        "        STA $01",
+        "        INC $02",
+        "        LDX $02",
+        "        LDA $00",
+        "        STA $0300,X",
+        "        RTI",
+        "        .org $FFFA",
+        "        .dw nmi, reset, reset",
+    )
+}
+
+/// An NROM game that draws tile 1 (color 1 all over, in $21) at the top
+/// left of the screen, puts sprite 0 (tile 2, color 3 all over, in $16) at
+/// (16, 16) and sprite 1 (tile 2, behind the background) at (0, 0) on a
+/// $0F backdrop, and turns on red emphasis from frame 3 on
+pub fn drawing_rom() -> Vec<u8> {
+    let prg = asm!(
+        "        .org $C000",
+        "reset:  LDA #$3F",
+        "        STA $2006",
+        "        LDA #$00",
+        "        STA $2006",
+        "        LDA #$0F", // backdrop
+        "        STA $2007",
+        "        LDA #$21", // background palette 0, color 1
+        "        STA $2007",
+        "        LDA #$3F",
+        "        STA $2006",
+        "        LDA #$13",
+        "        STA $2006",
+        "        LDA #$16", // sprite palette 0, color 3
+        "        STA $2007",
+        "        LDA #$20",
+        "        STA $2006",
+        "        LDA #$00",
+        "        STA $2006",
+        "        LDA #$01",
+        "        STA $2007",
+        "        LDA #$00",
+        "        STA $2003",
+        "        LDA #$0F", // sprite 0: Y (shows up a line lower), tile, attributes, X
+        "        STA $2004",
+        "        LDA #$02",
+        "        STA $2004",
+        "        LDA #$00",
+        "        STA $2004",
+        "        LDA #$10",
+        "        STA $2004",
+        "        LDA #$00", // sprite 1
+        "        STA $2004",
+        "        LDA #$02",
+        "        STA $2004",
+        "        LDA #$20",
+        "        STA $2004",
+        "        LDA #$00",
+        "        STA $2004",
+        "        LDA #$00",
+        "        STA $2005",
+        "        STA $2005",
+        "        LDA #$1E",
+        "        STA $2001",
+        "        LDA #$80",
+        "        STA $2000",
+        "loop:   JMP loop",
+        "nmi:    INC $00",
+        "        LDA $00",
+        "        CMP #$02",
+        "        BNE done",
+        "        LDA #$3E", // red emphasis
+        "        STA $2001",
+        "done:   RTI",
+        "        .org $FFFA",
+        "        .dw nmi, reset, reset",
+    );
+    let mut chr = vec![0; 0x30];
+    chr[0x10..0x18].fill(0xff);
+    chr[0x20..0x30].fill(0xff);
+    ines(0, 0, &prg, &chr)
+}
+
+/// A program at 0x0600 that calls a subroutine (which calls another one)
+/// twice, counting up X, and stores X at 0x10
+pub fn subroutine_program() -> Vec<u8> {
+    asm!(
+        "        .org $0600",
+        "        LDX #$00",
+        "        JSR inc_x", // 0x0602
+        "        JSR inc_x", // 0x0605
+        "        STX $10",   // 0x0608
+        "        BRK",
+        "inc_x:  INX", // 0x060B
+        "        JSR nested",
+        "        RTS", // 0x060F
+        "nested: INX", // 0x0610
+        "        RTS",
+    )
+}
+
+/// A file to put in a test archive: (name, contents, deflated contents)
+pub type ZipFile<'a> = (&'a str, &'a [u8], Option<&'a [u8]>);
+
+/// Builds a ZIP archive, storing the files that aren't deflated
+pub fn zip(files: &[ZipFile]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for &(name, contents, deflated) in files {
+        let (method, data) = match deflated {
+            Some(deflated) => (8u16, deflated),
+            None => (0u16, contents),
+        };
+        let mut header = Vec::new();
+        header.extend(20u16.to_le_bytes()); // version needed
+        header.extend(0u16.to_le_bytes()); // flags
+        header.extend(method.to_le_bytes());
+        header.extend(0u32.to_le_bytes()); // time and date
+        header.extend(crc32(contents).to_le_bytes());
+        header.extend((data.len() as u32).to_le_bytes());
+        header.extend((contents.len() as u32).to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes()); // extra field length
+
+        directory.extend(0x02014b50u32.to_le_bytes());
+        directory.extend(20u16.to_le_bytes()); // version made by
+        directory.extend(&header);
+        directory.extend([0; 10]); // comment length, disk, attributes
+        directory.extend((archive.len() as u32).to_le_bytes());
+        directory.extend(name.as_bytes());
+
+        archive.extend(0x04034b50u32.to_le_bytes());
+        archive.extend(&header);
+        archive.extend(name.as_bytes());
+        archive.extend(data);
+    }
+    let directory_offset = archive.len() as u32;
+    archive.extend(&directory);
+    archive.extend(0x06054b50u32.to_le_bytes());
+    archive.extend([0; 4]); // disk numbers
+    archive.extend((files.len() as u16).to_le_bytes());
+    archive.extend((files.len() as u16).to_le_bytes());
+    archive.extend((directory.len() as u32).to_le_bytes());
+    archive.extend(directory_offset.to_le_bytes());
+    archive.extend(0u16.to_le_bytes()); // comment length
+    archive
+}
+
+/// Records `cycles` CPU cycles of audio and returns the loudest and the
+/// quietest sample (leaving out the first, which is partly from before)
+pub fn audio_range(bus: &mut Bus, cycles: usize) -> (f32, f32) {
+    bus.record_audio(true);
+    bus.take_audio();
+    bus.tick(cycles);
+    let samples = &bus.take_audio()[1..];
+    let loudest = samples.iter().cloned().fold(f32::MIN, f32::max);
+    let quietest = samples.iter().cloned().fold(f32::MAX, f32::min);
+    (loudest, quietest)
+}
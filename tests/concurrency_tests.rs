@@ -0,0 +1,76 @@
+//! Tests that machines keep all of their state to themselves reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::Mem;
+    use nes_emulator::asm::assemble;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_machines_are_send() {
+        assert_send::<CPU>();
+        assert_send::<Bus>();
+    }
+
+    #[test]
+    fn test_machines_on_many_threads() {
+        // every thread loads a program at its own address that sums up the
+        // numbers 1 to n into 0x0010
+        let handles = (0..8u16)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let n = 10 + i as u8;
+                    let origin = 0x0200 + i * 0x80;
+                    let program = assemble(&format!(
+                        "        .org ${origin:04X}\n\
+                                 LDA #$00\n\
+                                 LDX #${n:02X}\n\
+                         loop:   STX $11\n\
+                                 CLC\n\
+                                 ADC $11\n\
+                                 DEX\n\
+                                 BNE loop\n\
+                                 STA $10\n\
+                                 BRK"
+                    ))
+                    .unwrap()
+                    .bytes;
+                    let mut cpu = CPU::new();
+                    for _ in 0..50 {
+                        cpu.load_at(origin, &program);
+                        cpu.reset();
+                        assert_eq!(cpu.program_counter, origin);
+                        cpu.mem_write(0x10, 0);
+                        cpu.run();
+                        assert_eq!(cpu.mem_read(0x10) as u32, (1..=n as u32).sum::<u32>() % 256);
+                        std::thread::yield_now();
+                    }
+                    (origin, cpu)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            let (origin, mut cpu) = handle.join().unwrap();
+            // the machine comes back from its thread still knowing where its
+            // program is
+            cpu.reset();
+            assert_eq!(cpu.program_counter, origin);
+        }
+    }
+
+    #[test]
+    fn test_loading_leaves_other_machines_alone() {
+        let mut first = CPU::new();
+        let mut second = CPU::new();
+        first.load_at(0x0300, &[0xea, 0x00]);
+        second.test_load(&[0xea, 0x00]);
+        first.reset();
+        second.reset();
+        assert_eq!(first.program_counter, 0x0300);
+        assert_eq!(second.program_counter, 0x0600);
+    }
+}
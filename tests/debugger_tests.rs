@@ -0,0 +1,259 @@
+//! Debugger (monitor) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{ines, load_cartridge, subroutine_program};
+    use nes_emulator::Mem;
+    use nes_emulator::asm;
+    use nes_emulator::bus::Access;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::debugger::{Debugger, StopReason, WatchKind};
+
+    /// Loads a program at 0x0600 and wraps the CPU in a debugger
+    fn debugger_with(program: &[u8]) -> Debugger {
+        let mut cpu = CPU::new();
+        cpu.load_at(0x0600, program);
+        cpu.reset();
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn test_debugger_breakpoint_and_continue() {
+        let mut debugger = debugger_with(&subroutine_program());
+        debugger.add_breakpoint(0x060b);
+
+        assert_eq!(debugger.resume(), StopReason::Breakpoint(0x060b));
+        assert_eq!(debugger.cpu.register_x, 0);
+        // resuming from a breakpoint doesn't immediately stop on it again
+        assert_eq!(debugger.resume(), StopReason::Breakpoint(0x060b));
+        assert_eq!(debugger.cpu.register_x, 2);
+
+        assert!(debugger.remove_breakpoint(0x060b));
+        assert_eq!(debugger.resume(), StopReason::Brk);
+        assert_eq!(debugger.cpu.mem_read(0x10), 4);
+    }
+
+    #[test]
+    fn test_debugger_step_into_over_and_out() {
+        let mut debugger = debugger_with(&subroutine_program());
+
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.cpu.program_counter, 0x0602);
+
+        // step over the whole JSR (including the nested one)
+        assert_eq!(debugger.step_over(), StopReason::Return);
+        assert_eq!(debugger.cpu.program_counter, 0x0605);
+        assert_eq!(debugger.cpu.register_x, 2);
+
+        // step into the next JSR and then back out of it
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.cpu.program_counter, 0x060b);
+        assert_eq!(debugger.step_out(), StopReason::Return);
+        assert_eq!(debugger.cpu.program_counter, 0x0608);
+        assert_eq!(debugger.cpu.register_x, 4);
+    }
+
+    #[test]
+    fn test_debugger_stops_on_illegal_opcode() {
+        let mut debugger = debugger_with(&asm!(
+            "        .org $0600",
+            "        LDX #$01",
+            "        .db $02", // KIL
+            "        INX",
+            "        BRK",
+        ));
+
+        let reason = debugger.resume();
+        assert_eq!(reason, StopReason::IllegalOpcode(0x0602, 0x02));
+        assert_eq!(debugger.cpu.program_counter, 0x0602);
+        assert_eq!(debugger.cpu.register_x, 1);
+
+        // the session survives it, stepping stops on the opcode again
+        let stop = debugger.execute("step").unwrap();
+        assert!(stop.starts_with("Illegal opcode $02 at $0602"), "{stop}");
+        debugger.cpu.program_counter = 0x0603;
+        assert_eq!(debugger.resume(), StopReason::Brk);
+        assert_eq!(debugger.cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_debugger_watchpoints() {
+        let mut debugger = debugger_with(&asm!(
+            "        .org $0600",
+            "        LDA #$05",
+            "        STA $0811", // mirror of 0x0011
+            "        LDA $11",
+            "        BRK",
+        ));
+        debugger.add_watchpoint(0x0011, WatchKind::Write);
+
+        match debugger.resume() {
+            StopReason::Watchpoint(hit) => {
+                assert_eq!(hit.addr, 0x0011);
+                assert_eq!(hit.access, Access::Write);
+                assert_eq!(hit.value, 5);
+            }
+            reason => panic!("unexpected stop: {reason}"),
+        }
+        assert_eq!(debugger.cpu.program_counter, 0x0605);
+
+        debugger.add_watchpoint(0x0011, WatchKind::Read);
+        assert!(matches!(
+            debugger.resume(),
+            StopReason::Watchpoint(hit) if hit.access == Access::Read
+        ));
+
+        assert!(debugger.remove_watchpoint(0x0011));
+        assert_eq!(debugger.resume(), StopReason::Brk);
+    }
+
+    #[test]
+    fn test_debugger_watches_io_register_mirrors() {
+        let mut debugger = debugger_with(&asm!(
+            "        .org $0600",
+            "        LDA $3FFA", // mirror of PPUSTATUS
+            "        BRK",
+        ));
+        debugger.add_watchpoint(0x2002, WatchKind::Read);
+
+        assert!(matches!(
+            debugger.resume(),
+            StopReason::Watchpoint(hit) if hit.addr == 0x2002
+        ));
+    }
+
+    #[test]
+    fn test_debugger_watching_a_mirror() {
+        let mut debugger = debugger_with(&asm!(
+            "        .org $0600",
+            "        LDA #$05",
+            "        STA $11",
+            "        STA $1811", // another mirror of 0x0011
+            "        BRK",
+        ));
+        debugger.add_watchpoint(0x0811, WatchKind::Write);
+
+        assert!(matches!(
+            debugger.resume(),
+            StopReason::Watchpoint(hit) if hit.addr == 0x0011 && hit.value == 5
+        ));
+        assert_eq!(debugger.cpu.program_counter, 0x0604);
+
+        // and it's removed through any of the mirrors too
+        assert!(debugger.remove_watchpoint(0x1011));
+        assert_eq!(debugger.resume(), StopReason::Brk);
+    }
+
+    #[test]
+    fn test_debugger_inspecting_memory_does_not_disturb_ppu() {
+        let mut debugger = debugger_with(&asm!("        .org $0600", "loop:   JMP loop",));
+
+        // wait for VBlank to start in the second frame
+        debugger.run_until_frame();
+        while debugger.cpu.bus.ppu().scanline() < 241 {
+            debugger.step();
+        }
+        assert_eq!(debugger.cpu.mem_peek(0x2002) & 0x80, 0x80);
+
+        // dumping PPUSTATUS any number of times leaves the VBlank flag alone
+        for _ in 0..3 {
+            let dump = debugger.execute("mem 2002 1").unwrap();
+            assert_eq!(dump, "2002: 80");
+        }
+        assert_eq!(debugger.cpu.bus.ppu().peek_status() & 0x80, 0x80);
+
+        // while an actual CPU read clears it
+        assert_eq!(debugger.cpu.mem_read(0x2002) & 0x80, 0x80);
+        assert_eq!(debugger.cpu.mem_peek(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn test_debugger_run_until_nmi_and_frame() {
+        // a cartridge of zeros, so the NMI vector reads as 0x0000 and that's
+        // where the handler lives
+        let mut cpu = CPU::with_bus(load_cartridge(&ines(0, 0, &[0; 0x8000], &[0; 0x2000])));
+        cpu.load_at(0x0000, &asm!("INC $10", "RTI"));
+        cpu.load_at(
+            0x0600,
+            &asm!(
+                "        .org $0600",
+                "        LDA #$80",
+                "        STA $2000",
+                "loop:   JMP loop",
+            ),
+        );
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        let mut debugger = Debugger::new(cpu);
+
+        assert_eq!(debugger.run_until_nmi(), StopReason::Nmi);
+        assert_eq!(debugger.cpu.program_counter, 0x0000);
+        assert_eq!(debugger.cpu.bus.ppu().scanline(), 241);
+
+        assert_eq!(debugger.run_until_frame(), StopReason::Frame(1));
+        assert_eq!(debugger.run_until_nmi(), StopReason::Nmi);
+        assert_eq!(debugger.cpu.mem_peek(0x10), 1);
+    }
+
+    #[test]
+    fn test_debugger_commands() {
+        let mut debugger = debugger_with(&subroutine_program());
+
+        let regs = debugger.execute("regs").unwrap();
+        assert!(regs.starts_with("A=$00 X=$00 Y=$00 SP=$FD P=$24 [nv-bdIzc] PC=$0600"));
+
+        assert_eq!(
+            debugger.execute("b $0610").unwrap(),
+            "Breakpoint set at $0610"
+        );
+        let stop = debugger.execute("c").unwrap();
+        assert!(stop.starts_with("Breakpoint at $0610"), "{stop}");
+        assert!(stop.contains(">*0610  E8        INX"), "{stop}");
+
+        // an empty line repeats the last command
+        let stop = debugger.execute("").unwrap();
+        assert!(stop.starts_with("Breakpoint at $0610"), "{stop}");
+
+        let dis = debugger.execute("dis").unwrap();
+        assert!(dis.contains("060B  E8        INX"), "{dis}");
+        assert!(dis.contains(">*0610  E8        INX"), "{dis}");
+
+        assert!(debugger.execute("bogus").unwrap().starts_with("Error"));
+        assert!(debugger.execute("b zzzz").unwrap().starts_with("Error"));
+        assert_eq!(debugger.execute("quit"), None);
+    }
+
+    #[test]
+    fn test_debugger_step_count_stops_early() {
+        let mut debugger = debugger_with(&subroutine_program());
+        debugger.add_breakpoint(0x0610);
+        let stop = debugger.execute("step 1000").unwrap();
+        assert!(stop.starts_with("Breakpoint at $0610"), "{stop}");
+        assert_eq!(debugger.cpu.register_x, 1);
+
+        debugger.remove_breakpoint(0x0610);
+        debugger.add_watchpoint(0x0010, WatchKind::Write);
+        let stop = debugger.execute("step 1000").unwrap();
+        assert!(stop.starts_with("Watchpoint: write to $0010"), "{stop}");
+
+        let stop = debugger.execute("step 1000").unwrap();
+        assert!(stop.starts_with("BRK reached"), "{stop}");
+        assert_eq!(debugger.cpu.mem_peek(0x10), 4);
+    }
+
+    #[test]
+    fn test_debugger_repl() {
+        let mut debugger = debugger_with(&subroutine_program());
+        let input = b"step 2\nregs\nquit\nregs\n";
+        let mut output = Vec::new();
+        debugger.repl(&input[..], &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("X=$00"), "{output}");
+        assert!(output.contains("PC=$060B"), "{output}");
+        // nothing after quit is executed
+        assert_eq!(output.matches("PC=").count(), 1, "{output}");
+    }
+}
@@ -0,0 +1,118 @@
+//! Disassembler tests reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::disasm::{Symbols, disassemble, disassemble_one, disassemble_range};
+
+    fn texts(bytes: &[u8], start: u16, symbols: Option<&Symbols>) -> Vec<String> {
+        disassemble(bytes, start, symbols)
+            .iter()
+            .map(|line| line.text.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_disasm_addressing_mode_syntax() {
+        let program = [
+            0xa9, 0x10, // LDA #$10
+            0xb5, 0x10, // LDA $10,X
+            0xb6, 0x20, // LDX $20,Y
+            0xb1, 0x10, // LDA ($10),Y
+            0xa1, 0x10, // LDA ($10,X)
+            0xbd, 0x00, 0x02, // LDA $0200,X
+            0xb9, 0x34, 0x12, // LDA $1234,Y
+            0x6c, 0xfc, 0xff, // JMP ($FFFC)
+            0x0a, // ASL A
+            0xe8, // INX
+        ];
+
+        assert_eq!(
+            texts(&program, 0x0600, None),
+            vec![
+                "LDA #$10",
+                "LDA $10,X",
+                "LDX $20,Y",
+                "LDA ($10),Y",
+                "LDA ($10,X)",
+                "LDA $0200,X",
+                "LDA $1234,Y",
+                "JMP ($FFFC)",
+                "ASL A",
+                "INX",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disasm_resolves_branch_targets() {
+        // BNE -2 loops back onto itself, BEQ +4 lands on $0604 + 4 = $0608
+        let program = [0xd0, 0xfe, 0xf0, 0x04];
+        assert_eq!(
+            texts(&program, 0x0600, None),
+            vec!["BNE $0600", "BEQ $0608"]
+        );
+    }
+
+    #[test]
+    fn test_disasm_unknown_and_truncated_bytes_are_data() {
+        // 0x02 is not an opcode and 0x8d needs two more bytes than we have
+        let program = [0x02, 0xea, 0x8d, 0x00];
+        assert_eq!(
+            texts(&program, 0x8000, None),
+            vec![".db $02", "NOP", ".db $8D", "BRK"]
+        );
+    }
+
+    #[test]
+    fn test_disasm_line_format() {
+        let line = disassemble_one(&[0x8d, 0x00, 0x20], 0xc000, None);
+        assert_eq!(line.to_string(), "C000  8D 00 20  STA $2000");
+
+        let line = disassemble_one(&[0x60], 0xc003, None);
+        assert_eq!(line.to_string(), "C003  60        RTS");
+    }
+
+    #[test]
+    fn test_disasm_label_substitution() {
+        let symbols = Symbols::parse(
+            "; comment lines are skipped\n\
+             main = $0600\n\
+             $0610#read_pads#reads both controllers\n\
+             apple_lo = 0x00FE\n",
+        )
+        .unwrap();
+
+        let program = [
+            0x20, 0x10, 0x06, // JSR read_pads
+            0xa5, 0xfe, // LDA apple_lo
+            0xd0, 0xf9, // BNE main
+        ];
+        assert_eq!(
+            texts(&program, 0x0600, Some(&symbols)),
+            vec!["JSR read_pads", "LDA apple_lo", "BNE main"]
+        );
+        assert_eq!(symbols.addr_of("main"), Some(0x0600));
+    }
+
+    #[test]
+    fn test_disasm_invalid_symbol_file() {
+        assert!(Symbols::parse("main $0600").is_err());
+        assert!(Symbols::parse("main = $zz").is_err());
+    }
+
+    #[test]
+    fn test_disasm_memory_range() {
+        let mut cpu = CPU::new();
+        cpu.test_load(&[0xa2, 0x08, 0xca, 0x8e, 0x00, 0x02, 0x00]);
+
+        let lines = disassemble_range(&cpu, 0x0600, 0x0603, None);
+        let texts = lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect::<Vec<_>>();
+        // the STX at 0x0603 is kept whole even though it runs past the end
+        assert_eq!(texts, vec!["LDX #$08", "DEX", "STX $0200"]);
+        assert_eq!(lines[2].bytes, vec![0x8e, 0x00, 0x02]);
+    }
+}
@@ -0,0 +1,315 @@
+//! Famicom Disk System (disk images, IPS patches, the RAM adapter's timer,
+//! the disk drive and the wavetable channel) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::audio_range;
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::rom::fds::{DiskImage, SIDE_SIZE, TRACK_SIZE};
+    use nes_emulator::rom::{Rom, ips};
+
+    // $4025 bits
+    const MOTOR_ON: u8 = 0x01;
+    const READ_MODE: u8 = 0x04;
+    const TRANSFER_START: u8 = 0x40;
+    const DISK_IRQ: u8 = 0x80;
+
+    /// A side with the disk info, a file count of 1 and a 4 byte file
+    /// holding `contents`
+    fn side(contents: [u8; 4]) -> Vec<u8> {
+        let mut side = vec![0x01];
+        side.extend_from_slice(b"*NINTENDO-HVC*");
+        side.resize(56, 0);
+        side.extend_from_slice(&[0x02, 1]);
+        // file 0 called FILE, loaded at 0x6000, 4 bytes long
+        side.extend_from_slice(&[
+            0x03, 0, 0, b'F', b'I', b'L', b'E', 0, 0, 0, 0x60, 0, 0, 4, 0, 0,
+        ]);
+        side.push(0x04);
+        side.extend_from_slice(&contents);
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    /// A `.fds` file with `sides` sides, with fwNES's header or without
+    fn disk_file(sides: usize, header: bool) -> Vec<u8> {
+        let mut raw = Vec::new();
+        if header {
+            raw.extend_from_slice(&[0x46, 0x44, 0x53, 0x1A, sides as u8]);
+            raw.resize(16, 0);
+        }
+        for number in 0..sides {
+            raw.extend(side([number as u8, 1, 2, 3]));
+        }
+        raw
+    }
+
+    /// A BIOS that's all NOPs, with the vectors pointing at 0xE000
+    fn bios() -> Vec<u8> {
+        let mut bios = vec![0xEA; 0x2000];
+        bios[0x1FFA..].copy_from_slice(&[0x00, 0xE0, 0x00, 0xE0, 0x00, 0xE0]);
+        bios
+    }
+
+    fn disk_system(sides: usize) -> Bus {
+        let disk = DiskImage::new(&disk_file(sides, true)).unwrap();
+        let mut bus = Bus::with_rom(Rom::from_fds(disk, &bios()).unwrap()).unwrap();
+        // keep the APU's frame IRQ out of the way
+        bus.mem_write(0x4017, 0x40);
+        bus
+    }
+
+    /// Runs the drive until it hands over a byte, returning it (None if it
+    /// takes longer than a trip around the disk)
+    fn next_byte(bus: &mut Bus) -> Option<u8> {
+        for _ in 0..TRACK_SIZE * 160 {
+            bus.tick(1);
+            if bus.mem_peek(0x4030) & 0b10 != 0 {
+                return Some(bus.mem_read(0x4031));
+            }
+        }
+        None
+    }
+
+    /// Plays a square wave at full volume
+    fn play_square(bus: &mut Bus) {
+        bus.mem_write(0x4089, 0x80);
+        for step in 0..64 {
+            bus.mem_write(0x4040 + step, if step < 32 { 63 } else { 0 });
+        }
+        bus.mem_write(0x4089, 0x00);
+        bus.mem_write(0x4080, 0x80 | 32);
+        bus.mem_write(0x4082, 0x00);
+        bus.mem_write(0x4083, 0x01);
+    }
+
+    #[test]
+    fn test_disk_image() {
+        let with_header = DiskImage::new(&disk_file(2, true)).unwrap();
+        let without_header = DiskImage::new(&disk_file(2, false)).unwrap();
+        assert_eq!(with_header, without_header);
+        assert_eq!(with_header.sides.len(), 2);
+        assert_eq!(with_header.to_bytes(), disk_file(2, true));
+
+        assert!(DiskImage::new(&disk_file(1, false)[1..]).is_err());
+        let mut no_info = disk_file(1, false);
+        no_info[1] = b'?';
+        assert!(DiskImage::new(&no_info).is_err());
+
+        // the blocks survive the trip to the drive and back
+        let tracks: Vec<Vec<u8>> = (0..2).map(|side| with_header.to_track(side)).collect();
+        assert!(tracks.iter().all(|track| track.len() == TRACK_SIZE));
+        assert_eq!(DiskImage::from_tracks(&tracks), with_header);
+    }
+
+    #[test]
+    fn test_ips() {
+        let original: Vec<u8> = (0..=255).collect();
+        let mut modified = original.clone();
+        modified[10..14].copy_from_slice(&[0, 0, 0, 0]);
+        modified[200] = 0xAA;
+        let patch = ips::create(&original, &modified).unwrap();
+        assert!(!ips::is_empty(&patch));
+        assert_eq!(ips::apply(&original, &patch).unwrap(), modified);
+
+        // growing and shrinking the file
+        let longer = [original.as_slice(), &[1, 2, 3]].concat();
+        let patch = ips::create(&original, &longer).unwrap();
+        assert_eq!(ips::apply(&original, &patch).unwrap(), longer);
+        let patch = ips::create(&original, &original[..100]).unwrap();
+        assert_eq!(ips::apply(&original, &patch).unwrap(), &original[..100]);
+
+        assert!(ips::is_empty(&ips::create(&original, &original).unwrap()));
+        // a run of one value
+        let rle = b"PATCH\x00\x00\x02\x00\x00\x00\x03\x77EOF";
+        assert_eq!(
+            ips::apply(&[0; 6], rle).unwrap(),
+            vec![0, 0, 0x77, 0x77, 0x77, 0]
+        );
+        assert!(ips::apply(&original, b"PATCH\x00\x00\x02\x00").is_err());
+        assert!(ips::apply(&original, b"NOT A PATCH").is_err());
+    }
+
+    #[test]
+    fn test_fds_memory() {
+        let disk = DiskImage::new(&disk_file(1, true)).unwrap();
+        assert!(Rom::from_fds(disk.clone(), &[0; 0x1000]).is_err());
+        let rom = Rom::from_fds(disk, &bios()).unwrap();
+        assert_eq!(rom.mapper, 20);
+
+        let mut bus = disk_system(1);
+        assert_eq!(bus.mem_peek(0xE000), 0xEA);
+        assert_eq!(bus.mem_peek(0xFFFC), 0x00);
+        // 32 KiB of RAM up to the BIOS
+        bus.mem_write(0x6000, 0x11);
+        bus.mem_write(0xDFFF, 0x22);
+        bus.mem_write(0xE000, 0x33);
+        assert_eq!(bus.mem_peek(0x6000), 0x11);
+        assert_eq!(bus.mem_peek(0xDFFF), 0x22);
+        assert_eq!(bus.mem_peek(0xE000), 0xEA);
+    }
+
+    #[test]
+    fn test_fds_timer_irq() {
+        let mut bus = disk_system(1);
+        bus.mem_write(0x4020, 0x10);
+        bus.mem_write(0x4021, 0x00);
+        bus.mem_write(0x4022, 0x02);
+        bus.tick(0x10);
+        assert!(!bus.irq_pending());
+        bus.tick(1);
+        assert!(bus.irq_pending());
+        // reading the status acknowledges it
+        assert_eq!(bus.mem_read(0x4030) & 0b01, 0b01);
+        assert!(!bus.irq_pending());
+        // it only fires once without repeat
+        bus.tick(0x100);
+        assert!(!bus.irq_pending());
+
+        bus.mem_write(0x4022, 0x03);
+        bus.tick(0x11);
+        assert!(bus.irq_pending());
+        bus.mem_read(0x4030);
+        bus.tick(0x11);
+        assert!(bus.irq_pending());
+
+        // turning the disk registers off stops it
+        bus.mem_write(0x4023, 0x00);
+        assert!(!bus.irq_pending());
+        bus.tick(0x100);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_fds_drive() {
+        let mut bus = disk_system(2);
+        assert_eq!(bus.disk_sides(), 2);
+        assert_eq!(bus.disk_side(), Some(0));
+        // the disk's in, but the motor's off
+        assert_eq!(bus.mem_peek(0x4032), 0b010);
+        assert_eq!(bus.mem_peek(0x4033), 0x80);
+
+        bus.eject_disk().unwrap();
+        assert_eq!(bus.disk_side(), None);
+        assert_eq!(bus.mem_peek(0x4032), 0b111);
+        bus.insert_disk(1).unwrap();
+        assert_eq!(bus.disk_side(), Some(1));
+        assert!(bus.insert_disk(2).is_err());
+
+        // switching sides takes the disk out for a second
+        bus.insert_disk(0).unwrap();
+        assert_eq!(bus.disk_side(), None);
+        bus.tick(1_789_773);
+        assert_eq!(bus.disk_side(), Some(0));
+
+        let mut cartridge = Bus::new();
+        assert_eq!(cartridge.disk_sides(), 0);
+        assert!(cartridge.insert_disk(0).is_err());
+        assert!(cartridge.disk_diff().is_err());
+    }
+
+    #[test]
+    fn test_fds_read_disk() {
+        let mut bus = disk_system(2);
+        // nothing comes through until the transfer starts
+        bus.mem_write(0x4025, MOTOR_ON | READ_MODE);
+        bus.tick(100_000);
+        assert_eq!(bus.mem_peek(0x4030), 0);
+        assert_eq!(bus.mem_peek(0x4032), 0);
+
+        bus.mem_write(0x4025, MOTOR_ON | READ_MODE | TRANSFER_START | DISK_IRQ);
+        let first = next_byte(&mut bus);
+        assert_eq!(first, Some(0x01));
+        let verification: Vec<u8> = (0..14).filter_map(|_| next_byte(&mut bus)).collect();
+        assert_eq!(verification, b"*NINTENDO-HVC*");
+
+        // with the disk IRQ on, every byte raises it
+        while !bus.irq_pending() {
+            bus.tick(1);
+        }
+        bus.mem_read(0x4031);
+        assert!(!bus.irq_pending());
+
+        // the motor stopping takes the drive out of ready
+        bus.mem_write(0x4025, 0);
+        bus.tick(1);
+        assert_eq!(bus.mem_peek(0x4032), 0b010);
+    }
+
+    #[test]
+    fn test_fds_write_disk() {
+        let mut bus = disk_system(1);
+        bus.mem_write(0x4025, MOTOR_ON | READ_MODE | TRANSFER_START);
+        for _ in 0..15 {
+            next_byte(&mut bus).unwrap();
+        }
+        // overwrite the byte after the verification text
+        bus.mem_write(0x4024, b'#');
+        bus.mem_write(0x4025, MOTOR_ON | TRANSFER_START);
+        next_byte(&mut bus);
+        bus.mem_write(0x4025, 0);
+
+        let written = bus.disk_image().unwrap();
+        assert_eq!(written.sides[0][15], b'#');
+        assert_eq!(written.sides[0][16..], side([0, 1, 2, 3])[16..]);
+
+        let patch = bus.disk_diff().unwrap();
+        assert!(!ips::is_empty(&patch));
+        let mut other = disk_system(1);
+        assert!(ips::is_empty(&other.disk_diff().unwrap()));
+        other.apply_disk_diff(&patch).unwrap();
+        assert_eq!(other.disk_image(), Some(written));
+
+        assert!(other.apply_disk_diff(b"NOT A PATCH").is_err());
+        assert_eq!(other.disk_image().unwrap().sides[0][15], b'#');
+    }
+
+    #[test]
+    fn test_fds_audio() {
+        let mut bus = disk_system(1);
+        let (silence, quietest) = audio_range(&mut bus, 20000);
+        assert_eq!(silence, quietest);
+
+        play_square(&mut bus);
+        assert_eq!(bus.mem_read(0x4090) & 0x3F, 32);
+        let (loudest, quietest) = audio_range(&mut bus, 20000);
+        // 63 * 32 * 36 / 1152 steps of 0.0035
+        assert!(loudest - quietest > 0.2, "{loudest} {quietest}");
+
+        // the lowest master volume is 2/5 of that
+        bus.mem_write(0x4089, 0x03);
+        let (loudest, quietest) = audio_range(&mut bus, 20000);
+        assert!(loudest - quietest < 0.1, "{loudest} {quietest}");
+
+        // halting the waveform silences it
+        bus.mem_write(0x4083, 0x80);
+        let (loudest, quietest) = audio_range(&mut bus, 20000);
+        assert_eq!(loudest, quietest);
+    }
+
+    #[test]
+    fn test_savestate() {
+        let mut cpu = CPU::with_bus(disk_system(2));
+        cpu.mem_write(0x6000, 0x42);
+        play_square(&mut cpu.bus);
+        cpu.mem_write(0x4025, MOTOR_ON | READ_MODE | TRANSFER_START);
+        next_byte(&mut cpu.bus);
+        cpu.bus.insert_disk(1).unwrap();
+        let state = cpu.save_state();
+
+        let mut other = CPU::with_bus(disk_system(2));
+        other.load_state(&state).unwrap();
+        assert_eq!(other.bus.mem_peek(0x6000), 0x42);
+        assert_eq!(other.bus.disk_side(), None);
+        let (loudest, quietest) = audio_range(&mut cpu.bus, 5000);
+        assert_eq!(audio_range(&mut other.bus, 5000), (loudest, quietest));
+        cpu.bus.tick(1_789_773);
+        other.bus.tick(1_789_773);
+        assert_eq!(other.bus.disk_side(), Some(1));
+        assert_eq!(other.bus.disk_image(), cpu.bus.disk_image());
+    }
+}
@@ -0,0 +1,158 @@
+//! NTSC filter tests reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::debugger::Debugger;
+    use nes_emulator::filter::{NtscFilter, NtscPreset};
+    use nes_emulator::palette::{NtscSettings, Palette};
+    use nes_emulator::screenshot::Screenshot;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 4;
+
+    fn filter(preset: NtscPreset) -> NtscFilter {
+        NtscFilter::new(preset, 2).unwrap()
+    }
+
+    /// The output color at (x, y)
+    fn at(output: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let start = (y * width + x) * 3;
+        [output[start], output[start + 1], output[start + 2]]
+    }
+
+    fn close(a: [u8; 3], b: [u8; 3]) -> bool {
+        (0..3).all(|channel| a[channel].abs_diff(b[channel]) <= 1)
+    }
+
+    /// A frame that's black on the left half and white on the right
+    fn edge() -> Vec<u16> {
+        (0..WIDTH * HEIGHT)
+            .map(|pixel| {
+                if pixel % WIDTH < WIDTH / 2 {
+                    0x0F
+                } else {
+                    0x30
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_presets_and_scales() {
+        assert_eq!(
+            NtscPreset::parse("Composite").unwrap(),
+            NtscPreset::Composite
+        );
+        assert_eq!(NtscPreset::parse("s-video").unwrap(), NtscPreset::SVideo);
+        assert_eq!(NtscPreset::parse("rgb").unwrap(), NtscPreset::Rgb);
+        assert_eq!(
+            NtscPreset::parse("vga").unwrap_err(),
+            "Unknown filter vga (expected composite, svideo or rgb)"
+        );
+
+        assert_eq!(filter(NtscPreset::Rgb).output_width(256), 512);
+        let wide = NtscFilter::new(NtscPreset::Composite, 3).unwrap();
+        assert_eq!(wide.output_width(256), 768);
+        let output = wide.filter(&edge(), WIDTH, 0);
+        assert_eq!(output.len(), WIDTH * 3 * HEIGHT * 3);
+        assert_eq!(
+            NtscFilter::new(NtscPreset::Rgb, 4).unwrap_err(),
+            "Unsupported filter scale 4 (expected 2 or 3)"
+        );
+    }
+
+    #[test]
+    fn test_flat_colors_match_the_generated_palette() {
+        let palette = Palette::generate_ntsc(&NtscSettings::default());
+        // a red with green emphasis, and some plain colors
+        for pixel in [0x16, 0x21, 0x2A, 0x00, 0x30, 0x16 | 0b010 << 6] {
+            let expected = palette.rgb(pixel as u8 & 0x3F, (pixel >> 6) as u8);
+            let frame = vec![pixel; WIDTH * HEIGHT];
+            for preset in [NtscPreset::Composite, NtscPreset::SVideo, NtscPreset::Rgb] {
+                let output = filter(preset).filter(&frame, WIDTH, 0);
+                // away from the ends of the scanlines, where the signal
+                // turns black
+                for y in 0..HEIGHT {
+                    for x in 8..WIDTH * 2 - 8 {
+                        let actual = at(&output, WIDTH * 2, x, y);
+                        assert!(
+                            close(actual, expected),
+                            "{preset:?} {pixel:03X} at ({x}, {y}): {actual:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgb_has_no_artifacts() {
+        let output = filter(NtscPreset::Rgb).filter(&edge(), WIDTH, 0);
+        let palette = Palette::generate_ntsc(&NtscSettings::default());
+        for y in 0..HEIGHT {
+            assert_eq!(at(&output, WIDTH * 2, WIDTH - 1, y), palette.rgb(0x0F, 0));
+            assert_eq!(at(&output, WIDTH * 2, WIDTH, y), palette.rgb(0x30, 0));
+        }
+        assert_eq!(output, filter(NtscPreset::Rgb).filter(&edge(), WIDTH, 1));
+    }
+
+    #[test]
+    fn test_composite_artifacts_move_with_the_phase() {
+        let composite = filter(NtscPreset::Composite);
+        let output = composite.filter(&edge(), WIDTH, 0);
+        let row = |output: &[u8], y: usize| {
+            (WIDTH - 4..WIDTH + 4)
+                .map(|x| at(output, WIDTH * 2, x, y))
+                .collect::<Vec<_>>()
+        };
+        // the edge smears into color fringes
+        let fringe = row(&output, 0);
+        assert!(fringe.iter().any(|&[r, g, b]| r != g || g != b));
+        // which look different on every scanline, repeating every 3 since
+        // each starts a third of a cycle later
+        assert_ne!(row(&output, 0), row(&output, 1));
+        assert_ne!(row(&output, 1), row(&output, 2));
+        assert_eq!(row(&output, 0), row(&output, 3));
+        // and move between frames
+        let odd = composite.filter(&edge(), WIDTH, 1);
+        assert_ne!(row(&output, 0), row(&odd, 0));
+        assert_eq!(row(&odd, 0), row(&output, 1));
+        assert_eq!(output, composite.filter(&edge(), WIDTH, 2));
+    }
+
+    #[test]
+    fn test_svideo_luma_is_sharper() {
+        let composite = filter(NtscPreset::Composite).filter(&edge(), WIDTH, 0);
+        let svideo = filter(NtscPreset::SVideo).filter(&edge(), WIDTH, 0);
+        let brightness = |output: &[u8], x: usize| {
+            at(output, WIDTH * 2, x, 0)
+                .iter()
+                .map(|&channel| channel as u32)
+                .sum::<u32>()
+        };
+        // right before the edge, composite luma is already brightening while
+        // S-Video is nearly black, and right after it S-Video is nearly white
+        assert!(brightness(&svideo, WIDTH - 1) < brightness(&composite, WIDTH - 1));
+        assert!(brightness(&svideo, WIDTH) > brightness(&composite, WIDTH));
+    }
+
+    #[test]
+    fn test_filtered_screenshots() {
+        let cpu = CPU::new();
+        let screenshot = Screenshot::capture_ntsc(&cpu, &filter(NtscPreset::SVideo));
+        assert_eq!((screenshot.width, screenshot.height), (512, 240));
+        assert_eq!(screenshot.pixels.len(), 512 * 240 * 3);
+
+        let mut debugger = Debugger::new(cpu);
+        assert_eq!(
+            debugger.execute("filter composite").unwrap(),
+            "Using the composite NTSC filter"
+        );
+        assert_eq!(
+            debugger.execute("filter pal").unwrap(),
+            "Error: Unknown filter pal (expected composite, svideo or rgb)"
+        );
+        assert_eq!(debugger.execute("filter off").unwrap(), "NTSC filter off");
+    }
+}
@@ -0,0 +1,168 @@
+//! Sunsoft FME-7 (banking and the cycle IRQ) and 5B audio tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{audio_range, ines, numbered_chr, numbered_prg};
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::ppu::Mirroring;
+    use nes_emulator::rom::Rom;
+
+    /// An iNES image for mapper 69 with 128 KiB of PRG ROM (every 8 KiB
+    /// filled with its bank number) and 128 KiB of CHR ROM (every KiB filled
+    /// with its number)
+    fn fme7_rom() -> Vec<u8> {
+        ines(69, 0, &numbered_prg(8), &numbered_chr(16))
+    }
+
+    fn cartridge() -> Bus {
+        let mut bus = Bus::with_rom(Rom::new(&fme7_rom()).unwrap()).unwrap();
+        // keep the APU's frame IRQ out of the way
+        bus.mem_write(0x4017, 0x40);
+        bus
+    }
+
+    fn command(bus: &mut Bus, command: u8, value: u8) {
+        bus.mem_write(0x8000, command);
+        bus.mem_write(0xA000, value);
+    }
+
+    fn audio(bus: &mut Bus, register: u8, value: u8) {
+        bus.mem_write(0xC000, register);
+        bus.mem_write(0xE000, value);
+    }
+
+    #[test]
+    fn test_fme7_banks() {
+        let mut bus = cartridge();
+        assert_eq!(bus.mem_peek(0xE000), 15);
+        command(&mut bus, 9, 4);
+        command(&mut bus, 10, 5);
+        command(&mut bus, 11, 6);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000]
+            .iter()
+            .map(|&addr| bus.mem_peek(addr))
+            .collect();
+        assert_eq!(banks, vec![4, 5, 6, 15]);
+
+        command(&mut bus, 3, 0x42);
+        let ppu = bus.ppu();
+        assert_eq!(ppu.chr_rom[ppu.chr_offset(0x0C00)], 0x42);
+
+        command(&mut bus, 12, 1);
+        assert_eq!(bus.ppu().mirroring, Mirroring::Horizontal);
+        command(&mut bus, 12, 3);
+        assert_eq!(bus.ppu().mirroring, Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_fme7_low_bank() {
+        let mut bus = cartridge();
+        // ROM at 0x6000, and writes don't go anywhere
+        command(&mut bus, 8, 3);
+        assert_eq!(bus.mem_peek(0x6000), 3);
+        bus.mem_write(0x6000, 0x99);
+        assert_eq!(bus.mem_peek(0x6000), 3);
+
+        // RAM that's enabled
+        command(&mut bus, 8, 0xC0);
+        bus.mem_write(0x6000, 0x99);
+        assert_eq!(bus.mem_peek(0x6000), 0x99);
+
+        // RAM that's disabled keeps its contents
+        command(&mut bus, 8, 0x40);
+        bus.mem_write(0x6000, 0x11);
+        command(&mut bus, 8, 0xC0);
+        assert_eq!(bus.mem_peek(0x6000), 0x99);
+    }
+
+    #[test]
+    fn test_fme7_irq() {
+        let mut bus = cartridge();
+        command(&mut bus, 14, 0x10);
+        command(&mut bus, 15, 0x00);
+        command(&mut bus, 13, 0x81);
+        // it counts down through 0 and fires when it wraps around
+        bus.tick(0x10);
+        assert!(!bus.irq_pending());
+        bus.tick(1);
+        assert!(bus.irq_pending());
+        // writing the control acknowledges it
+        command(&mut bus, 13, 0x81);
+        assert!(!bus.irq_pending());
+        bus.tick(0x10000);
+        assert!(bus.irq_pending());
+
+        // counting without raising it
+        command(&mut bus, 13, 0x80);
+        bus.tick(0x20000);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_5b_audio() {
+        let mut bus = cartridge();
+        let (loudest, quietest) = audio_range(&mut bus, 20000);
+        assert_eq!(loudest, quietest);
+        let silence = loudest;
+
+        // channel A's tone at full volume, no noise
+        audio(&mut bus, 0, 0x40);
+        audio(&mut bus, 7, 0b11_1110);
+        audio(&mut bus, 8, 0x0F);
+        let (loudest, quietest) = audio_range(&mut bus, 20000);
+        assert!(loudest > silence + 0.1, "{loudest}");
+        assert!((quietest - silence).abs() < 0.01, "{quietest}");
+
+        // every volume step is 3 dB quieter
+        audio(&mut bus, 7, 0b11_1111);
+        let (full, _) = audio_range(&mut bus, 2000);
+        audio(&mut bus, 8, 0x0D);
+        let (quieter, _) = audio_range(&mut bus, 2000);
+        let ratio = (quieter - silence) / (full - silence);
+        assert!((ratio - 10f32.powf(-6.0 / 20.0)).abs() < 0.01, "{ratio}");
+
+        // an envelope that decays once and stays silent
+        audio(&mut bus, 8, 0x10);
+        audio(&mut bus, 11, 0x01);
+        audio(&mut bus, 12, 0x00);
+        audio(&mut bus, 13, 0x00);
+        bus.tick(8 * 32 + 8);
+        let (loudest, quietest) = audio_range(&mut bus, 2000);
+        assert_eq!(loudest, quietest);
+        assert!((loudest - silence).abs() < 0.001);
+
+        // one that decays once and holds at the top
+        audio(&mut bus, 13, 0x0B);
+        bus.tick(8 * 32 + 8);
+        let (loudest, quietest) = audio_range(&mut bus, 2000);
+        assert!((loudest - full).abs() < 0.001 && loudest == quietest);
+    }
+
+    #[test]
+    fn test_savestate() {
+        let mut cpu = CPU::with_bus(cartridge());
+        command(&mut cpu.bus, 9, 7);
+        command(&mut cpu.bus, 14, 0xFF);
+        command(&mut cpu.bus, 15, 0x7F);
+        command(&mut cpu.bus, 13, 0x81);
+        audio(&mut cpu.bus, 0, 0x23);
+        audio(&mut cpu.bus, 6, 0x05);
+        audio(&mut cpu.bus, 7, 0b11_0110);
+        audio(&mut cpu.bus, 8, 0x0C);
+        cpu.bus.tick(5000);
+        let state = cpu.save_state();
+
+        let mut other = CPU::with_bus(cartridge());
+        other.load_state(&state).unwrap();
+        assert_eq!(other.bus.mem_peek(0x8000), 7);
+        let (loudest, quietest) = audio_range(&mut cpu.bus, 5000);
+        assert_eq!(audio_range(&mut other.bus, 5000), (loudest, quietest));
+        cpu.bus.tick(0x8000);
+        other.bus.tick(0x8000);
+        assert!(cpu.bus.irq_pending() && other.bus.irq_pending());
+    }
+}
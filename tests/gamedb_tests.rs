@@ -0,0 +1,181 @@
+//! Game database (header corrections by CRC-32) tests reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::hash::crc32;
+    use nes_emulator::ppu::Mirroring;
+    use nes_emulator::region::Region;
+    use nes_emulator::rom::Rom;
+    use nes_emulator::rom::db::{self, ExpansionDevice, GameInfo};
+
+    /// An iNES file with one page of PRG and CHR ROM, `flags_6` and `header`
+    /// written over bytes 7 - 15 of the header
+    fn raw(flags_6: u8, header: [u8; 9]) -> Vec<u8> {
+        let mut raw = vec![b'N', b'E', b'S', 0x1A, 1, 1, flags_6];
+        raw.extend_from_slice(&header);
+        raw.extend((0..0x4000).map(|i| i as u8));
+        raw.extend(vec![0x55; 0x2000]);
+        raw
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert!(db::len() > 0);
+        let game = db::lookup(0x3337EC46).unwrap();
+        assert_eq!(game.title, "Super Mario Bros.");
+        assert_eq!(game.mapper, 0);
+        assert_eq!(game.mirroring, Some(Mirroring::Vertical));
+        assert!(!game.battery);
+        assert_eq!(game.region, Some(Region::Ntsc));
+        assert!(db::lookup(0xDEADBEEF).is_none());
+    }
+
+    /// Overwrites the last 4 bytes of CHR ROM so the ROM's CRC-32 comes out
+    /// as `target`, which stands in for a dump of a game in the database
+    /// (CRC-32 is linear, so 4 bytes can be worked out backwards from any
+    /// CRC)
+    fn forge_crc32(raw: &mut [u8], target: u32) {
+        let table: Vec<u32> = (0..256)
+            .map(|byte| {
+                (0..8).fold(byte, |crc, _| {
+                    if crc & 1 != 0 {
+                        (crc >> 1) ^ 0xEDB88320
+                    } else {
+                        crc >> 1
+                    }
+                })
+            })
+            .collect();
+        let end = raw.len() - 4;
+        let before = !crc32(&raw[16..end]);
+        let mut wanted = !target;
+        for _ in 0..4 {
+            let index = table
+                .iter()
+                .position(|entry| entry >> 24 == wanted >> 24)
+                .unwrap();
+            wanted = ((wanted ^ table[index]) << 8) | index as u32;
+        }
+        raw[end..].copy_from_slice(&(wanted ^ before).to_le_bytes());
+    }
+
+    #[test]
+    fn test_database_corrects_a_wrong_header() {
+        // says mapper 1, horizontal mirroring, a battery, four screen VRAM
+        // and PAL in an NES 2.0 header, for Super Mario Bros. (NROM,
+        // vertical, no battery, NTSC)
+        let mut raw = raw(0x1A, [0x08, 0, 0, 0, 0, 0x01, 0, 0, 0]);
+        forge_crc32(&mut raw, 0x3337EC46);
+        assert_eq!(crc32(&raw[16..]), 0x3337EC46);
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.title, Some("Super Mario Bros."));
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(!rom.battery);
+        assert_eq!(rom.region, Some(Region::Ntsc));
+        assert_eq!(rom.expansion, Some(ExpansionDevice::StandardControllers));
+
+        // taken as it is, the header is wrong enough not to load
+        assert!(Rom::parse(&raw, false).is_err());
+    }
+
+    #[test]
+    fn test_crc32_covers_prg_and_chr() {
+        let raw = raw(0, [0; 9]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.crc32(), crc32(&raw[16..]));
+        // unknown to the database, so it's as the header says
+        assert_eq!(rom.title, None);
+        assert_eq!(rom.expansion, None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut rom = Rom::new(&raw(0, [0; 9])).unwrap();
+        let game = GameInfo {
+            title: "Test",
+            mapper: 4,
+            submapper: 1,
+            mirroring: Some(Mirroring::Vertical),
+            battery: true,
+            region: Some(Region::Pal),
+            expansion: ExpansionDevice::Zapper,
+        };
+        game.apply(&mut rom);
+        assert_eq!(rom.title, Some("Test"));
+        assert_eq!(rom.mapper, 4);
+        assert_eq!(rom.submapper, 1);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.region, Some(Region::Pal));
+        assert_eq!(rom.expansion, Some(ExpansionDevice::Zapper));
+    }
+
+    #[test]
+    fn test_apply_keeps_what_the_database_doesnt_know() {
+        // an NES 2.0 header for a vertically mirrored Dendy game
+        let mut rom = Rom::new(&raw(0x01, [0x08, 0, 0, 0, 0, 0x03, 0, 0, 0])).unwrap();
+        let game = GameInfo {
+            title: "Test",
+            mapper: 0,
+            submapper: 0,
+            mirroring: None,
+            battery: false,
+            region: None,
+            expansion: ExpansionDevice::StandardControllers,
+        };
+        game.apply(&mut rom);
+        assert_eq!(rom.title, Some("Test"));
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert_eq!(rom.region, Some(Region::Dendy));
+    }
+
+    #[test]
+    fn test_without_the_database() {
+        let raw = raw(0x12, [0; 9]);
+        let rom = Rom::parse(&raw, false).unwrap();
+        assert_eq!(rom.mapper, 1);
+        assert!(rom.battery);
+        assert_eq!(rom.title, None);
+        // four screen games still aren't supported, unless the database
+        // says the flag is wrong
+        assert!(Rom::parse(&self::raw(0x08, [0; 9]), true).is_err());
+        assert!(Rom::parse(&self::raw(0x08, [0; 9]), false).is_err());
+    }
+
+    #[test]
+    fn test_expansion_device_from_header() {
+        let nes_2 = |device| raw(0, [0x08, 0, 0, 0, 0, 0, 0, 0, device]);
+        assert_eq!(Rom::new(&nes_2(0)).unwrap().expansion, None);
+        assert_eq!(
+            Rom::new(&nes_2(0x01)).unwrap().expansion,
+            Some(ExpansionDevice::StandardControllers)
+        );
+        assert_eq!(
+            Rom::new(&nes_2(0x08)).unwrap().expansion,
+            Some(ExpansionDevice::Zapper)
+        );
+        // only the low 6 bits count
+        assert_eq!(
+            Rom::new(&nes_2(0xC2)).unwrap().expansion,
+            Some(ExpansionDevice::FourScore)
+        );
+        // iNES headers don't have it
+        assert_eq!(
+            Rom::new(&raw(0, [0, 0, 0, 0, 0, 0, 0, 0, 0x08]))
+                .unwrap()
+                .expansion,
+            None
+        );
+
+        assert_eq!(
+            ExpansionDevice::from_nes_2(0x0C),
+            Some(ExpansionDevice::PowerPad)
+        );
+        assert_eq!(
+            ExpansionDevice::from_nes_2(0x2A),
+            Some(ExpansionDevice::Other(0x2A))
+        );
+    }
+}
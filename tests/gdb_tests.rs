@@ -0,0 +1,233 @@
+//! GDB remote serial protocol stub tests reside here. Each test runs the
+//! server on a loopback socket and talks to it with a tiny scripted client
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+
+    use nes_emulator::asm;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::gdb::GdbServer;
+
+    /// Minimal GDB client that speaks the packet framing
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn send_raw(&mut self, data: &str) {
+            let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            write!(self.stream, "${data}#{sum:02x}").unwrap();
+        }
+
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        /// Reads a reply packet, checking its checksum and acknowledging it
+        fn recv(&mut self) -> String {
+            while self.read_byte() != b'$' {}
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let sum = [self.read_byte(), self.read_byte()];
+            let sum = u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap();
+            let data = String::from_utf8(data).unwrap();
+            assert_eq!(
+                sum,
+                data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+            );
+            self.stream.write_all(b"+").unwrap();
+            data
+        }
+
+        /// Sends a command and returns the reply
+        fn command(&mut self, data: &str) -> String {
+            self.send_raw(data);
+            assert_eq!(self.read_byte(), b'+', "packet {data} wasn't acknowledged");
+            self.recv()
+        }
+    }
+
+    /// Starts a server for the program loaded at 0x0600 and connects to it
+    fn connect(program: &[u8]) -> (Client, JoinHandle<GdbServer>) {
+        let mut cpu = CPU::new();
+        cpu.load_at(0x0600, program);
+        cpu.reset();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut server = GdbServer::new(cpu);
+            server.serve(&listener).unwrap();
+            server
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        (Client { stream }, server)
+    }
+
+    fn counter_program() -> Vec<u8> {
+        asm!(
+            "        .org $0600",
+            "        LDX #$03",
+            "loop:   INC $10", // 0x0602
+            "        DEX",
+            "        BNE loop",
+            "        STX $0200", // 0x0607
+            "        BRK",       // 0x060A
+        )
+    }
+
+    #[test]
+    fn test_gdb_handshake_and_registers() {
+        let (mut client, server) = connect(&counter_program());
+
+        let supported = client.command("qSupported:multiprocess+;xmlRegisters=i386");
+        assert!(supported.contains("qXfer:features:read+"), "{supported}");
+        assert_eq!(client.command("?"), "S05");
+        assert_eq!(client.command("qAttached"), "1");
+        assert_eq!(client.command("Hg0"), "OK");
+        assert_eq!(client.command("vMustReplyEmpty"), "");
+
+        let xml = client.command("qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with('l'), "{xml}");
+        assert!(xml.contains(r#"name="pc" bitsize="16""#), "{xml}");
+        // a length running past the end of the address space reads the rest
+        let rest = client.command("qXfer:features:read:target.xml:1,ffffffffffffffff");
+        assert_eq!(rest, format!("l{}", &xml[2..]));
+
+        // A X Y SP P PC(lo hi)
+        assert_eq!(client.command("g"), "000000fd240006");
+        assert_eq!(client.command("p5"), "0006");
+        assert_eq!(client.command("P0=42"), "OK");
+        assert_eq!(client.command("G0102033f25"), "E01");
+        assert_eq!(client.command("G010203fd240206"), "OK");
+        assert_eq!(client.command("g"), "010203fd240206");
+
+        client.send_raw("k");
+        assert_eq!(client.read_byte(), b'+');
+        let server = server.join().unwrap();
+        assert_eq!(server.debugger.cpu.program_counter, 0x0602);
+        assert_eq!(server.debugger.cpu.register_y, 3);
+    }
+
+    #[test]
+    fn test_gdb_memory_read_and_write() {
+        let (mut client, server) = connect(&counter_program());
+
+        assert_eq!(client.command("m600,4"), "a203e610");
+        // writes go through the bus, so RAM mirrors see them
+        assert_eq!(client.command("M10,2:beef"), "OK");
+        assert_eq!(client.command("m810,2"), "beef");
+        assert_eq!(client.command("M10,2:be"), "E01");
+        assert_eq!(client.command("mzz,2"), "E01");
+        // lengths past the address space are refused
+        assert_eq!(client.command("m0,ffffffff"), "E01");
+        assert_eq!(client.command("Z2,0,ffffffff"), "E01");
+        assert_eq!(client.command("mfffe,10001"), "E01");
+        assert_eq!(client.command("mfffe,4").len(), 8);
+
+        assert_eq!(client.command("D"), "OK");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_gdb_breakpoints_step_and_continue() {
+        let (mut client, server) = connect(&counter_program());
+
+        assert_eq!(client.command("Z0,602,1"), "OK");
+        assert_eq!(client.command("c"), "S05");
+        assert_eq!(client.command("p5"), "0206");
+        assert_eq!(client.command("c"), "S05");
+        assert_eq!(client.command("m10,1"), "01");
+
+        assert_eq!(client.command("s"), "S05");
+        assert_eq!(client.command("p5"), "0406");
+
+        assert_eq!(client.command("z0,602,1"), "OK");
+        // runs to BRK
+        assert_eq!(client.command("c"), "S05");
+        assert_eq!(client.command("m10,1"), "03");
+        assert_eq!(client.command("p5"), "0a06");
+
+        assert_eq!(client.command("D"), "OK");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_gdb_watchpoints() {
+        let (mut client, server) = connect(&counter_program());
+
+        assert_eq!(client.command("Z2,200,1"), "OK");
+        assert_eq!(client.command("c"), "T05watch:0200;");
+        assert_eq!(client.command("z2,200,1"), "OK");
+
+        // INC both reads and writes 0x10
+        let (mut client2, server2) = connect(&counter_program());
+        assert_eq!(client2.command("Z3,10,1"), "OK");
+        assert_eq!(client2.command("c"), "T05rwatch:0010;");
+        assert_eq!(client2.command("z3,10,1"), "OK");
+        assert_eq!(client2.command("Z4,f,2"), "OK");
+        assert_eq!(client2.command("c"), "T05awatch:0010;");
+
+        assert_eq!(client.command("D"), "OK");
+        assert_eq!(client2.command("D"), "OK");
+        server.join().unwrap();
+        server2.join().unwrap();
+    }
+
+    #[test]
+    fn test_gdb_illegal_opcode() {
+        let (mut client, server) = connect(&asm!(
+            "        .org $0600",
+            "        LDX #$01",
+            "        .db $02", // KIL
+            "        BRK",
+        ));
+
+        assert_eq!(client.command("c"), "T04");
+        assert_eq!(client.command("p5"), "0206");
+        // the connection survives, and stepping stops on it again
+        assert_eq!(client.command("s"), "T04");
+        assert_eq!(client.command("c603"), "S05");
+
+        assert_eq!(client.command("D"), "OK");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_gdb_interrupt_and_no_ack_mode() {
+        let (mut client, server) = connect(&asm!("        .org $0600", "loop:   JMP loop"));
+
+        assert_eq!(client.command("QStartNoAckMode"), "OK");
+
+        client.send_raw("c");
+        client.stream.write_all(&[0x03]).unwrap();
+        // no more '+' acks from the server
+        assert_eq!(client.read_byte(), b'$');
+        let mut reply = [0; 6];
+        client.stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"S02#b5");
+
+        client.send_raw("p5");
+        assert_eq!(client.read_byte(), b'$');
+        let mut reply = [0; 7];
+        client.stream.read_exact(&mut reply).unwrap();
+        assert!(
+            reply.starts_with(b"0"),
+            "{:?}",
+            String::from_utf8_lossy(&reply)
+        );
+
+        client.send_raw("k");
+        server.join().unwrap();
+    }
+}
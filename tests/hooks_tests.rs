@@ -0,0 +1,188 @@
+//! Execution hooks tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{ines, load_cartridge, subroutine_program};
+    use std::sync::{Arc, Mutex};
+
+    use nes_emulator::Mem;
+    use nes_emulator::asm;
+    use nes_emulator::bus::Access;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::cpu::hooks::{HookAction, Interrupt};
+    use nes_emulator::debugger::{Debugger, StopReason};
+
+    fn cpu_with(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load_at(0x0600, program);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_pc_hook_stops_before_instruction() {
+        let mut cpu = cpu_with(&subroutine_program());
+        let hits = Arc::new(Mutex::new(0));
+        let counter = hits.clone();
+        cpu.add_pc_hook(0x060b, move |cpu| {
+            *counter.lock().unwrap() += 1;
+            assert_eq!(cpu.program_counter, 0x060b);
+            HookAction::Stop
+        });
+
+        cpu.run();
+        assert!(cpu.stopped_by_hook());
+        assert_eq!(cpu.program_counter, 0x060b);
+        assert_eq!(cpu.register_x, 0);
+
+        // resuming runs the instruction instead of stopping on it again (the
+        // subroutine counts X up twice before it's called again)
+        cpu.run();
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(*hits.lock().unwrap(), 2);
+
+        cpu.run();
+        assert!(!cpu.stopped_by_hook());
+        assert_eq!(cpu.mem_peek(0x10), 4);
+        assert_eq!(*hits.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_pc_hook_can_modify_state() {
+        let mut cpu = cpu_with(&subroutine_program());
+        // patch the subroutine out: pretend it ran by adding 10 to X and
+        // jumping to its RTS
+        cpu.add_pc_hook(0x060b, |cpu| {
+            cpu.register_x += 10;
+            cpu.program_counter = 0x060f;
+            HookAction::Continue
+        });
+        cpu.run();
+        assert_eq!(cpu.mem_peek(0x10), 20);
+    }
+
+    #[test]
+    fn test_memory_hooks_and_removal() {
+        let mut cpu = cpu_with(&asm!(
+            "        .org $0600",
+            "        LDA #$07",
+            "        STA $0810", // mirror of 0x0010
+            "        LDA $11",
+            "        STA $12",
+            "        BRK",
+        ));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let writes = log.clone();
+        let write_hook = cpu.add_write_hook(0x0010..=0x0011, move |_, hit| {
+            writes.lock().unwrap().push(hit);
+            HookAction::Continue
+        });
+        let reads = log.clone();
+        cpu.add_read_hook(0x0000..=0x00ff, move |cpu, hit| {
+            reads.lock().unwrap().push(hit);
+            // hooks can change state: make the next store write 0x42
+            cpu.register_a = 0x42;
+            HookAction::Stop
+        });
+
+        cpu.run();
+        assert!(cpu.stopped_by_hook());
+        assert_eq!(cpu.program_counter, 0x0607);
+        cpu.run();
+        assert_eq!(cpu.mem_peek(0x12), 0x42);
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 2, "{log:?}");
+        assert_eq!(
+            (log[0].addr, log[0].access, log[0].value),
+            (0x10, Access::Write, 7)
+        );
+        assert_eq!((log[1].addr, log[1].access), (0x11, Access::Read));
+        drop(log);
+
+        assert!(cpu.remove_hook(write_hook));
+        assert!(!cpu.remove_hook(write_hook));
+    }
+
+    #[test]
+    fn test_interrupt_and_frame_hooks() {
+        // a cartridge of zeros, so the NMI vector reads as 0x0000 and that's
+        // where the handler lives
+        let mut cpu = CPU::with_bus(load_cartridge(&ines(0, 0, &[0; 0x8000], &[0; 0x2000])));
+        cpu.load_at(0x0000, &asm!("INC $10", "RTI"));
+        cpu.load_at(
+            0x0600,
+            &asm!(
+                "        .org $0600",
+                "        LDA #$80",
+                "        STA $2000",
+                "loop:   JMP loop",
+            ),
+        );
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let interrupts = events.clone();
+        cpu.add_interrupt_hook(move |cpu, interrupt| {
+            assert_eq!(interrupt, Interrupt::Nmi);
+            assert_eq!(cpu.program_counter, 0x0000);
+            interrupts
+                .lock()
+                .unwrap()
+                .push(format!("nmi {}", cpu.bus.ppu().frame()));
+            HookAction::Continue
+        });
+        let frames = events.clone();
+        cpu.add_frame_hook(move |_, frame| {
+            frames.lock().unwrap().push(format!("frame {frame}"));
+            if frame == 2 {
+                HookAction::Stop
+            } else {
+                HookAction::Continue
+            }
+        });
+
+        cpu.run();
+        assert!(cpu.stopped_by_hook());
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["nmi 0", "frame 1", "nmi 1", "frame 2"]
+        );
+        assert_eq!(cpu.mem_peek(0x10), 2);
+    }
+
+    #[test]
+    fn test_illegal_opcode_hook() {
+        let mut cpu = cpu_with(&[0xa2, 0x01, 0x02, 0xe8, 0x02, 0x00]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let opcodes = seen.clone();
+        cpu.add_illegal_opcode_hook(move |cpu, opcode| {
+            opcodes.lock().unwrap().push((cpu.program_counter, opcode));
+            HookAction::Continue
+        });
+        // illegal opcodes are skipped instead of panicking
+        cpu.run();
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(*seen.lock().unwrap(), [(0x0602, 0x02), (0x0604, 0x02)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Illegal instruction")]
+    fn test_illegal_opcode_without_hook_panics() {
+        let mut cpu = cpu_with(&[0x02]);
+        cpu.run();
+    }
+
+    #[test]
+    fn test_debugger_stops_on_hook() {
+        let mut cpu = cpu_with(&subroutine_program());
+        cpu.add_write_hook(0x0010..=0x0010, |_, _| HookAction::Stop);
+        let mut debugger = Debugger::new(cpu);
+        assert_eq!(debugger.resume(), StopReason::Hook);
+        assert_eq!(debugger.cpu.program_counter, 0x060a);
+        assert_eq!(debugger.resume(), StopReason::Brk);
+    }
+}
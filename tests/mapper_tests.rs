@@ -0,0 +1,291 @@
+//! Mapper (discrete logic boards, bank switching and bus conflicts) tests
+//! reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{ines, load_cartridge, numbered_chr};
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::ppu::Mirroring;
+    use nes_emulator::rom::Rom;
+
+    /// Every 8 KiB of PRG ROM is filled with its bank number, except for its
+    /// last 256 bytes, which count up from 0 so every value can be written
+    /// somewhere without a bus conflict (like games do it)
+    fn prg(pages: usize) -> Vec<u8> {
+        (0..pages * 0x4000)
+            .map(|offset| {
+                if offset % 0x2000 >= 0x1F00 {
+                    offset as u8
+                } else {
+                    (offset / 0x2000) as u8
+                }
+            })
+            .collect()
+    }
+
+    /// A cartridge with `prg_pages` 16 KiB pages of PRG ROM (see `prg`) and
+    /// `chr_pages` 8 KiB pages of CHR ROM (see `numbered_chr`)
+    fn cartridge(mapper: u8, prg_pages: usize, chr_pages: usize) -> Bus {
+        load_cartridge(&ines(mapper, 0, &prg(prg_pages), &numbered_chr(chr_pages)))
+    }
+
+    /// The 8 KiB PRG bank at `addr`
+    fn prg_bank(bus: &Bus, addr: u16) -> u8 {
+        bus.mem_peek(addr)
+    }
+
+    /// The 1 KiB CHR bank at `addr` in the pattern tables
+    fn chr_bank(bus: &Bus, addr: u16) -> u8 {
+        let ppu = bus.ppu();
+        ppu.chr_rom[ppu.chr_offset(addr)]
+    }
+
+    /// Writes `value` to the address in the 0xFF00 page that holds it, so
+    /// boards with bus conflicts see it as is
+    fn write_register(bus: &mut Bus, value: u8) {
+        bus.mem_write(0xFF00 | value as u16, value);
+    }
+
+    #[test]
+    fn test_nrom() {
+        let mut bus = cartridge(0, 1, 1);
+        // 16 KiB is mirrored into both halves
+        assert_eq!(prg_bank(&bus, 0x8000), 0);
+        assert_eq!(prg_bank(&bus, 0xA000), 1);
+        assert_eq!(prg_bank(&bus, 0xC000), 0);
+        assert_eq!(bus.prg_offset(0xE000), Some(0x2000));
+        // writes to ROM don't switch anything, PRG RAM works
+        bus.mem_write(0x8000, 0xFF);
+        assert_eq!(prg_bank(&bus, 0x8000), 0);
+        bus.mem_write(0x6123, 0x42);
+        assert_eq!(bus.mem_read(0x6123), 0x42);
+        assert_eq!(bus.prg_ram()[0x123], 0x42);
+
+        let raw = ines(4, 0, &prg(1), &numbered_chr(1));
+        assert_eq!(
+            Bus::with_rom(Rom::new(&raw).unwrap()).err().unwrap(),
+            "Mapper 4 is not supported yet"
+        );
+    }
+
+    #[test]
+    fn test_axrom() {
+        let mut bus = cartridge(7, 8, 0);
+        assert_eq!(prg_bank(&bus, 0x8000), 0);
+        assert_eq!(bus.ppu().mirroring, Mirroring::SingleScreenLower);
+
+        // no bus conflicts: 0x8000 holds 0
+        bus.mem_write(0x8000, 0x13);
+        assert_eq!(prg_bank(&bus, 0x8000), 12);
+        assert_eq!(prg_bank(&bus, 0xE000), 15);
+        assert_eq!(bus.prg_offset(0x8000), Some(3 * 0x8000));
+        assert_eq!(bus.ppu().mirroring, Mirroring::SingleScreenUpper);
+
+        // all 4 name tables show the same KiB of VRAM
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_write(0x2007, 0x55);
+        bus.mem_write(0x2006, 0x2C);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_read(0x2007);
+        assert_eq!(bus.mem_read(0x2007), 0x55);
+        assert_eq!(bus.ppu().vram[0x400], 0x55);
+
+        // the bank number wraps around the size of the ROM
+        bus.mem_write(0x8000, 0x07);
+        assert_eq!(prg_bank(&bus, 0x8000), 12);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // Color Dreams: PRG in bits 0 - 1, CHR in bits 4 - 7
+        let mut bus = cartridge(11, 8, 16);
+        bus.mem_write(0x8000, 0x32);
+        assert_eq!(prg_bank(&bus, 0x8000), 0, "0x8000 holds 0");
+        write_register(&mut bus, 0x32);
+        assert_eq!(prg_bank(&bus, 0x8000), 8);
+        assert_eq!(chr_bank(&bus, 0x0000), 24);
+        assert_eq!(chr_bank(&bus, 0x1C00), 31);
+        // a write where ROM holds 0x13 only keeps the bits both have
+        bus.mem_write(0xFF13, 0x31);
+        assert_eq!(prg_bank(&bus, 0x8000), 4);
+        assert_eq!(chr_bank(&bus, 0x0000), 8);
+
+        // GxROM: PRG in bits 4 - 5, CHR in bits 0 - 1
+        let mut bus = cartridge(66, 8, 4);
+        bus.mem_write(0x8000, 0x21);
+        assert_eq!(prg_bank(&bus, 0x8000), 0);
+        write_register(&mut bus, 0x21);
+        assert_eq!(prg_bank(&bus, 0x8000), 8);
+        assert_eq!(chr_bank(&bus, 0x0400), 9);
+
+        // BNROM: the whole value picks the PRG bank
+        let mut bus = cartridge(34, 8, 0);
+        write_register(&mut bus, 2);
+        assert_eq!(prg_bank(&bus, 0xC000), 10);
+        // 0x8000 holds 8 now, which has no bits in common with 3
+        bus.mem_write(0x8000, 3);
+        assert_eq!(prg_bank(&bus, 0xC000), 2);
+
+        // AxROM only has them when NES 2.0 says it's ANROM, AMROM or AOROM
+        // (submapper 2)
+        let mut raw = ines(7, 0, &prg(8), &[]);
+        raw[7] |= 0x08;
+        raw[8] = 2 << 4;
+        let mut bus = load_cartridge(&raw);
+        bus.mem_write(0x8000, 0x13);
+        assert_eq!(prg_bank(&bus, 0x8000), 0, "0x8000 holds 0");
+        write_register(&mut bus, 0x13);
+        assert_eq!(prg_bank(&bus, 0x8000), 12);
+        assert_eq!(bus.ppu().mirroring, Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_nina_001() {
+        // mapper 34 with more than 8 KiB of CHR ROM is NINA-001
+        let mut bus = cartridge(34, 4, 4);
+        bus.mem_write(0x7FFD, 1);
+        assert_eq!(prg_bank(&bus, 0x8000), 4);
+        bus.mem_write(0x7FFE, 3);
+        bus.mem_write(0x7FFF, 6);
+        assert_eq!(chr_bank(&bus, 0x0000), 12);
+        assert_eq!(chr_bank(&bus, 0x0C00), 15);
+        assert_eq!(chr_bank(&bus, 0x1000), 24);
+        // the registers are in PRG RAM, which still works
+        assert_eq!(bus.mem_read(0x7FFE), 3);
+        bus.mem_write(0x6000, 0x99);
+        assert_eq!(bus.mem_read(0x6000), 0x99);
+        // writes to ROM don't do anything
+        bus.mem_write(0x8000, 0);
+        assert_eq!(prg_bank(&bus, 0x8000), 4);
+    }
+
+    #[test]
+    fn test_mapper_34_submappers() {
+        let mapper_34 = |submapper: u8, chr_pages| {
+            let mut raw = ines(34, 0, &prg(4), &numbered_chr(chr_pages));
+            raw[7] |= 0x08;
+            raw[8] = submapper << 4;
+            load_cartridge(&raw)
+        };
+        // NINA-001 with just 8 KiB of CHR ROM
+        let mut bus = mapper_34(1, 1);
+        bus.mem_write(0x7FFD, 1);
+        assert_eq!(prg_bank(&bus, 0x8000), 4);
+        // BNROM with more than that
+        let mut bus = mapper_34(2, 2);
+        bus.mem_write(0x7FFD, 1);
+        assert_eq!(prg_bank(&bus, 0x8000), 0);
+        write_register(&mut bus, 1);
+        assert_eq!(prg_bank(&bus, 0x8000), 4);
+    }
+
+    #[test]
+    fn test_camerica() {
+        let raw = ines(71, 1, &prg(8), &[]);
+        let mut bus = Bus::with_rom(Rom::new(&raw).unwrap()).unwrap();
+        // the last 16 KiB are fixed at 0xC000
+        assert_eq!(prg_bank(&bus, 0x8000), 0);
+        assert_eq!(prg_bank(&bus, 0xC000), 14);
+        assert_eq!(bus.ppu().mirroring, Mirroring::Vertical);
+
+        bus.mem_write(0xC000, 3);
+        assert_eq!(prg_bank(&bus, 0x8000), 6);
+        assert_eq!(prg_bank(&bus, 0xA000), 7);
+        assert_eq!(prg_bank(&bus, 0xE000), 15);
+        // writes to 0x8000 - 0x8FFF don't do anything (some games write
+        // there), writes to 0x9000 - 0x9FFF pick the single screen
+        bus.mem_write(0x8000, 0x10);
+        assert_eq!(bus.ppu().mirroring, Mirroring::Vertical);
+        bus.mem_write(0x9000, 0x10);
+        assert_eq!(bus.ppu().mirroring, Mirroring::SingleScreenUpper);
+        bus.mem_write(0x9FFF, 0x00);
+        assert_eq!(bus.ppu().mirroring, Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn test_registers_below_prg_rom() {
+        // NINA-03/06: 0x4100 - 0x5FFF with A8 set
+        let mut bus = cartridge(79, 4, 8);
+        bus.mem_write(0x40FF, 0x0D);
+        assert_eq!(prg_bank(&bus, 0x8000), 0);
+        bus.mem_write(0x5100, 0x0D);
+        assert_eq!(prg_bank(&bus, 0x8000), 4);
+        assert_eq!(chr_bank(&bus, 0x0000), 40);
+        bus.mem_write(0x4200, 0x00);
+        assert_eq!(prg_bank(&bus, 0x8000), 4);
+
+        // 87: bits 0 and 1 of the CHR bank are swapped, PRG is fixed
+        let mut bus = cartridge(87, 2, 4);
+        bus.mem_write(0x6000, 0b01);
+        assert_eq!(chr_bank(&bus, 0x0000), 16);
+        bus.mem_write(0x7FFF, 0b10);
+        assert_eq!(chr_bank(&bus, 0x0000), 8);
+        assert_eq!(prg_bank(&bus, 0xC000), 2);
+        // no PRG RAM, the register is there (and write only, reading it is
+        // open bus: the last value written)
+        assert!(bus.prg_ram().is_empty());
+        assert_eq!(bus.mem_read(0x6000), 0b10);
+
+        // 140: PRG in bits 4 - 5, CHR in bits 0 - 3
+        let mut bus = cartridge(140, 8, 16);
+        bus.mem_write(0x6000, 0x2F);
+        assert_eq!(prg_bank(&bus, 0x8000), 8);
+        assert_eq!(chr_bank(&bus, 0x1400), 125);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        // with nothing in cartridge space, reads get the last value that
+        // went over the bus
+        let mut bus = Bus::new();
+        bus.mem_write(0x0000, 0x42);
+        assert_eq!(bus.mem_read(0x8000), 0x42);
+        assert_eq!(bus.mem_read(0x4018), 0x42);
+        bus.mem_write(0x0001, 0x17);
+        assert_eq!(bus.mem_read(0x0001), 0x17);
+        assert_eq!(bus.mem_peek(0xFFFC), 0x17);
+        bus.mem_write(0x8000, 0x99);
+        assert_eq!(bus.mem_read(0x5000), 0x99);
+
+        // the same goes for where a board has nothing (NROM's PRG RAM is
+        // there, 0x4020 - 0x5FFF isn't)
+        let mut bus = cartridge(0, 1, 1);
+        assert_eq!(bus.mem_read(0x8000), 0);
+        bus.mem_write(0x6000, 0x33);
+        assert_eq!(bus.mem_read(0x5000), 0x33);
+    }
+
+    #[test]
+    fn test_chr_banks_are_drawn_with() {
+        let mut bus = cartridge(66, 2, 4);
+        write_register(&mut bus, 0x02);
+        // PPUDATA reads go through the banks too
+        bus.mem_write(0x2006, 0x04);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_read(0x2007);
+        assert_eq!(bus.mem_read(0x2007), 17);
+    }
+
+    #[test]
+    fn test_banks_in_savestates_and_power_cycles() {
+        let raw = ines(7, 0, &prg(8), &[]);
+        let mut cpu = CPU::with_bus(Bus::with_rom(Rom::new(&raw).unwrap()).unwrap());
+        cpu.bus.mem_write(0x8000, 0x12);
+        let state = cpu.save_state();
+
+        cpu.bus.mem_write(0x8000, 0x01);
+        assert_eq!(prg_bank(&cpu.bus, 0x8000), 4);
+        cpu.load_state(&state).unwrap();
+        assert_eq!(prg_bank(&cpu.bus, 0x8000), 8);
+        assert_eq!(cpu.bus.ppu().mirroring, Mirroring::SingleScreenUpper);
+
+        cpu.power_cycle();
+        assert_eq!(prg_bank(&cpu.bus, 0x8000), 0);
+        assert_eq!(cpu.bus.ppu().mirroring, Mirroring::SingleScreenLower);
+    }
+}
@@ -0,0 +1,253 @@
+//! MMC5 (banking modes, ExRAM, extended video, scanline IRQ, multiplier and
+//! expansion audio) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{ines, load_cartridge, numbered_chr, numbered_prg};
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+
+    /// An MMC5 cartridge with 128 KiB of PRG ROM (every 8 KiB filled with its
+    /// bank number) and 64 KiB of CHR ROM (every KiB filled with its number)
+    fn mmc5() -> Bus {
+        let mut bus = load_cartridge(&ines(5, 0, &numbered_prg(8), &numbered_chr(8)));
+        // keep the APU's frame IRQ out of the way
+        bus.mem_write(0x4017, 0x40);
+        bus
+    }
+
+    fn prg_banks(bus: &Bus) -> Vec<u8> {
+        [0x8000, 0xA000, 0xC000, 0xE000]
+            .iter()
+            .map(|&addr| bus.mem_peek(addr))
+            .collect()
+    }
+
+    fn chr_banks(bus: &Bus) -> Vec<u8> {
+        let ppu = bus.ppu();
+        (0..8)
+            .map(|slot| ppu.chr_rom[ppu.chr_offset(slot * 0x400)])
+            .collect()
+    }
+
+    /// Runs the bus (and so the PPU) until it's on `scanline`
+    fn run_to_scanline(bus: &mut Bus, scanline: u16) {
+        while bus.ppu().scanline() != scanline {
+            bus.tick(1);
+        }
+    }
+
+    fn read_vram(bus: &mut Bus, addr: u16) -> u8 {
+        bus.mem_write(0x2006, (addr >> 8) as u8);
+        bus.mem_write(0x2006, addr as u8);
+        // the first read only fills the buffer
+        bus.mem_read(0x2007);
+        bus.mem_read(0x2007)
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut bus = mmc5();
+        // mode 3 at power on, with the last bank at 0xE000
+        assert_eq!(prg_banks(&bus)[3], 15);
+
+        bus.mem_write(0x5100, 3);
+        for (register, bank) in (0x5114..=0x5117).zip([0x85, 0x82, 0x87, 0x03]) {
+            bus.mem_write(register, bank);
+        }
+        assert_eq!(prg_banks(&bus), [5, 2, 7, 3]);
+
+        // 16 KiB banks ignore the low bit
+        bus.mem_write(0x5100, 1);
+        assert_eq!(prg_banks(&bus), [2, 3, 2, 3]);
+        bus.mem_write(0x5100, 2);
+        assert_eq!(prg_banks(&bus), [2, 3, 7, 3]);
+        // and 32 KiB banks the low 2 bits
+        bus.mem_write(0x5100, 0);
+        bus.mem_write(0x5117, 0x06);
+        assert_eq!(prg_banks(&bus), [4, 5, 6, 7]);
+        assert_eq!(bus.prg_offset(0xA000), Some(5 * 0x2000));
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut bus = mmc5();
+        // writes are ignored until both protect registers are unlocked
+        bus.mem_write(0x6000, 0x42);
+        assert_eq!(bus.mem_read(0x6000), 0);
+        bus.mem_write(0x5102, 2);
+        bus.mem_write(0x5103, 1);
+        bus.mem_write(0x6000, 0x42);
+        assert_eq!(bus.mem_read(0x6000), 0x42);
+
+        // another RAM bank at 0x6000, and RAM banked into 0x8000
+        bus.mem_write(0x5113, 3);
+        bus.mem_write(0x6001, 0x43);
+        assert_eq!(bus.prg_ram()[3 * 0x2000 + 1], 0x43);
+        bus.mem_write(0x5114, 0x03);
+        assert_eq!(bus.mem_read(0x8001), 0x43);
+        assert_eq!(bus.prg_offset(0x8001), None);
+        bus.mem_write(0x8002, 0x44);
+        assert_eq!(bus.mem_read(0x6002), 0x44);
+        // the last bank is always ROM
+        bus.mem_write(0x5117, 0x00);
+        bus.mem_write(0xE000, 0x45);
+        assert_eq!(bus.mem_read(0xE000), 0);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut bus = mmc5();
+        bus.mem_write(0x5101, 3);
+        for (register, bank) in (0x5120..=0x5127).zip([9, 8, 7, 6, 5, 4, 3, 2]) {
+            bus.mem_write(register, bank);
+        }
+        assert_eq!(chr_banks(&bus), [9, 8, 7, 6, 5, 4, 3, 2]);
+        bus.mem_write(0x5101, 2);
+        assert_eq!(chr_banks(&bus), [16, 17, 12, 13, 8, 9, 4, 5]);
+        bus.mem_write(0x5101, 1);
+        assert_eq!(chr_banks(&bus), [24, 25, 26, 27, 8, 9, 10, 11]);
+        bus.mem_write(0x5101, 0);
+        assert_eq!(chr_banks(&bus), [16, 17, 18, 19, 20, 21, 22, 23]);
+
+        // the upper bits are taken at the time of the write (and wrap
+        // around the 64 banks there are)
+        bus.mem_write(0x5130, 1);
+        bus.mem_write(0x5127, 1);
+        bus.mem_write(0x5130, 0);
+        assert_eq!(chr_banks(&bus)[0], (0x101 * 8 % 64) as u8);
+    }
+
+    #[test]
+    fn test_ex_ram_and_name_tables() {
+        let mut bus = mmc5();
+        // mode 2 is plain RAM
+        bus.mem_write(0x5104, 2);
+        bus.mem_write(0x5C10, 0x42);
+        assert_eq!(bus.mem_read(0x5C10), 0x42);
+        // mode 3 is read only
+        bus.mem_write(0x5104, 3);
+        bus.mem_write(0x5C10, 0x43);
+        assert_eq!(bus.mem_read(0x5C10), 0x42);
+        // modes 0 and 1 can't be read, and store 0 outside of rendering
+        bus.mem_write(0x5104, 0);
+        assert_eq!(bus.mem_read(0x5C10), 0);
+        bus.mem_write(0x5C10, 0x44);
+        bus.mem_write(0x5104, 2);
+        assert_eq!(bus.mem_read(0x5C10), 0);
+
+        // name tables: VRAM A, VRAM B, ExRAM and fill
+        bus.mem_write(0x5C20, 0x55);
+        bus.mem_write(0x5104, 0);
+        bus.mem_write(0x5106, 0x77);
+        bus.mem_write(0x5107, 2);
+        bus.mem_write(0x5105, 0b11_10_01_00);
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2007, 0x11);
+        bus.mem_write(0x2006, 0x24);
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2007, 0x22);
+        assert_eq!(read_vram(&mut bus, 0x2020), 0x11);
+        assert_eq!(read_vram(&mut bus, 0x2420), 0x22);
+        assert_eq!(read_vram(&mut bus, 0x2820), 0x55);
+        assert_eq!(read_vram(&mut bus, 0x2C20), 0x77);
+        assert_eq!(read_vram(&mut bus, 0x2FC0), 0b10_10_10_10);
+        // VRAM B is where the PPU mirrors the second name table to
+        bus.mem_write(0x5105, 0b01_01_01_01);
+        assert_eq!(read_vram(&mut bus, 0x2020), 0x22);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut bus = mmc5();
+        bus.mem_write(0x5203, 100);
+        bus.mem_write(0x5204, 0x80);
+        // nothing happens while the PPU isn't rendering
+        run_to_scanline(&mut bus, 120);
+        assert!(!bus.irq_pending());
+        assert_eq!(bus.mem_read(0x5204) & 0x40, 0);
+
+        bus.mem_write(0x2001, 0x18);
+        run_to_scanline(&mut bus, 0);
+        run_to_scanline(&mut bus, 99);
+        assert!(!bus.irq_pending());
+        assert_eq!(bus.mem_peek(0x5204), 0x40);
+        run_to_scanline(&mut bus, 100);
+        assert!(bus.irq_pending());
+        // reading the status acknowledges it
+        assert_eq!(bus.mem_read(0x5204), 0xC0);
+        assert!(!bus.irq_pending());
+
+        // and it drops out of frame in vertical blank
+        run_to_scanline(&mut bus, 241);
+        assert_eq!(bus.mem_read(0x5204), 0);
+        // with the IRQ disabled the flag is still set
+        bus.mem_write(0x5204, 0);
+        run_to_scanline(&mut bus, 101);
+        assert!(!bus.irq_pending());
+        assert_eq!(bus.mem_read(0x5204), 0xC0);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut bus = mmc5();
+        assert_eq!(bus.mem_read(0x5205), 0x01);
+        assert_eq!(bus.mem_read(0x5206), 0xFE);
+        bus.mem_write(0x5205, 200);
+        bus.mem_write(0x5206, 123);
+        assert_eq!(bus.mem_read(0x5205), (200 * 123) as u8);
+        assert_eq!(bus.mem_read(0x5206), ((200 * 123) >> 8) as u8);
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let mut bus = mmc5();
+        bus.record_audio(true);
+        // a pulse at full volume, and its length counter
+        bus.mem_write(0x5015, 0b01);
+        bus.mem_write(0x5000, 0b1011_1111);
+        bus.mem_write(0x5002, 0xFD);
+        bus.mem_write(0x5003, 0x08);
+        assert_eq!(bus.mem_read(0x5015), 0b01);
+        bus.tick(30_000);
+        let samples = bus.take_audio();
+        assert!(samples.iter().any(|&sample| sample > 0.05));
+
+        // the PCM channel's raw level
+        bus.mem_write(0x5015, 0);
+        bus.mem_write(0x5011, 0xFF);
+        bus.tick(1000);
+        let samples = bus.take_audio();
+        assert!(samples.last().unwrap() > &0.1);
+
+        // in read mode, reading a 0 raises the IRQ
+        bus.mem_write(0x5010, 0x81);
+        bus.mem_write(0x5114, 0x80);
+        assert!(!bus.irq_pending());
+        bus.mem_read(0x8000);
+        assert!(bus.irq_pending());
+        assert_eq!(bus.mem_read(0x5010), 0x80);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_savestate() {
+        let mut cpu = CPU::with_bus(mmc5());
+        cpu.mem_write(0x5104, 2);
+        cpu.mem_write(0x5C00, 0x42);
+        cpu.mem_write(0x5114, 0x85);
+        cpu.mem_write(0x5101, 3);
+        cpu.mem_write(0x5120, 9);
+        let state = cpu.save_state();
+
+        let mut other = CPU::with_bus(mmc5());
+        other.load_state(&state).unwrap();
+        assert_eq!(other.mem_read(0x5C00), 0x42);
+        assert_eq!(prg_banks(&other.bus)[0], 5);
+        assert_eq!(chr_banks(&other.bus)[0], 9);
+    }
+}
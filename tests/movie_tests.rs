@@ -0,0 +1,242 @@
+//! Controller, savestate and movie (FM2) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{controller_program, machine, run_frames};
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::cpu::hooks::HookAction;
+    use nes_emulator::gamepad::{BUTTON_A, RIGHT, START, UP};
+    use nes_emulator::hash::md5;
+    use nes_emulator::movie::{
+        COMMAND_HARD_RESET, COMMAND_SOFT_RESET, FrameInput, Movie, MoviePlayer, MovieRecorder,
+    };
+
+    /// Changes what's held on controller 1 every frame
+    fn drive_input(cpu: &mut CPU) {
+        let pattern = |frame: u64| (frame * 37 % 256) as u8;
+        cpu.bus.gamepad_mut(0).set_buttons(pattern(0));
+        cpu.add_frame_hook(move |cpu, frame| {
+            cpu.bus.gamepad_mut(0).set_buttons(pattern(frame));
+            HookAction::Continue
+        });
+    }
+
+    #[test]
+    fn test_md5() {
+        let hex = |digest: [u8; 16]| {
+            digest
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+        };
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(md5(b"The quick brown fox jumps over the lazy dog")),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        assert_eq!(
+            hex(md5(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[test]
+    fn test_gamepad_reads() {
+        let mut bus = Bus::new();
+        bus.gamepad_mut(0).set_buttons(BUTTON_A | START | RIGHT);
+        bus.gamepad_mut(1).set_button(UP, true);
+
+        // while strobing, reads keep returning A
+        bus.mem_write(0x4016, 1);
+        assert_eq!(bus.mem_read(0x4016), 1);
+        assert_eq!(bus.mem_read(0x4016), 1);
+        bus.mem_write(0x4016, 0);
+
+        let port_1 = (0..10).map(|_| bus.mem_read(0x4016)).collect::<Vec<_>>();
+        assert_eq!(port_1, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+        let port_2 = (0..8).map(|_| bus.mem_read(0x4017)).collect::<Vec<_>>();
+        assert_eq!(port_2, [0, 0, 0, 0, 1, 0, 0, 0]);
+
+        bus.gamepad_mut(1).set_button(UP, false);
+        assert_eq!(bus.gamepad(1).buttons(), 0);
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let text = "version 3\n\
+                    emuVersion 22020\n\
+                    rerecordCount 12\n\
+                    palFlag 0\n\
+                    romFilename game.nes\n\
+                    romChecksum base64:jmY2nqnWe4GoHO6bIQiVKA==\n\
+                    guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n\
+                    fourscore 0\n\
+                    microphone 0\n\
+                    port0 1\n\
+                    port1 0\n\
+                    port2 0\n\
+                    FDS 0\n\
+                    NewPPU 0\n\
+                    comment author Someone\n\
+                    |0|....T..A|||\n\
+                    |1|R.......|||\n\
+                    |2|........|||\n";
+        let movie = Movie::parse(text).unwrap();
+        assert_eq!(movie.rom_filename, "game.nes");
+        assert_eq!(movie.rom_checksum[..3], [0x8e, 0x66, 0x36]);
+        assert_eq!(movie.rerecord_count, 12);
+        assert_eq!(movie.gamepads, [true, false]);
+        assert_eq!(movie.comments, ["author Someone"]);
+        assert_eq!(
+            movie.frames,
+            [
+                FrameInput {
+                    commands: 0,
+                    gamepads: [START | BUTTON_A, 0]
+                },
+                FrameInput {
+                    commands: COMMAND_SOFT_RESET,
+                    gamepads: [RIGHT, 0]
+                },
+                FrameInput {
+                    commands: COMMAND_HARD_RESET,
+                    gamepads: [0, 0]
+                },
+            ]
+        );
+        assert_eq!(movie.to_fm2(), text);
+
+        // hex checksums and buttons written as letters other than RLDUTSBA
+        let movie = Movie::parse(
+            "version 3\n\
+             romChecksum 0x000102030405060708090a0b0c0d0e0f\n\
+             |0|xxxxxxxx|..... ..|\n",
+        )
+        .unwrap();
+        assert_eq!(movie.rom_checksum[15], 0x0f);
+        assert_eq!(movie.frames[0].gamepads, [0xff, 0]);
+
+        assert!(Movie::parse("|0|........|||").is_err());
+        assert!(Movie::parse("version 2").is_err());
+        assert!(Movie::parse("version 3\nfourscore 1").is_err());
+        assert_eq!(
+            Movie::parse("version 3\n|0|.......|||").unwrap_err(),
+            "line 2: Invalid controller input ......."
+        );
+    }
+
+    #[test]
+    fn test_savestates() {
+        let mut cpu = machine(&controller_program());
+        drive_input(&mut cpu);
+        run_frames(&mut cpu, 5);
+        let state = cpu.save_state();
+        let ram = cpu.bus.ram().to_vec();
+        let pc = cpu.program_counter;
+        let frame = cpu.bus.ppu().frame();
+
+        run_frames(&mut cpu, 5);
+        assert_ne!(cpu.bus.ram(), ram);
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.bus.ram(), ram);
+        assert_eq!(cpu.program_counter, pc);
+        assert_eq!(cpu.bus.ppu().frame(), frame);
+        assert_eq!(cpu.save_state(), state);
+
+        // bad savestates leave the machine alone
+        assert!(cpu.load_state(&state[..state.len() - 1]).is_err());
+        assert!(cpu.load_state(b"garbage").is_err());
+        assert_eq!(cpu.save_state(), state);
+
+        let mut other_game = controller_program();
+        other_game[0] = 0xea;
+        let mut other = machine(&other_game);
+        assert_eq!(
+            other.load_state(&state).unwrap_err(),
+            "Savestate is for a different ROM"
+        );
+    }
+
+    #[test]
+    fn test_record_and_play_back() {
+        let prg = controller_program();
+        let mut cpu = machine(&prg);
+        let recorder = MovieRecorder::start(&mut cpu, "controller.nes", true).unwrap();
+        drive_input(&mut cpu);
+        run_frames(&mut cpu, 10);
+        recorder.soft_reset();
+        run_frames(&mut cpu, 20);
+        let movie = recorder.finish(&mut cpu);
+
+        assert_eq!(movie.frames.len(), 30);
+        assert_eq!(movie.frames[11].commands, COMMAND_SOFT_RESET);
+        assert_eq!(movie.frames[3].gamepads[0], 3 * 37);
+        // power on, then the reset button
+        assert_eq!(cpu.mem_peek(0x03), 2);
+
+        // play it back from the file on a machine that has run a while
+        let mut replay = machine(&prg);
+        run_frames(&mut replay, 3);
+        let movie = Movie::parse(&movie.to_fm2()).unwrap();
+        let player = MoviePlayer::start(&mut replay, movie).unwrap();
+        replay.run();
+        assert!(player.is_finished());
+        assert_eq!(player.frame(), 30);
+        assert_eq!(replay.bus.ram(), cpu.bus.ram());
+        assert_eq!(replay.program_counter, cpu.program_counter);
+        assert_eq!(replay.bus.cycles(), cpu.bus.cycles());
+
+        // once the movie is over the machine runs on normally
+        let movie = player.stop(&mut replay);
+        assert_eq!(movie.frames.len(), 30);
+        run_frames(&mut replay, 1);
+    }
+
+    #[test]
+    fn test_movie_from_savestate() {
+        let prg = controller_program();
+        let mut cpu = machine(&prg);
+        cpu.bus.gamepad_mut(0).set_buttons(START);
+        run_frames(&mut cpu, 7);
+        let recorder = MovieRecorder::start(&mut cpu, "controller.nes", false).unwrap();
+        drive_input(&mut cpu);
+        recorder.hard_reset();
+        run_frames(&mut cpu, 12);
+        let movie = recorder.finish(&mut cpu);
+        assert!(movie.savestate.is_some());
+        assert_eq!(movie.frames[1].commands, COMMAND_HARD_RESET);
+
+        let mut replay = machine(&prg);
+        let player = MoviePlayer::start(&mut replay, movie).unwrap();
+        replay.run();
+        assert!(player.is_finished());
+        assert_eq!(replay.bus.ram(), cpu.bus.ram());
+        assert_eq!(replay.mem_peek(0x03), 1);
+    }
+
+    #[test]
+    fn test_movie_desync_detection() {
+        let mut cpu = machine(&controller_program());
+        let recorder = MovieRecorder::start(&mut cpu, "controller.nes", true).unwrap();
+        run_frames(&mut cpu, 2);
+        let movie = recorder.finish(&mut cpu);
+        assert_eq!(movie.rom_checksum, cpu.bus.rom_checksum().unwrap());
+
+        let mut other_game = controller_program();
+        other_game[0x10] ^= 0xff;
+        let mut other = machine(&other_game);
+        let err = MoviePlayer::start(&mut other, movie.clone()).err().unwrap();
+        assert!(err.starts_with("Movie desync"), "{err}");
+
+        // raw programs aren't cartridges
+        assert!(MoviePlayer::start(&mut CPU::new(), movie).is_err());
+        assert!(MovieRecorder::start(&mut CPU::new(), "raw", true).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Namco 163 (banking, the IRQ counter, the sound RAM and wavetable audio)
+//! tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{audio_range, ines, numbered_chr, numbered_prg};
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::ppu::Mirroring;
+    use nes_emulator::rom::Rom;
+
+    /// An iNES image for mapper 19 with 128 KiB of PRG ROM (every 8 KiB
+    /// filled with its bank number) and 128 KiB of CHR ROM (every KiB filled
+    /// with its number)
+    fn n163_rom(battery: bool) -> Vec<u8> {
+        let flags_6 = if battery { 0b10 } else { 0 };
+        ines(19, flags_6, &numbered_prg(8), &numbered_chr(16))
+    }
+
+    fn cartridge(battery: bool) -> Bus {
+        let mut bus = Bus::with_rom(Rom::new(&n163_rom(battery)).unwrap()).unwrap();
+        // keep the APU's frame IRQ out of the way
+        bus.mem_write(0x4017, 0x40);
+        bus
+    }
+
+    /// Writes `values` to the sound RAM from `address` on
+    fn write_sound_ram(bus: &mut Bus, address: u8, values: &[u8]) {
+        bus.mem_write(0xF800, 0x80 | address);
+        for value in values {
+            bus.mem_write(0x4800, *value);
+        }
+    }
+
+    /// Puts a square wave (8 samples of 15 and 8 of 0) at the start of the
+    /// sound RAM and plays it on channel 7 at full volume
+    fn play_square(bus: &mut Bus) {
+        write_sound_ram(bus, 0x00, &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+        // frequency 0x2000, 16 samples long, starting at sample 0
+        write_sound_ram(bus, 0x78, &[0x00, 0, 0x20, 0, 0xF0, 0, 0x00, 0x0F]);
+    }
+
+    /// Sets channel 7's phase back to 0
+    fn reset_phase(bus: &mut Bus) {
+        for address in [0x79, 0x7B, 0x7D] {
+            write_sound_ram(bus, address, &[0]);
+        }
+    }
+
+    #[test]
+    fn test_n163_banks() {
+        let mut bus = cartridge(false);
+        bus.mem_write(0xE000, 4);
+        bus.mem_write(0xE800, 5);
+        bus.mem_write(0xF000, 6);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000]
+            .iter()
+            .map(|&addr| bus.mem_peek(addr))
+            .collect();
+        assert_eq!(banks, vec![4, 5, 6, 15]);
+
+        bus.mem_write(0x9800, 0x42);
+        let ppu = bus.ppu();
+        assert_eq!(ppu.chr_rom[ppu.chr_offset(0x0C00)], 0x42);
+
+        // the header's mirroring until the name tables are picked
+        assert_eq!(bus.ppu().mirroring, Mirroring::Horizontal);
+        for (addr, page) in [
+            (0xC000, 0xE0),
+            (0xC800, 0xE1),
+            (0xD000, 0xE0),
+            (0xD800, 0xE1),
+        ] {
+            bus.mem_write(addr, page);
+        }
+        assert_eq!(bus.ppu().mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_n163_prg_ram_protection() {
+        let mut bus = cartridge(false);
+        bus.mem_write(0x6000, 0x11);
+        assert_eq!(bus.mem_peek(0x6000), 0);
+        // unlocked, minus the second 2 KiB
+        bus.mem_write(0xF800, 0x42);
+        bus.mem_write(0x6000, 0x11);
+        bus.mem_write(0x6800, 0x22);
+        assert_eq!((bus.mem_peek(0x6000), bus.mem_peek(0x6800)), (0x11, 0));
+    }
+
+    #[test]
+    fn test_n163_irq() {
+        let mut bus = cartridge(false);
+        bus.mem_write(0x5000, 0xF0);
+        bus.mem_write(0x5800, 0xFF);
+        // it counts up to 0x7FFF and stays there
+        bus.tick(0x0E);
+        assert!(!bus.irq_pending());
+        bus.tick(2);
+        assert!(bus.irq_pending());
+        assert_eq!((bus.mem_peek(0x5000), bus.mem_peek(0x5800)), (0xFF, 0xFF));
+        // writing the counter acknowledges it
+        bus.mem_write(0x5800, 0x7F);
+        assert!(!bus.irq_pending());
+        bus.tick(0x100);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_n163_sound_ram() {
+        let mut bus = cartridge(false);
+        write_sound_ram(&mut bus, 0x7E, &[1, 2, 3]);
+        // it wraps around the end of the 128 bytes
+        bus.mem_write(0xF800, 0x80 | 0x7E);
+        let read: Vec<u8> = (0..3).map(|_| bus.mem_read(0x4800)).collect();
+        assert_eq!(read, vec![1, 2, 3]);
+        // without auto increment the address stays put
+        bus.mem_write(0xF800, 0x00);
+        assert_eq!((bus.mem_read(0x4800), bus.mem_read(0x4800)), (3, 3));
+
+        // battery backed sound RAM survives a power cycle
+        bus.power_cycle();
+        bus.mem_write(0xF800, 0x7E);
+        assert_eq!(bus.mem_read(0x4800), 0);
+        let mut bus = cartridge(true);
+        write_sound_ram(&mut bus, 0x7E, &[1]);
+        bus.power_cycle();
+        bus.mem_write(0xF800, 0x7E);
+        assert_eq!(bus.mem_read(0x4800), 1);
+    }
+
+    #[test]
+    fn test_n163_battery_ram_round_trip() {
+        assert_eq!(cartridge(false).battery_ram(), None);
+        assert!(cartridge(false).load_battery_ram(&[0; 0x2080]).is_err());
+
+        let mut bus = cartridge(true);
+        bus.mem_write(0xF800, 0x40);
+        bus.mem_write(0x6123, 0x11);
+        write_sound_ram(&mut bus, 0x05, &[0x22]);
+        // the PRG RAM followed by the sound RAM
+        let save = bus.battery_ram().unwrap();
+        assert_eq!(save.len(), 0x2000 + 0x80);
+        assert_eq!((save[0x0123], save[0x2005]), (0x11, 0x22));
+
+        let mut bus = cartridge(true);
+        assert!(bus.load_battery_ram(&save[..0x2000]).is_err());
+        bus.load_battery_ram(&save).unwrap();
+        assert_eq!(bus.mem_peek(0x6123), 0x11);
+        bus.mem_write(0xF800, 0x05);
+        assert_eq!(bus.mem_read(0x4800), 0x22);
+        assert_eq!(bus.battery_ram(), Some(save));
+    }
+
+    #[test]
+    fn test_n163_audio() {
+        let mut bus = cartridge(false);
+        let (silence, quietest) = audio_range(&mut bus, 20000);
+        assert_eq!(silence, quietest);
+
+        play_square(&mut bus);
+        let (loudest, quietest) = audio_range(&mut bus, 20000);
+        // from (15 - 8) * 15 to (0 - 8) * 15
+        assert!(loudest > silence + 0.08, "{loudest}");
+        assert!(quietest < silence - 0.1, "{quietest}");
+
+        // disabling the sound silences it
+        bus.mem_write(0xE000, 0x40);
+        let (loudest, quietest) = audio_range(&mut bus, 20000);
+        assert_eq!(loudest, quietest);
+    }
+
+    #[test]
+    fn test_n163_channel_count() {
+        let mut bus = cartridge(false);
+        play_square(&mut bus);
+        // with 8 channels on channel 7 only gets every 8th update, so its
+        // phase moves 8 times slower
+        write_sound_ram(&mut bus, 0x7F, &[0x7F]);
+        reset_phase(&mut bus);
+        bus.tick(15 * 8 * 4);
+        bus.mem_write(0xF800, 0x7B);
+        assert_eq!(bus.mem_read(0x4800), 0x20 * 4);
+
+        write_sound_ram(&mut bus, 0x7F, &[0x0F]);
+        reset_phase(&mut bus);
+        bus.tick(15 * 4);
+        bus.mem_write(0xF800, 0x7B);
+        assert_eq!(bus.mem_read(0x4800), 0x20 * 4);
+    }
+
+    #[test]
+    fn test_savestate() {
+        let mut cpu = CPU::with_bus(cartridge(false));
+        cpu.mem_write(0xE000, 7);
+        play_square(&mut cpu.bus);
+        cpu.bus.tick(5000);
+        let state = cpu.save_state();
+
+        let mut other = CPU::with_bus(cartridge(false));
+        other.load_state(&state).unwrap();
+        assert_eq!(other.bus.mem_peek(0x8000), 7);
+        let (loudest, quietest) = audio_range(&mut cpu.bus, 5000);
+        assert_eq!(audio_range(&mut other.bus, 5000), (loudest, quietest));
+    }
+}
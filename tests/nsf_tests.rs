@@ -0,0 +1,329 @@
+//! NSF tests (loading `.nsf` and `.nsfe` files, bankswitching, the player
+//! driver, expansion audio and WAV files) reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::Mem;
+    use nes_emulator::apu::{CPU_CLOCK, SAMPLE_RATE};
+    use nes_emulator::asm;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::nsf::player::NsfPlayer;
+    use nes_emulator::nsf::{self, Nsf};
+    use nes_emulator::region::Region;
+    use nes_emulator::wav;
+
+    /// INIT at $8000 keeps the song and TV system at $00/$01, PLAY at $8010
+    /// counts its calls at $02
+    fn program() -> Vec<u8> {
+        let mut code = asm!(
+            "    STA $00",
+            "    STX $01",
+            "    LDA #$00",
+            "    STA $02",
+            "    RTS",
+        );
+        code.resize(0x10, 0xEA);
+        code.extend(asm!("    INC $02", "    RTS"));
+        code
+    }
+
+    fn header(songs: u8, load: u16, banks: [u8; 8], expansion: u8) -> Vec<u8> {
+        let mut raw = b"NESM\x1A\x01".to_vec();
+        raw.extend_from_slice(&[songs, 2]);
+        raw.extend_from_slice(&load.to_le_bytes());
+        raw.extend_from_slice(&0x8000u16.to_le_bytes());
+        raw.extend_from_slice(&0x8010u16.to_le_bytes());
+        for text in ["Test Tune", "Somebody", "2026 Nobody"] {
+            let mut field = text.as_bytes().to_vec();
+            field.resize(32, 0);
+            raw.extend(field);
+        }
+        raw.extend_from_slice(&16639u16.to_le_bytes());
+        raw.extend_from_slice(&banks);
+        raw.extend_from_slice(&19997u16.to_le_bytes());
+        raw.extend_from_slice(&[0, expansion, 0, 0, 0, 0]);
+        assert_eq!(raw.len(), 0x80);
+        raw
+    }
+
+    fn tune(expansion: u8) -> Nsf {
+        let mut raw = header(3, 0x8000, [0; 8], expansion);
+        raw.extend(program());
+        Nsf::new(&raw).unwrap()
+    }
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn test_nsf_header() {
+        let nsf = tune(nsf::VRC6);
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(
+            (nsf.load_addr, nsf.init_addr, nsf.play_addr),
+            (0x8000, 0x8000, 0x8010)
+        );
+        assert_eq!(nsf.title, "Test Tune");
+        assert_eq!(nsf.artist, "Somebody");
+        assert_eq!(nsf.copyright, "2026 Nobody");
+        assert_eq!(nsf.banks, None);
+        assert!(nsf.ntsc && !nsf.pal);
+        assert_eq!(nsf.expansion, nsf::VRC6);
+        assert_eq!(nsf.play_speed(false), 16639);
+        assert_eq!(nsf.track_name(0), "Song 1");
+        assert_eq!(
+            nsf.track_length(0),
+            (nsf::DEFAULT_LENGTH, nsf::DEFAULT_FADE)
+        );
+
+        assert!(Nsf::new(b"NES\x1A").is_err());
+        assert!(Nsf::new(&header(1, 0x8000, [0; 8], 0)[..0x40]).is_err());
+        // nothing to play
+        assert!(Nsf::new(&header(1, 0x8000, [0; 8], 0)).is_err());
+        let mut raw = header(0, 0x8000, [0; 8], 0);
+        raw.extend(program());
+        assert!(Nsf::new(&raw).is_err());
+        // only FDS tunes can load into RAM
+        let mut raw = header(1, 0x6000, [0; 8], 0);
+        raw.extend(program());
+        assert!(Nsf::new(&raw).is_err());
+        let mut raw = header(1, 0x6000, [0; 8], nsf::FDS);
+        raw.extend(program());
+        assert!(Nsf::new(&raw).is_ok());
+    }
+
+    #[test]
+    fn test_nsfe_chunks() {
+        let mut info = vec![];
+        info.extend_from_slice(&0x8000u16.to_le_bytes());
+        info.extend_from_slice(&0x8000u16.to_le_bytes());
+        info.extend_from_slice(&0x8010u16.to_le_bytes());
+        info.extend_from_slice(&[1, nsf::N163, 2, 1]);
+        let mut time = 90_000i32.to_le_bytes().to_vec();
+        time.extend_from_slice(&(-1i32).to_le_bytes());
+
+        let mut raw = b"NSFE".to_vec();
+        raw.extend(chunk(b"INFO", &info));
+        raw.extend(chunk(b"DATA", &program()));
+        raw.extend(chunk(b"RATE", &[0x0A, 0x1A, 0, 0]));
+        raw.extend(chunk(b"auth", b"Title\0Artist\0(c)\0Ripper"));
+        raw.extend(chunk(b"tlbl", b"Intro\0Boss"));
+        raw.extend(chunk(b"time", &time));
+        raw.extend(chunk(b"fade", &1_000i32.to_le_bytes()));
+        // lower case chunks can be skipped
+        raw.extend(chunk(b"xtra", b"whatever"));
+        let end = chunk(b"NEND", &[]);
+
+        let nsf = Nsf::new(&[raw.clone(), end.clone()].concat()).unwrap();
+        assert_eq!(nsf.songs, 2);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.play_addr, 0x8010);
+        assert_eq!(nsf.data, program());
+        assert_eq!(nsf.play_speed(false), 0x1A0A);
+        assert!(nsf.pal && !nsf.ntsc);
+        assert_eq!(nsf.expansion, nsf::N163);
+        assert_eq!(
+            (&*nsf.title, &*nsf.artist, &*nsf.copyright, &*nsf.ripper),
+            ("Title", "Artist", "(c)", "Ripper")
+        );
+        assert_eq!(nsf.track_name(0), "Intro");
+        assert_eq!(nsf.track_name(1), "Boss");
+        assert_eq!(nsf.track_length(0), (90_000, 1_000));
+        assert_eq!(
+            nsf.track_length(1),
+            (nsf::DEFAULT_LENGTH, nsf::DEFAULT_FADE)
+        );
+
+        // upper case chunks are needed to play the tune
+        let mut unknown = raw.clone();
+        unknown.extend(chunk(b"WHAT", &[]));
+        unknown.extend(end.clone());
+        assert!(Nsf::new(&unknown).is_err());
+        // no NEND
+        assert!(Nsf::new(&raw).is_err());
+        let mut no_data = b"NSFE".to_vec();
+        no_data.extend(chunk(b"INFO", &info));
+        no_data.extend(end);
+        assert!(Nsf::new(&no_data).is_err());
+    }
+
+    #[test]
+    fn test_load_and_bankswitching() {
+        // loads at $8100, so the first bank is 0x100 bytes short
+        let mut raw = header(1, 0x8100, [0, 1, 2, 3, 0, 0, 0, 0], 0);
+        let mut data = vec![0x10; 0xF00];
+        data.extend(vec![0x11; 0x1000]);
+        data.extend(vec![0x12; 0x1000]);
+        data[0] = 0x55;
+        raw.extend(data);
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!(nsf.banks, Some([0, 1, 2, 3, 0, 0, 0, 0]));
+
+        let mut bus = Bus::with_nsf(&nsf);
+        assert_eq!(bus.mem_peek(0x8000), 0);
+        assert_eq!(bus.mem_peek(0x8100), 0x55);
+        assert_eq!(bus.mem_peek(0x8101), 0x10);
+        assert_eq!(bus.mem_peek(0x9000), 0x11);
+        assert_eq!(bus.mem_peek(0xA000), 0x12);
+        // past the end of the data reads as 0
+        assert_eq!(bus.mem_peek(0xB000), 0);
+        assert_eq!(bus.mem_peek(0xF000), 0x00);
+
+        bus.mem_write(0x5FF8, 2);
+        bus.mem_write(0x5FFF, 1);
+        assert_eq!(bus.mem_peek(0x8000), 0x12);
+        assert_eq!(bus.mem_peek(0xF000), 0x11);
+        // ROM stays ROM, and there's RAM below it
+        bus.mem_write(0x8000, 0x99);
+        assert_eq!(bus.mem_peek(0x8000), 0x12);
+        bus.mem_write(0x6000, 0x99);
+        assert_eq!(bus.mem_peek(0x6000), 0x99);
+    }
+
+    #[test]
+    fn test_fds_tune_runs_from_ram() {
+        let mut raw = header(1, 0x8000, [0; 8], nsf::FDS);
+        raw.extend(program());
+        let mut bus = Bus::with_nsf(&Nsf::new(&raw).unwrap());
+        assert_eq!(bus.mem_peek(0x8000), program()[0]);
+        bus.mem_write(0x8000, 0x99);
+        assert_eq!(bus.mem_peek(0x8000), 0x99);
+        bus.mem_write(0xD000, 0x42);
+        assert_eq!(bus.mem_peek(0xD000), 0x42);
+    }
+
+    #[test]
+    fn test_init_and_play() {
+        let mut player = NsfPlayer::new(tune(0));
+        assert_eq!(player.song(), 1);
+        player.run(1000);
+        assert!(!player.is_running());
+        assert_eq!(player.cpu.bus.mem_peek(0x00), 1);
+        assert_eq!(player.cpu.bus.mem_peek(0x01), 0);
+
+        // 16639 microseconds is a little over 60 times a second
+        player.run(CPU_CLOCK - 1000);
+        assert_eq!(player.cpu.bus.mem_peek(0x02), 60);
+
+        player.start(2).unwrap();
+        player.run(1000);
+        assert_eq!(player.song(), 2);
+        assert_eq!(player.cpu.bus.mem_peek(0x00), 2);
+        assert_eq!(player.cpu.bus.mem_peek(0x02), 0);
+        assert!(player.start(3).is_err());
+    }
+
+    #[test]
+    fn test_illegal_opcodes_end_the_routine() {
+        // INIT and PLAY both start with KIL
+        let mut raw = header(1, 0x8000, [0; 8], 0);
+        raw.extend([0x02; 0x20]);
+        let mut player = NsfPlayer::new(Nsf::new(&raw).unwrap());
+        player.run(1000);
+        assert!(!player.is_running());
+        assert_eq!(player.cpu.program_counter, 0x8000);
+
+        let samples = player.render(100, 0);
+        assert!(samples.len().abs_diff(SAMPLE_RATE as usize / 10) < 10);
+    }
+
+    #[test]
+    fn test_regions() {
+        // NTSC and dual tunes play on NTSC
+        let player = NsfPlayer::new(tune(0));
+        assert_eq!(player.region(), Region::Ntsc);
+
+        let mut raw = header(1, 0x8000, [0; 8], 0);
+        raw[0x7A] = 1;
+        raw.extend(program());
+        let pal = Nsf::new(&raw).unwrap();
+        assert!(pal.pal && !pal.ntsc);
+        let mut player = NsfPlayer::new(pal);
+        assert_eq!(player.region(), Region::Pal);
+        player.run(Region::Pal.cpu_clock());
+        assert_eq!(player.cpu.bus.mem_peek(0x01), 1);
+        // 19997 microseconds is 50 times a second
+        assert_eq!(player.cpu.bus.mem_peek(0x02), 50);
+
+        // a Dendy plays at the PAL rate, but passes for NTSC
+        player.set_region(Region::Dendy);
+        player.run(Region::Dendy.cpu_clock());
+        assert_eq!(player.cpu.bus.mem_peek(0x01), 0);
+        assert_eq!(player.cpu.bus.mem_peek(0x02), 50);
+    }
+
+    /// Renders a bit of a tune whose INIT starts a VRC6 pulse at constant
+    /// volume
+    fn vrc6_samples(expansion: u8) -> Vec<f32> {
+        let mut raw = header(1, 0x8000, [0; 8], expansion);
+        let mut code = asm!(
+            "    LDA #$8F",
+            "    STA $9000",
+            "    LDA #$80",
+            "    STA $9002",
+            "    RTS",
+        );
+        code.resize(0x10, 0xEA);
+        code.extend(asm!("    RTS"));
+        raw.extend(code);
+        let mut player = NsfPlayer::new(Nsf::new(&raw).unwrap());
+        player.render(100, 0)
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let loudest = |samples: &[f32]| samples.iter().copied().fold(0.0, f32::max);
+        // the triangle rests at a level of its own, so compare to a tune
+        // without the flag, where the writes go nowhere
+        assert!(loudest(&vrc6_samples(nsf::VRC6)) > loudest(&vrc6_samples(0)) + 0.05);
+    }
+
+    #[test]
+    fn test_render_fades_out() {
+        let mut raw = header(1, 0x8000, [0; 8], 0);
+        // a square wave on pulse 1, at constant volume
+        let mut code = asm!(
+            "    LDA #$BF",
+            "    STA $4000",
+            "    LDA #$FD",
+            "    STA $4002",
+            "    LDA #$00",
+            "    STA $4003",
+            "    RTS",
+        );
+        code.resize(0x10, 0xEA);
+        code.extend(asm!("    RTS"));
+        raw.extend(code);
+        let mut player = NsfPlayer::new(Nsf::new(&raw).unwrap());
+        let samples = player.render(500, 500);
+
+        let expected = SAMPLE_RATE as usize;
+        assert!(samples.len().abs_diff(expected) < 10);
+        let loudest = |samples: &[f32]| samples.iter().copied().fold(0.0, f32::max);
+        let half = samples.len() / 2;
+        assert!(loudest(&samples[..half]) > 0.0);
+        assert!(loudest(&samples[samples.len() - 100..]) < loudest(&samples[half..half + 100]));
+        assert!(samples.last().unwrap().abs() < 0.01);
+    }
+
+    #[test]
+    fn test_wav() {
+        let samples = vec![0.5; 1000];
+        let wav = wav::encode(&samples, 44100);
+        assert_eq!(wav.len(), 44 + 2000);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 2000);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 1);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44100);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 2000);
+        // a constant level is just an offset, which gets filtered out
+        assert!(wav[44..].chunks(2).all(|sample| sample == [0, 0]));
+    }
+}
@@ -0,0 +1,147 @@
+//! Built-in palette, .pal file and NTSC palette generator tests reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::palette::{BUILTIN_PALETTES, NtscSettings, Palette};
+
+    /// Whether a color is more `channel` than anything else
+    fn dominant(color: [u8; 3], channel: usize) -> bool {
+        (0..3).all(|other| other == channel || color[channel] > color[other])
+    }
+
+    #[test]
+    fn test_builtin_palettes() {
+        for name in BUILTIN_PALETTES {
+            let palette = Palette::builtin(name).unwrap();
+            // every palette agrees that 0x0D is black and red is red
+            assert_eq!(palette.rgb(0x0D, 0), [0, 0, 0], "{name}");
+            assert!(dominant(palette.rgb(0x16, 0), 0), "{name}");
+            assert!(dominant(palette.rgb(0x1A, 0), 1), "{name}");
+            assert!(dominant(palette.rgb(0x12, 0), 2), "{name}");
+        }
+        assert_eq!(Palette::builtin("default").unwrap(), Palette::default());
+        assert_eq!(
+            Palette::builtin("2c02").unwrap().rgb(0x16, 0),
+            [0x98, 0x22, 0x20]
+        );
+        // FCEUX keeps 6 bits per channel
+        assert_eq!(
+            Palette::builtin("FCEUX").unwrap().rgb(0x30, 0),
+            [0xFC, 0xFC, 0xFC]
+        );
+        assert_eq!(
+            Palette::builtin("nestopia-yuv").unwrap().rgb(0x00, 0),
+            [0x66, 0x66, 0x66]
+        );
+
+        assert_eq!(
+            Palette::builtin("vga").unwrap_err(),
+            "Unknown palette vga (expected a .pal file, ntsc or one of default, 2c02, fceux, nestopia-yuv)"
+        );
+    }
+
+    #[test]
+    fn test_pal_files() {
+        // a 64 color file gets its emphasis variants worked out
+        let colors: [[u8; 3]; 64] = std::array::from_fn(|i| [i as u8, 100, 200]);
+        let short = Palette::from_pal(&colors.concat()).unwrap();
+        assert_eq!(short, Palette::from_colors(&colors));
+        assert_eq!(short.rgb(0x05, 0), [5, 100, 200]);
+        assert_eq!(short.rgb(0x05, 0b100), [4, 82, 200]);
+
+        // a 512 color file has them already
+        let full = (0..512)
+            .flat_map(|i: u32| [(i / 64) as u8, i as u8 & 0x3F, 7])
+            .collect::<Vec<_>>();
+        let palette = Palette::from_pal(&full).unwrap();
+        assert_eq!(palette.rgb(0x2A, 0), [0, 0x2A, 7]);
+        assert_eq!(palette.rgb(0x2A, 0b101), [5, 0x2A, 7]);
+        assert_eq!(palette.to_pal(), full);
+        assert_eq!(short.to_pal().len(), 1536);
+
+        assert_eq!(
+            Palette::from_pal(&[0; 100]).unwrap_err(),
+            "Invalid palette file (100 bytes, expected 192 or 1536)"
+        );
+
+        let path = std::env::temp_dir().join(format!("palette_test_{}.pal", std::process::id()));
+        palette.save(&path).unwrap();
+        assert_eq!(Palette::load(&path).unwrap(), palette);
+        assert_eq!(Palette::find(path.to_str().unwrap()).unwrap(), palette);
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            Palette::find("missing.pal")
+                .unwrap_err()
+                .starts_with("missing.pal: ")
+        );
+    }
+
+    #[test]
+    fn test_ntsc_generator() {
+        let palette = Palette::generate_ntsc(&NtscSettings::default());
+        assert_eq!(Palette::find("ntsc").unwrap(), palette);
+
+        // hue 0 is grey, hues 13 - 15 of the darkest row and 14 - 15 of all
+        // of them are black
+        for index in [0x00, 0x10, 0x20, 0x30, 0x2D] {
+            let [r, g, b] = palette.rgb(index, 0);
+            assert!(r == g && g == b, "{index:02X}");
+        }
+        for index in [0x0D, 0x0E, 0x0F, 0x1E, 0x3F] {
+            assert_eq!(palette.rgb(index, 0), [0, 0, 0], "{index:02X}");
+        }
+        let greys = [0x00, 0x10, 0x20].map(|index| palette.rgb(index, 0)[0]);
+        assert!(greys[0] < greys[1] && greys[1] < greys[2]);
+
+        assert!(dominant(palette.rgb(0x16, 0), 0));
+        assert!(dominant(palette.rgb(0x1A, 0), 1));
+        assert!(dominant(palette.rgb(0x12, 0), 2));
+
+        // emphasizing red keeps red up and takes green and blue down
+        let [r, g, b] = palette.rgb(0x20, 0);
+        let [er, eg, eb] = palette.rgb(0x20, 0b001);
+        assert!(eg < g && eb < b && r - er < g - eg);
+        // all three take everything down evenly
+        let [r, g, b] = palette.rgb(0x10, 0b111);
+        assert!(r == g && g == b && r < palette.rgb(0x10, 0)[0]);
+    }
+
+    #[test]
+    fn test_ntsc_settings() {
+        let default = Palette::generate_ntsc(&NtscSettings::default());
+        let generate = |settings: NtscSettings| Palette::generate_ntsc(&settings);
+
+        let grey = generate(NtscSettings {
+            saturation: 0.0,
+            ..Default::default()
+        });
+        let [r, g, b] = grey.rgb(0x16, 0);
+        assert!(r == g && g == b);
+
+        // every hue is 30 degrees apart, so 120 degrees turns red into blue
+        let rotated = generate(NtscSettings {
+            hue: 120.0,
+            ..Default::default()
+        });
+        assert_eq!(rotated.rgb(0x16, 0), default.rgb(0x12, 0));
+
+        let brighter = generate(NtscSettings {
+            brightness: 0.1,
+            ..Default::default()
+        });
+        let darker = generate(NtscSettings {
+            contrast: 0.5,
+            ..Default::default()
+        });
+        assert!(brighter.rgb(0x00, 0)[0] > default.rgb(0x00, 0)[0]);
+        assert!(darker.rgb(0x10, 0)[0] < default.rgb(0x10, 0)[0]);
+
+        // a display gamma of 2.2 needs no correction, while a display with
+        // the default 1.8 shows everything brighter, so the greys get darkened
+        let linear = generate(NtscSettings {
+            gamma: 2.2,
+            ..Default::default()
+        });
+        assert!(linear.rgb(0x00, 0)[0] > default.rgb(0x00, 0)[0]);
+    }
+}
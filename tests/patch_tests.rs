@@ -0,0 +1,183 @@
+//! ZIP archive loading and IPS, UPS and BPS patch tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::zip;
+    use nes_emulator::hash::crc32;
+    use nes_emulator::rom::{self, Rom, bps, ips, ups};
+
+    const ORIGINAL: &[u8] = b"Hello, world!";
+
+    /// Writes a BPS/UPS number
+    fn number(patch: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                patch.push(0x80 | byte);
+                return;
+            }
+            patch.push(byte);
+            value -= 1;
+        }
+    }
+
+    /// Adds the 3 CRC-32s BPS and UPS patches end with
+    fn footer(patch: &mut Vec<u8>, original: &[u8], patched: &[u8]) {
+        patch.extend(crc32(original).to_le_bytes());
+        patch.extend(crc32(patched).to_le_bytes());
+        patch.extend(crc32(patch).to_le_bytes());
+    }
+
+    /// A BPS patch that turns `ORIGINAL` into "Hello, NES!!!!!!" with each
+    /// of the actions
+    fn bps_patch() -> Vec<u8> {
+        let action = |len: u64, action: u64| ((len - 1) << 2) | action;
+        let mut patch = b"BPS1".to_vec();
+        number(&mut patch, ORIGINAL.len() as u64);
+        number(&mut patch, 16);
+        number(&mut patch, 4);
+        patch.extend(b"meta");
+        // "Hello, " from the original
+        number(&mut patch, action(7, 0));
+        // "NES" from the patch
+        number(&mut patch, action(3, 1));
+        patch.extend(b"NES");
+        // "!" from the end of the original
+        number(&mut patch, action(1, 2));
+        number(&mut patch, 12 << 1);
+        // and 5 more, copied from itself as it grows
+        number(&mut patch, action(5, 3));
+        number(&mut patch, 10 << 1);
+        footer(&mut patch, ORIGINAL, b"Hello, NES!!!!!!");
+        patch
+    }
+
+    /// A UPS patch that turns `original` into `patched`
+    fn ups_patch(original: &[u8], patched: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        number(&mut patch, original.len() as u64);
+        number(&mut patch, patched.len() as u64);
+        let xor = |offset: usize| {
+            original.get(offset).copied().unwrap_or(0) ^ patched.get(offset).copied().unwrap_or(0)
+        };
+        let len = original.len().max(patched.len());
+        let (mut offset, mut last) = (0, 0);
+        while offset < len {
+            if xor(offset) == 0 {
+                offset += 1;
+                continue;
+            }
+            number(&mut patch, (offset - last) as u64);
+            while offset < len && xor(offset) != 0 {
+                patch.push(xor(offset));
+                offset += 1;
+            }
+            patch.push(0);
+            offset += 1;
+            last = offset;
+        }
+        footer(&mut patch, original, patched);
+        patch
+    }
+
+    /// An NROM cartridge with `fill` all over its PRG ROM
+    fn nes(fill: u8) -> Vec<u8> {
+        let mut raw = vec![b'N', b'E', b'S', 0x1A, 1, 0];
+        raw.resize(16, 0);
+        raw.extend(vec![fill; 0x4000]);
+        raw
+    }
+
+    #[test]
+    fn test_bps() {
+        let patch = bps_patch();
+        assert_eq!(bps::apply(ORIGINAL, &patch).unwrap(), b"Hello, NES!!!!!!");
+
+        // made for another file
+        let err = bps::apply(b"Hello, World!", &patch).unwrap_err();
+        assert!(err.contains("different ROM"), "{err}");
+        // damaged
+        let mut damaged = patch.clone();
+        damaged[20] ^= 1;
+        assert!(bps::apply(ORIGINAL, &damaged).is_err());
+        assert!(bps::apply(ORIGINAL, b"BPS1").is_err());
+        assert!(bps::apply(ORIGINAL, &ups_patch(ORIGINAL, b"Hello")).is_err());
+    }
+
+    #[test]
+    fn test_ups() {
+        for patched in [
+            b"Jello, world!".as_slice(),
+            b"Hello, world!!!",
+            b"Jello, NES",
+            ORIGINAL,
+        ] {
+            let patch = ups_patch(ORIGINAL, patched);
+            assert_eq!(ups::apply(ORIGINAL, &patch).unwrap(), patched);
+        }
+
+        let patch = ups_patch(ORIGINAL, b"Jello, NES");
+        let err = ups::apply(b"Hello, World!", &patch).unwrap_err();
+        assert!(err.contains("different ROM"), "{err}");
+        // a wrong target CRC (with the patch's own CRC fixed up)
+        let mut wrong = patch[..patch.len() - 8].to_vec();
+        wrong.extend(0u32.to_le_bytes());
+        wrong.extend(crc32(&wrong).to_le_bytes());
+        assert!(ups::apply(ORIGINAL, &wrong).is_err());
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        assert_eq!(
+            rom::apply_patch(ORIGINAL, &bps_patch()).unwrap(),
+            b"Hello, NES!!!!!!"
+        );
+        assert_eq!(
+            rom::apply_patch(ORIGINAL, &ups_patch(ORIGINAL, b"Jello")).unwrap(),
+            b"Jello"
+        );
+        let ips = ips::create(ORIGINAL, b"Hello, there!").unwrap();
+        assert_eq!(rom::apply_patch(ORIGINAL, &ips).unwrap(), b"Hello, there!");
+        assert!(rom::apply_patch(ORIGINAL, b"not a patch").is_err());
+
+        // patched before the header's parsed
+        let patched = rom::apply_patch(&nes(0xAA), &ups_patch(&nes(0xAA), &nes(0x55))).unwrap();
+        assert_eq!(Rom::new(&patched).unwrap().prg_rom[0], 0x55);
+    }
+
+    #[test]
+    fn test_read_file() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!("patch_test_{}_{name}", std::process::id()))
+                .to_string_lossy()
+                .to_string()
+        };
+
+        let archive = zip(&[
+            ("readme.txt", b"not a ROM", None),
+            ("roms/Game.NES", &nes(0xAA), None),
+            ("other.nes", &nes(0x55), None),
+        ]);
+        let zipped = path("games.zip");
+        std::fs::write(&zipped, archive).unwrap();
+        let (name, raw) = rom::read_file(&zipped).unwrap();
+        assert_eq!(name, "roms/Game.NES");
+        assert_eq!(raw, nes(0xAA));
+
+        let empty = path("empty.zip");
+        std::fs::write(&empty, zip(&[("readme.txt", b"not a ROM", None)])).unwrap();
+        assert!(rom::read_file(&empty).is_err());
+
+        let plain = path("game.nes");
+        std::fs::write(&plain, nes(0x55)).unwrap();
+        assert_eq!(rom::read_file(&plain).unwrap(), (plain.clone(), nes(0x55)));
+
+        for file in [zipped, empty, plain] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! RAM search tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{ines, load_cartridge};
+    use nes_emulator::Mem;
+    use nes_emulator::asm;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::debugger::Debugger;
+    use nes_emulator::ramsearch::{CompareTo, Comparison, RamSearch, ValueSize};
+
+    /// Builds a bus with an (empty) 16 KiB cartridge, which comes with 8 KiB
+    /// of PRG RAM
+    fn cartridge() -> Bus {
+        load_cartridge(&ines(0, 0, &[0; 0x4000], &[0; 0x2000]))
+    }
+
+    #[test]
+    fn test_search_bytes() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0075, 3);
+        bus.mem_write(0x0300, 3);
+        let mut search = RamSearch::new(&bus, ValueSize::Byte, false);
+        // no cartridge, so just the internal RAM
+        assert_eq!(search.candidates().len(), 0x800);
+
+        // lose a life (and something else changes too)
+        bus.mem_write(0x0875, 2); // a mirror of $0075
+        bus.mem_write(0x0300, 4);
+        bus.mem_write(0x0400, 1);
+        assert_eq!(
+            search.filter(&bus, Comparison::NotEqual, CompareTo::Previous),
+            3
+        );
+        assert_eq!(search.candidates(), [0x0075, 0x0300, 0x0400]);
+        assert_eq!(search.value(0x0075), Some(2));
+
+        assert_eq!(
+            search.filter(&bus, Comparison::Equal, CompareTo::Previous),
+            3
+        );
+        search.filter(&bus, Comparison::LessOrEqual, CompareTo::Value(2));
+        assert_eq!(search.candidates(), [0x0075, 0x0400]);
+
+        bus.mem_write(0x0075, 1);
+        bus.mem_write(0x0400, 3);
+        search.filter(&bus, Comparison::Equal, CompareTo::ChangedBy(-1));
+        assert_eq!(search.candidates(), [0x0075]);
+
+        search.reset(&bus);
+        assert_eq!(search.candidates().len(), 0x800);
+    }
+
+    #[test]
+    fn test_search_signed_words() {
+        let mut bus = Bus::new();
+        let mut search = RamSearch::new(&bus, ValueSize::Word, true);
+        // a word can't start on the last byte of RAM
+        assert_eq!(search.candidates().len(), 0x7ff);
+        assert!(search.is_signed());
+
+        bus.mem_write_u16(0x0010, 0xffff);
+        search.filter(&bus, Comparison::Equal, CompareTo::Value(-1));
+        assert_eq!(search.candidates(), [0x0010]);
+
+        bus.mem_write_u16(0x0010, 0x0001);
+        assert_eq!(
+            search.filter(&bus, Comparison::Equal, CompareTo::ChangedBy(2)),
+            1
+        );
+        assert_eq!(search.value(0x0010), Some(1));
+        assert_eq!(search.value(0x07ff), None);
+
+        // unsigned, 0xFFFF is bigger than anything
+        let mut search = RamSearch::new(&bus, ValueSize::Word, false);
+        bus.mem_write_u16(0x0020, 0xffff);
+        search.filter(&bus, Comparison::Greater, CompareTo::Value(0xff00));
+        assert_eq!(search.candidates(), [0x0020]);
+    }
+
+    #[test]
+    fn test_search_prg_ram() {
+        let mut bus = cartridge();
+        let search = RamSearch::new(&bus, ValueSize::Word, false);
+        assert_eq!(search.candidates().len(), 0x7ff + 0x1fff);
+        assert_eq!(search.value(0x07ff), None);
+        assert_eq!(search.value(0x7fff), None);
+
+        let mut search = RamSearch::new(&bus, ValueSize::Byte, false);
+        assert_eq!(search.candidates().len(), 0x800 + 0x2000);
+        bus.mem_write(0x6123, 0x40);
+        search.filter(&bus, Comparison::Greater, CompareTo::Previous);
+        assert_eq!(search.candidates(), [0x6123]);
+        assert_eq!(search.value(0x6123), Some(0x40));
+        assert_eq!(search.value(0x8000), None);
+    }
+
+    #[test]
+    fn test_search_large_prg_ram() {
+        // MMC5 has 64 KiB of PRG RAM, only 8 KiB of which is at 0x6000
+        let mut bus = load_cartridge(&ines(5, 0, &[0; 0x8000], &[0; 0x2000]));
+        assert!(bus.prg_ram().len() > 0x2000);
+        let mut search = RamSearch::new(&bus, ValueSize::Byte, false);
+        assert_eq!(search.candidates().len(), 0x800 + 0x2000);
+        assert_eq!(*search.candidates().last().unwrap(), 0x7fff);
+        assert_eq!(
+            search.filter(&bus, Comparison::Equal, CompareTo::Previous),
+            0x800 + 0x2000
+        );
+
+        // a change too big for an i32 drops the candidates instead of
+        // overflowing
+        bus.mem_write(0x0010, 1);
+        search.filter(&bus, Comparison::NotEqual, CompareTo::Previous);
+        assert_eq!(search.candidates(), [0x0010]);
+        assert_eq!(
+            search.filter(&bus, Comparison::Equal, CompareTo::ChangedBy(i32::MAX)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_debugger_search_commands() {
+        let mut cpu = CPU::new();
+        cpu.load_at(
+            0x0600,
+            &asm!(
+                ".org $0600",
+                "loop: DEC $40", // counts down from 0 (255)
+                "      INC $41",
+                "      JMP loop",
+            ),
+        );
+        cpu.reset();
+        let mut debugger = Debugger::new(cpu);
+        assert_eq!(
+            debugger.execute("search =").unwrap(),
+            "Error: No search started"
+        );
+        assert_eq!(
+            debugger.execute("search start 8 s").unwrap(),
+            "2048 candidates"
+        );
+
+        debugger.execute("step 3");
+        assert_eq!(debugger.execute("search changed").unwrap(), "2 candidates");
+        assert_eq!(
+            debugger.execute("search list").unwrap(),
+            "$0040: -1\n$0041: 1"
+        );
+
+        debugger.execute("step 3");
+        assert_eq!(debugger.execute("search = by -1").unwrap(), "1 candidates");
+        assert_eq!(debugger.execute("search list").unwrap(), "$0040: -2");
+        assert_eq!(debugger.execute("search = $FE").unwrap(), "0 candidates");
+
+        assert!(debugger.execute("search ~ 3").unwrap().starts_with("Error"));
+        assert!(
+            debugger
+                .execute("search start 32")
+                .unwrap()
+                .starts_with("Error")
+        );
+        assert_eq!(
+            debugger.execute("search start 16").unwrap(),
+            "2047 candidates"
+        );
+    }
+}
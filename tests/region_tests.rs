@@ -0,0 +1,202 @@
+//! Region (NTSC, PAL and Dendy timing) tests reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::movie::{Movie, MoviePlayer, MovieRecorder};
+    use nes_emulator::region::Region;
+    use nes_emulator::rom::Rom;
+
+    /// An NROM cartridge that loops forever, with `header` written over
+    /// bytes 7 - 15 of the header
+    fn rom(header: [u8; 9]) -> Rom {
+        let mut raw = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0];
+        raw.extend_from_slice(&header);
+        let mut prg = vec![0; 0x4000];
+        // JMP $8000
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        Rom::new(&raw).unwrap()
+    }
+
+    /// An NES 2.0 header with `timing` in byte 12
+    fn nes_2(timing: u8) -> [u8; 9] {
+        [0x08, 0, 0, 0, 0, timing, 0, 0, 0]
+    }
+
+    fn bus(region: Region) -> Bus {
+        let mut bus = Bus::with_rom(rom([0; 9])).unwrap();
+        bus.set_region(region);
+        bus
+    }
+
+    /// How many CPU cycles it takes the PPU to get through its first frame
+    fn frame_cycles(bus: &mut Bus) -> usize {
+        while bus.ppu().frame() == 0 {
+            bus.tick(1);
+        }
+        bus.cycles()
+    }
+
+    #[test]
+    fn test_regions() {
+        assert_eq!(Region::default(), Region::Ntsc);
+        assert_eq!(Region::parse("pal").unwrap(), Region::Pal);
+        assert_eq!(Region::parse("DENDY").unwrap(), Region::Dendy);
+        assert_eq!(Region::parse("NTSC").unwrap(), Region::Ntsc);
+        assert!(Region::parse("secam").is_err());
+        assert_eq!(Region::Dendy.to_string(), "Dendy");
+
+        assert!((Region::Ntsc.frame_rate() - 60.0985).abs() < 0.001);
+        assert!((Region::Pal.frame_rate() - 50.007).abs() < 0.001);
+        assert!((Region::Dendy.frame_rate() - 50.007).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_region_from_header() {
+        assert_eq!(rom([0; 9]).region, None);
+        assert_eq!(rom(nes_2(0)).region, Some(Region::Ntsc));
+        assert_eq!(rom(nes_2(1)).region, Some(Region::Pal));
+        // multi-region
+        assert_eq!(rom(nes_2(2)).region, None);
+        assert_eq!(rom(nes_2(3)).region, Some(Region::Dendy));
+        // iNES has a PAL bit in byte 9
+        assert_eq!(rom([0, 0, 1, 0, 0, 0, 0, 0, 0]).region, Some(Region::Pal));
+        // unless the header's full of junk
+        assert_eq!(rom(*b"DiskDude!").region, None);
+        // which goes for the mapper number's high nibble in byte 7 too
+        let disk_dude = rom(*b"DiskDude!");
+        assert_eq!(disk_dude.mapper, 0);
+        assert!(Bus::with_rom(disk_dude).is_ok());
+
+        let bus = Bus::with_rom(rom(nes_2(3))).unwrap();
+        assert_eq!(bus.region(), Region::Dendy);
+        assert_eq!(Bus::with_rom(rom(nes_2(2))).unwrap().region(), Region::Ntsc);
+    }
+
+    #[test]
+    fn test_frame_timing() {
+        // 262 * 341 dots at 3 a cycle
+        assert_eq!(frame_cycles(&mut bus(Region::Ntsc)), 29781);
+        // 312 * 341 dots at 3.2 a cycle
+        assert_eq!(frame_cycles(&mut bus(Region::Pal)), 33248);
+        // 312 * 341 dots at 3 a cycle
+        assert_eq!(frame_cycles(&mut bus(Region::Dendy)), 35464);
+
+        // PAL keeps the fractions of a dot, so frames don't drift
+        let mut pal = bus(Region::Pal);
+        while pal.ppu().frame() < 10 {
+            pal.tick(1);
+        }
+        assert_eq!(pal.cycles(), 332475);
+    }
+
+    #[test]
+    fn test_vblank_starts() {
+        for (region, scanline) in [
+            (Region::Ntsc, 241),
+            (Region::Pal, 241),
+            (Region::Dendy, 291),
+        ] {
+            let mut bus = bus(region);
+            bus.mem_write(0x2000, 0x80);
+            while !bus.nmi_pending() {
+                bus.tick(1);
+            }
+            assert_eq!(bus.ppu().scanline(), scanline, "{region}");
+            assert_ne!(bus.mem_peek(0x2002) & 0x80, 0);
+            let vblank_start = bus.cycles();
+            while bus.mem_peek(0x2002) & 0x80 != 0 {
+                bus.tick(1);
+            }
+            // it lasts until the frame ends
+            let (dots, cycles) = region.ppu_dots_per_cycles();
+            let lines = (bus.cycles() - vblank_start) as f64 * dots as f64 / cycles as f64 / 341.0;
+            let expected = (region.scanlines() - scanline) as f64;
+            assert!((lines - expected).abs() < 0.1, "{region}: {lines}");
+        }
+    }
+
+    #[test]
+    fn test_apu_frame_counter() {
+        for (region, irq_cycle) in [
+            (Region::Ntsc, 29829),
+            (Region::Pal, 33253),
+            (Region::Dendy, 29829),
+        ] {
+            let mut bus = bus(region);
+            bus.mem_write(0x4017, 0x00);
+            bus.tick(irq_cycle - 1);
+            assert!(!bus.apu().irq(), "{region}");
+            bus.tick(1);
+            assert!(bus.apu().irq(), "{region}");
+        }
+    }
+
+    #[test]
+    fn test_audio_is_sampled_at_the_region_clock() {
+        for region in Region::ALL {
+            let mut bus = bus(region);
+            bus.record_audio(true);
+            bus.tick(region.cpu_clock() as usize);
+            assert_eq!(bus.take_audio().len(), 44_100, "{region}");
+        }
+    }
+
+    #[test]
+    fn test_emphasis() {
+        for (region, expected) in [
+            (Region::Ntsc, 0b001),
+            (Region::Pal, 0b010),
+            (Region::Dendy, 0b010),
+        ] {
+            let mut bus = bus(region);
+            // bit 5: red on NTSC, green on PAL
+            bus.mem_write(0x2001, 0b0010_0000);
+            frame_cycles(&mut bus);
+            assert_eq!(bus.ppu().frame_emphasis(), expected, "{region}");
+        }
+    }
+
+    #[test]
+    fn test_savestates_keep_the_region() {
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom(nes_2(1))).unwrap());
+        cpu.reset();
+        cpu.bus.tick(1001);
+        let state = cpu.save_state();
+
+        cpu.bus.set_region(Region::Ntsc);
+        cpu.power_cycle();
+        assert_eq!(cpu.bus.region(), Region::Ntsc);
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.bus.region(), Region::Pal);
+        assert_eq!(cpu.save_state(), state);
+    }
+
+    #[test]
+    fn test_movies_pick_the_region() {
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom(nes_2(1))).unwrap());
+        let recorder = MovieRecorder::start(&mut cpu, "test.nes", true).unwrap();
+        let movie = recorder.finish(&mut cpu);
+        assert!(movie.pal);
+
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom([0; 9])).unwrap());
+        MoviePlayer::start(&mut cpu, movie.clone()).unwrap();
+        assert_eq!(cpu.bus.region(), Region::Pal);
+
+        let ntsc = Movie {
+            pal: false,
+            ..movie
+        };
+        MoviePlayer::start(&mut cpu, ntsc.clone()).unwrap();
+        assert_eq!(cpu.bus.region(), Region::Ntsc);
+        // a Dendy can't be told apart from NTSC in a movie, so it's kept
+        cpu.bus.set_region(Region::Dendy);
+        MoviePlayer::start(&mut cpu, ntsc).unwrap();
+        assert_eq!(cpu.bus.region(), Region::Dendy);
+    }
+}
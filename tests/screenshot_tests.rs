@@ -0,0 +1,200 @@
+//! Palette, PNG encoding and screenshot tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{boot, ines};
+    use nes_emulator::asm;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::debugger::Debugger;
+    use nes_emulator::hash::{adler32, crc32};
+    use nes_emulator::palette::Palette;
+    use nes_emulator::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use nes_emulator::screenshot::{Screenshot, encode_png};
+    use nes_emulator::zip::inflate;
+
+    /// Makes the backdrop white ($30), draws tile 1 (in $16) at the top left
+    /// and turns on red emphasis from frame 3 on
+    fn drawing_program() -> Vec<u8> {
+        asm!(
+            "        .org $C000",
+            "reset:  LDA #$3F",
+            "        STA $2006",
+            "        LDA #$00",
+            "        STA $2006",
+            "        LDA #$30",
+            "        STA $2007",
+            "        LDA #$16",
+            "        STA $2007",
+            "        LDA #$20",
+            "        STA $2006",
+            "        LDA #$00",
+            "        STA $2006",
+            "        LDA #$01",
+            "        STA $2007",
+            "        LDA #$00",
+            "        STA $2005",
+            "        STA $2005",
+            "        LDA #$0A",
+            "        STA $2001",
+            "        LDA #$80",
+            "        STA $2000",
+            "loop:   JMP loop",
+            "nmi:    INC $00",
+            "        LDA $00",
+            "        CMP #$02",
+            "        BNE done",
+            "        LDA #$2A",
+            "        STA $2001",
+            "done:   RTI",
+            "        .org $FFFA",
+            "        .dw nmi, reset, reset",
+        )
+    }
+
+    fn machine() -> CPU {
+        // tile 1 is color 1 all over
+        let mut chr = vec![0; 0x10];
+        chr.extend([0xff; 8]);
+        boot(&ines(0, 0, &drawing_program(), &chr))
+    }
+
+    /// Checks the structure of a PNG image and returns its width, height
+    /// and the decompressed image data
+    fn decode_png(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut pos = 8;
+        let mut kinds = Vec::new();
+        let (mut width, mut height, mut zlib) = (0, 0, Vec::new());
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = &png[pos + 4..pos + 8];
+            let data = &png[pos + 8..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[pos + 4..pos + 8 + len]));
+            match kind {
+                b"IHDR" => {
+                    width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+                    height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+                    assert_eq!(&data[8..], [8, 2, 0, 0, 0]);
+                }
+                b"IDAT" => zlib.extend_from_slice(data),
+                _ => {}
+            }
+            kinds.push(String::from_utf8(kind.to_vec()).unwrap());
+            pos += 12 + len;
+        }
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+
+        assert_eq!((zlib[0] as u16 * 256 + zlib[1] as u16) % 31, 0);
+        let data = inflate(&zlib[2..zlib.len() - 4]).unwrap();
+        let adler = u32::from_be_bytes(zlib[zlib.len() - 4..].try_into().unwrap());
+        assert_eq!(adler, adler32(&data));
+        (width, height, data)
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"a"), 0x00620062);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        // big enough for the sums to wrap
+        assert_eq!(adler32(&[0xff; 6000]), 0xa49759ea);
+    }
+
+    #[test]
+    fn test_palette_emphasis() {
+        let palette = Palette::default();
+        assert_eq!(palette.rgb(0x30, 0), [0xff, 0xff, 0xff]);
+        assert_eq!(palette.rgb(0x0f, 0), palette.rgb(0x4f, 0));
+        // red emphasis darkens green and blue, all three darken everything
+        assert_eq!(palette.rgb(0x30, 0b001), [0xff, 0xd0, 0xd0]);
+        assert_eq!(palette.rgb(0x30, 0b110), [0xd0, 0xff, 0xff]);
+        assert_eq!(palette.rgb(0x30, 0b111), [0xff, 0xff, 0xff]);
+
+        let grey = Palette::from_colors(&[[100, 100, 100]; 64]);
+        assert_eq!(grey.rgb(0x12, 0b010), [82, 100, 82]);
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let pixels = [
+            0xff, 0, 0, 0, 0xff, 0, 0, 0, 0xff, 1, 2, 3, 4, 5, 6, 7, 8, 9,
+        ];
+        let (width, height, data) = decode_png(&encode_png(3, 2, &pixels));
+        assert_eq!((width, height), (3, 2));
+        // every row starts with filter type 0
+        assert_eq!(data[0], 0);
+        assert_eq!(data[1..10], pixels[..9]);
+        assert_eq!(data[10], 0);
+        assert_eq!(data[11..], pixels[9..]);
+
+        // a row-less image still has a valid (empty) zlib stream
+        let (_, _, data) = decode_png(&encode_png(0, 0, &[]));
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_screenshot() {
+        let mut cpu = machine();
+        let mut debugger = Debugger::new(cpu);
+        // the frame gets drawn even when the file can't be written
+        let output = debugger
+            .execute("screenshot /nonexistent/shot.png 2")
+            .unwrap();
+        assert!(
+            output.starts_with("Error: /nonexistent/shot.png: "),
+            "{output}"
+        );
+        cpu = debugger.cpu;
+        assert_eq!(cpu.bus.ppu().frame(), 2);
+
+        let palette = Palette::default();
+        let screenshot = Screenshot::capture(&cpu, &palette);
+        assert_eq!(
+            (screenshot.width, screenshot.height),
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        );
+        assert_eq!(screenshot.frame, 2);
+        let pixel = |screenshot: &Screenshot, x: usize, y: usize| {
+            let start = (y * SCREEN_WIDTH + x) * 3;
+            screenshot.pixels[start..start + 3].to_vec()
+        };
+        assert_eq!(pixel(&screenshot, 0, 0), palette.rgb(0x16, 0));
+        assert_eq!(pixel(&screenshot, 8, 0), palette.rgb(0x30, 0));
+
+        // the PNG is big enough to take several stored blocks
+        let (width, height, data) = decode_png(&screenshot.to_png());
+        assert_eq!((width, height), (256, 240));
+        assert_eq!(data.len(), 240 * (256 * 3 + 1));
+        assert_eq!(data[1..4], palette.rgb(0x16, 0));
+        assert_eq!(data[data.len() - 3..], palette.rgb(0x30, 0));
+    }
+
+    #[test]
+    fn test_screenshot_with_emphasis() {
+        let mut debugger = Debugger::new(machine());
+        let path = std::env::temp_dir().join(format!("screenshot_test_{}.png", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let output = debugger.execute(&format!("screenshot {path} 4")).unwrap();
+        assert_eq!(output, format!("Saved frame 4 to {path}"));
+        let (_, _, data) = decode_png(&std::fs::read(path).unwrap());
+        std::fs::remove_file(path).unwrap();
+        let palette = Palette::default();
+        assert_eq!(debugger.cpu.bus.ppu().frame_emphasis(), 0b001);
+        assert_eq!(data[1..4], palette.rgb(0x16, 0b001));
+        assert_eq!(data[data.len() - 3..], [0xff, 0xd0, 0xd0]);
+
+        // a frame that's been drawn already is taken right away
+        let output = debugger.execute(&format!("screenshot {path} 1")).unwrap();
+        assert_eq!(output, format!("Saved frame 4 to {path}"));
+        std::fs::remove_file(path).unwrap();
+
+        let output = debugger.execute("screenshot").unwrap();
+        assert_eq!(output, "Error: Missing file");
+        let output = debugger.execute(&format!("screenshot {path} x")).unwrap();
+        assert_eq!(output, "Error: Invalid frame x");
+    }
+}
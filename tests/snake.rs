@@ -1,10 +1,10 @@
 use nes_emulator::Mem;
 use nes_emulator::cpu::CPU;
+use nes_emulator::palette::Palette;
 use rand::Rng;
 use sdl2::EventPump;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
 
 /// Records the WASD, arrow key, or escape input and writes that into
@@ -67,34 +67,30 @@ fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
     }
 }
 
-/// Interprets the color of a pixel (represented as 1 byte)
-/// in memory
-fn color(byte: u8) -> Color {
-    // keep in mind that our snake game uses 1 byte for color
-    // on each pixel while sdl2 expects 3 bytes
-    // so we take the 1 byte from the 0x200..0x600 region and map
-    // it to a 3 byte color value
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
-    }
+/// The NES palette indices closest to the 16 colors the snake game (written
+/// for easy6502) uses: black, white, red, cyan, purple, green, blue, yellow,
+/// orange, brown, light red, dark grey, grey, light green, light blue and
+/// light grey
+const SNAKE_COLORS: [u8; 16] = [
+    0x0F, 0x30, 0x16, 0x2C, 0x14, 0x1A, 0x12, 0x28, 0x27, 0x07, 0x26, 0x2D, 0x00, 0x2A, 0x21, 0x10,
+];
+
+/// Interprets the color of a pixel (represented as 1 byte) in memory.
+/// Keep in mind that our snake game uses 1 byte for color on each pixel
+/// while sdl2 expects 3 bytes, so we take the 1 byte from the 0x200..0x600
+/// region and look it up in the palette
+fn color(palette: &Palette, byte: u8) -> [u8; 3] {
+    palette.rgb(SNAKE_COLORS[(byte & 0x0F) as usize], 0)
 }
 
 /// Reads the whole screen state of the game from 0x200..0x600 and copies the color
 /// of each byte over to the frame buffer of the sdl2 window
-fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
+fn read_screen_state(cpu: &CPU, palette: &Palette, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     let mut frame_idx = 0;
     let mut update = false;
     for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
+        let color_idx = cpu.mem_peek(i as u16);
+        let [b1, b2, b3] = color(palette, color_idx);
         if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
             frame[frame_idx] = b1;
             frame[frame_idx + 1] = b2;
@@ -168,6 +164,7 @@ fn snake_test() {
     ];
 
     let mut screen_state = [0_u8; 32 * 3 * 32];
+    let palette = Palette::default();
     let mut rng = rand::thread_rng();
 
     // Instantiate the CPU and loads the snake game into memory
@@ -184,7 +181,7 @@ fn snake_test() {
 
         // read the screen state from the cpu and renders it accordingly
         // on the canvas
-        if read_screen_state(cpu, &mut screen_state) {
+        if read_screen_state(cpu, &palette, &mut screen_state) {
             texture.update(None, &screen_state, 32 * 3).unwrap();
             canvas.copy(&texture, None, None).unwrap();
             canvas.present();
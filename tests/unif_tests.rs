@@ -0,0 +1,149 @@
+//! UNIF file tests reside here
+
+#[cfg(test)]
+mod test {
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::ppu::Mirroring;
+    use nes_emulator::region::Region;
+    use nes_emulator::rom::Rom;
+    use nes_emulator::rom::db::ExpansionDevice;
+    use nes_emulator::rom::unif;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    /// A UNIF file made of `chunks`
+    fn unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut raw = b"UNIF".to_vec();
+        raw.extend_from_slice(&7u32.to_le_bytes());
+        raw.resize(32, 0);
+        for chunk in chunks {
+            raw.extend_from_slice(chunk);
+        }
+        raw
+    }
+
+    #[test]
+    fn test_nrom() {
+        let raw = unif(&[
+            chunk(b"MAPR", b"NES-NROM-256\0"),
+            chunk(b"PRG0", &[0xAA; 0x8000]),
+            chunk(b"CHR0", &[0x55; 0x2000]),
+            chunk(b"MIRR", &[1]),
+            chunk(b"NAME", b"Test\0"),
+        ]);
+        let rom = Rom::from_unif(&raw).unwrap();
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.chr_rom, vec![0x55; 0x2000]);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert!(!rom.battery);
+        assert_eq!(rom.region, None);
+
+        let mut bus = Bus::with_rom(rom).unwrap();
+        assert_eq!(bus.mem_read(0x8000), 0xAA);
+        assert_eq!(bus.mem_read(0xFFFF), 0xAA);
+    }
+
+    #[test]
+    fn test_chunks_go_in_order() {
+        let raw = unif(&[
+            chunk(b"MAPR", b"NES-ANROM\0"),
+            chunk(b"PRG1", &[1; 0x4000]),
+            chunk(b"PRG0", &[0; 0x4000]),
+            chunk(b"BATR", &[0]),
+            chunk(b"TVCI", &[1]),
+            chunk(b"CTRL", &[0b0000_0011]),
+        ]);
+        let rom = Rom::from_unif(&raw).unwrap();
+        assert_eq!(rom.mapper, 7);
+        assert_eq!(rom.prg_rom[0x3FFF], 0);
+        assert_eq!(rom.prg_rom[0x4000], 1);
+        // CHR RAM
+        assert!(rom.chr_rom.is_empty());
+        assert!(rom.battery);
+        assert_eq!(rom.region, Some(Region::Pal));
+        assert_eq!(rom.expansion, Some(ExpansionDevice::Zapper));
+    }
+
+    #[test]
+    fn test_board_names() {
+        let boards = [
+            ("NES-NROM-128", (0, 0)),
+            ("HVC-NROM-128", (0, 0)),
+            ("NES-EKROM", (5, 0)),
+            ("NES-ANROM", (7, 2)),
+            ("NES-AN1ROM", (7, 1)),
+            ("COLORDREAMS-74*377", (11, 0)),
+            ("KONAMI-VRC-2A", (22, 0)),
+            ("KONAMI-VRC-4E", (23, 2)),
+            ("KONAMI-VRC-6B", (26, 0)),
+            ("KONAMI-VRC-7", (85, 0)),
+            ("NES-GNROM", (66, 0)),
+            ("SUNSOFT-FME-7", (69, 0)),
+            ("CAMERICA-BF9097", (71, 1)),
+            ("AVE-NINA-03", (79, 0)),
+            ("JALECO-JF-09", (87, 0)),
+            ("TAITO-74*139/74", (87, 0)),
+        ];
+        for (board, mapper) in boards {
+            assert_eq!(unif::board_mapper(board), Some(mapper), "{board}");
+        }
+        assert_eq!(unif::board_mapper("NES-SLROM"), None);
+        assert_eq!(unif::board_mapper("UNL-8237"), None);
+    }
+
+    #[test]
+    fn test_vrc_wiring() {
+        // VRC4e has its register lines on A2 and A3
+        let raw = unif(&[
+            chunk(b"MAPR", b"KONAMI-VRC-4E\0"),
+            chunk(
+                b"PRG0",
+                &(0..16).flat_map(|bank| [bank; 0x2000]).collect::<Vec<_>>(),
+            ),
+            chunk(b"CHR0", &[0; 0x2000]),
+        ]);
+        let rom = Rom::from_unif(&raw).unwrap();
+        assert_eq!((rom.mapper, rom.submapper), (23, 2));
+        let mut bus = Bus::with_rom(rom).unwrap();
+        bus.mem_write(0x8000, 3);
+        assert_eq!(bus.mem_read(0x8000), 3);
+        // A1 doesn't count on VRC4e (it would for VRC4f, the other board
+        // mapper 23 stands for), so this is the mirroring...
+        bus.mem_write(0x9002, 0b10);
+        assert_eq!(bus.mem_read(0xC000), 14);
+        // ...and this $9000's register 2, the PRG swap mode
+        bus.mem_write(0x9008, 0b10);
+        assert_eq!(bus.mem_read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_errors() {
+        let prg = chunk(b"PRG0", &[0; 0x4000]);
+        assert!(Rom::from_unif(b"NES\x1A").is_err());
+        // no board
+        assert!(Rom::from_unif(&unif(std::slice::from_ref(&prg))).is_err());
+        let err =
+            Rom::from_unif(&unif(&[chunk(b"MAPR", b"NES-SLROM\0"), prg.clone()])).unwrap_err();
+        assert!(err.contains("NES-SLROM"), "{err}");
+        // no PRG ROM
+        assert!(Rom::from_unif(&unif(&[chunk(b"MAPR", b"NES-NROM-128\0")])).is_err());
+        // four screen
+        let four_screen = unif(&[
+            chunk(b"MAPR", b"NES-NROM-128\0"),
+            prg.clone(),
+            chunk(b"MIRR", &[4]),
+        ]);
+        assert!(Rom::from_unif(&four_screen).is_err());
+        // a chunk longer than the file
+        let mut truncated = unif(&[chunk(b"MAPR", b"NES-NROM-128\0"), prg]);
+        truncated.truncate(truncated.len() - 1);
+        assert!(Rom::from_unif(&truncated).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Rendering and determinism checking (replay verification) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{boot, controller_program, drawing_rom, machine, run_frames};
+    use nes_emulator::Mem;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::cpu::hooks::HookAction;
+    use nes_emulator::hash::fnv1a;
+    use nes_emulator::movie::{COMMAND_SOFT_RESET, FrameInput, Movie};
+    use nes_emulator::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use nes_emulator::verify::{HashLog, Subsystem, check_determinism, hash_movie};
+
+    /// A movie for the controller program that presses something different
+    /// every frame and resets the console once
+    fn movie(cpu: &CPU) -> Movie {
+        let mut movie = Movie::new("controller.nes", cpu.bus.rom_checksum().unwrap());
+        for frame in 0..20u8 {
+            movie.frames.push(FrameInput {
+                commands: if frame == 12 { COMMAND_SOFT_RESET } else { 0 },
+                gamepads: [frame.wrapping_mul(37), 0],
+            });
+        }
+        movie
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_render_frame() {
+        let mut cpu = boot(&drawing_rom());
+        run_frames(&mut cpu, 2);
+
+        let frame = cpu.bus.ppu().frame_buffer();
+        assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        let pixel = |x: usize, y: usize| frame[y * SCREEN_WIDTH + x];
+        assert_eq!(pixel(0, 0), 0x21);
+        assert_eq!(pixel(7, 7), 0x21);
+        assert_eq!(pixel(8, 0), 0x0f);
+        assert_eq!(pixel(8, 8), 0x0f);
+        assert_eq!(pixel(16, 16), 0x16);
+        assert_eq!(pixel(23, 23), 0x16);
+        assert_eq!(pixel(16, 15), 0x0f);
+        assert_eq!(pixel(24, 16), 0x0f);
+        // sprite 1 is behind the background, so it only shows on its last
+        // line, below the tile
+        assert_eq!(pixel(0, 1), 0x21);
+        assert_eq!(pixel(0, 8), 0x16);
+        assert_eq!(frame.iter().filter(|&&color| color == 0x16).count(), 64 + 8);
+    }
+
+    #[test]
+    fn test_rendering_off_shows_the_backdrop() {
+        let mut cpu = machine(&controller_program());
+        run_frames(&mut cpu, 1);
+        assert!(cpu.bus.ppu().frame_buffer().iter().all(|&color| color == 0));
+    }
+
+    #[test]
+    fn test_deterministic_replay() {
+        let prg = controller_program();
+        let movie = movie(&machine(&prg));
+        let divergence = check_determinism(&mut machine(&prg), &mut machine(&prg), &movie).unwrap();
+        assert_eq!(divergence, None);
+
+        let log = hash_movie(&mut machine(&prg), movie.clone()).unwrap();
+        assert_eq!(log.frames.len(), 20);
+        assert_eq!(log.frames[0].frame, 1);
+        assert_eq!(log.frames[19].frame, 20);
+        // a machine that ran a while gets power cycled by the movie first
+        let mut used = machine(&prg);
+        run_frames(&mut used, 5);
+        assert_eq!(hash_movie(&mut used, movie).unwrap(), log);
+    }
+
+    #[test]
+    fn test_divergence_detection() {
+        let prg = controller_program();
+        let movie = movie(&machine(&prg));
+        let expected = hash_movie(&mut machine(&prg), movie.clone()).unwrap();
+
+        // hooks that meddle with the machine at the end of a frame, which is
+        // when the state gets captured
+        let diverge = |meddle: fn(&mut CPU)| {
+            let mut cpu = machine(&prg);
+            let mut frames = 0;
+            cpu.add_frame_hook(move |cpu, _| {
+                frames += 1;
+                if frames == 7 {
+                    meddle(cpu);
+                }
+                HookAction::Continue
+            });
+            let actual = hash_movie(&mut cpu, movie.clone()).unwrap();
+            expected.compare(&actual).unwrap()
+        };
+
+        let divergence = diverge(|cpu| cpu.register_y = 0x42);
+        assert_eq!(
+            (divergence.frame, divergence.subsystem),
+            (7, Subsystem::Cpu)
+        );
+        assert!(divergence.actual.contains("Y=$42"), "{}", divergence.actual);
+
+        let divergence = diverge(|cpu| cpu.mem_write(0x0400, 1));
+        assert_eq!(
+            (divergence.frame, divergence.subsystem),
+            (7, Subsystem::Ram)
+        );
+
+        // the backdrop color changes, which shows in the next frame drawn
+        let divergence = diverge(|cpu| {
+            cpu.mem_write(0x2006, 0x3f);
+            cpu.mem_write(0x2006, 0x00);
+            cpu.mem_write(0x2007, 0x30);
+        });
+        assert_eq!(
+            (divergence.frame, divergence.subsystem),
+            (8, Subsystem::FrameBuffer)
+        );
+        assert_eq!(
+            divergence.to_string(),
+            format!(
+                "Frame 8: frame buffer diverged (expected {}, got {})",
+                divergence.expected, divergence.actual
+            )
+        );
+
+        let mut shorter = expected.clone();
+        shorter.frames.truncate(15);
+        let divergence = expected.compare(&shorter).unwrap();
+        assert_eq!(
+            (divergence.frame, divergence.subsystem),
+            (16, Subsystem::Length)
+        );
+    }
+
+    #[test]
+    fn test_hash_log_files() {
+        let prg = controller_program();
+        let movie = movie(&machine(&prg));
+        let log = hash_movie(&mut machine(&prg), movie).unwrap();
+        let text = log.to_text();
+        assert!(text.starts_with("# frame a x y sp p pc cycles ram frame_buffer\n1 "));
+        assert_eq!(HashLog::parse(&text).unwrap(), log);
+
+        let path = std::env::temp_dir().join(format!("verify_test_{}.log", std::process::id()));
+        log.save(&path).unwrap();
+        assert_eq!(HashLog::load(&path).unwrap(), log);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            HashLog::parse("# comment\n1 00 00 00 FD 24 C000 7 0 zz").unwrap_err(),
+            "line 2: Invalid state 1 00 00 00 FD 24 C000 7 0 zz"
+        );
+    }
+}
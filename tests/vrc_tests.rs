@@ -0,0 +1,281 @@
+//! Konami VRC2 / VRC4 / VRC6 / VRC7 (banking, wirings, the IRQ counter and
+//! expansion audio) tests reside here
+
+mod common;
+
+#[cfg(test)]
+mod test {
+    use crate::common::{audio_range, ines, numbered_chr, numbered_prg};
+    use nes_emulator::Mem;
+    use nes_emulator::bus::Bus;
+    use nes_emulator::cpu::CPU;
+    use nes_emulator::ppu::Mirroring;
+    use nes_emulator::rom::Rom;
+
+    /// An NES 2.0 image with 128 KiB of PRG ROM (every 8 KiB filled with its
+    /// bank number) and 128 KiB of CHR ROM (every KiB filled with its number)
+    fn nes2(mapper: u8, submapper: u8) -> Vec<u8> {
+        let mut raw = ines(mapper, 0, &numbered_prg(8), &numbered_chr(16));
+        raw[7] |= 0b1000;
+        raw[8] = submapper << 4;
+        raw
+    }
+
+    fn cartridge(mapper: u8, submapper: u8) -> Bus {
+        let mut bus = Bus::with_rom(Rom::new(&nes2(mapper, submapper)).unwrap()).unwrap();
+        // keep the APU's frame IRQ out of the way
+        bus.mem_write(0x4017, 0x40);
+        bus
+    }
+
+    fn prg_banks(bus: &Bus) -> Vec<u8> {
+        [0x8000, 0xA000, 0xC000, 0xE000]
+            .iter()
+            .map(|&addr| bus.mem_peek(addr))
+            .collect()
+    }
+
+    fn chr_bank(bus: &Bus, addr: u16) -> u8 {
+        let ppu = bus.ppu();
+        ppu.chr_rom[ppu.chr_offset(addr)]
+    }
+
+    #[test]
+    fn test_submapper_parsing() {
+        assert_eq!(Rom::new(&nes2(23, 3)).unwrap().submapper, 3);
+        // only NES 2.0 headers have one
+        let mut ines = nes2(23, 3);
+        ines[7] = 23 & 0xF0;
+        assert_eq!(Rom::new(&ines).unwrap().submapper, 0);
+    }
+
+    #[test]
+    fn test_vrc4_wirings() {
+        // VRC4a (A1, A2) and VRC4c (A6, A7) on mapper 21
+        for (submapper, chr_high) in [(1, 0xB002), (2, 0xB040), (0, 0xB040), (0, 0xB002)] {
+            let mut bus = cartridge(21, submapper);
+            bus.mem_write(0xB000, 0x05);
+            bus.mem_write(chr_high, 0x01);
+            assert_eq!(chr_bank(&bus, 0x0000), 0x15, "submapper {submapper}");
+        }
+        // VRC4a doesn't listen to VRC4c's lines, so that's register 0
+        let mut bus = cartridge(21, 1);
+        bus.mem_write(0xB040, 0x01);
+        assert_eq!(chr_bank(&bus, 0x0000), 0x01);
+
+        // VRC4b (A1, A0), VRC4d (A3, A2) on mapper 25: register 1 is the
+        // high bits of the first bank, register 2 the low bits of the second
+        for (submapper, second_low) in [(1, 0xC001), (2, 0xC004), (0, 0xC001)] {
+            let mut bus = cartridge(25, submapper);
+            bus.mem_write(second_low, 0x07);
+            assert_eq!(chr_bank(&bus, 0x0C00), 0x07, "submapper {submapper}");
+        }
+    }
+
+    #[test]
+    fn test_vrc4_prg_and_mirroring() {
+        let mut bus = cartridge(23, 1);
+        assert_eq!(prg_banks(&bus), [0, 1, 14, 15]);
+        bus.mem_write(0x8000, 3);
+        bus.mem_write(0xA000, 5);
+        assert_eq!(prg_banks(&bus), [3, 5, 14, 15]);
+        // swap mode puts the switchable bank at 0xC000
+        bus.mem_write(0x9002, 0b10);
+        assert_eq!(prg_banks(&bus), [14, 5, 3, 15]);
+
+        for (value, mirroring) in [
+            (0, Mirroring::Vertical),
+            (1, Mirroring::Horizontal),
+            (2, Mirroring::SingleScreenLower),
+            (3, Mirroring::SingleScreenUpper),
+        ] {
+            bus.mem_write(0x9000, value);
+            assert_eq!(bus.ppu().mirroring, mirroring);
+        }
+    }
+
+    #[test]
+    fn test_vrc2() {
+        // VRC2a drops the low bit of the CHR banks
+        let mut bus = cartridge(22, 0);
+        bus.mem_write(0xB000, 0x07);
+        assert_eq!(chr_bank(&bus, 0x0000), 0x03);
+        // and only has vertical and horizontal mirroring
+        bus.mem_write(0x9000, 0x03);
+        assert_eq!(bus.ppu().mirroring, Mirroring::Horizontal);
+
+        // VRC2b has no swap mode nor IRQ
+        let mut bus = cartridge(23, 3);
+        bus.mem_write(0x8000, 3);
+        bus.mem_write(0x9002, 0b10);
+        assert_eq!(prg_banks(&bus)[0], 3);
+        bus.mem_write(0xF002, 0b110);
+        bus.tick(1000);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_vrc_irq() {
+        let mut bus = cartridge(23, 1);
+        // cycle mode: 0x100 - 0xF0 cycles to the wrap around
+        bus.mem_write(0xF000, 0x0);
+        bus.mem_write(0xF001, 0xF);
+        bus.mem_write(0xF002, 0b111);
+        bus.tick(0x0F);
+        assert!(!bus.irq_pending());
+        bus.tick(1);
+        assert!(bus.irq_pending());
+        // acknowledging keeps it enabled (bit 0 of control), and it reloads
+        bus.mem_write(0xF003, 0);
+        assert!(!bus.irq_pending());
+        bus.tick(0x10);
+        assert!(bus.irq_pending());
+
+        // scanline mode: every 341 / 3 CPU cycles
+        bus.mem_write(0xF001, 0xF);
+        bus.mem_write(0xF002, 0b010);
+        bus.tick(16 * 341 / 3 - 2);
+        assert!(!bus.irq_pending());
+        bus.tick(3);
+        assert!(bus.irq_pending());
+        // this time acknowledging disables it
+        bus.mem_write(0xF003, 0);
+        bus.tick(20 * 341 / 3);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_vrc6() {
+        let mut bus = cartridge(24, 0);
+        bus.mem_write(0x8000, 2);
+        bus.mem_write(0xC000, 9);
+        assert_eq!(prg_banks(&bus), [4, 5, 9, 15]);
+        bus.mem_write(0xD003, 0x21);
+        bus.mem_write(0xE000, 0x42);
+        assert_eq!(chr_bank(&bus, 0x0C00), 0x21);
+        assert_eq!(chr_bank(&bus, 0x1000), 0x42);
+        bus.mem_write(0xB003, 0b0000_0100);
+        assert_eq!(bus.ppu().mirroring, Mirroring::Horizontal);
+
+        // mapper 26 swaps the lines for registers 1 and 2
+        let mut bus = cartridge(26, 0);
+        bus.mem_write(0xD001, 0x21);
+        assert_eq!(chr_bank(&bus, 0x0800), 0x21);
+        bus.mem_write(0xF000, 0xFE);
+        bus.mem_write(0xF002, 0b111);
+        bus.tick(2);
+        assert!(bus.irq_pending());
+    }
+
+    #[test]
+    fn test_vrc6_audio() {
+        let mut bus = cartridge(24, 0);
+        let (loudest, quietest) = audio_range(&mut bus, 10_000);
+        assert_eq!(loudest, quietest);
+
+        // pulse 1 at full volume with a 50% duty cycle
+        bus.mem_write(0x9000, 0b0111_1111);
+        bus.mem_write(0x9001, 0xFF);
+        bus.mem_write(0x9002, 0x80);
+        let (loudest, quietest) = audio_range(&mut bus, 10_000);
+        assert!(loudest - quietest > 0.1);
+
+        // the halt bit freezes the channels
+        bus.mem_write(0x9003, 1);
+        let (loudest, quietest) = audio_range(&mut bus, 10_000);
+        assert_eq!(loudest, quietest);
+
+        // the sawtooth
+        bus.mem_write(0x9003, 0);
+        bus.mem_write(0x9002, 0);
+        bus.mem_write(0xB000, 42);
+        bus.mem_write(0xB001, 0x20);
+        bus.mem_write(0xB002, 0x80);
+        let (loudest, quietest) = audio_range(&mut bus, 10_000);
+        assert!(loudest - quietest > 0.1);
+    }
+
+    #[test]
+    fn test_vrc7() {
+        let mut bus = cartridge(85, 2);
+        assert_eq!(prg_banks(&bus), [0, 1, 2, 15]);
+        bus.mem_write(0x8000, 7);
+        bus.mem_write(0x8010, 8);
+        bus.mem_write(0x9000, 9);
+        assert_eq!(prg_banks(&bus), [7, 8, 9, 15]);
+        bus.mem_write(0xA010, 0x31);
+        bus.mem_write(0xD010, 0x32);
+        assert_eq!(chr_bank(&bus, 0x0400), 0x31);
+        assert_eq!(chr_bank(&bus, 0x1C00), 0x32);
+        bus.mem_write(0xE000, 1);
+        assert_eq!(bus.ppu().mirroring, Mirroring::Horizontal);
+
+        // VRC7b has the second registers at A3
+        let mut bus = cartridge(85, 1);
+        bus.mem_write(0x8008, 8);
+        assert_eq!(prg_banks(&bus)[1], 8);
+        bus.mem_write(0xE008, 0xFF);
+        bus.mem_write(0xF000, 0b110);
+        bus.tick(1);
+        assert!(bus.irq_pending());
+        bus.mem_write(0xF008, 0);
+        assert!(!bus.irq_pending());
+    }
+
+    /// Plays an A (440 Hz) with instrument `instrument` on channel 0
+    fn key_on(bus: &mut Bus, instrument: u8) {
+        for (register, value) in [(0x30, instrument << 4), (0x10, 0x20), (0x20, 0x19)] {
+            bus.mem_write(0x9010, register);
+            bus.mem_write(0x9030, value);
+        }
+    }
+
+    #[test]
+    fn test_vrc7_audio() {
+        let mut bus = cartridge(85, 0);
+        let (loudest, quietest) = audio_range(&mut bus, 10_000);
+        assert_eq!(loudest, quietest);
+
+        key_on(&mut bus, 1);
+        let (loudest, quietest) = audio_range(&mut bus, 20_000);
+        assert!(loudest - quietest > 0.05);
+
+        // silenced
+        bus.mem_write(0xE000, 0b0100_0000);
+        let (loudest, quietest) = audio_range(&mut bus, 10_000);
+        assert_eq!(loudest, quietest);
+        bus.mem_write(0xE000, 0);
+
+        // key off fades it out
+        bus.mem_write(0x9010, 0x20);
+        bus.mem_write(0x9030, 0x09);
+        bus.tick(1_000_000);
+        let (loudest, quietest) = audio_range(&mut bus, 10_000);
+        assert!(loudest - quietest < 0.001);
+
+        // the custom instrument: a plain sine on the carrier
+        for (register, value) in [(0, 0x01), (1, 0x21), (2, 0x3F), (5, 0xF0), (7, 0x0F)] {
+            bus.mem_write(0x9010, register);
+            bus.mem_write(0x9030, value);
+        }
+        key_on(&mut bus, 0);
+        let (loudest, quietest) = audio_range(&mut bus, 20_000);
+        // a full channel swings by twice its volume
+        assert!(loudest - quietest > 0.09);
+    }
+
+    #[test]
+    fn test_savestate() {
+        let mut cpu = CPU::with_bus(cartridge(85, 0));
+        cpu.mem_write(0x8000, 7);
+        key_on(&mut cpu.bus, 3);
+        cpu.bus.tick(5000);
+        let state = cpu.save_state();
+
+        let mut other = CPU::with_bus(cartridge(85, 0));
+        other.load_state(&state).unwrap();
+        assert_eq!(prg_banks(&other.bus)[0], 7);
+        let (loudest, quietest) = audio_range(&mut cpu.bus, 5000);
+        assert_eq!(audio_range(&mut other.bus, 5000), (loudest, quietest));
+    }
+}